@@ -0,0 +1,86 @@
+//! A minimal decoder for uncompressed (`BI_RGB`) 24bpp and 32bpp BMP images, used for the
+//! bootloader's boot splash.
+
+use core::convert::TryInto;
+
+/// A parsed, uncompressed BMP image. Borrows the original file bytes rather than copying pixel
+/// data out, mirroring `elf_parser::ElfParser`'s approach of parsing in place.
+pub struct BmpImage<'a> {
+    data: &'a [u8],
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+    bits_per_pixel: u16,
+    pixel_data_offset: usize,
+    row_stride: usize,
+    /// BMP rows are conventionally stored bottom-up (a negative height in the DIB header means
+    /// top-down instead)
+    bottom_up: bool,
+}
+
+impl<'a> BmpImage<'a> {
+    /// Parses `data` as a BMP file. Only uncompressed 24bpp and 32bpp images are supported -
+    /// anything else (compressed, indexed-color, RLE) returns `None`.
+    pub fn parse(data: &'a [u8]) -> Option<BmpImage<'a>> {
+        if data.len() < 54 || &data[0..2] != b"BM" {
+            return None;
+        }
+
+        let pixel_data_offset: usize =
+            u32::from_le_bytes(data[10..14].try_into().ok()?).try_into().ok()?;
+
+        // The DIB header size tells us which header variant follows; every variant we support
+        // (BITMAPINFOHEADER and later) places width/height/bpp/compression at the same offsets
+        let dib_header_size = u32::from_le_bytes(data[14..18].try_into().ok()?);
+        if dib_header_size < 40 {
+            return None;
+        }
+
+        let width = i32::from_le_bytes(data[18..22].try_into().ok()?);
+        let height = i32::from_le_bytes(data[22..26].try_into().ok()?);
+        let bits_per_pixel = u16::from_le_bytes(data[28..30].try_into().ok()?);
+        let compression = u32::from_le_bytes(data[30..34].try_into().ok()?);
+
+        if compression != 0 {
+            // Only BI_RGB (uncompressed) is supported
+            return None;
+        }
+        if bits_per_pixel != 24 && bits_per_pixel != 32 {
+            return None;
+        }
+        if width <= 0 || height == 0 {
+            return None;
+        }
+
+        let width: u32 = width.try_into().ok()?;
+        let bottom_up = height > 0;
+        let height: u32 = height.unsigned_abs();
+
+        let row_stride = ((width as usize * (bits_per_pixel as usize / 8)) + 3) & !3;
+        let pixel_data_size = row_stride.checked_mul(height as usize)?;
+        if pixel_data_offset.checked_add(pixel_data_size)? > data.len() {
+            return None;
+        }
+
+        Some(BmpImage { data, width, height, bits_per_pixel, pixel_data_offset, row_stride, bottom_up })
+    }
+
+    /// Returns the (red, green, blue) components of the pixel at `(x, y)`, where `(0, 0)` is the
+    /// top-left corner regardless of the file's underlying row order. `x` and `y` must be within
+    /// `width`/`height`.
+    pub fn pixel_at(&self, x: u32, y: u32) -> (u8, u8, u8) {
+        let row = if self.bottom_up { self.height - 1 - y } else { y };
+        let bytes_per_pixel = self.bits_per_pixel as usize / 8;
+        let pixel_offset = self.pixel_data_offset + row as usize * self.row_stride
+            + x as usize * bytes_per_pixel;
+
+        // Pixel data is stored as BGR (24bpp) or BGRA (32bpp); the alpha byte, if present, is
+        // unused by the splash
+        let blue = self.data[pixel_offset];
+        let green = self.data[pixel_offset + 1];
+        let red = self.data[pixel_offset + 2];
+
+        (red, green, blue)
+    }
+}
@@ -14,40 +14,56 @@ struct DiskAddressPacket {
 	start_sector_offset:    u64
 }
 
-/// The number of sectors to read in each call to the BIOS. The buffer is allocated on the stack,
-/// so this can't be too large.
-const SECTOR_BUFFER_SIZE: u32 = 8;
-
-pub fn read_kernel(boot_disk_id: u8, bootloader_size: u32) -> Option<Vec<u8>> {
-	// Get the sector count of the boot disk. We cast to u32, because we don't have enough memory
-	// to load more sectors than that anyway
-	let disk_sector_count = get_disk_sector_count(boot_disk_id)? as u32;
-
-    // Dividing the size by 512 while rounding up gives us the bootloader sector count
-    let bootloader_sector_count = (bootloader_size + 511) / 512;
-    // We assume that the rest of the sectors on disk are kernel sectors
-    let kernel_sector_count = disk_sector_count - bootloader_sector_count;
-    
+/// The number of bytes read from the BIOS per low-level read call. The buffer is allocated on the
+/// stack, so this can't be too large; it must be a multiple of every sector size we support (512
+/// for a FAT32 hard disk, 2048 for El Torito no-emulation CD-ROM boot).
+const SECTOR_BUFFER_BYTES: u32 = 4096;
+
+/// Offset of the partition table within the MBR (LBA 0)
+const MBR_PARTITION_TABLE_OFFSET: usize = 0x1BE;
+/// Number of partition entries in the MBR
+const MBR_PARTITION_ENTRY_COUNT: usize = 4;
+/// Size in bytes of a single MBR partition entry
+const MBR_PARTITION_ENTRY_SIZE: usize = 16;
+
+/// The partition type bytes used for FAT32 partitions
+const FAT32_PARTITION_TYPES: [u8; 2] = [0x0B, 0x0C];
+
+/// The end-of-chain marker: any FAT32 cluster value at or above this means there is no next
+/// cluster. Values are always masked to 28 bits before being compared against this.
+const FAT32_END_OF_CHAIN: u32 = 0x0FFFFFF8;
+
+/// The 8.3 (name padded to 8 bytes, extension padded to 3, both space-padded, no dot) names of the
+/// files we look for in a FAT32 root directory
+const FAT32_KERNEL_FILENAME: &[u8; 11] = b"KERNEL  BIN";
+const FAT32_INITRAMFS_FILENAME: &[u8; 11] = b"INITRD  BIN";
+const FAT32_CMDLINE_FILENAME: &[u8; 11] = b"CMDLINE TXT";
+
+/// Reads `sector_count` logical sectors of `sector_size` bytes each, starting at `start_lba`, from
+/// the disk `boot_disk_id`, using the BIOS extended read (int 13h/ah=42h). `sector_size` must be
+/// 512 for a FAT32 hard disk, or 2048 for an El Torito no-emulation CD-ROM.
+fn read_sectors(boot_disk_id: u8, start_lba: u64, sector_count: u32, sector_size: u32)
+    -> Option<Vec<u8>> {
     // Local stack buffer which is under the 64K limit that the BIOS can read to
-    let mut sector_buffer = [0u8; 512*SECTOR_BUFFER_SIZE as usize];
+    let mut sector_buffer = [0u8; SECTOR_BUFFER_BYTES as usize];
+    let sectors_per_chunk = SECTOR_BUFFER_BYTES / sector_size;
+
+    let mut sectors: Vec<u8> = Vec::with_capacity((sector_count * sector_size) as usize);
 
-	let mut kernel_image: Vec<u8> = Vec::with_capacity((kernel_sector_count * 512) as usize);
+    for sector_off in (0..sector_count).step_by(sectors_per_chunk as usize) {
+        // We either read `sectors_per_chunk` sectors, or if we are at the end of the requested
+        // range, the remaining sectors
+        let sectors_to_read = core::cmp::min(sectors_per_chunk, sector_count - sector_off);
 
-	// Read each kernel sector
-    for sector_off in (0..kernel_sector_count).step_by(SECTOR_BUFFER_SIZE as usize) {
-        // We either read `SECTOR_BUFFER_SIZE` sectors, or if we are at the end of the image, the
-        // remaining sectors
-        let sectors_to_read = core::cmp::min(SECTOR_BUFFER_SIZE, kernel_sector_count - sector_off);
-        
         let mut disk_address_packet = DiskAddressPacket {
             struct_size: 0x10,
             _unused: 0,
             sector_read_count: sectors_to_read as u16,
             memory_buffer_offset: &mut sector_buffer as *mut _ as u16,
             memory_buffer_segment: 0,
-            start_sector_offset: (bootloader_sector_count + sector_off) as u64
+            start_sector_offset: start_lba + sector_off as u64
         };
-    
+
         let mut register_context = RegisterState {
             eax: 0x4200,
             edx: boot_disk_id as u32,
@@ -57,61 +73,336 @@ pub fn read_kernel(boot_disk_id: u8, bootloader_size: u32) -> Option<Vec<u8>> {
 
         // Perform the extended BIOS read
         unsafe { invoke_realmode_interrupt(0x13, &mut register_context); }
-    
+
         // CF is set on error
-		if (register_context.eflags & 1) != 0 {
+        if (register_context.eflags & 1) != 0 {
             println!("Failed to read drive sector (int 13h/ah=42h)");
             return None;
         }
 
-        // Append the read sectors to the kernel image
-		kernel_image.extend(&sector_buffer[..sectors_to_read as usize * 512]);
-	}
-    
+        // Append the read sectors to the result
+        sectors.extend(&sector_buffer[..sectors_to_read as usize * sector_size as usize]);
+    }
+
+    Some(sectors)
+}
+
+/// A parsed MBR partition table entry
+struct MbrPartitionEntry {
+    lba_start: u32,
+}
+
+/// Parses the partition table out of the MBR (`sector` must be the 512 bytes of LBA 0) and returns
+/// the first partition whose type byte marks it as FAT32
+fn find_fat32_partition(sector: &[u8]) -> Option<MbrPartitionEntry> {
+    for entry_index in 0..MBR_PARTITION_ENTRY_COUNT {
+        let entry_offset = MBR_PARTITION_TABLE_OFFSET + entry_index * MBR_PARTITION_ENTRY_SIZE;
+        let entry = &sector[entry_offset..entry_offset + MBR_PARTITION_ENTRY_SIZE];
+
+        let partition_type = entry[4];
+        if !FAT32_PARTITION_TYPES.contains(&partition_type) {
+            continue;
+        }
+
+        let lba_start = u32::from_le_bytes(entry[8..12].try_into().ok()?);
+        return Some(MbrPartitionEntry { lba_start });
+    }
+
+    println!("Failed to find a FAT32 partition in the MBR");
+    None
+}
+
+/// The fields of a FAT32 BIOS Parameter Block that matter for reading files. We only support a
+/// sector size of 512 bytes (same assumption the rest of the disk code already makes), so that
+/// field itself isn't kept around after being checked.
+struct Fat32Bpb {
+    sectors_per_cluster: u8,
+    reserved_sectors:    u16,
+    num_fats:            u8,
+    fat_size:            u32,
+    root_cluster:        u32,
+}
+
+impl Fat32Bpb {
+    /// Parses the BPB out of a partition's boot sector (the first sector of the partition)
+    fn parse(sector: &[u8]) -> Option<Fat32Bpb> {
+        let bytes_per_sector = u16::from_le_bytes(sector[0x0B..0x0D].try_into().ok()?);
+        let sectors_per_cluster = sector[0x0D];
+        let reserved_sectors = u16::from_le_bytes(sector[0x0E..0x10].try_into().ok()?);
+        let num_fats = sector[0x10];
+        let fat_size = u32::from_le_bytes(sector[0x24..0x28].try_into().ok()?);
+        let root_cluster = u32::from_le_bytes(sector[0x2C..0x30].try_into().ok()?);
+
+        if bytes_per_sector != 512 {
+            println!("FAT32 partition uses non standard sector size");
+            return None;
+        }
+
+        Some(Fat32Bpb { sectors_per_cluster, reserved_sectors, num_fats, fat_size, root_cluster })
+    }
+}
+
+/// Bundles the addresses needed to walk a FAT32 volume's cluster chains, so they don't have to be
+/// threaded through every helper individually
+struct Fat32Volume {
+    boot_disk_id:      u8,
+    /// LBA of the first sector of the partition
+    partition_lba:     u64,
+    /// LBA of the first FAT, relative to the start of the disk
+    fat_lba:           u64,
+    /// LBA of the first data sector (cluster 2), relative to the start of the partition
+    first_data_sector: u32,
+    sectors_per_cluster: u8,
+}
+
+impl Fat32Volume {
+    /// Reads the 32-bit FAT entry for `cluster`, masked down to its 28 meaningful bits
+    fn fat_entry(&self, cluster: u32) -> Option<u32> {
+        let fat_byte_offset = cluster as u64 * 4;
+        let fat_sector = fat_byte_offset / 512;
+        let offset_in_sector = (fat_byte_offset % 512) as usize;
+
+        let sector = read_sectors(self.boot_disk_id, self.fat_lba + fat_sector, 1, 512)?;
+        let raw_entry = u32::from_le_bytes(sector[offset_in_sector..offset_in_sector + 4]
+            .try_into().ok()?);
+
+        Some(raw_entry & 0x0FFFFFFF)
+    }
+
+    /// Reads every cluster in the chain starting at `start_cluster`, following the FAT until the
+    /// end-of-chain marker. If `max_bytes` is given, stops as soon as at least that many bytes have
+    /// been read (the caller is expected to truncate the exact size afterwards, since cluster reads
+    /// are always whole-cluster-sized).
+    fn read_cluster_chain(&self, start_cluster: u32, max_bytes: Option<usize>) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut cluster = start_cluster;
+
+        while cluster >= 2 && cluster < FAT32_END_OF_CHAIN {
+            let cluster_lba = self.partition_lba + self.first_data_sector as u64
+                + (cluster - 2) as u64 * self.sectors_per_cluster as u64;
+            let cluster_data = read_sectors(self.boot_disk_id, cluster_lba,
+                self.sectors_per_cluster as u32, 512)?;
+            data.extend(cluster_data);
+
+            if let Some(max_bytes) = max_bytes {
+                if data.len() >= max_bytes {
+                    break;
+                }
+            }
+
+            cluster = self.fat_entry(cluster)?;
+        }
+
+        Some(data)
+    }
+}
+
+/// A matching entry found while scanning a FAT32 directory's entries
+struct Fat32DirEntry {
+    first_cluster: u32,
+    file_size:     u32,
+}
+
+/// Scans the raw bytes of a directory (the concatenated clusters of its chain) for an entry with
+/// the 8.3 name `name`
+fn find_directory_entry(directory: &[u8], name: &[u8; 11]) -> Option<Fat32DirEntry> {
+    for entry in directory.chunks_exact(32) {
+        // An all-zero first byte marks the end of the directory; 0xE5 marks a deleted entry
+        if entry[0] == 0x00 {
+            break;
+        }
+        if entry[0] == 0xE5 {
+            continue;
+        }
+
+        if &entry[0..11] != name {
+            continue;
+        }
+
+        let cluster_high = u16::from_le_bytes(entry[20..22].try_into().ok()?) as u32;
+        let cluster_low = u16::from_le_bytes(entry[26..28].try_into().ok()?) as u32;
+        let file_size = u32::from_le_bytes(entry[28..32].try_into().ok()?);
+
+        return Some(Fat32DirEntry { first_cluster: (cluster_high << 16) | cluster_low, file_size });
+    }
+
+    None
+}
+
+impl Fat32Volume {
+    /// Locates the first FAT32 partition on `boot_disk_id` and parses its BPB, returning the
+    /// volume plus the starting cluster of its root directory
+    fn open(boot_disk_id: u8) -> Option<(Fat32Volume, u32)> {
+        // Read the MBR and find the first FAT32 partition
+        let mbr = read_sectors(boot_disk_id, 0, 1, 512)?;
+        let partition = find_fat32_partition(&mbr)?;
+
+        // Read the partition's own boot sector to get its BPB
+        let boot_sector = read_sectors(boot_disk_id, partition.lba_start as u64, 1, 512)?;
+        let bpb = Fat32Bpb::parse(&boot_sector)?;
+
+        let volume = Fat32Volume {
+            boot_disk_id,
+            partition_lba: partition.lba_start as u64,
+            fat_lba: partition.lba_start as u64 + bpb.reserved_sectors as u64,
+            first_data_sector: bpb.reserved_sectors as u32 + bpb.num_fats as u32 * bpb.fat_size,
+            sectors_per_cluster: bpb.sectors_per_cluster,
+        };
+
+        Some((volume, bpb.root_cluster))
+    }
+
+    /// Reads `name` out of the volume's root directory (whose starting cluster is `root_cluster`)
+    fn read_file(&self, root_cluster: u32, name: &[u8; 11]) -> Option<Vec<u8>> {
+        let root_directory = self.read_cluster_chain(root_cluster, None)?;
+        let entry = find_directory_entry(&root_directory, name)?;
+
+        // Read the file's cluster chain and trim it down to its exact size
+        let mut file = self.read_cluster_chain(entry.first_cluster,
+            Some(entry.file_size as usize))?;
+        file.truncate(entry.file_size as usize);
+
+        Some(file)
+    }
+}
+
+/// Reads `name` out of the root directory of the first FAT32 partition found on `boot_disk_id`
+fn read_file_from_fat32(boot_disk_id: u8, name: &[u8; 11]) -> Option<Vec<u8>> {
+    let (volume, root_cluster) = Fat32Volume::open(boot_disk_id)?;
+    volume.read_file(root_cluster, name)
+}
+
+/// Reads the kernel image off of a hard disk: `KERNEL.BIN` in the root directory of the first
+/// FAT32 partition found on `boot_disk_id`
+fn read_kernel_from_fat32(boot_disk_id: u8) -> Option<Vec<u8>> {
+    let kernel_image = read_file_from_fat32(boot_disk_id, FAT32_KERNEL_FILENAME)?;
+
     println!("Read kernel image: {} bytes, at {:#x?}", kernel_image.len(), kernel_image.as_ptr());
     Some(kernel_image)
 }
 
-/// The result of a int 13h/ah=48h BIOS call
-#[derive(Default)]
-#[repr(C)]
-struct DriveParametersResult {
-	struct_size:            u16,
-	info_flags:             u16,
-	phys_cylinder_count:    u32,
-	phys_head_count:        u32,
-	phys_sectors_per_track: u32,
-	total_sector_count:     u64,
-	bytes_per_sector:       u16
-}
-
-/// Gets the total sector count of the disk with id `disk_id`. Uses int 13h/ah=48h of the BIOS
-fn get_disk_sector_count(disk_id: u8) -> Option<u64> {
-	let mut drive_params = DriveParametersResult {
-        struct_size: 0x1A, // A size of 0x1A means we use the v1.x version of this call
-        ..Default::default()
-    };
-
-    let mut register_context = RegisterState {
-        eax: 0x4800,
-        edx: disk_id as u32,
-        esi: &mut drive_params as *mut DriveParametersResult as u32,
-        ..Default::default()
-    };
-
-    // Invoke the interrupt to get the drive info, we are only interested in the sector count
-    unsafe { invoke_realmode_interrupt(0x13, &mut register_context); }
-
-    // CF is set on error
-    if (register_context.eflags & 1) != 0 {
-        println!("Failed to get drive parameters (int 13h/ah=48h)");
-        return None;
+/// Logical sector size used by El Torito no-emulation CD-ROM boot. The BIOS still services the
+/// read through int 13h/ah=42h, but in units of the disc's 2048-byte sectors instead of a hard
+/// disk's 512-byte ones.
+const ISO9660_SECTOR_SIZE: u32 = 2048;
+
+/// LBA of the Primary Volume Descriptor, fixed by the ISO9660 standard
+const ISO9660_PVD_LBA: u64 = 16;
+
+/// Every ISO9660 volume descriptor starts with this signature, at offset 1
+const ISO9660_SIGNATURE: &[u8; 5] = b"CD001";
+
+/// The names we look for in the root directory, compared up to the ";" version suffix ISO9660
+/// appends to file identifiers
+const ISO9660_KERNEL_FILENAME: &[u8] = b"KERNEL.BIN";
+const ISO9660_INITRAMFS_FILENAME: &[u8] = b"INITRD.BIN";
+const ISO9660_CMDLINE_FILENAME: &[u8] = b"CMDLINE.TXT";
+
+/// A matching entry found while scanning an ISO9660 directory's records
+struct IsoDirEntry {
+    extent_lba: u32,
+    size:       u32,
+}
+
+/// Parses a single ISO9660 directory record: extent LBA (the first, little-endian half of the
+/// standard's both-endian field) at offset 2, data length (same both-endian convention) at offset
+/// 10
+fn parse_iso_dir_record(record: &[u8]) -> Option<IsoDirEntry> {
+    let extent_lba = u32::from_le_bytes(record.get(2..6)?.try_into().ok()?);
+    let size = u32::from_le_bytes(record.get(10..14)?.try_into().ok()?);
+
+    Some(IsoDirEntry { extent_lba, size })
+}
+
+/// Scans the raw bytes of a directory extent (its concatenated sectors) for a record named `name`
+fn find_iso_directory_entry(directory: &[u8], name: &[u8]) -> Option<IsoDirEntry> {
+    let mut offset = 0;
+    while offset < directory.len() {
+        let record_len = *directory.get(offset)? as usize;
+
+        // A zero length marks unused space at the end of the current sector: directory records
+        // never span a sector boundary, so skip ahead to the start of the next one
+        if record_len == 0 {
+            offset += ISO9660_SECTOR_SIZE as usize - (offset % ISO9660_SECTOR_SIZE as usize);
+            continue;
+        }
+
+        let record = directory.get(offset..offset + record_len)?;
+        let name_len = *record.get(32)? as usize;
+        let raw_name = record.get(33..33 + name_len)?;
+        let name_end = raw_name.iter().position(|&b| b == b';').unwrap_or(raw_name.len());
+
+        if &raw_name[..name_end] == name {
+            return parse_iso_dir_record(record);
+        }
+
+        offset += record_len;
     }
 
-    if drive_params.bytes_per_sector != 512 {
-        println!("Boot disk uses non standard sector size");
+    None
+}
+
+/// Reads `name` out of the root directory of an El Torito no-emulation bootable CD-ROM: looks for
+/// the "CD001" signature of a Primary Volume Descriptor at LBA 16, then walks its root directory
+/// for `name` and reads the matching entry's extent. Returns `None` if `boot_disk_id` doesn't look
+/// like an ISO9660 disc, so the caller can fall back to the FAT32 hard-disk path; a missing
+/// signature is not logged, though a failed BIOS sector read still is.
+fn read_file_from_iso9660(boot_disk_id: u8, name: &[u8]) -> Option<Vec<u8>> {
+    let pvd = read_sectors(boot_disk_id, ISO9660_PVD_LBA, 1, ISO9660_SECTOR_SIZE)?;
+    if pvd.get(1..6)? != ISO9660_SIGNATURE {
         return None;
     }
-	
-	Some(drive_params.total_sector_count)
-} 
\ No newline at end of file
+
+    // The root directory record is embedded directly in the PVD, starting at offset 156
+    let root_entry = parse_iso_dir_record(pvd.get(156..)?)?;
+
+    let root_directory_sectors = root_entry.size.checked_add(ISO9660_SECTOR_SIZE - 1)?
+        / ISO9660_SECTOR_SIZE;
+    let root_directory = read_sectors(boot_disk_id, root_entry.extent_lba as u64,
+        root_directory_sectors, ISO9660_SECTOR_SIZE)?;
+    let entry = find_iso_directory_entry(&root_directory, name)?;
+
+    // Read the file's extent and trim it down to its exact size
+    let file_sectors = entry.size.checked_add(ISO9660_SECTOR_SIZE - 1)? / ISO9660_SECTOR_SIZE;
+    let mut file = read_sectors(boot_disk_id, entry.extent_lba as u64, file_sectors,
+        ISO9660_SECTOR_SIZE)?;
+    file.truncate(entry.size as usize);
+
+    Some(file)
+}
+
+/// Reads the kernel image off of an El Torito no-emulation bootable CD-ROM: `KERNEL.BIN` in its
+/// root directory
+fn read_kernel_from_iso9660(boot_disk_id: u8) -> Option<Vec<u8>> {
+    let kernel_image = read_file_from_iso9660(boot_disk_id, ISO9660_KERNEL_FILENAME)?;
+
+    println!("Read kernel image from ISO9660: {} bytes, at {:#x?}", kernel_image.len(),
+        kernel_image.as_ptr());
+    Some(kernel_image)
+}
+
+/// Reads the kernel image off of disk, trying an El Torito no-emulation CD-ROM boot path first and
+/// falling back to booting from a FAT32 partition on a hard disk
+pub fn read_kernel(boot_disk_id: u8) -> Option<Vec<u8>> {
+    if let Some(kernel_image) = read_kernel_from_iso9660(boot_disk_id) {
+        return Some(kernel_image);
+    }
+
+    read_kernel_from_fat32(boot_disk_id)
+}
+
+/// Reads the initramfs blob off of disk (`INITRD.BIN`), trying the same CD-ROM-then-hard-disk order
+/// as `read_kernel`. Returns `None` if neither boot source has such a file - having no initramfs is
+/// not an error.
+pub fn read_initramfs(boot_disk_id: u8) -> Option<Vec<u8>> {
+    read_file_from_iso9660(boot_disk_id, ISO9660_INITRAMFS_FILENAME)
+        .or_else(|| read_file_from_fat32(boot_disk_id, FAT32_INITRAMFS_FILENAME))
+}
+
+/// Reads the kernel command line off of disk (`CMDLINE.TXT`), trying the same CD-ROM-then-hard-disk
+/// order as `read_kernel`. Returns an empty command line if neither boot source has such a file.
+pub fn read_cmdline(boot_disk_id: u8) -> Vec<u8> {
+    read_file_from_iso9660(boot_disk_id, ISO9660_CMDLINE_FILENAME)
+        .or_else(|| read_file_from_fat32(boot_disk_id, FAT32_CMDLINE_FILENAME))
+        .unwrap_or_default()
+}
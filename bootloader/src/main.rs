@@ -11,14 +11,19 @@ mod panic;
 mod real_mode;
 mod memory_manager;
 mod screen;
+mod vbe;
+mod font;
+mod bmp;
 mod disk;
+mod pxe;
+mod gzip;
 
 use core::convert::TryInto;
 use serial::println;
 use elf_parser::ElfParser;
-use page_tables::{PageDirectory, VirtAddr, PhysAddr};
+use page_tables::{CacheMode, PageDirectory, VirtAddr, PhysAddr};
 use boot_args::{BootArgs, KERNEL_STACK_SIZE, KERNEL_STACK_BASE_VADDR, LAST_PAGE_TABLE_VADDR,
-    KERNEL_ALLOCATIONS_BASE_VADDR};
+    KERNEL_ALLOCATIONS_BASE_VADDR, CMDLINE_MAX_LEN};
 
 /// Rust bootloader entry point
 #[no_mangle]
@@ -31,6 +36,13 @@ pub extern fn entry(boot_disk_id: u8, bootloader_size: u32) -> ! {
     // Initialize the memory manager which handles physical allocations
     memory_manager::init(bootloader_size);
 
+    // Try to bring up a graphical linear frame buffer console, preferring higher resolutions; if
+    // no VESA mode matches any of the preferences we just keep using the VGA text buffer
+    let video_mode = vbe::setup_vesa(&[(1024, 768, 32), (800, 600, 32), (640, 480, 32)]);
+    if let Some(mode) = video_mode {
+        screen::init_framebuffer_console(mode);
+    }
+
     // Clear the screen and display a message, because if the kernel is big this might take a couple
     // seconds
     screen::reset();
@@ -38,7 +50,27 @@ pub extern fn entry(boot_disk_id: u8, bootloader_size: u32) -> ! {
 
     // Load and map the kernel
     let (kernel_entry, kernel_stack, new_cr3, last_page_table_paddr) =
-        setup_kernel(boot_disk_id, bootloader_size);
+        setup_kernel(boot_disk_id);
+
+    // Load the optional initramfs and kernel command line from the same boot source the kernel
+    // came from. Neither is fatal if missing.
+    let (initramfs_paddr, initramfs_size) = match pxe::load_initramfs_source(boot_disk_id) {
+        Some(initramfs) => {
+            screen::print(&alloc::format!("Read {} byte initramfs!", initramfs.len()));
+            let paddr = PhysAddr(initramfs.as_ptr() as u32);
+            let size = initramfs.len() as u32;
+            // Leak the backing allocation: it must stay resident (and excluded from
+            // `free_memory`) until the kernel is done with it
+            core::mem::forget(initramfs);
+            (Some(paddr), size)
+        },
+        None => (None, 0),
+    };
+
+    let cmdline_bytes = pxe::load_cmdline_source(boot_disk_id);
+    let cmdline_len = core::cmp::min(cmdline_bytes.len(), CMDLINE_MAX_LEN);
+    let mut cmdline = [0u8; CMDLINE_MAX_LEN];
+    cmdline[..cmdline_len].copy_from_slice(&cmdline_bytes[..cmdline_len]);
 
     // Grab the lock of physical memory and serial ports so we can transfer them to the kernel
     let mut pmem = memory_manager::PHYS_MEM.lock();
@@ -46,9 +78,16 @@ pub extern fn entry(boot_disk_id: u8, bootloader_size: u32) -> ! {
 
     // Construct the boot args for the kernel
     let boot_args = BootArgs {
-        free_memory: core::mem::replace(&mut *pmem, None).unwrap().0,
+        free_memory: core::mem::replace(&mut *pmem, None).unwrap().available,
         serial_port: core::mem::replace(&mut *serial, None).unwrap(),
-        last_page_table_paddr
+        last_page_table_paddr,
+        frame_buffer_paddr: video_mode.map(|mode| mode.framebuffer_paddr).unwrap_or(PhysAddr(0)),
+        frame_buffer_width: video_mode.map(|mode| mode.width).unwrap_or(0),
+        frame_buffer_height: video_mode.map(|mode| mode.height).unwrap_or(0),
+        initramfs_paddr,
+        initramfs_size,
+        cmdline,
+        cmdline_len: cmdline_len as u16,
     };
 
     // Release the locks because we will never return from the kernel so they would not be released
@@ -70,16 +109,20 @@ pub extern fn entry(boot_disk_id: u8, bootloader_size: u32) -> ! {
 
 /// Reads the kernel from disk and maps it into memory. Also maps kernel stack and 1MiB identity.
 /// Returns (kernel entry vaddr, kernel stack vaddr, new cr3, last page table vaddr)
-fn setup_kernel(boot_disk_id: u8, bootloader_size: u32) -> (u32, u32, u32, PhysAddr) {
-    // Read the kernel from disk
-    let kernel_image = disk::read_kernel(boot_disk_id, bootloader_size);
-    if kernel_image.is_none() { 
+fn setup_kernel(boot_disk_id: u8) -> (u32, u32, u32, PhysAddr) {
+    // Load the kernel, from the network if we were PXE-booted, or from disk otherwise
+    let kernel_image = pxe::load_kernel_source(boot_disk_id);
+    if kernel_image.is_none() {
         screen::print_with_attributes("Failed to read kernel from disk.", 0xf4);
         panic!("Failed to read kernel from disk.");
     }
     let kernel_image = kernel_image.unwrap();
     screen::print(&alloc::format!("Read {} bytes from disk!", kernel_image.len()));
 
+    // Transparently decompress a gzip-compressed kernel image; images that aren't gzipped are
+    // returned unchanged
+    let kernel_image = gzip::maybe_decompress(kernel_image);
+
     // Parse the ELF of the kernel
     let kernel_elf = ElfParser::parse(&kernel_image);
     if kernel_elf.is_none() {
@@ -96,10 +139,11 @@ fn setup_kernel(boot_disk_id: u8, bootloader_size: u32) -> (u32, u32, u32, PhysA
     let mut directory = PageDirectory::new(phys_mem).expect("Failed to create page directory");
 
     // Map the elf segments into pages
-    kernel_elf.for_segment(|vaddr, size, init_bytes, read, write, exec| {
-        let r = if read { 'R' } else { '_' };
-        let w = if write { 'W' } else { '_' };
-        let x = if exec { 'X' } else { '_' };
+    kernel_elf.for_segment(|vaddr, init_bytes, bss_len, flags| {
+        let size = init_bytes.len() + bss_len;
+        let r = if flags & elf_parser::SEGMENT_FLAGS_PF_R != 0 { 'R' } else { '_' };
+        let w = if flags & elf_parser::SEGMENT_FLAGS_PF_W != 0 { 'W' } else { '_' };
+        let x = if flags & elf_parser::SEGMENT_FLAGS_PF_X != 0 { 'X' } else { '_' };
         println!("Mapping kernel segment {:#09x} {:#09x} [{}{}{}]", vaddr, size, r, w, x);
 
         // The kernel cannot extend beyond 0xC4000000 because that is where we place our kernel
@@ -107,6 +151,7 @@ fn setup_kernel(boot_disk_id: u8, bootloader_size: u32) -> (u32, u32, u32, PhysA
         assert!(vaddr + (size - 1) < KERNEL_ALLOCATIONS_BASE_VADDR as usize);
 
         // Create a virtual mapping for the kernel segment
+        let write = flags & elf_parser::SEGMENT_FLAGS_PF_W != 0;
         directory.map_init(phys_mem, VirtAddr(vaddr.try_into().ok()?), size.try_into().ok()?,
             write, false, |offset| {
             if offset < init_bytes.len() {
@@ -129,7 +174,7 @@ fn setup_kernel(boot_disk_id: u8, bootloader_size: u32) -> (u32, u32, u32, PhysA
     // Temp identity map of the first 1MiB so we can continue executing after changing cr3
     for paddr in (0..(1024*1024)).step_by(4096) {
         directory.map_to_phys_page(phys_mem, VirtAddr(paddr), PhysAddr(paddr), true, false, false,
-            true).expect("Failed to map temp identity map");
+            CacheMode::WriteBack, false).expect("Failed to map temp identity map");
     }
 
     // The new CR3 is the physical address of the page directory
@@ -141,7 +186,7 @@ fn setup_kernel(boot_disk_id: u8, bootloader_size: u32) -> (u32, u32, u32, PhysA
     let table_paddr = directory.get_page_table(phys_mem, VirtAddr(0xFFFFF000))
         .expect("Failed to get the phys addr of the last page table");
     directory.map_to_phys_page(phys_mem, VirtAddr(LAST_PAGE_TABLE_VADDR), table_paddr, true, false,
-        false, true).expect("Failed to map page directory");
+        false, CacheMode::WriteBack, false).expect("Failed to map page directory");
     
     println!("Kernel entry at {:#x}, Page directory at {:#x}", kernel_entry, new_cr3);
 
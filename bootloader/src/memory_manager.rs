@@ -3,12 +3,21 @@
 use crate::real_mode::{invoke_realmode_interrupt, RegisterState};
 
 use core::convert::TryInto;
-use range_set::{RangeSet, InclusiveRange};
+use range_set::{RangeSet, InclusiveRange, RangeSet64, InclusiveRange64};
 use core::alloc::{GlobalAlloc, Layout};
 use lock_cell::LockCell;
-use page_tables::{PhysAddr, PhysMem};
+use page_tables::{Frame, PhysAddr, PhysMem};
 
-pub struct PhysicalMemory(pub RangeSet);
+pub struct PhysicalMemory {
+    /// The 32-bit set of available memory which drives `PhysMem`/`GlobalAlloc` allocations
+    pub available: RangeSet,
+
+    /// The full, untrimmed set of available memory reported by E820, including ranges which start
+    /// or extend above the 4 GiB line. This is not used for allocation (it's 64-bit and the
+    /// allocator is 32-bit only); it exists so the bootloader can hand the complete memory map to
+    /// the kernel once it enables paging/PAE and can make use of memory above 4 GiB.
+    pub high_mem: RangeSet64,
+}
 
 impl PhysMem for PhysicalMemory {
     unsafe fn translate_phys(&mut self, phys_addr: PhysAddr, size: usize) -> Option<*mut u8> {
@@ -27,10 +36,11 @@ impl PhysMem for PhysicalMemory {
         Some(phys_addr_start as *mut u8)
     }
 
-    fn allocate_phys_mem(&mut self, layout: Layout) -> Option<PhysAddr> {
-        let addr = self.0.allocate(layout.size().try_into().ok()?, layout.align().try_into().ok()?);
+    fn allocate_phys_mem(&mut self, layout: Layout) -> Option<Frame> {
+        let addr = self.available.allocate(layout.size().try_into().ok()?,
+            layout.align().try_into().ok()?)?;
 
-        addr.map(PhysAddr)
+        Some(Frame { addr: PhysAddr(addr), size: layout.size() })
     }
 
     fn release_phys_mem(&mut self, phys_addr: PhysAddr, size: usize) {
@@ -38,7 +48,7 @@ impl PhysMem for PhysicalMemory {
             return;
         }
 
-        self.0.insert(InclusiveRange {
+        self.available.insert(InclusiveRange {
             start: phys_addr.0,
             end: phys_addr.0.saturating_add((size - 1) as u32)
         });
@@ -73,7 +83,7 @@ unsafe impl GlobalAlloc for GlobalAllocator {
         }
         
 		// Allocate physical memory from the `RangeSet`
-    	if let Some(addr) = pmem.as_mut().unwrap().0.allocate(size, align) {
+    	if let Some(addr) = pmem.as_mut().unwrap().available.allocate(size, align) {
             addr as *mut u8
 		} else {
 			core::ptr::null_mut()
@@ -90,7 +100,7 @@ unsafe impl GlobalAlloc for GlobalAllocator {
         // Check the memory manager is initialized
         if let Some(free_mem) = pmem.as_mut() {
             // Insert the range back into the set as free memory
-            free_mem.0.insert(InclusiveRange {
+            free_mem.available.insert(InclusiveRange {
                 start: ptr as u32,
                 end: ptr as u32 + (layout.size() as u32 - 1)
             });
@@ -101,12 +111,17 @@ unsafe impl GlobalAlloc for GlobalAllocator {
 }
 
 /// A range descriptor which is returned from a BIOS E820 call
+///
+/// ACPI 3.0 BIOSes append a fourth `extended_attributes` field, making the descriptor 24 bytes
+/// instead of 20. We always request the 24 byte form and fall back to treating `extended_attributes`
+/// as its default value of `0x1` when the BIOS only fills in the first 20 bytes.
 #[derive(Default)]
 #[repr(C)]
 struct E820RangeDescriptor {
 	base_addr: u64,
 	length: u64,
-	mem_type: u32
+	mem_type: u32,
+	extended_attributes: u32
 }
 
 /// Initialize the physical memory manager. Builds a memory map of available and reserved memory.
@@ -124,27 +139,37 @@ pub fn init(bootloader_size: u32) {
     // overlaps a "free" range might appear before the free range in the E820 list.
     let mut reserved_ranges = RangeSet::new();
 
+    // The full, untrimmed 64-bit memory map, including ranges above the 4 GiB line that the 32-bit
+    // `available_memory`/`reserved_ranges` sets above can't represent
+    let mut high_mem = RangeSet64::new();
+
     // An opaque value used by the BIOS to report the next entry every time we call it. The initial
     // value is zero
     let mut continuation_value = 0;
     let mut result_descriptor = E820RangeDescriptor::default();
     let mut register_context = RegisterState::default();
     loop {
-        // Set the parameters for the E820 call
+        // Reset the extended attributes word before every call: a BIOS which only returns 20 bytes
+        // won't touch it, and we want the ACPI 3.0 default of "valid, not non-volatile" (bit 0 set,
+        // bit 1 clear) in that case
+        result_descriptor.extended_attributes = 0x1;
+
+        // Set the parameters for the E820 call. We ask for the 24-byte ACPI 3.0 extended descriptor,
+        // but some BIOSes will only ever fill in the original 20 bytes
         register_context.eax = 0xE820;
         register_context.ebx = continuation_value;
-        register_context.ecx = 20;
+        register_context.ecx = 24;
         register_context.edi = &mut result_descriptor as *mut E820RangeDescriptor as u32;
         register_context.edx = u32::from_be_bytes(*b"SMAP");
         unsafe { invoke_realmode_interrupt(0x15, &mut register_context); }
 
-        // Assert we recieved the correct signature and descriptor size
+        // Assert we recieved the correct signature and a descriptor size we know how to handle
         assert_eq!(register_context.eax, u32::from_be_bytes(*b"SMAP"));
-        assert_eq!(register_context.ecx, 20);
-        
+        assert!(register_context.ecx == 20 || register_context.ecx == 24);
+
         // Save the continuation value for the next E820 call
         continuation_value = register_context.ebx;
-        
+
         // We can only use ranges which start inside the 32-bit address limit
         if result_descriptor.base_addr <= core::u32::MAX as u64 {
             // If the range extends beyond the address limit, we trim it
@@ -155,14 +180,37 @@ pub fn init(bootloader_size: u32) {
                 start: result_descriptor.base_addr as u32,
                 end: range_end
             };
-            // A memory type of 1 is memory that we are free to use. Any other type is reserved
-            if result_descriptor.mem_type == 1 {
-                available_memory.insert(new_range);
-            } else {
-                reserved_ranges.insert(new_range);
+
+            // Bit 0 of the extended attributes word means the range should be considered at all -
+            // if it is clear, modern BIOSes want us to ignore this entry entirely
+            let range_is_valid = result_descriptor.extended_attributes & 0x1 != 0;
+            // Bit 1 means the range is non-volatile memory, which we should not hand out as regular
+            // available RAM even if `mem_type == 1`
+            let range_is_non_volatile = result_descriptor.extended_attributes & 0x2 != 0;
+
+            if range_is_valid {
+                // A memory type of 1 is memory that we are free to use. Any other type is reserved
+                if result_descriptor.mem_type == 1 && !range_is_non_volatile {
+                    available_memory.insert(new_range);
+                } else {
+                    reserved_ranges.insert(new_range);
+                }
             }
         }
 
+        // Record the full, untrimmed range in the 64-bit map regardless of where it starts, as long
+        // as it's valid and actually usable - this is the map we keep around for a future long-mode
+        // handoff where the kernel can make use of memory above 4 GiB
+        let range_is_valid = result_descriptor.extended_attributes & 0x1 != 0;
+        let range_is_non_volatile = result_descriptor.extended_attributes & 0x2 != 0;
+        if range_is_valid && result_descriptor.mem_type == 1 && !range_is_non_volatile
+                && result_descriptor.length > 0 {
+            high_mem.insert(InclusiveRange64 {
+                start: result_descriptor.base_addr,
+                end: result_descriptor.base_addr + (result_descriptor.length - 1)
+            });
+        }
+
         // If CF is set or the continuation is zero, this is the last range
         if register_context.eflags&0x1 == 1 || register_context.ebx == 0 {
             break;
@@ -194,5 +242,5 @@ pub fn init(bootloader_size: u32) {
     serial::println!("{:#x?}", available_memory.ranges());
 
     // Store the initialized physical memory RangeSet
-    *pmem = Some(PhysicalMemory(available_memory));
+    *pmem = Some(PhysicalMemory { available: available_memory, high_mem });
 }
\ No newline at end of file
@@ -0,0 +1,202 @@
+//! PXE/TFTP network boot: pulls the kernel image over the network when this machine was started
+//! via PXE, instead of reading it off of a local disk.
+//!
+//! The BIOS/UNDI stack that started us leaves a `PXENV+` structure in real-mode memory, found with
+//! int 1Ah/ax=5650h, describing a 16-bit real-mode entry point we far-call into for every other PXE
+//! API request (opening/reading/closing a TFTP connection). We stream the file's blocks into a
+//! growable `Vec<u8>`, exactly like the BIOS disk read path does.
+
+use alloc::vec::Vec;
+use serial::println;
+use crate::real_mode::{invoke_realmode_interrupt, RegisterState};
+
+/// A real-mode far pointer, encoded offset-then-segment the way the PXE spec lays it out
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct SegOff16 {
+    offset:  u16,
+    segment: u16,
+}
+
+/// The fields of the `PXENV+` structure we actually need: its signature (to confirm what int
+/// 1Ah/ax=5650h handed back really is one) and its 16-bit real-mode API entry point
+#[repr(C)]
+struct PxenvPlus {
+    signature: [u8; 6],
+    version:   u16,
+    length:    u8,
+    checksum:  u8,
+    rm_entry:  SegOff16,
+}
+
+/// TFTP filenames, relative to the TFTP server root
+const TFTP_KERNEL_FILENAME: &[u8] = b"KERNEL.BIN";
+const TFTP_INITRAMFS_FILENAME: &[u8] = b"INITRD.BIN";
+const TFTP_CMDLINE_FILENAME: &[u8] = b"CMDLINE.TXT";
+
+/// PXE API opcodes we use
+const PXENV_TFTP_OPEN: u16  = 0x0020;
+const PXENV_TFTP_CLOSE: u16 = 0x0021;
+const PXENV_TFTP_READ: u16  = 0x0022;
+
+/// Status code a successful PXE API call leaves in AX
+const PXENV_EXIT_SUCCESS: u16 = 0x0000;
+
+/// The largest packet `PXENV_TFTP_READ` can fill in one call
+const TFTP_PACKET_SIZE: u16 = 512;
+
+#[repr(C, packed)]
+struct PxenvTftpOpen {
+    status:      u16,
+    server_ip:   [u8; 4],
+    gateway_ip:  [u8; 4],
+    filename:    [u8; 128],
+    tftp_port:   u16,
+    packet_size: u16,
+}
+
+#[repr(C, packed)]
+struct PxenvTftpRead {
+    status:        u16,
+    packet_number: u16,
+    buffer:        SegOff16,
+    buffer_size:   u16,
+}
+
+#[repr(C, packed)]
+struct PxenvTftpClose {
+    status: u16,
+}
+
+extern {
+    /// Far-calls the PXE API entry point `entry_segment:entry_offset` with `opcode` and a far
+    /// pointer to the `param_segment:param_offset` parameter block, following the PXENV+ calling
+    /// convention (the parameter pointer and opcode are pushed, the entry point is far-called, and
+    /// the pushed words are popped again on return). Returns the status PXENV leaves in AX.
+    fn invoke_pxe_call(entry_segment: u16, entry_offset: u16, opcode: u16, param_segment: u16,
+        param_offset: u16) -> u16;
+}
+
+/// Locates the `PXENV+` structure left behind by the BIOS/UNDI stack, if this machine was actually
+/// started over PXE
+fn find_pxenv() -> Option<&'static PxenvPlus> {
+    let mut regs = RegisterState { eax: 0x5650, ..Default::default() };
+    unsafe { invoke_realmode_interrupt(0x1A, &mut regs); }
+
+    // Carry set, or AX != 0x564E ("NV", the back half of "PXENV!") means there is no PXE stack
+    if (regs.eflags & 1) != 0 || regs.eax as u16 != 0x564E {
+        return None;
+    }
+
+    let pxenv_addr = ((regs.es as u32) << 4) + (regs.ebx & 0xFFFF);
+    let pxenv = unsafe { &*(pxenv_addr as *const PxenvPlus) };
+
+    if &pxenv.signature != b"PXENV+" {
+        return None;
+    }
+
+    Some(pxenv)
+}
+
+/// Calls into the PXE API with `opcode`, passing `param` as the parameter block, and returns
+/// whether the call reported success
+fn pxe_call<T>(pxenv: &PxenvPlus, opcode: u16, param: &mut T) -> bool {
+    let status = unsafe {
+        invoke_pxe_call(pxenv.rm_entry.segment, pxenv.rm_entry.offset, opcode, 0,
+            param as *mut T as u16)
+    };
+
+    status == PXENV_EXIT_SUCCESS
+}
+
+/// Reads `filename` over TFTP from the server that handed us our PXE boot info
+fn read_file_over_tftp(pxenv: &PxenvPlus, filename: &[u8]) -> Option<Vec<u8>> {
+    let mut open_params = PxenvTftpOpen {
+        status:      0,
+        server_ip:   [0; 4],
+        gateway_ip:  [0; 4],
+        filename:    [0; 128],
+        tftp_port:   69u16.to_be(),
+        packet_size: TFTP_PACKET_SIZE,
+    };
+    open_params.filename[..filename.len()].copy_from_slice(filename);
+
+    if !pxe_call(pxenv, PXENV_TFTP_OPEN, &mut open_params) {
+        return None;
+    }
+    // The server may negotiate a smaller packet size than we asked for
+    let packet_size = open_params.packet_size;
+
+    let mut file = Vec::new();
+    let mut packet_buffer = [0u8; TFTP_PACKET_SIZE as usize];
+    let mut packet_number: u16 = 1;
+
+    loop {
+        let mut read_params = PxenvTftpRead {
+            status:        0,
+            packet_number,
+            buffer: SegOff16 { offset: &mut packet_buffer as *mut _ as u16, segment: 0 },
+            buffer_size:   0,
+        };
+
+        if !pxe_call(pxenv, PXENV_TFTP_READ, &mut read_params) {
+            pxe_call(pxenv, PXENV_TFTP_CLOSE, &mut PxenvTftpClose { status: 0 });
+            return None;
+        }
+
+        let bytes_read = read_params.buffer_size as usize;
+        file.extend(&packet_buffer[..bytes_read]);
+
+        // A short (or empty) packet marks the last one
+        if bytes_read < packet_size as usize {
+            break;
+        }
+
+        packet_number += 1;
+    }
+
+    pxe_call(pxenv, PXENV_TFTP_CLOSE, &mut PxenvTftpClose { status: 0 });
+
+    Some(file)
+}
+
+/// Loads the kernel image, preferring a PXE/TFTP network boot if this machine was started that
+/// way, and falling back to `disk::read_kernel` otherwise
+pub fn load_kernel_source(boot_disk_id: u8) -> Option<Vec<u8>> {
+    if let Some(pxenv) = find_pxenv() {
+        if let Some(kernel_image) = read_file_over_tftp(&pxenv, TFTP_KERNEL_FILENAME) {
+            println!("Read kernel image over TFTP: {} bytes", kernel_image.len());
+            return Some(kernel_image);
+        }
+        println!("PXE stack present but TFTP load failed, falling back to disk boot");
+    }
+
+    crate::disk::read_kernel(boot_disk_id)
+}
+
+/// Loads the initramfs blob, preferring a PXE/TFTP network boot if this machine was started that
+/// way, and falling back to `disk::read_initramfs` otherwise. Returns `None` if neither boot source
+/// has an initramfs to offer - that is not an error.
+pub fn load_initramfs_source(boot_disk_id: u8) -> Option<Vec<u8>> {
+    if let Some(pxenv) = find_pxenv() {
+        if let Some(initramfs) = read_file_over_tftp(&pxenv, TFTP_INITRAMFS_FILENAME) {
+            println!("Read initramfs over TFTP: {} bytes", initramfs.len());
+            return Some(initramfs);
+        }
+    }
+
+    crate::disk::read_initramfs(boot_disk_id)
+}
+
+/// Loads the kernel command line, preferring a PXE/TFTP network boot if this machine was started
+/// that way, and falling back to `disk::read_cmdline` otherwise. Returns an empty command line if
+/// neither boot source has one.
+pub fn load_cmdline_source(boot_disk_id: u8) -> Vec<u8> {
+    if let Some(pxenv) = find_pxenv() {
+        if let Some(cmdline) = read_file_over_tftp(&pxenv, TFTP_CMDLINE_FILENAME) {
+            return cmdline;
+        }
+    }
+
+    crate::disk::read_cmdline(boot_disk_id)
+}
@@ -1,8 +1,15 @@
-//! Basic VGA text-mode print functions
+//! Basic VGA text-mode print functions, plus a framebuffer-backed console used once a VESA mode
+//! has been set up
 
-use page_tables::PhysAddr;
+use alloc::vec::Vec;
 
+use lock_cell::LockCell;
+use page_tables::{CacheMode, PageDirectory, PhysAddr, PhysMem, VirtAddr};
+
+use crate::bmp;
+use crate::font;
 use crate::real_mode::{RegisterState, invoke_realmode_interrupt};
+use crate::vbe::{VideoMode, VBE_MEMORY_MODEL_PACKED_PIXEL};
 
 const SCREEN_BUFFER_ADDRESS: usize = 0xb8000;
 const SCREEN_HEIGHT: usize = 25;
@@ -14,6 +21,12 @@ const REG_SCREEN_DATA_PORT: u16 = 0x3D5;
 const CURSOR_HIGH_REG_INDEX: u8 = 14;
 const CURSOR_LOW_REG_INDEX: u8 = 15;
 
+/// VGA DAC port to write the palette index to set, before streaming R/G/B triplets to
+/// `VGA_DAC_DATA_PORT`
+const VGA_DAC_WRITE_INDEX_PORT: u16 = 0x3C8;
+/// VGA DAC port to stream palette R/G/B component values to, one byte at a time
+const VGA_DAC_DATA_PORT: u16 = 0x3C9;
+
 /// Prints `message` on screen at the cursor
 pub fn print(message: &str) {
     for &ch in message.as_bytes() {
@@ -29,8 +42,18 @@ pub fn print_with_attributes(message: &str, attributes: u8) {
 }
 
 /// Prints one `character` to the screen with the specified `attributes` at the cursor, and then
-/// advances the cursor. Also handles new lines.
+/// advances the cursor. Also handles new lines. If a VESA mode has been set up via
+/// `init_framebuffer_console`, this draws into the framebuffer console instead of the VGA text
+/// buffer; `attributes` is ignored in that case, since the framebuffer console only draws in a
+/// fixed foreground/background color.
 pub fn print_char(character: u8, attributes: u8) {
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.print_char(character);
+        return;
+    }
+    core::mem::drop(framebuffer_console);
+
     let screen_buffer = get_screen_buffer();
 
     let cursor_offset = get_cursor_offset();
@@ -68,6 +91,13 @@ pub fn print_char(character: u8, attributes: u8) {
 
 /// Clears the screen and resets the cursor offset
 pub fn reset() {
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.clear();
+        return;
+    }
+    core::mem::drop(framebuffer_console);
+
     clear_screen();
     set_cursor_offset(0);
 }
@@ -117,6 +147,20 @@ fn get_screen_buffer() -> &'static mut [u16] {
     }
 }
 
+/// Programs `entries.len()` consecutive VGA DAC palette entries, starting at index `start`. Each
+/// entry is an (R, G, B) triplet in the DAC's currently-configured component width (6 bits, 0-63,
+/// unless the DAC has been switched to 8-bit width - see `switch_dac_width`).
+pub fn set_palette(start: u8, entries: &[(u8, u8, u8)]) {
+    unsafe {
+        cpu::out8(VGA_DAC_WRITE_INDEX_PORT, start);
+        for &(r, g, b) in entries {
+            cpu::out8(VGA_DAC_DATA_PORT, r);
+            cpu::out8(VGA_DAC_DATA_PORT, g);
+            cpu::out8(VGA_DAC_DATA_PORT, b);
+        }
+    }
+}
+
 /// Retrieves the character cursor offset from the VGA device.
 fn get_cursor_offset() -> usize {
     unsafe {
@@ -133,236 +177,298 @@ fn get_cursor_offset() -> usize {
     }
 }
 
-#[repr(C, packed)]
-struct VBEInfoBlock {
-    signature: [u8; 4],
-    version: u16,
-    oem_string_ptr: u32,
-    capabilities: u32,
-    video_mode_ptr: u32,
-    total_memory: u16,
-    oem_software_revision: u16,
-    oem_vendor_name_ptr: u32,
-    oem_product_name_ptr: u32,
-    oem_product_rev_ptr: u32,
-    reserved: [u8; 222],
-    oem_data: [u8; 256],
+/// The active framebuffer console, if a VESA mode has been set up via
+/// `init_framebuffer_console`. `print`/`print_char`/`reset` transparently dispatch here instead of
+/// the VGA text buffer whenever this is populated.
+static FRAMEBUFFER_CONSOLE: LockCell<Option<FramebufferConsole>> = LockCell::new(None);
+
+/// Sets up the framebuffer console for `mode`, so subsequent `print`/`print_char`/`reset` calls
+/// draw into the linear frame buffer instead of the VGA text buffer. `mode` should come from a
+/// prior `setup_vesa` call.
+pub fn init_framebuffer_console(mode: VideoMode) {
+    *FRAMEBUFFER_CONSOLE.lock() = Some(FramebufferConsole::new(mode));
 }
 
-#[repr(C, packed)]
-struct ModeInfoBlock {
-    mode_attributes: u16,
-    window_a_attributes: u8,
-    window_b_attributes: u8,
-    window_granularity: u16,
-    window_size: u16,
-    window_a_start_segment: u16,
-    window_b_start_segment: u16,
-    window_function_ptr: u32,
-    bytes_per_scanline: u16,
-
-    x_resolution: u16,
-    y_resolution: u16,
-    x_char_size: u8,
-    y_char_size: u8,
-    number_of_planes: u8,
-    bits_per_pixel: u8,
-    number_of_banks: u8,
-    memory_model: u8,
-    bank_size: u8,
-    number_of_image_pages: u8,
-    reserved_1: u8, // Always 1
-
-    // Masks are specified by a (size, position) pair which specificy how many bits and the
-    // lsb of the mask
-    red_mask_size: u8,
-    red_field_position: u8,
-    green_mask_size: u8,
-    green_field_position: u8,
-    blue_mask_size: u8,
-    blue_field_position: u8,
-    reserved_mask_size: u8,
-    reserved_field_position: u8,
-    direct_color_mode_attributes: u8,
-
-    phys_frame_buffer_ptr: u32,
-    reserved_2: u32,
-    reserved_3: u16,
-
-    linear_bytes_per_scanline: u16,
-    banked_number_of_image_pages: u8,
-    linear_number_of_image_pages: u8,
-    linear_red_mask_size: u8,
-    linear_red_field_position: u8,
-    linear_green_mask_size: u8,
-    linear_green_field_position: u8,
-    linear_blue_mask_size: u8,
-    linear_blue_field_position: u8,
-    linear_reserved_mask_size: u8,
-    linear_reserved_field_position: u8,
-    max_pixel_clock: u32,
-    reserved_4: [u8; 189],
-    unknown: u8, // VBE3 Spec says the structure is 256 bytes long, but specifies only the previous
-                 // fields which add to 255 bytes...
+/// A simple character-cell text console drawn on top of a linear VESA frame buffer, using the
+/// embedded bitmap font in the `font` module. Mirrors the VGA text console's behavior (cursor
+/// advance, newline handling, scroll-on-last-row) but blits pixels instead of writing cells.
+struct FramebufferConsole {
+    mode: VideoMode,
+    bytes_per_pixel: usize,
+    foreground: u32,
+    background: u32,
+    columns: usize,
+    rows: usize,
+    cursor_col: usize,
+    cursor_row: usize,
 }
 
+impl FramebufferConsole {
+    fn new(mode: VideoMode) -> Self {
+        FramebufferConsole {
+            mode,
+            bytes_per_pixel: ((mode.bits_per_pixel as usize) + 7) / 8,
+            foreground: foreground_pixel(&mode),
+            background: background_pixel(&mode),
+            columns: mode.width as usize / font::GLYPH_WIDTH,
+            rows: mode.height as usize / font::GLYPH_HEIGHT,
+            cursor_col: 0,
+            cursor_row: 0,
+        }
+    }
 
-/// Sets up graphics mode using the BIOS VESA interface. Returns the physical address of the frame
-/// buffer, and its width and height
-pub fn setup_vesa() -> (PhysAddr, u16, u16) {
-    let mut info_block = VBEInfoBlock {
-        signature: [0x56, 0x42, 0x45, 0x32], // Pre-setting "VBE2" as the signature signifies we
-                                             // we want VESA3.0
-        version: 0,
-        oem_string_ptr: 0,
-        capabilities: 0,
-        video_mode_ptr: 0,
-        total_memory: 0,
-        oem_software_revision: 0,
-        oem_vendor_name_ptr: 0,
-        oem_product_name_ptr: 0,
-        oem_product_rev_ptr: 0,
-        reserved: [0; 222],
-        oem_data: [0; 256],
-    };
-    assert!(core::mem::size_of::<VBEInfoBlock>() == 512);
+    /// Returns a slice over the entire linear frame buffer
+    fn framebuffer(&self) -> &'static mut [u8] {
+        let len = self.mode.bytes_per_scanline as usize * self.mode.height as usize;
+        unsafe {
+            core::slice::from_raw_parts_mut(self.mode.framebuffer_paddr.0 as *mut u8, len)
+        }
+    }
 
-    let mut register_context = RegisterState {
-        eax: 0x4F00, // Return VBE Controller Information
-        edi: &mut info_block as *mut VBEInfoBlock as u32,
-        ..Default::default()
-    };
+    fn put_pixel(&self, x: usize, y: usize, value: u32) {
+        let offset = y * self.mode.bytes_per_scanline as usize + x * self.bytes_per_pixel;
+        let bytes = value.to_le_bytes();
+        self.framebuffer()[offset..offset + self.bytes_per_pixel]
+            .copy_from_slice(&bytes[..self.bytes_per_pixel]);
+    }
 
-    unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+    /// Blits the glyph for `character` at the current cursor cell, without advancing the cursor
+    fn draw_glyph(&self, character: u8) {
+        let glyph = &font::FONT[if character < 128 { character as usize } else { b'?' as usize }];
+        let base_x = self.cursor_col * font::GLYPH_WIDTH;
+        let base_y = self.cursor_row * font::GLYPH_HEIGHT;
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..font::GLYPH_WIDTH {
+                let set = (bits & (1 << (font::GLYPH_WIDTH - 1 - col))) != 0;
+                self.put_pixel(base_x + col, base_y + row, if set { self.foreground } else { self.background });
+            }
+        }
+    }
 
-    if register_context.eax != 0x4F {
-        panic!("Failed to get VBE controller info");
+    /// Clears the entire framebuffer and resets the cursor
+    fn clear(&mut self) {
+        self.framebuffer().fill(0);
+        self.cursor_col = 0;
+        self.cursor_row = 0;
     }
-    assert!(info_block.version == 0x0300);
-
-    // Convert from seg:off 16bit pointer to 32-bit pointer
-    let real_mode_ptr = |ptr: u32| ((ptr & 0xFFFF0000) >> 12) + (ptr & 0xFFFF);
-    let mode_list_ptr = real_mode_ptr(info_block.video_mode_ptr) as *const u16;
-
-    // We iterate over all available modes, searching for a 1440x900 32 bits/pixel graphics mode.
-    // This is obviously not a final solution, we should find the best mode available and inform
-    // the kernel about the result
-    let mut mode_to_set = None;
-    let mut i = 0;
-    loop {
-        let mode = unsafe { *mode_list_ptr.offset(i) };
-        i += 1;
-
-        if mode == 0xFFFF {
-            break;
-        }
 
-        let mut mode_info = ModeInfoBlock {
-            mode_attributes: 0,
-            window_a_attributes: 0,
-            window_b_attributes: 0,
-            window_granularity: 0,
-            window_size: 0,
-            window_a_start_segment: 0,
-            window_b_start_segment: 0,
-            window_function_ptr: 0,
-            bytes_per_scanline: 0,
-            x_resolution: 0,
-            y_resolution: 0,
-            x_char_size: 0,
-            y_char_size: 0,
-            number_of_planes: 0,
-            bits_per_pixel: 0,
-            number_of_banks: 0,
-            memory_model: 0,
-            bank_size: 0,
-            number_of_image_pages: 0,
-            reserved_1: 1,
-            red_mask_size: 0,
-            red_field_position: 0,
-            green_mask_size: 0,
-            green_field_position: 0,
-            blue_mask_size: 0,
-            blue_field_position: 0,
-            reserved_mask_size: 0,
-            reserved_field_position: 0,
-            direct_color_mode_attributes: 0,
-            phys_frame_buffer_ptr: 0,
-            reserved_2: 0,
-            reserved_3: 0,
-            linear_bytes_per_scanline: 0,
-            banked_number_of_image_pages: 0,
-            linear_number_of_image_pages: 0,
-            linear_red_mask_size: 0,
-            linear_red_field_position: 0,
-            linear_green_mask_size: 0,
-            linear_green_field_position: 0,
-            linear_blue_mask_size: 0,
-            linear_blue_field_position: 0,
-            linear_reserved_mask_size: 0,
-            linear_reserved_field_position: 0,
-            max_pixel_clock: 0,
-            reserved_4: [0; 189],
-            unknown: 0
-        };
-        assert!(core::mem::size_of::<ModeInfoBlock>() == 256);
+    /// Moves the cursor to the start of the next row, scrolling if it was on the last row
+    fn newline(&mut self) {
+        self.cursor_col = 0;
+        if self.cursor_row == self.rows - 1 {
+            self.scroll_one_line();
+        } else {
+            self.cursor_row += 1;
+        }
+    }
 
-        let mut register_context = RegisterState {
-            eax: 0x4F01, // Return VBE Mode Information
-            ecx: mode as u32,
-            edi: &mut mode_info as *mut ModeInfoBlock as u32,
-            ..Default::default()
-        };
-    
-        unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
-    
-        if register_context.eax != 0x4F {
-            panic!("Failed to get VBE mode info");
+    /// Scrolls the framebuffer up by one character row's worth of scanlines, by memmoving the
+    /// trailing scanlines up and clearing the last row - the pixel equivalent of the VGA text
+    /// console's `scroll_one_line`.
+    fn scroll_one_line(&mut self) {
+        let stride = self.mode.bytes_per_scanline as usize;
+        let row_bytes = stride * font::GLYPH_HEIGHT;
+        let scroll_bytes = row_bytes * (self.rows - 1);
+        let framebuffer = self.framebuffer();
+
+        unsafe {
+            core::ptr::copy(framebuffer.as_ptr().add(row_bytes), framebuffer.as_mut_ptr(),
+                scroll_bytes);
         }
 
-        let mode_supported = (mode_info.mode_attributes & 1) != 0;
-        let color_mode = (mode_info.mode_attributes & 8) != 0;
-        let graphics_mode = (mode_info.mode_attributes & 16) != 0;
-        let linear_frame_buffer = (mode_info.mode_attributes & 128) != 0;
+        framebuffer[scroll_bytes..].fill(0);
+    }
 
-        if !mode_supported || !linear_frame_buffer || !graphics_mode || !color_mode {
-            continue;
+    /// Draws one `character`, advancing the cursor and handling new lines/scrolling
+    fn print_char(&mut self, character: u8) {
+        if character == b'\n' {
+            self.newline();
+            return;
         }
 
-        if mode_info.memory_model != 6 {
-            continue;
-        }
+        self.draw_glyph(character);
 
-        if mode_info.linear_red_mask_size != 8 || mode_info.linear_blue_mask_size != 8
-            || mode_info.linear_green_mask_size != 8 {
-            continue;
+        self.cursor_col += 1;
+        if self.cursor_col == self.columns {
+            self.newline();
         }
+    }
+}
+
+/// Packs an (8-bit-per-channel) RGB color into a bit-packed direct color value for `mode`, using
+/// its RGB mask fields. Packed-pixel modes get no palette index from this; their mask fields are
+/// meaningless, so callers handle them separately.
+fn pack_direct_color(mode: &VideoMode, red: u8, green: u8, blue: u8) -> u32 {
+    let channel = |value: u8, mask_size: u8, field_position: u8| -> u32 {
+        let scaled = if mask_size >= 8 { value as u32 } else { (value >> (8 - mask_size)) as u32 };
+        scaled << field_position
+    };
+
+    channel(red, mode.red_mask_size, mode.red_field_position)
+        | channel(green, mode.green_mask_size, mode.green_field_position)
+        | channel(blue, mode.blue_mask_size, mode.blue_field_position)
+}
+
+/// The foreground (text) pixel value for `mode`
+fn foreground_pixel(mode: &VideoMode) -> u32 {
+    if mode.memory_model == VBE_MEMORY_MODEL_PACKED_PIXEL {
+        // Index 0xFF is the brightest entry (full red/green/blue) of the default 3:3:2 palette
+        // cube installed by `setup_vesa`
+        0xFF
+    } else {
+        pack_direct_color(mode, 0xFF, 0xFF, 0xFF)
+    }
+}
+
+/// The background pixel value for `mode`
+fn background_pixel(_mode: &VideoMode) -> u32 {
+    // Index 0 of the default palette, and an all-zero direct color value, are both black
+    0
+}
+
+/// The frame buffer's length in bytes: `bytes_per_scanline * height`, i.e. exactly the VESA
+/// aperture `mode` describes, regardless of any stride padding beyond `width`.
+pub fn framebuffer_len(mode: &VideoMode) -> u32 {
+    mode.bytes_per_scanline as u32 * mode.height as u32
+}
+
+/// Maps the linear frame buffer described by `mode` into `directory` at `vaddr_base` as
+/// write-combining, so writes to it (the console and splash blits above) don't pay the cost of
+/// strongly uncacheable accesses. The mapping covers exactly `framebuffer_len(mode)` bytes,
+/// rounded up to a whole number of pages.
+pub fn map_framebuffer_write_combining(mode: &VideoMode, vaddr_base: VirtAddr,
+    directory: &mut PageDirectory, phys_mem: &mut impl PhysMem) -> Option<()> {
+    unsafe { page_tables::init_pat(); }
+
+    let page_count = (framebuffer_len(mode) + 0xFFF) / 0x1000;
+    for page in 0..page_count {
+        let offset = page * 0x1000;
+        directory.map_to_phys_page(phys_mem, VirtAddr(vaddr_base.0.checked_add(offset)?),
+            PhysAddr(mode.framebuffer_paddr.0.checked_add(offset)?), true, false, false,
+            CacheMode::WriteCombining, false)?;
+    }
+
+    Some(())
+}
 
-        if mode_info.x_resolution != 1440 || mode_info.y_resolution != 900 || mode_info.bits_per_pixel != 32 {
+/// Draws `image` centered on the framebuffer described by `mode`, converting its BGR(A) pixels to
+/// `mode`'s RGB mask layout. Only meaningful for direct-color modes - `mode`'s mask fields are
+/// undefined for packed-pixel modes, so this isn't called for those. If `image` is larger than the
+/// screen in either dimension, it's clipped rather than scaled.
+pub fn draw_splash(mode: &VideoMode, image: &bmp::BmpImage) {
+    let bytes_per_pixel = ((mode.bits_per_pixel as usize) + 7) / 8;
+    let stride = mode.bytes_per_scanline as usize;
+    let framebuffer = unsafe {
+        core::slice::from_raw_parts_mut(mode.framebuffer_paddr.0 as *mut u8, stride * mode.height as usize)
+    };
+
+    let offset_x = (mode.width as i32 - image.width as i32) / 2;
+    let offset_y = (mode.height as i32 - image.height as i32) / 2;
+
+    for y in 0..image.height {
+        let screen_y = offset_y + y as i32;
+        if screen_y < 0 || screen_y >= mode.height as i32 {
             continue;
         }
 
-        assert!(mode_info.linear_blue_field_position == 0);
-        assert!(mode_info.linear_green_field_position == 8);
-        assert!(mode_info.linear_red_field_position == 16);
+        for x in 0..image.width {
+            let screen_x = offset_x + x as i32;
+            if screen_x < 0 || screen_x >= mode.width as i32 {
+                continue;
+            }
+
+            let (red, green, blue) = image.pixel_at(x, y);
+            let pixel = pack_direct_color(mode, red, green, blue);
 
-        mode_to_set = Some((mode, mode_info.phys_frame_buffer_ptr));
-        break;
+            let fb_offset = screen_y as usize * stride + screen_x as usize * bytes_per_pixel;
+            let bytes = pixel.to_le_bytes();
+            framebuffer[fb_offset..fb_offset + bytes_per_pixel].copy_from_slice(&bytes[..bytes_per_pixel]);
+        }
     }
+}
 
-    let (mode_to_set, framebuffer_addr) = mode_to_set.expect("No support for 1440x900 32 bits/pixel");
+/// Where `DoubleBuffer` actually renders frames before they're presented
+enum BackingStore {
+    /// A second page of VRAM, `page_bytes` past the visible page's framebuffer address, panned
+    /// onto the display via VBE function 0x4F07
+    Vram { page_bytes: usize },
+    /// The adapter doesn't have room for a second page - render into ordinary RAM instead, and
+    /// have `flip` blit it onto the single visible page
+    Ram(Vec<u8>),
+}
 
-    let mut register_context = RegisterState {
-        eax: 0x4F02, // Set VBE Mode
-        ebx: (mode_to_set as u32) | (1 << 14), // Bit 14 signifies we want a linear frame buffer
-        ..Default::default()
-    };
+/// Double-buffers a VESA linear frame buffer so the kernel can render a whole frame before it's
+/// shown, avoiding the tearing and flicker of drawing directly to the visible page. Adapters that
+/// advertise a second image page (`VideoMode::image_pages >= 2`) are panned between pages with
+/// the "Set Display Start" BIOS call; adapters without one fall back to a RAM back buffer that's
+/// blitted onto the (only) visible page on every `flip`.
+pub struct DoubleBuffer {
+    mode: VideoMode,
+    backing: BackingStore,
+    /// Index (0 or 1) of the VRAM page currently being displayed. Unused for the RAM fallback.
+    visible_page: usize,
+}
+
+impl DoubleBuffer {
+    /// Sets up double buffering for `mode`
+    pub fn new(mode: VideoMode) -> DoubleBuffer {
+        let page_bytes = mode.bytes_per_scanline as usize * mode.height as usize;
+        let backing = if mode.image_pages >= 2 {
+            BackingStore::Vram { page_bytes }
+        } else {
+            BackingStore::Ram(alloc::vec![0u8; page_bytes])
+        };
 
-    unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+        DoubleBuffer { mode, backing, visible_page: 0 }
+    }
 
-    if register_context.eax != 0x4F {
-        panic!("Failed to set VBE mode");
+    /// Returns the buffer the caller should render the next frame into. This is always the
+    /// buffer *not* currently being displayed, so drawing here can never cause tearing.
+    pub fn back_buffer(&mut self) -> &mut [u8] {
+        match &mut self.backing {
+            BackingStore::Vram { page_bytes } => {
+                let offscreen_page = 1 - self.visible_page;
+                let paddr = self.mode.framebuffer_paddr.0 as usize + offscreen_page * *page_bytes;
+                unsafe { core::slice::from_raw_parts_mut(paddr as *mut u8, *page_bytes) }
+            },
+            BackingStore::Ram(buffer) => buffer,
+        }
     }
 
-    (PhysAddr(framebuffer_addr), 1440, 900)
+    /// Presents the back buffer that was just drawn into: pans the CRTC display start to the
+    /// off-screen VRAM page during vertical retrace, or, for the RAM fallback, blits the back
+    /// buffer onto the single visible page.
+    pub fn flip(&mut self) {
+        match &self.backing {
+            BackingStore::Vram { page_bytes } => {
+                let offscreen_page = 1 - self.visible_page;
+                let first_scanline = (offscreen_page * *page_bytes)
+                    / self.mode.bytes_per_scanline as usize;
+
+                let mut register_context = RegisterState {
+                    eax: 0x4F07, // Set Display Start
+                    ebx: 0x80, // Set Display Start during Vertical Retrace
+                    ecx: 0, // First displayed pixel in scanline
+                    edx: first_scanline as u32, // First displayed scanline
+                    ..Default::default()
+                };
+
+                unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+                if register_context.eax != 0x4F {
+                    panic!("Failed to set VBE display start");
+                }
+
+                self.visible_page = offscreen_page;
+            },
+            BackingStore::Ram(buffer) => {
+                let page_bytes = self.mode.bytes_per_scanline as usize * self.mode.height as usize;
+                let visible_page = unsafe {
+                    core::slice::from_raw_parts_mut(self.mode.framebuffer_paddr.0 as *mut u8,
+                        page_bytes)
+                };
+                visible_page.copy_from_slice(buffer);
+            },
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,372 @@
+//! VBE (VESA BIOS Extensions) video mode enumeration and selection, via real-mode INT 0x10 calls
+
+use alloc::vec::Vec;
+
+use page_tables::PhysAddr;
+
+use crate::screen;
+use crate::real_mode::{RegisterState, invoke_realmode_interrupt};
+
+/// VBE memory model for 8bpp packed-pixel, palettized-color modes
+pub const VBE_MEMORY_MODEL_PACKED_PIXEL: u8 = 4;
+/// VBE memory model for direct-color modes (the RGB masks describe the pixel layout)
+pub const VBE_MEMORY_MODEL_DIRECT_COLOR: u8 = 6;
+
+#[repr(C, packed)]
+struct VBEInfoBlock {
+    signature: [u8; 4],
+    version: u16,
+    oem_string_ptr: u32,
+    capabilities: u32,
+    video_mode_ptr: u32,
+    total_memory: u16,
+    oem_software_revision: u16,
+    oem_vendor_name_ptr: u32,
+    oem_product_name_ptr: u32,
+    oem_product_rev_ptr: u32,
+    reserved: [u8; 222],
+    oem_data: [u8; 256],
+}
+
+#[repr(C, packed)]
+struct ModeInfoBlock {
+    mode_attributes: u16,
+    window_a_attributes: u8,
+    window_b_attributes: u8,
+    window_granularity: u16,
+    window_size: u16,
+    window_a_start_segment: u16,
+    window_b_start_segment: u16,
+    window_function_ptr: u32,
+    bytes_per_scanline: u16,
+
+    x_resolution: u16,
+    y_resolution: u16,
+    x_char_size: u8,
+    y_char_size: u8,
+    number_of_planes: u8,
+    bits_per_pixel: u8,
+    number_of_banks: u8,
+    memory_model: u8,
+    bank_size: u8,
+    number_of_image_pages: u8,
+    reserved_1: u8, // Always 1
+
+    // Masks are specified by a (size, position) pair which specificy how many bits and the
+    // lsb of the mask
+    red_mask_size: u8,
+    red_field_position: u8,
+    green_mask_size: u8,
+    green_field_position: u8,
+    blue_mask_size: u8,
+    blue_field_position: u8,
+    reserved_mask_size: u8,
+    reserved_field_position: u8,
+    direct_color_mode_attributes: u8,
+
+    phys_frame_buffer_ptr: u32,
+    reserved_2: u32,
+    reserved_3: u16,
+
+    linear_bytes_per_scanline: u16,
+    banked_number_of_image_pages: u8,
+    linear_number_of_image_pages: u8,
+    linear_red_mask_size: u8,
+    linear_red_field_position: u8,
+    linear_green_mask_size: u8,
+    linear_green_field_position: u8,
+    linear_blue_mask_size: u8,
+    linear_blue_field_position: u8,
+    linear_reserved_mask_size: u8,
+    linear_reserved_field_position: u8,
+    max_pixel_clock: u32,
+    reserved_4: [u8; 189],
+    unknown: u8, // VBE3 Spec says the structure is 256 bytes long, but specifies only the previous
+                 // fields which add to 255 bytes...
+}
+
+/// A usable linear-framebuffer VESA mode, as collected by `enumerate_video_modes`. Either a
+/// direct-color mode (`memory_model == VBE_MEMORY_MODEL_DIRECT_COLOR`), where the RGB mask
+/// fields describe the pixel layout, or an 8bpp packed-pixel palettized mode
+/// (`memory_model == VBE_MEMORY_MODEL_PACKED_PIXEL`), where the mask fields are meaningless and
+/// the palette must be programmed through `screen::set_palette`.
+#[derive(Clone, Copy, Debug)]
+pub struct VideoMode {
+    /// The VBE mode number, as passed to the "Set VBE Mode" BIOS call
+    pub mode: u16,
+    /// Width in pixels
+    pub width: u16,
+    /// Height in pixels
+    pub height: u16,
+    /// Bits per pixel
+    pub bits_per_pixel: u8,
+    /// Bytes per scanline of the linear frame buffer
+    pub bytes_per_scanline: u16,
+    /// Physical address of the linear frame buffer
+    pub framebuffer_paddr: PhysAddr,
+    /// `VBE_MEMORY_MODEL_DIRECT_COLOR` or `VBE_MEMORY_MODEL_PACKED_PIXEL`
+    pub memory_model: u8,
+    /// Size in bits, and lsb position, of the red/green/blue channels within a pixel. Meaningless
+    /// for `VBE_MEMORY_MODEL_PACKED_PIXEL` modes.
+    pub red_mask_size: u8,
+    pub red_field_position: u8,
+    pub green_mask_size: u8,
+    pub green_field_position: u8,
+    pub blue_mask_size: u8,
+    pub blue_field_position: u8,
+    /// Number of image pages of VRAM available in this mode with the linear frame buffer enabled.
+    /// `>= 2` means the adapter has enough VRAM for a second, off-screen page that `DoubleBuffer`
+    /// can pan to via `flip`; `1` means there's only room for the visible page.
+    pub image_pages: u8,
+}
+
+/// Issues the "Return VBE Controller Information" BIOS call and returns the filled-in info block
+fn fetch_controller_info() -> VBEInfoBlock {
+    let mut info_block = VBEInfoBlock {
+        signature: [0x56, 0x42, 0x45, 0x32], // Pre-setting "VBE2" as the signature signifies we
+                                             // we want VESA3.0
+        version: 0,
+        oem_string_ptr: 0,
+        capabilities: 0,
+        video_mode_ptr: 0,
+        total_memory: 0,
+        oem_software_revision: 0,
+        oem_vendor_name_ptr: 0,
+        oem_product_name_ptr: 0,
+        oem_product_rev_ptr: 0,
+        reserved: [0; 222],
+        oem_data: [0; 256],
+    };
+    assert!(core::mem::size_of::<VBEInfoBlock>() == 512);
+
+    let mut register_context = RegisterState {
+        eax: 0x4F00, // Return VBE Controller Information
+        edi: &mut info_block as *mut VBEInfoBlock as u32,
+        ..Default::default()
+    };
+
+    unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+
+    if register_context.eax != 0x4F {
+        panic!("Failed to get VBE controller info");
+    }
+    assert!(info_block.version == 0x0300);
+
+    info_block
+}
+
+/// Walks the BIOS VBE mode list and collects every mode usable as a linear frame buffer: mode
+/// attribute bit 0 (supported) and bit 4 (graphics) and bit 7 (linear frame buffer) set, and
+/// either a direct color or an 8bpp packed-pixel memory model
+fn enumerate_video_modes() -> Vec<VideoMode> {
+    let info_block = fetch_controller_info();
+
+    // Convert from seg:off 16bit pointer to 32-bit pointer
+    let real_mode_ptr = |ptr: u32| ((ptr & 0xFFFF0000) >> 12) + (ptr & 0xFFFF);
+    let mode_list_ptr = real_mode_ptr(info_block.video_mode_ptr) as *const u16;
+
+    let mut modes = Vec::new();
+    let mut i = 0;
+    loop {
+        let mode = unsafe { *mode_list_ptr.offset(i) };
+        i += 1;
+
+        if mode == 0xFFFF {
+            break;
+        }
+
+        let mut mode_info = ModeInfoBlock {
+            mode_attributes: 0,
+            window_a_attributes: 0,
+            window_b_attributes: 0,
+            window_granularity: 0,
+            window_size: 0,
+            window_a_start_segment: 0,
+            window_b_start_segment: 0,
+            window_function_ptr: 0,
+            bytes_per_scanline: 0,
+            x_resolution: 0,
+            y_resolution: 0,
+            x_char_size: 0,
+            y_char_size: 0,
+            number_of_planes: 0,
+            bits_per_pixel: 0,
+            number_of_banks: 0,
+            memory_model: 0,
+            bank_size: 0,
+            number_of_image_pages: 0,
+            reserved_1: 1,
+            red_mask_size: 0,
+            red_field_position: 0,
+            green_mask_size: 0,
+            green_field_position: 0,
+            blue_mask_size: 0,
+            blue_field_position: 0,
+            reserved_mask_size: 0,
+            reserved_field_position: 0,
+            direct_color_mode_attributes: 0,
+            phys_frame_buffer_ptr: 0,
+            reserved_2: 0,
+            reserved_3: 0,
+            linear_bytes_per_scanline: 0,
+            banked_number_of_image_pages: 0,
+            linear_number_of_image_pages: 0,
+            linear_red_mask_size: 0,
+            linear_red_field_position: 0,
+            linear_green_mask_size: 0,
+            linear_green_field_position: 0,
+            linear_blue_mask_size: 0,
+            linear_blue_field_position: 0,
+            linear_reserved_mask_size: 0,
+            linear_reserved_field_position: 0,
+            max_pixel_clock: 0,
+            reserved_4: [0; 189],
+            unknown: 0
+        };
+        assert!(core::mem::size_of::<ModeInfoBlock>() == 256);
+
+        let mut register_context = RegisterState {
+            eax: 0x4F01, // Return VBE Mode Information
+            ecx: mode as u32,
+            edi: &mut mode_info as *mut ModeInfoBlock as u32,
+            ..Default::default()
+        };
+
+        unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+
+        if register_context.eax != 0x4F {
+            panic!("Failed to get VBE mode info");
+        }
+
+        let mode_supported = (mode_info.mode_attributes & 1) != 0;
+        let graphics_mode = (mode_info.mode_attributes & 16) != 0;
+        let linear_frame_buffer = (mode_info.mode_attributes & 128) != 0;
+
+        if !mode_supported || !graphics_mode || !linear_frame_buffer {
+            continue;
+        }
+
+        if mode_info.memory_model != VBE_MEMORY_MODEL_DIRECT_COLOR
+            && mode_info.memory_model != VBE_MEMORY_MODEL_PACKED_PIXEL {
+            continue;
+        }
+        if mode_info.memory_model == VBE_MEMORY_MODEL_PACKED_PIXEL && mode_info.bits_per_pixel != 8 {
+            continue;
+        }
+
+        modes.push(VideoMode {
+            mode,
+            width: mode_info.x_resolution,
+            height: mode_info.y_resolution,
+            bits_per_pixel: mode_info.bits_per_pixel,
+            bytes_per_scanline: mode_info.linear_bytes_per_scanline,
+            framebuffer_paddr: PhysAddr(mode_info.phys_frame_buffer_ptr),
+            memory_model: mode_info.memory_model,
+            red_mask_size: mode_info.linear_red_mask_size,
+            red_field_position: mode_info.linear_red_field_position,
+            green_mask_size: mode_info.linear_green_mask_size,
+            green_field_position: mode_info.linear_green_field_position,
+            blue_mask_size: mode_info.linear_blue_mask_size,
+            blue_field_position: mode_info.linear_blue_field_position,
+            // This field holds the page count minus one
+            image_pages: mode_info.linear_number_of_image_pages + 1,
+        });
+    }
+
+    modes
+}
+
+/// Picks the best mode out of `modes` for the ordered list of `(width, height, bpp)` preferences.
+/// Preferences are tried in order, and the first one with any matching candidate wins - later,
+/// less-preferred tuples are never consulted once an earlier one has a candidate. A candidate
+/// matches a preference if its depth is at least the requested `bpp`; among matching candidates,
+/// the one whose resolution is closest to the requested `(width, height)` is chosen, with ties
+/// broken in favor of higher `bits_per_pixel`.
+fn select_video_mode<'a>(modes: &'a [VideoMode], preferences: &[(u16, u16, u8)])
+    -> Option<&'a VideoMode> {
+    for &(target_width, target_height, target_bpp) in preferences {
+        let best = modes.iter()
+            .filter(|mode| mode.bits_per_pixel >= target_bpp)
+            .min_by_key(|mode| {
+                let distance = (mode.width as i32 - target_width as i32).unsigned_abs()
+                    + (mode.height as i32 - target_height as i32).unsigned_abs();
+                (distance, u8::MAX - mode.bits_per_pixel)
+            });
+
+        if best.is_some() {
+            return best;
+        }
+    }
+
+    None
+}
+
+/// Sets up graphics mode using the BIOS VESA interface, choosing the best available mode for the
+/// ordered list of `(width, height, bpp)` `preferences`. Returns the chosen `VideoMode`, which
+/// describes the mode's actual pixel format, or `None` if no
+/// enumerated mode matched any of the preferences - callers should fall back to the VGA text
+/// buffer in that case.
+pub fn setup_vesa(preferences: &[(u16, u16, u8)]) -> Option<VideoMode> {
+    let modes = enumerate_video_modes();
+    let chosen_mode = *select_video_mode(&modes, preferences)?;
+
+    let mut register_context = RegisterState {
+        eax: 0x4F02, // Set VBE Mode
+        ebx: (chosen_mode.mode as u32) | (1 << 14), // Bit 14 signifies we want a linear frame buffer
+        ..Default::default()
+    };
+
+    unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+
+    if register_context.eax != 0x4F {
+        panic!("Failed to set VBE mode");
+    }
+
+    // Packed-pixel modes are meaningless without a palette - install a default one, widening the
+    // DAC to 8 bits per component first if the adapter supports it
+    if chosen_mode.memory_model == VBE_MEMORY_MODEL_PACKED_PIXEL {
+        let dac_switchable_to_8bit = fetch_controller_info().capabilities & 1 != 0;
+        let dac_width = if dac_switchable_to_8bit { switch_dac_width(8) } else { 6 };
+        install_default_palette(dac_width);
+    }
+
+    Some(chosen_mode)
+}
+
+/// Issues the "Set/Get DAC Palette Format" BIOS call to request `bits` (6 or 8) per DAC component,
+/// returning the width the adapter actually committed to
+fn switch_dac_width(bits: u8) -> u8 {
+    let mut register_context = RegisterState {
+        eax: 0x4F08,
+        ebx: (bits as u32) << 8, // bl=0 (set), bh=requested width
+        ..Default::default()
+    };
+
+    unsafe { invoke_realmode_interrupt(0x10, &mut register_context); }
+
+    if register_context.eax & 0xFFFF != 0x4F {
+        // Not actually supported despite the controller advertising it - stick to the legacy width
+        return 6;
+    }
+
+    ((register_context.ebx >> 8) & 0xFF) as u8
+}
+
+/// Installs a default 3:3:2 (R:G:B) color cube as the palette for all 256 indices, which gives an
+/// 8bpp packed-pixel mode a usable, deterministic set of colors out of the box. Component values
+/// are scaled down from 8 bits to `dac_width` bits (6 or 8) before being programmed.
+fn install_default_palette(dac_width: u8) {
+    let scale = |component: u8| -> u8 {
+        if dac_width == 8 { component } else { component >> (8 - dac_width) }
+    };
+
+    let mut entries = [(0u8, 0u8, 0u8); 256];
+    for (index, entry) in entries.iter_mut().enumerate() {
+        let red = (((index >> 5) & 0x7) * 255 / 7) as u8;
+        let green = (((index >> 2) & 0x7) * 255 / 7) as u8;
+        let blue = ((index & 0x3) * 255 / 3) as u8;
+        *entry = (scale(red), scale(green), scale(blue));
+    }
+
+    screen::set_palette(0, &entries);
+}
@@ -1,31 +1,193 @@
+//! A parser for read-only access to Ext2/Ext3/Ext4-compatible file systems.
+//!
+//! [`Ext2Parser`] is generic over a [`Volume`], an abstraction over where the filesystem's bytes
+//! actually live, rather than over a single in-memory buffer. Every access - the super block, the
+//! block group descriptor table, the inode table, indirect/extent block pointers, and directory and
+//! file data blocks - is fetched on demand through [`Volume::read_at`], so a [`Volume`] backed by a
+//! real disk or a lazily-mapped region never needs the whole filesystem image resident at once.
+//! [`RamVolume`] is the zero-copy implementation used when the image already is fully resident.
+
 #![no_std]
 #![feature(const_trait_impl)]
 
+use core::convert::TryFrom;
+
 use enum_bitflags::bitor_flags;
 
-/// The offset from the start of the disk where the super block is located
-const SUPER_BLOCK_OFFSET: usize = 1024;
-/// The size in bytes of the super block
-const SUPER_BLOCK_SIZE: usize = 1024;
+/// The offset from the start of the volume where the super block is located
+const SUPER_BLOCK_OFFSET: u64 = 1024;
 /// The value of a the signature field of a valid super block
 const SUPER_BLOCK_MAGIC_SIGNATURE: u16 = 0xEF53;
 /// The number of direct pointers in an inode
 const INODE_DIRECT_PTR_COUNT: usize = 12;
 /// The inode number of the root directory
 pub const ROOT_INODE: u32 = 2;
+/// The maximum number of bytes in a directory entry's name
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// The size, in bytes, of the inode's block-pointer area, in which ext2 stores a "fast" symlink
+/// target instead of allocating data blocks for it; only targets strictly shorter than this are
+/// read inline
+const INLINE_SYMLINK_MAX_LEN: usize = 60;
+/// The maximum number of symlinks [`Ext2Parser::resolve_path_to_inode`] will follow while
+/// resolving a single path, to guard against symlink loops; matches Linux's `MAXSYMLINKS`
+const MAX_SYMLINK_HOPS: u32 = 40;
+/// The maximum combined length of a symlink target and the remaining unresolved path components,
+/// used to size the on-stack buffer path resolution expands symlinks into
+const MAX_EXPANDED_SYMLINK_PATH_LEN: usize = 1024;
 
 /// Bitmask of required features the implementation supports
-const SUPPORTED_REQUIRED_FEATURES_MASK: u32 = 
+const SUPPORTED_REQUIRED_FEATURES_MASK: u32 =
     RequiredFeatureFlags::DirectoryEntriesContainTypeField as u32;
 /// Bitmask of features required for writing the implemention supports
-const SUPPORTED_WRITING_FEATURES_MASK: u32 = 
+const SUPPORTED_WRITING_FEATURES_MASK: u32 =
     WritingFeatureFlags::SparseSuperblocksAndGroupDescriptorTables | WritingFeatureFlags::FileSize64Bit;
 
+/// The magic value at the start of an extended attribute block, see [`XattrHeader`]
+const XATTR_BLOCK_MAGIC: u32 = 0xEA020000;
+/// The maximum length of a reconstructed extended attribute name (prefix, see
+/// [`xattr_name_prefix`], plus the stored name suffix), used to size an on-stack buffer in
+/// [`Ext2Parser::for_each_xattr`]
+const MAX_XATTR_NAME_LEN: usize = 280;
+
+/// The largest block size Ext2 allows (the block size exponent is bounded so that `1024 <<
+/// exponent` cannot exceed this), used to size on-stack scratch buffers for block reads
+const MAX_BLOCK_SIZE: usize = 4096;
+/// The largest number of block pointers that can fit inside a single pointer block
+const MAX_PTRS_PER_BLOCK: usize = MAX_BLOCK_SIZE / core::mem::size_of::<BlockAddr>();
+
 /// An address in disk, as a multiple of the block size
 #[derive(Clone, Copy, Debug)]
 #[repr(transparent)]
 pub struct BlockAddr(u32);
 
+/// An error encountered while reading through a [`Volume`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The requested read extends past the end of the volume
+    OutOfBounds,
+    /// The underlying storage failed to satisfy the read
+    ReadFailed,
+}
+
+/// A source of the bytes that make up a filesystem. This abstracts over how those bytes are
+/// actually stored, so the parser can be driven by a real block device or lazily-mapped storage
+/// instead of requiring the whole filesystem image to be resident in memory up front
+pub trait Volume {
+    /// The size, in bytes, of a single physical sector of this volume
+    fn sector_size(&self) -> usize;
+
+    /// Reads `buf.len()` bytes starting at byte offset `addr` into `buf`
+    fn read_at(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error>;
+}
+
+/// A [`Volume`] backed by a byte slice that is fully resident in memory
+#[derive(Clone, Copy, Debug)]
+pub struct RamVolume<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RamVolume<'a> {
+    /// Wraps an in-memory byte slice as a [`Volume`]
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl<'a> Volume for RamVolume<'a> {
+    fn sector_size(&self) -> usize {
+        512
+    }
+
+    fn read_at(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let start = usize::try_from(addr).map_err(|_| Error::OutOfBounds)?;
+        let end = start.checked_add(buf.len()).ok_or(Error::OutOfBounds)?;
+        if end > self.bytes.len() {
+            return Err(Error::OutOfBounds);
+        }
+
+        buf.copy_from_slice(&self.bytes[start..end]);
+        Ok(())
+    }
+}
+
+/// The largest sector size a [`BufferedVolume`] can cache a single sector of
+const MAX_SECTOR_SIZE: usize = 4096;
+
+/// A provider of fixed-size sectors, the granularity at which a real block device (disk,
+/// removable media, etc.) transfers data. Implementors back a [`BufferedVolume`]
+pub trait SectorDevice {
+    /// The size, in bytes, of a single sector of this device
+    fn sector_size(&self) -> usize;
+
+    /// Reads the sector numbered `sector_index` into `buf`, which is exactly
+    /// [`sector_size`](Self::sector_size) bytes long
+    fn read_sector(&self, sector_index: u64, buf: &mut [u8]) -> Result<(), Error>;
+}
+
+/// A [`Volume`] that pulls data on demand, sector by sector, through a [`SectorDevice`], instead of
+/// requiring the whole filesystem image to be resident like [`RamVolume`] does. Only the single
+/// most recently read sector is cached, which is enough to avoid re-reading a sector for every byte
+/// of it this crate's own (sub-sector-sized, largely sequential) accesses touch
+pub struct BufferedVolume<D: SectorDevice> {
+    device: D,
+    cache: core::cell::RefCell<Option<(u64, [u8; MAX_SECTOR_SIZE])>>,
+}
+
+impl<D: SectorDevice> BufferedVolume<D> {
+    /// Wraps `device` as a [`Volume`]. Panics if its sector size is larger than
+    /// [`MAX_SECTOR_SIZE`]
+    pub fn new(device: D) -> Self {
+        assert!(device.sector_size() <= MAX_SECTOR_SIZE);
+        Self { device, cache: core::cell::RefCell::new(None) }
+    }
+
+    /// Makes sure the sector numbered `sector_index` is the one currently cached, reading it
+    /// through the device if it isn't
+    fn ensure_sector_cached(&self, sector_index: u64) -> Result<(), Error> {
+        let needs_read = match &*self.cache.borrow() {
+            Some((cached_index, _)) => *cached_index != sector_index,
+            None => true,
+        };
+        if needs_read {
+            let sector_size = self.device.sector_size();
+            let mut bytes = [0u8; MAX_SECTOR_SIZE];
+            self.device.read_sector(sector_index, &mut bytes[..sector_size])?;
+            *self.cache.borrow_mut() = Some((sector_index, bytes));
+        }
+        Ok(())
+    }
+}
+
+impl<D: SectorDevice> Volume for BufferedVolume<D> {
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn read_at(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let sector_size = self.device.sector_size() as u64;
+
+        let mut written = 0usize;
+        let mut current = addr;
+        while written < buf.len() {
+            let sector_index = current / sector_size;
+            let sector_offset = (current % sector_size) as usize;
+
+            self.ensure_sector_cached(sector_index)?;
+            let cache = self.cache.borrow();
+            let (_, sector_bytes) = cache.as_ref().unwrap();
+
+            let take = (sector_size as usize - sector_offset).min(buf.len() - written);
+            buf[written..written+take].copy_from_slice(&sector_bytes[sector_offset..sector_offset+take]);
+
+            written += take;
+            current += take as u64;
+        }
+
+        Ok(())
+    }
+}
+
 /// The structure of the super-block, containing all the metadata about the filesystem
 #[derive(Clone, Copy, Debug)]
 #[repr(C, packed)]
@@ -102,14 +264,14 @@ struct SuperBlockExtension {
     /// Bitmask of features that the driver is required to support in order to write, see
     /// [`WritingFeatureFlags`]
     writing_feature_flags: u32,
-    /// 128bit value uniquely identifying the filesystem 
+    /// 128bit value uniquely identifying the filesystem
     filesystem_id: [u8; 16],
     /// Name of the volume, usually unused
     volume_name_cstr: [u8; 16],
     /// The last mount-point path, usually unused
     last_mount_path_cstr: [u8; 64],
     /// The type of compression algorithm used, if compression is used
-    compression_algorithm: u32, 
+    compression_algorithm: u32,
     /// Number of blocks the driver should attempt to pre-allocate for new files
     file_block_preallocation_count: u8,
     /// Number of blocks the driver should attempt to pre-allcoate for new directories
@@ -119,6 +281,14 @@ struct SuperBlockExtension {
     journal_inode: u32,
     journal_device: u32,
     orphan_inode_list_head: u32,
+    /// Seed used by the HTree directory-hash algorithms, see [`compute_dir_hash`]
+    hash_seed: [u32; 4],
+    /// The hash algorithm (see [`compute_dir_hash`]) used by default for new hash-indexed
+    /// directories
+    default_hash_version: u8,
+    jnl_backup_type: u8,
+    /// Size of each block group descriptor, when the 64bit feature is enabled
+    descriptor_size: u16,
 }
 
 /// Feature flags that are not required for reading or writing from a filesystem
@@ -142,7 +312,7 @@ enum RequiredFeatureFlags {
 bitor_flags!(RequiredFeatureFlags, u32);
 
 /// Feature flags that are required to support writing to a filesystem
-enum WritingFeatureFlags { 
+enum WritingFeatureFlags {
     SparseSuperblocksAndGroupDescriptorTables = 0x1,
     FileSize64Bit = 0x2,
     DirectoryContentsBinarySearchTree = 0x4,
@@ -296,6 +466,9 @@ pub enum InodeFlags {
     BTreeOrHashIndexedDirectory = 0x1000,
     AFSDirectory = 0x2000,
     Ext3JournalData = 0x4000,
+    /// The inode's direct/indirect pointer area is instead an ext4-style extent tree, see
+    /// [`ExtentHeader`]
+    ExtentsUsed = 0x80000,
 }
 bitor_flags!(InodeFlags, u32);
 
@@ -329,17 +502,38 @@ pub enum DirEntryType {
     SymbolicLink = 7,
 }
 
+/// A directory entry's name, copied out of its data block since the backing [`Volume`] no longer
+/// guarantees the bytes it was read from stay resident
+#[derive(Clone, Copy)]
+pub struct FileName {
+    bytes: [u8; MAX_FILE_NAME_LEN],
+    len: u8,
+}
+
+impl FileName {
+    /// Views the name as a string slice
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+}
+
+impl core::fmt::Debug for FileName {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
 /// A parsed Ext2 file system
 #[derive(Debug)]
-pub struct Ext2Parser<'a> {
-    /// The raw file system bytes
-    raw_bytes: &'a [u8],
-    /// Refernce to the main super block
-    super_block: &'a SuperBlock,
-    /// Reference to the main super block extended fields
-    super_block_extension: &'a SuperBlockExtension,
-    /// Reference to the main block group descriptor table
-    block_group_descriptor_table: &'a [BlockGroupDescriptor],
+pub struct Ext2Parser<V: Volume> {
+    /// The volume this filesystem's blocks are read from
+    volume: V,
+    /// A copy of the main super block
+    super_block: SuperBlock,
+    /// A copy of the main super block extended fields
+    super_block_extension: SuperBlockExtension,
+    /// Byte offset of the main block group descriptor table's first entry
+    block_group_descriptor_table_offset: u64,
 
     /// Size of a block in bytes
     block_size: usize,
@@ -355,6 +549,238 @@ pub struct Ext2Parser<'a> {
     block_group_count: u32,
     /// The number of pointers that fit in a pointer block
     num_ptrs_per_block: usize,
+    /// Whether the volume was opened via [`parse_read_only`](Self::parse_read_only), ignoring
+    /// unsupported "writing" feature flags that only restrict writers, not readers
+    read_only: bool,
+    /// Which superblock copy this parser was actually opened from, see
+    /// [`parse_with_recovery`](Self::parse_with_recovery)
+    superblock_source: SuperBlockSource,
+
+    /// LRU cache of recently decoded [`Inode`]s keyed by inode number, see [`InodeCache`]
+    inode_cache: core::cell::RefCell<InodeCache>,
+    /// LRU cache of recently read pointer blocks keyed by [`BlockAddr`], see [`PtrBlockCache`]
+    ptr_block_cache: core::cell::RefCell<PtrBlockCache>,
+}
+
+/// Default capacity of the inode cache for parsers constructed via [`Ext2Parser::parse`],
+/// [`Ext2Parser::parse_read_only`] and [`Ext2Parser::parse_with_recovery`]; use
+/// [`Ext2Parser::parse_with_cache_capacity`] to tune this
+const DEFAULT_INODE_CACHE_CAPACITY: usize = 8;
+/// Default capacity of the pointer-block cache, see [`DEFAULT_INODE_CACHE_CAPACITY`]
+const DEFAULT_PTR_BLOCK_CACHE_CAPACITY: usize = 4;
+
+/// The largest inode cache capacity [`Ext2Parser::parse_with_cache_capacity`] will honor; capacities
+/// above this are silently clamped down to it
+const MAX_INODE_CACHE_ENTRIES: usize = 16;
+/// The largest pointer-block cache capacity [`Ext2Parser::parse_with_cache_capacity`] will honor,
+/// see [`MAX_INODE_CACHE_ENTRIES`]
+const MAX_PTR_BLOCK_CACHE_ENTRIES: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+struct InodeCacheEntry {
+    inode: u32,
+    metadata: Inode,
+}
+
+/// A tiny fixed-capacity LRU cache of decoded [`Inode`]s, avoiding the block-group/offset
+/// recomputation and volume read [`Ext2Parser::get_inode`] would otherwise repeat every time the
+/// same inode is looked up again, e.g. while resolving a path that re-touches the same directory
+/// inodes, or walking many files that share a parent. Entries are kept most-recently-used first and
+/// evicted from the back; `capacity` (always `<= MAX_INODE_CACHE_ENTRIES`) is fixed at construction
+#[derive(Debug)]
+struct InodeCache {
+    entries: [Option<InodeCacheEntry>; MAX_INODE_CACHE_ENTRIES],
+    capacity: usize,
+}
+
+impl InodeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: [None; MAX_INODE_CACHE_ENTRIES],
+            capacity: capacity.min(MAX_INODE_CACHE_ENTRIES),
+        }
+    }
+
+    fn get(&mut self, inode: u32) -> Option<Inode> {
+        let pos = self.entries[..self.capacity].iter()
+            .position(|entry| matches!(entry, Some(entry) if entry.inode == inode))?;
+        let entry = self.entries[pos].unwrap();
+        self.move_to_front(pos);
+        Some(entry.metadata)
+    }
+
+    fn insert(&mut self, inode: u32, metadata: Inode) {
+        if self.capacity == 0 {
+            return;
+        }
+        // Make room at the front by shifting everything else back one slot, dropping whatever was
+        // in the last (least-recently-used) slot
+        for i in (1..self.capacity).rev() {
+            self.entries[i] = self.entries[i - 1];
+        }
+        self.entries[0] = Some(InodeCacheEntry { inode, metadata });
+    }
+
+    /// Moves the entry at `pos` to the front of the cache, shifting everything before it back one
+    /// slot, since it was just used and is now the most-recently-used entry
+    fn move_to_front(&mut self, pos: usize) {
+        let entry = self.entries[pos];
+        for i in (1..=pos).rev() {
+            self.entries[i] = self.entries[i - 1];
+        }
+        self.entries[0] = entry;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PtrBlockCacheEntry {
+    addr: BlockAddr,
+    ptrs: [BlockAddr; MAX_PTRS_PER_BLOCK],
+}
+
+/// A tiny fixed-capacity LRU cache of recently read pointer blocks, keyed by their [`BlockAddr`].
+/// Large files and deep directories dereference the same indirect/doubly-indirect pointer blocks
+/// over and over while their sibling direct blocks are walked, so caching them turns repeated reads
+/// into O(1) hits instead of re-reading through the [`Volume`] every time. Same shape as
+/// [`InodeCache`]; see its documentation for the eviction policy
+#[derive(Debug)]
+struct PtrBlockCache {
+    entries: [Option<PtrBlockCacheEntry>; MAX_PTR_BLOCK_CACHE_ENTRIES],
+    capacity: usize,
+}
+
+impl PtrBlockCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: [None; MAX_PTR_BLOCK_CACHE_ENTRIES],
+            capacity: capacity.min(MAX_PTR_BLOCK_CACHE_ENTRIES),
+        }
+    }
+
+    fn get(&mut self, addr: BlockAddr) -> Option<[BlockAddr; MAX_PTRS_PER_BLOCK]> {
+        let pos = self.entries[..self.capacity].iter()
+            .position(|entry| matches!(entry, Some(entry) if entry.addr.0 == addr.0))?;
+        let entry = self.entries[pos].unwrap();
+        self.move_to_front(pos);
+        Some(entry.ptrs)
+    }
+
+    fn insert(&mut self, addr: BlockAddr, ptrs: [BlockAddr; MAX_PTRS_PER_BLOCK]) {
+        if self.capacity == 0 {
+            return;
+        }
+        for i in (1..self.capacity).rev() {
+            self.entries[i] = self.entries[i - 1];
+        }
+        self.entries[0] = Some(PtrBlockCacheEntry { addr, ptrs });
+    }
+
+    fn move_to_front(&mut self, pos: usize) {
+        let entry = self.entries[pos];
+        for i in (1..=pos).rev() {
+            self.entries[i] = self.entries[i - 1];
+        }
+        self.entries[0] = entry;
+    }
+}
+
+/// Records which copy of the superblock a successfully-parsed [`Ext2Parser`] was built from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuperBlockSource {
+    /// The primary superblock, at the fixed offset near the start of the volume
+    Primary,
+    /// The primary superblock was unusable; this is a backup copy found in the given block group
+    Backup { group: u32 },
+}
+
+/// The maximum number of mismatches [`Ext2Parser::verify`] records before giving up; on a
+/// seriously corrupt image there could otherwise be an unbounded number of them
+const MAX_CONSISTENCY_MISMATCHES: usize = 8;
+
+/// A single problem found by [`Ext2Parser::verify`]
+#[derive(Clone, Copy, Debug)]
+pub enum ConsistencyMismatch {
+    /// Block group `group`'s block-usage bitmap reports `bitmap_free` unallocated blocks, but its
+    /// descriptor's `unallocated_blocks_count` claims `descriptor_free`
+    GroupFreeBlocksMismatch { group: u32, bitmap_free: u32, descriptor_free: u32 },
+    /// Block group `group`'s inode-usage bitmap reports `bitmap_free` unallocated inodes, but its
+    /// descriptor's `unallocated_inodes_count` claims `descriptor_free`
+    GroupFreeInodesMismatch { group: u32, bitmap_free: u32, descriptor_free: u32 },
+    /// Block group `group` has `counted` inodes whose type is [`InodeType::Directory`], but its
+    /// descriptor's `directories_count` claims `descriptor`
+    GroupDirectoriesCountMismatch { group: u32, counted: u32, descriptor: u32 },
+    /// The sum of every group's free block count is `summed`, but the super block's
+    /// `unallocated_blocks_count` claims `super_block`
+    TotalFreeBlocksMismatch { summed: u32, super_block: u32 },
+    /// The sum of every group's free inode count is `summed`, but the super block's
+    /// `unallocated_inodes_count` claims `super_block`
+    TotalFreeInodesMismatch { summed: u32, super_block: u32 },
+    /// The directory with inode number `dir_inode` has an entry pointing at `target_inode`, which
+    /// is outside of `1..=inode_count`
+    DirectoryEntryInodeOutOfRange { dir_inode: u32, target_inode: u32 },
+}
+
+/// Returned by [`Ext2Parser::verify`] when it either could not finish the check or finished it and
+/// found the filesystem inconsistent with itself
+#[derive(Clone, Debug)]
+pub enum ConsistencyError {
+    /// A read needed to perform the check itself failed, so the check could not be completed
+    ReadFailed(Error),
+    /// The check completed, and found the mismatches enumerated here; capped at
+    /// [`MAX_CONSISTENCY_MISMATCHES`] even if more exist
+    Mismatches([Option<ConsistencyMismatch>; MAX_CONSISTENCY_MISMATCHES]),
+}
+
+impl ConsistencyError {
+    /// The mismatches found, in the order they were discovered, or an empty iterator if the check
+    /// didn't complete (see [`ReadFailed`](Self::ReadFailed))
+    pub fn mismatches(&self) -> impl Iterator<Item = &ConsistencyMismatch> {
+        static EMPTY: [Option<ConsistencyMismatch>; MAX_CONSISTENCY_MISMATCHES] =
+            [None; MAX_CONSISTENCY_MISMATCHES];
+        let mismatches = match self {
+            ConsistencyError::Mismatches(mismatches) => mismatches,
+            ConsistencyError::ReadFailed(_) => &EMPTY,
+        };
+        mismatches.iter().filter_map(Option::as_ref)
+    }
+}
+
+impl From<Error> for ConsistencyError {
+    fn from(err: Error) -> Self {
+        ConsistencyError::ReadFailed(err)
+    }
+}
+
+/// Accumulates up to [`MAX_CONSISTENCY_MISMATCHES`] [`ConsistencyMismatch`]es while
+/// [`Ext2Parser::verify`] runs
+struct MismatchAccumulator {
+    mismatches: [Option<ConsistencyMismatch>; MAX_CONSISTENCY_MISMATCHES],
+    count: usize,
+}
+
+impl MismatchAccumulator {
+    fn new() -> Self {
+        Self { mismatches: [None; MAX_CONSISTENCY_MISMATCHES], count: 0 }
+    }
+
+    fn push(&mut self, mismatch: ConsistencyMismatch) {
+        if self.count < MAX_CONSISTENCY_MISMATCHES {
+            self.mismatches[self.count] = Some(mismatch);
+            self.count += 1;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.count >= MAX_CONSISTENCY_MISMATCHES
+    }
+
+    fn into_result(self) -> Result<(), ConsistencyError> {
+        if self.count == 0 {
+            Ok(())
+        } else {
+            Err(ConsistencyError::Mismatches(self.mismatches))
+        }
+    }
 }
 
 /// Return value of an iteration callback that decides if iteration should continue or end
@@ -364,16 +790,247 @@ pub enum IterationDecision {
     Break,
 }
 
-impl<'a> Ext2Parser<'a> {
-    /// Tries to parse the raw bytes of the filesystem
-    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
-        // Check that the superblock fits inside the recieved bytes slice
-        if bytes.len() < SUPER_BLOCK_OFFSET + SUPER_BLOCK_SIZE {
+/// A handle for reading a single file's contents at arbitrary offsets, returned by
+/// [`Ext2Parser::open_reader`]. Caches the inode metadata it was opened with, so callers doing many
+/// [`read_at`](Self::read_at) calls against the same file only pay for the inode-table lookup once
+/// instead of on every call, the way [`Ext2Parser::get_contents_with_offset`] does
+pub struct InodeReader<'p, V: Volume> {
+    parser: &'p Ext2Parser<V>,
+    inode_metadata: Inode,
+}
+
+impl<'p, V: Volume> InodeReader<'p, V> {
+    /// Reads this file's contents into `out_buffer` starting at `offset`, exactly like
+    /// [`Ext2Parser::get_contents_with_offset`], without re-fetching the inode metadata
+    pub fn read_at(&self, out_buffer: &mut [u8], offset: u64) -> Result<usize, Error> {
+        self.parser.get_contents_with_offset_impl(&self.inode_metadata, out_buffer, offset)
+    }
+}
+
+/// One data block belonging to a file, yielded by [`DataBlocks`]. Valid for its first `len` bytes
+/// (always the filesystem's block size); derefs to that slice
+pub struct DataBlock {
+    bytes: [u8; MAX_BLOCK_SIZE],
+    len: usize,
+}
+
+impl core::ops::Deref for DataBlock {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Lazily iterates over the data blocks belonging to an inode, in logical order, descending the
+/// direct/indirect pointer tree (or extent tree) one logical block at a time via
+/// [`Ext2Parser::logical_block_to_addr`] and stopping at the first hole (an unmapped logical block,
+/// which resolves to physical block address zero). See [`Ext2Parser::blocks`]
+pub struct DataBlocks<'p, V: Volume> {
+    parser: &'p Ext2Parser<V>,
+    inode_metadata: Inode,
+    next_logical_block: u32,
+}
+
+impl<'p, V: Volume> Iterator for DataBlocks<'p, V> {
+    type Item = Result<DataBlock, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block_addr =
+            match self.parser.logical_block_to_addr(&self.inode_metadata, self.next_logical_block) {
+                Ok(block_addr) => block_addr,
+                Err(err) => return Some(Err(err)),
+            };
+        if block_addr.0 == 0 {
+            return None;
+        }
+        self.next_logical_block += 1;
+
+        let mut data = DataBlock { bytes: [0u8; MAX_BLOCK_SIZE], len: self.parser.block_size };
+        match self.parser.read_block(block_addr, &mut data.bytes) {
+            Ok(()) => Some(Ok(data)),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Lazily iterates over the entries of a directory, in on-disk order, yielding
+/// `(inode, name, entry_type)` for each live (non-deleted) entry. See [`Ext2Parser::directory`]
+pub struct DirEntries<'p, V: Volume> {
+    blocks: DataBlocks<'p, V>,
+    current_block: Option<DataBlock>,
+    offset_in_block: usize,
+}
+
+impl<'p, V: Volume> Iterator for DirEntries<'p, V> {
+    type Item = Result<(u32, FileName, DirEntryType), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.current_block.is_none() {
+                let block = match self.blocks.next()? {
+                    Ok(block) => block,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.current_block = Some(block);
+                self.offset_in_block = 0;
+            }
+
+            let dir_entry = {
+                let block = self.current_block.as_ref().unwrap();
+                if self.offset_in_block >= block.len {
+                    None
+                } else {
+                    Some(unsafe {
+                        *(block[self.offset_in_block..].as_ptr() as *const DirectoryEntry)
+                    })
+                }
+            };
+
+            let dir_entry = match dir_entry {
+                Some(dir_entry) => dir_entry,
+                // We've consumed this whole block without finding the end-of-entries sentinel;
+                // move on to the next one
+                None => {
+                    self.current_block = None;
+                    continue;
+                }
+            };
+
+            // If the directory entries table does not end on a block-border, the rest is zero, so
+            // a zero-sized entry means there are no more entries in the whole directory
+            if dir_entry.size == 0 {
+                return None;
+            }
+
+            let name = {
+                let block = self.current_block.as_ref().unwrap();
+                read_entry_name(block, self.offset_in_block, dir_entry.name_length)
+            };
+            self.offset_in_block += dir_entry.size as usize;
+
+            // If the inode of an entry is zero, it means the entry is unused, so we skip it
+            if dir_entry.inode != 0 {
+                return Some(Ok((dir_entry.inode, name, dir_entry.type_indicator)));
+            }
+        }
+    }
+}
+
+impl<V: Volume> Ext2Parser<V> {
+    /// Tries to parse the filesystem backed by `volume`, rejecting it if it carries any
+    /// unsupported "writing" feature flag. Use [`parse_read_only`](Self::parse_read_only) to open
+    /// such filesystems anyway, since this crate never writes to the volume in the first place
+    pub fn parse(volume: V) -> Option<Self> {
+        Self::parse_impl(volume, false)
+    }
+
+    /// Like [`parse`](Self::parse), but ignores unsupported "writing" feature flags instead of
+    /// rejecting the filesystem, since those flags by definition only restrict writers and this
+    /// crate is read-only regardless. Callers can check [`is_read_only`](Self::is_read_only)
+    /// afterwards to tell whether the volume actually carried any such flag
+    pub fn parse_read_only(volume: V) -> Option<Self> {
+        Self::parse_impl(volume, true)
+    }
+
+    /// Like [`parse`](Self::parse), but if the primary superblock at [`SUPER_BLOCK_OFFSET`] is
+    /// missing or fails validation, scans the volume for a backup copy (in every block group after
+    /// group 0, or, when [`WritingFeatureFlags::SparseSuperblocksAndGroupDescriptorTables`] is set,
+    /// only in group 1 and the groups that are a power of 3, 5 or 7) and parses from the first
+    /// valid one found instead. Use
+    /// [`superblock_source`](Self::superblock_source) to tell which copy ended up being used
+    pub fn parse_with_recovery(volume: V) -> Option<Self> {
+        if let Some(meta) = Self::try_read_and_validate(
+            &volume, SUPER_BLOCK_OFFSET, false, SuperBlockSource::Primary) {
+            return Some(Self::from_meta(
+                volume, meta, DEFAULT_INODE_CACHE_CAPACITY, DEFAULT_PTR_BLOCK_CACHE_CAPACITY));
+        }
+
+        // The primary superblock didn't pass validation, but its raw fields may still be intact
+        // enough to compute where the backups should be, the same way e2fsck does when scanning for
+        // them
+        let mut super_block_bytes = [0u8; core::mem::size_of::<SuperBlock>()];
+        volume.read_at(SUPER_BLOCK_OFFSET, &mut super_block_bytes).ok()?;
+        let primary_guess = unsafe { *(super_block_bytes.as_ptr() as *const SuperBlock) };
+
+        let block_size = 1024usize.checked_shl(primary_guess.block_size_exponent)?;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE || primary_guess.num_blocks_in_block_group == 0 {
             return None;
         }
+        let blocks_per_group = primary_guess.num_blocks_in_block_group as u64;
+        let group_count_guess =
+            div_ceil(primary_guess.block_count, primary_guess.num_blocks_in_block_group)?;
+
+        let mut extension_bytes = [0u8; core::mem::size_of::<SuperBlockExtension>()];
+        let extended_fields_offset = SUPER_BLOCK_OFFSET + core::mem::size_of::<SuperBlock>() as u64;
+        let sparse = volume.read_at(extended_fields_offset, &mut extension_bytes)
+            .map(|()| unsafe { *(extension_bytes.as_ptr() as *const SuperBlockExtension) })
+            .map(|ext| (ext.writing_feature_flags
+                & WritingFeatureFlags::SparseSuperblocksAndGroupDescriptorTables as u32) != 0)
+            .unwrap_or(false);
+
+        for group in 1..group_count_guess {
+            if sparse && !is_backup_superblock_group(group) {
+                continue;
+            }
+
+            let offset = group as u64 * blocks_per_group * block_size as u64
+                + if block_size == 1024 { 1024 } else { 0 };
+
+            let meta = match Self::try_read_and_validate(
+                &volume, offset, false, SuperBlockSource::Backup { group }) {
+                Some(meta) => meta,
+                None => continue,
+            };
+            // Make sure this copy actually claims to belong to the group we expected to find it in
+            if meta.super_block_extension.containing_block_group as u32 != group {
+                continue;
+            }
+
+            return Some(Self::from_meta(
+                volume, meta, DEFAULT_INODE_CACHE_CAPACITY, DEFAULT_PTR_BLOCK_CACHE_CAPACITY));
+        }
+
+        None
+    }
+
+    /// Like [`parse`](Self::parse), but lets the caller tune the capacity of the inode and
+    /// pointer-block caches (see [`InodeCache`] and [`PtrBlockCache`]) instead of using the defaults
+    /// every other constructor does. Capacities above [`MAX_INODE_CACHE_ENTRIES`] /
+    /// [`MAX_PTR_BLOCK_CACHE_ENTRIES`] are silently clamped down to them; either can be `0` to
+    /// disable that cache entirely
+    pub fn parse_with_cache_capacity(
+        volume: V, inode_cache_capacity: usize, ptr_block_cache_capacity: usize) -> Option<Self> {
+        let meta = Self::try_read_and_validate(
+            &volume, SUPER_BLOCK_OFFSET, false, SuperBlockSource::Primary)?;
+        Some(Self::from_meta(volume, meta, inode_cache_capacity, ptr_block_cache_capacity))
+    }
+
+    /// Reads and validates the superblock and its extended fields at `offset`, without consuming
+    /// `volume`, so failed attempts (used while scanning for a backup in
+    /// [`parse_with_recovery`](Self::parse_with_recovery)) can be retried at a different offset
+    fn try_read_and_validate(volume: &V, offset: u64, ignore_unsupported_writing_features: bool,
+        source: SuperBlockSource) -> Option<ParsedSuperBlockMeta> {
+        let mut super_block_bytes = [0u8; core::mem::size_of::<SuperBlock>()];
+        volume.read_at(offset, &mut super_block_bytes).ok()?;
+        let super_block = unsafe { *(super_block_bytes.as_ptr() as *const SuperBlock) };
+
+        let extended_fields_offset = offset + core::mem::size_of::<SuperBlock>() as u64;
+        let mut extension_bytes = [0u8; core::mem::size_of::<SuperBlockExtension>()];
+        volume.read_at(extended_fields_offset, &mut extension_bytes).ok()?;
+        let super_block_extension = unsafe {
+            *(extension_bytes.as_ptr() as *const SuperBlockExtension)
+        };
 
-        // Read the super block and verify the Ext2 signature
-        let super_block = unsafe { &*(bytes[SUPER_BLOCK_OFFSET..].as_ptr() as *const SuperBlock) };
+        Self::validate_super_block(
+            super_block, super_block_extension, ignore_unsupported_writing_features, source)
+    }
+
+    /// Validates a superblock and its extended fields already read from some offset, returning the
+    /// metadata [`from_meta`](Self::from_meta) needs to finish building a parser around them, or
+    /// `None` if this copy is unusable
+    fn validate_super_block(super_block: SuperBlock, super_block_extension: SuperBlockExtension,
+        ignore_unsupported_writing_features: bool, source: SuperBlockSource)
+        -> Option<ParsedSuperBlockMeta> {
         if super_block.magic_signature != SUPER_BLOCK_MAGIC_SIGNATURE {
             return None;
         }
@@ -383,12 +1040,6 @@ impl<'a> Ext2Parser<'a> {
             return None;
         }
 
-        // Read the extended super block fields
-        let extended_fields_offset = SUPER_BLOCK_OFFSET + core::mem::size_of::<SuperBlock>();
-        let super_block_extension = unsafe {
-            &*(bytes[extended_fields_offset..].as_ptr() as *const SuperBlockExtension)
-        };
-
         // Fail if the filesystem uses a non-standard inode structure
         if super_block_extension.inode_size != core::mem::size_of::<Inode>() as u16 {
             return None;
@@ -405,73 +1056,207 @@ impl<'a> Ext2Parser<'a> {
             return None;
         }
 
-        // Fail if we don't support any of features needed for writing
-        if (super_block_extension.writing_feature_flags & !SUPPORTED_WRITING_FEATURES_MASK) != 0 {
-            // TODO: Read-only mode
+        // Fail if we don't support any of the features needed for writing, unless the caller asked
+        // us to ignore them since we're opening this volume read-only anyway
+        let has_unsupported_writing_features =
+            (super_block_extension.writing_feature_flags & !SUPPORTED_WRITING_FEATURES_MASK) != 0;
+        if has_unsupported_writing_features && !ignore_unsupported_writing_features {
             return None;
         }
+        let read_only = ignore_unsupported_writing_features && has_unsupported_writing_features;
 
         // The block_size_exponent is log2(block_size) - 10, therefore block_size is 1024<<(exp)
         let block_size = 1024usize.checked_shl(super_block.block_size_exponent)?;
+        // We don't support block sizes larger than our on-stack scratch buffers
+        if block_size > MAX_BLOCK_SIZE {
+            return None;
+        }
 
         // The block group count could either be calculated using the block count and number of
         // blocks in a block group, or using the inode count and the number of inodes in a block
         // group, so we calculate using both ways and compare as a sanity check. Note that a divide
         // with ceiling-rounding is used because the last block group might contain less blocks
-        let block_group_count = 
+        let block_group_count =
             div_ceil(super_block.block_count, super_block.num_blocks_in_block_group)?;
-        let block_group_count_alt = 
+        let block_group_count_alt =
             div_ceil(super_block.inode_count, super_block.num_blocks_in_block_group)?;
         if block_group_count != block_group_count_alt {
             return None;
         }
 
-        // Fail if the byte slice we received does not contain the entire filesystem
-        if bytes.len() < block_size.checked_mul(super_block.block_count as usize)? {
-            return None;
-        }
-
-        // Read the block group descriptor table. The table is located in the block immediately
-        // following the super block
-        let block_group_descriptor_table_offset = (super_block.superblock_block_number.0 as usize + 1) * block_size;
-        let block_group_descriptor_table = unsafe { core::slice::from_raw_parts(
-                bytes[block_group_descriptor_table_offset..].as_ptr() as *const BlockGroupDescriptor,
-                block_group_count as usize
-        )};
+        // The block group descriptor table is located in the block immediately following the
+        // super block
+        let block_group_descriptor_table_offset =
+            (super_block.superblock_block_number.0 as u64 + 1) * block_size as u64;
 
-        Some(Self {
-            raw_bytes: bytes,
+        Some(ParsedSuperBlockMeta {
             super_block,
             super_block_extension,
-            block_group_descriptor_table,
+            block_group_descriptor_table_offset,
             block_size,
-            inode_count: super_block.inode_count,
-            block_count: super_block.block_count,
-            blocks_per_block_group: super_block.num_blocks_in_block_group,
-            inodes_per_block_group: super_block.num_inodes_in_block_group,
             block_group_count,
-            num_ptrs_per_block: block_size / core::mem::size_of::<BlockAddr>()
+            read_only,
+            source,
         })
     }
 
+    /// Finishes building a parser around `volume` from the metadata a successful
+    /// [`validate_super_block`](Self::validate_super_block) produced, with its inode and
+    /// pointer-block caches sized to `inode_cache_capacity`/`ptr_block_cache_capacity`
+    fn from_meta(volume: V, meta: ParsedSuperBlockMeta, inode_cache_capacity: usize,
+        ptr_block_cache_capacity: usize) -> Self {
+        Self {
+            volume,
+            super_block: meta.super_block,
+            super_block_extension: meta.super_block_extension,
+            block_group_descriptor_table_offset: meta.block_group_descriptor_table_offset,
+            block_size: meta.block_size,
+            inode_count: meta.super_block.inode_count,
+            block_count: meta.super_block.block_count,
+            blocks_per_block_group: meta.super_block.num_blocks_in_block_group,
+            inodes_per_block_group: meta.super_block.num_inodes_in_block_group,
+            block_group_count: meta.block_group_count,
+            num_ptrs_per_block: meta.block_size / core::mem::size_of::<BlockAddr>(),
+            read_only: meta.read_only,
+            superblock_source: meta.source,
+            inode_cache: core::cell::RefCell::new(InodeCache::new(inode_cache_capacity)),
+            ptr_block_cache: core::cell::RefCell::new(PtrBlockCache::new(ptr_block_cache_capacity)),
+        }
+    }
+
+    fn parse_impl(volume: V, ignore_unsupported_writing_features: bool) -> Option<Self> {
+        let meta = Self::try_read_and_validate(
+            &volume, SUPER_BLOCK_OFFSET, ignore_unsupported_writing_features, SuperBlockSource::Primary)?;
+        Some(Self::from_meta(
+            volume, meta, DEFAULT_INODE_CACHE_CAPACITY, DEFAULT_PTR_BLOCK_CACHE_CAPACITY))
+    }
+
+    /// Returns `true` if this volume was opened via [`parse_read_only`](Self::parse_read_only) and
+    /// carries a "writing" feature flag this crate doesn't support, meaning it must not be written
+    /// to even though it can still be safely read from
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns which superblock copy this parser was actually built from, see
+    /// [`parse_with_recovery`](Self::parse_with_recovery)
+    pub fn superblock_source(&self) -> SuperBlockSource {
+        self.superblock_source
+    }
+
+    /// Runs an fsck-lite consistency check over this filesystem: cross-checks each block group's
+    /// block-usage and inode-usage bitmaps and `directories_count` against its descriptor,
+    /// cross-checks the summed free counts against the super block's `unallocated_blocks_count` /
+    /// `unallocated_inodes_count`, and confirms every directory entry points at an inode number
+    /// inside `1..=inode_count`. Returns `Ok(())` if everything matches, or a [`ConsistencyError`]
+    /// enumerating up to [`MAX_CONSISTENCY_MISMATCHES`] of the problems found otherwise. Callers
+    /// should run this before trusting an image whose `filesystem_state` was not cleanly unmounted
+    pub fn verify(&self) -> Result<(), ConsistencyError> {
+        let mut acc = MismatchAccumulator::new();
+
+        let mut total_free_blocks = 0u32;
+        let mut total_free_inodes = 0u32;
+
+        for group in 0..self.block_group_count {
+            let bgd = self.read_block_group_descriptor(group as usize)?;
+
+            let free_blocks =
+                self.count_clear_bitmap_bits(bgd.block_usage_bitmap_addr, self.blocks_per_block_group)?;
+            if free_blocks != bgd.unallocated_blocks_count as u32 {
+                acc.push(ConsistencyMismatch::GroupFreeBlocksMismatch {
+                    group, bitmap_free: free_blocks, descriptor_free: bgd.unallocated_blocks_count as u32
+                });
+            }
+            total_free_blocks += free_blocks;
+
+            let free_inodes =
+                self.count_clear_bitmap_bits(bgd.inode_usage_bitmap_addr, self.inodes_per_block_group)?;
+            if free_inodes != bgd.unallocated_inodes_count as u32 {
+                acc.push(ConsistencyMismatch::GroupFreeInodesMismatch {
+                    group, bitmap_free: free_inodes, descriptor_free: bgd.unallocated_inodes_count as u32
+                });
+            }
+            total_free_inodes += free_inodes;
+
+            let group_inode_start = group * self.inodes_per_block_group + 1;
+            let group_inode_end = core::cmp::min(
+                group_inode_start + self.inodes_per_block_group - 1, self.inode_count);
+            let mut counted_directories = 0u32;
+            for inode_num in group_inode_start..=group_inode_end {
+                if self.get_inode(inode_num)?.get_type() == InodeType::Directory {
+                    counted_directories += 1;
+                }
+            }
+            if counted_directories != bgd.directories_count as u32 {
+                acc.push(ConsistencyMismatch::GroupDirectoriesCountMismatch {
+                    group, counted: counted_directories, descriptor: bgd.directories_count as u32
+                });
+            }
+        }
+
+        if total_free_blocks != self.super_block.unallocated_blocks_count {
+            acc.push(ConsistencyMismatch::TotalFreeBlocksMismatch {
+                summed: total_free_blocks, super_block: self.super_block.unallocated_blocks_count
+            });
+        }
+        if total_free_inodes != self.super_block.unallocated_inodes_count {
+            acc.push(ConsistencyMismatch::TotalFreeInodesMismatch {
+                summed: total_free_inodes, super_block: self.super_block.unallocated_inodes_count
+            });
+        }
+
+        for inode_num in 1..=self.inode_count {
+            if acc.is_full() {
+                break;
+            }
+            if self.get_inode(inode_num)?.get_type() != InodeType::Directory {
+                continue;
+            }
+
+            self.for_each_directory_entry(inode_num, |entry_inode, _name, _entry_type| {
+                if entry_inode < 1 || entry_inode > self.inode_count {
+                    acc.push(ConsistencyMismatch::DirectoryEntryInodeOutOfRange {
+                        dir_inode: inode_num, target_inode: entry_inode
+                    });
+                }
+                if acc.is_full() {
+                    IterationDecision::Break
+                } else {
+                    IterationDecision::Continue
+                }
+            })?;
+        }
+
+        acc.into_result()
+    }
+
+    /// Counts the number of clear (zero) bits among the first `num_bits` bits of the usage bitmap
+    /// stored at block `addr`
+    fn count_clear_bitmap_bits(&self, addr: BlockAddr, num_bits: u32) -> Result<u32, Error> {
+        let mut buf = [0u8; MAX_BLOCK_SIZE];
+        self.read_block(addr, &mut buf)?;
+        Ok(count_clear_bits(&buf, num_bits))
+    }
+
     /// Returns the next directory entry of the directory with inode number `inode` or `None` if
     /// there are no more directory entries. The current directory entry is determined by the
     /// `opaque_offset` which must be zero for the first entry, and the first item in the returned
-    /// tuple for every subsequent call. The returned tuple is of the form 
+    /// tuple for every subsequent call. The returned tuple is of the form
     /// `(next_opaque_offset, inode, filename, entry_type)`
     pub fn get_next_directory_entry(&self, inode: u32, mut opaque_offset: u32)
-        -> Option<(u32, u32, &'a str, DirEntryType)> {
+        -> Result<Option<(u32, u32, FileName, DirEntryType)>, Error> {
         // Make sure this is actually a directory
-        assert!(self.get_inode(inode).get_type() == InodeType::Directory);
+        assert!(self.get_inode(inode)?.get_type() == InodeType::Directory);
 
         // FIXME: Don't iterate from the start every time
         let mut total_offset: u32 = 0;
         let mut result = None;
+        let mut scratch = [0u8; MAX_BLOCK_SIZE];
         // We iterate through all data blocks, iterating through all directory entries, keeping
         // track of the total offset, until we reach opaque_offset
-        self.for_each_data_block(inode, &mut |data_block| {
+        self.for_each_data_block(inode, &mut scratch, &mut |data_block| {
             let mut curr_offset = 0;
-            while curr_offset < self.block_size {
+            while curr_offset < data_block.len() {
                 let dir_entry = unsafe {
                     &*(data_block[curr_offset..].as_ptr() as *const DirectoryEntry)
                 };
@@ -482,22 +1267,17 @@ impl<'a> Ext2Parser<'a> {
                     return IterationDecision::Break;
                 }
 
-                
+
                 // We reached the offset of the requested (next) entry
                 if total_offset == opaque_offset {
                     // If the inode of an entry is zero, it means the entry is unused and we skip it
                     if dir_entry.inode == 0 {
                         opaque_offset += dir_entry.size as u32;
                     } else {
-                        let filename_offset = curr_offset + core::mem::size_of::<DirectoryEntry>();
-                        let filename = core::str::from_utf8(
-                            &data_block[filename_offset..filename_offset+dir_entry.name_length as usize]
-                        ).unwrap();
-
                         result = Some((
                             total_offset + dir_entry.size as u32,
                             dir_entry.inode,
-                            filename,
+                            read_entry_name(data_block, curr_offset, dir_entry.name_length),
                             dir_entry.type_indicator
                         ));
                         return IterationDecision::Break;
@@ -513,57 +1293,62 @@ impl<'a> Ext2Parser<'a> {
             }
 
             IterationDecision::Continue
-        });
+        })?;
 
-        result
+        Ok(result)
     }
 
-    /// Calls the `callback` for each entry in the directory whose inode number is `inode`. The
-    /// callback will be called with arguments `(inode, filename, entry_type)`
-    pub fn for_each_directory_entry<F>(&self, inode: u32, mut callback: F)
-        where F: FnMut(u32, &'a str, DirEntryType) -> IterationDecision {
+    /// Returns a lazy iterator over the entries of the directory whose inode number is `inode`,
+    /// yielding `(inode, filename, entry_type)` for each live entry. See
+    /// [`for_each_directory_entry`](Self::for_each_directory_entry) for the callback-driven
+    /// equivalent
+    pub fn directory(&self, inode: u32) -> Result<DirEntries<'_, V>, Error> {
         // Make sure this is really a directory
-        assert!(self.get_inode(inode).get_type() == InodeType::Directory);
-
-        self.for_each_data_block(inode, &mut |data_block| {
-            let mut curr_offset = 0;
-            while curr_offset < self.block_size {
-                let dir_entry = unsafe {
-                    &*(data_block[curr_offset..].as_ptr() as *const DirectoryEntry)
-                };
-
-                // If the directory entries table does not end on a block-border, the rest is zero,
-                // so a zero-sized entry means there are no more entries
-                if dir_entry.size == 0 {
-                    return IterationDecision::Break;
-                }
+        assert!(self.get_inode(inode)?.get_type() == InodeType::Directory);
 
-                // If the inode of an entry is zero, it means the entry is unused and we skip it
-                if dir_entry.inode != 0 {
-                    let filename_offset = curr_offset + core::mem::size_of::<DirectoryEntry>();
-                    let filename = core::str::from_utf8(
-                        &data_block[filename_offset..filename_offset+dir_entry.name_length as usize]
-                    ).unwrap();
-
-                    if callback(dir_entry.inode, filename, dir_entry.type_indicator) == IterationDecision::Break {
-                        return IterationDecision::Break;
-                    }
-                }
+        Ok(DirEntries { blocks: self.blocks(inode)?, current_block: None, offset_in_block: 0 })
+    }
 
-                curr_offset += dir_entry.size as usize;
+    /// Calls the `callback` for each entry in the directory whose inode number is `inode`. The
+    /// callback will be called with arguments `(inode, filename, entry_type)`
+    pub fn for_each_directory_entry<F>(&self, inode: u32, mut callback: F) -> Result<(), Error>
+        where F: FnMut(u32, FileName, DirEntryType) -> IterationDecision {
+        for entry in self.directory(inode)? {
+            let (entry_inode, name, entry_type) = entry?;
+            if callback(entry_inode, name, entry_type) == IterationDecision::Break {
+                break;
             }
+        }
+        Ok(())
+    }
 
-            IterationDecision::Continue
-        });
+    /// Resolves a path to an inode and directory entry type, if it exists, following any symbolic
+    /// links crossed along the way, including the final component. If the path is relative, the
+    /// base directory is specified by the `base_inode`
+    pub fn resolve_path_to_inode(&self, path: &str, base_inode: u32)
+        -> Result<Option<(u32, DirEntryType)>, Error> {
+        self.resolve_path_to_inode_impl(path, base_inode, true, 0)
+    }
+
+    /// Like [`resolve_path_to_inode`](Self::resolve_path_to_inode), but if the path's last
+    /// component is itself a symbolic link, returns the link's own inode and
+    /// [`DirEntryType::SymbolicLink`] instead of following it (`lstat` semantics). Symlinks crossed
+    /// earlier in the path are still followed as usual
+    pub fn resolve_path_to_inode_no_follow_final(&self, path: &str, base_inode: u32)
+        -> Result<Option<(u32, DirEntryType)>, Error> {
+        self.resolve_path_to_inode_impl(path, base_inode, false, 0)
     }
 
-    /// Resolves a path to an inode and directory entry type, if it exists. If the path is relative,
-    /// the base directory is specified by the `base_inode`
-    pub fn resolve_path_to_inode(&self, path: &str, mut base_inode: u32) -> Option<(u32, DirEntryType)> {
+    /// Implementation of [`resolve_path_to_inode`](Self::resolve_path_to_inode). If `follow_final`
+    /// is `false`, a symbolic link in the path's last component is returned as-is instead of being
+    /// followed. `hop_count` is the number of symlinks already followed across the whole
+    /// resolution, and is used to bail out of symlink loops
+    fn resolve_path_to_inode_impl(&self, path: &str, mut base_inode: u32, follow_final: bool, mut hop_count: u32)
+        -> Result<Option<(u32, DirEntryType)>, Error> {
         // The root directory is not handled by the path-walk code, but it has a static inode
         // so we just return it immediately
         if path == "/" {
-            return Some((ROOT_INODE, DirEntryType::Directory));
+            return Ok(Some((ROOT_INODE, DirEntryType::Directory)));
         }
 
         // If the path starts with a `/` it is an absolute path, and we turn it into a relative path
@@ -583,220 +1368,974 @@ impl<'a> Ext2Parser<'a> {
         } else {
             (path, false)
         };
-        
+
         // The current node in the path, starting with the base inode
         let mut inode = base_inode;
         let mut entry_type = DirEntryType::Directory;
-        // Boolean that keeps file if we reached a file which is not a directory in the path, which
-        // is only allowed to happen once, in the end
-        let mut reached_file = false;
-        for component in path.split('/') {
-            // An empty path component or a path that continues after reaching a file are both
-            // invalid
-            if component == "" || reached_file {
-                return None;
+        // The part of the path that has not been resolved yet
+        let mut remaining = path;
+        loop {
+            let (component, rest) = match remaining.find('/') {
+                Some(idx) => (&remaining[..idx], &remaining[idx+1..]),
+                None => (remaining, ""),
+            };
+            let is_last_component = rest.is_empty();
+
+            // An empty path component is invalid
+            if component == "" {
+                return Ok(None);
             }
 
-            // We iterate through all files in the directory, trying to find a file with a matching
-            // name
-            let mut found_match = false;
-            self.for_each_directory_entry(inode, |child_inode, child_name, child_type| {
-                if child_name == component {
-                    inode = child_inode;
-                    entry_type = child_type;
-
-                    if child_type == DirEntryType::SymbolicLink {
-                        todo!("Handle symbolic links");
-                    } else if child_type != DirEntryType::Directory {
-                        reached_file = true;
-                    }
-
-                    found_match = true;
-                    return IterationDecision::Break;
+            // Look up this component in the current directory, taking the HTree index fast path
+            // when it's available
+            let (child_inode, child_type) = match self.lookup_in_directory(inode, component)? {
+                Some(found) => found,
+                // If none of the directory's children match the component, the requested file does
+                // not exist
+                None => return Ok(None),
+            };
+
+            // A symlink is followed unless it is the final component and the caller asked for it
+            // not to be
+            if child_type == DirEntryType::SymbolicLink && (!is_last_component || follow_final) {
+                if hop_count >= MAX_SYMLINK_HOPS {
+                    // Likely a symlink loop
+                    return Ok(None);
                 }
+                hop_count += 1;
 
-                IterationDecision::Continue
-            });
+                let target = self.read_symlink_target(child_inode)?;
 
-            // If none of the directories children match the component, the requested file does not
-            // exist
-            if !found_match {
-                return None;
+                // The symlink is resolved relative to the directory containing it (`inode`), unless
+                // the target is itself absolute, which `resolve_path_to_inode_impl` handles for us.
+                // Any path components still left to resolve are appended after the target
+                let mut expanded = [0u8; MAX_EXPANDED_SYMLINK_PATH_LEN];
+                let expanded_len = expand_symlink_path(target.as_str(), rest, &mut expanded);
+                let expanded_path = core::str::from_utf8(&expanded[..expanded_len]).unwrap();
+
+                return self.resolve_path_to_inode_impl(expanded_path, inode, follow_final, hop_count);
+            }
+
+            inode = child_inode;
+            entry_type = child_type;
+
+            if is_last_component {
+                break;
+            }
+
+            // Only directories (and symlinks to them, already followed above) can be descended into
+            if entry_type != DirEntryType::Directory {
+                return Ok(None);
             }
+
+            remaining = rest;
         }
 
         // If the path ended with a `/`, it must be a directory
         if must_be_dir && entry_type != DirEntryType::Directory {
-            return None;
+            return Ok(None);
+        }
+
+        Ok(Some((inode, entry_type)))
+    }
+
+    /// Reads the target path of the symbolic link inode `inode`. Ext2 stores "fast" symlink targets
+    /// under [`INLINE_SYMLINK_MAX_LEN`] bytes directly in the inode's block-pointer area instead of
+    /// allocating data blocks for them; longer targets are read out of the data blocks as usual
+    fn read_symlink_target(&self, inode: u32) -> Result<FileName, Error> {
+        let inode_metadata = self.get_inode(inode)?;
+        let target_len = (inode_metadata.size_low as usize).min(MAX_FILE_NAME_LEN);
+
+        let mut bytes = [0u8; MAX_FILE_NAME_LEN];
+        if inode_metadata.size_low < INLINE_SYMLINK_MAX_LEN as u32 && inode_metadata.disk_sector_count == 0 {
+            let inline_target = unsafe {
+                core::slice::from_raw_parts(
+                    &inode_metadata.direct_pointers as *const _ as *const u8,
+                    target_len
+                )
+            };
+            bytes[..target_len].copy_from_slice(inline_target);
+        } else {
+            self.get_contents(inode, &mut bytes[..target_len])?;
+        }
+
+        Ok(FileName { bytes, len: target_len as u8 })
+    }
+
+    /// Calls `callback` with `(name_index, name, value)` for each extended attribute of the inode
+    /// whose number is `inode`, where `name` is the attribute's full reconstructed name (the
+    /// [`xattr_name_prefix`] for `name_index` followed by the name stored in the entry) and `value`
+    /// is its raw value bytes. Does nothing if the inode has no [`Inode::extended_attributes_block`]
+    pub fn for_each_xattr<F>(&self, inode: u32, mut callback: F) -> Result<(), Error>
+        where F: FnMut(u8, &str, &[u8]) -> IterationDecision {
+        let inode_metadata = self.get_inode(inode)?;
+        if inode_metadata.extended_attributes_block.0 == 0 {
+            return Ok(());
+        }
+
+        let mut block = [0u8; MAX_BLOCK_SIZE];
+        let block = &mut block[..self.block_size];
+        self.read_block(inode_metadata.extended_attributes_block, block)?;
+
+        let header = unsafe { &*(block.as_ptr() as *const XattrHeader) };
+        if header.magic != XATTR_BLOCK_MAGIC {
+            return Ok(());
+        }
+
+        let mut name_buf = [0u8; MAX_XATTR_NAME_LEN];
+        let mut entry_offset = core::mem::size_of::<XattrHeader>();
+        loop {
+            let entry = unsafe { &*(block[entry_offset..].as_ptr() as *const XattrEntry) };
+            // The entry list ends at the first all-zero entry
+            if entry.name_length == 0 && entry.name_index == 0 && entry.value_offset == 0 {
+                break;
+            }
+
+            let prefix = xattr_name_prefix(entry.name_index);
+            let name_offset = entry_offset + core::mem::size_of::<XattrEntry>();
+            let suffix = &block[name_offset..name_offset + entry.name_length as usize];
+
+            assert!(prefix.len() + suffix.len() <= name_buf.len());
+            name_buf[..prefix.len()].copy_from_slice(prefix.as_bytes());
+            name_buf[prefix.len()..prefix.len() + suffix.len()].copy_from_slice(suffix);
+            let name = core::str::from_utf8(&name_buf[..prefix.len() + suffix.len()]).unwrap_or("");
+
+            let value_offset = entry.value_offset as usize;
+            let value = &block[value_offset..value_offset + entry.value_size as usize];
+
+            if callback(entry.name_index, name, value) == IterationDecision::Break {
+                break;
+            }
+
+            // Entries are 4-byte aligned
+            let entry_size = div_ceil((core::mem::size_of::<XattrEntry>() + entry.name_length as usize) as u32, 4).unwrap() as usize * 4;
+            entry_offset += entry_size;
+            if entry_offset >= block.len() {
+                break;
+            }
         }
 
-        Some((inode, entry_type))
+        Ok(())
     }
 
     /// Reads the file with inode number `inode` into `out_buffer`, the amount of bytes read is
     /// returned, and it is limited by the size of `out_buffer`
-    pub fn get_contents(&self, inode: u32, out_buffer: &mut [u8]) -> usize {
+    pub fn get_contents(&self, inode: u32, out_buffer: &mut [u8]) -> Result<usize, Error> {
         self.get_contents_with_offset(inode, out_buffer, 0)
     }
 
+    /// Returns the logical size, in bytes, of the file described by `inode_metadata`. For regular
+    /// files on a filesystem advertising [`WritingFeatureFlags::FileSize64Bit`], `size_high` is
+    /// combined with `size_low` into the full 64bit size; `size_high` holds other data (e.g. the ACL
+    /// block) for non-regular files, so it must not be read for them
+    pub fn file_size(&self, inode_metadata: &Inode) -> u64 {
+        let mut size = inode_metadata.size_low as u64;
+        if inode_metadata.get_type() == InodeType::RegularFile
+            && (self.super_block_extension.writing_feature_flags & WritingFeatureFlags::FileSize64Bit as u32) != 0 {
+            size |= (inode_metadata.size_high as u64) << 32;
+        }
+        size
+    }
+
     /// Reads the file with inode number `inode` into `out_buffer` starting at the specified offset.
-    /// The amount of bytes read is returned, and it is limited by the size of `out_buffer`
-    pub fn get_contents_with_offset(&self, inode: u32, out_buffer: &mut [u8], offset: usize) -> usize {
+    /// The amount of bytes read is returned, and it is limited by the size of `out_buffer`.
+    /// Seeks directly to the first block covering `offset` via
+    /// [`logical_block_to_addr`](Self::logical_block_to_addr) instead of walking the file from the
+    /// start, so the cost is proportional to the number of blocks actually read, not to `offset`.
+    /// Callers doing many reads of the same file should use [`open_reader`](Self::open_reader)
+    /// instead, so the inode metadata is only fetched once
+    pub fn get_contents_with_offset(&self, inode: u32, out_buffer: &mut [u8], offset: u64)
+        -> Result<usize, Error> {
+        let inode_metadata = self.get_inode(inode)?;
+        self.get_contents_with_offset_impl(&inode_metadata, out_buffer, offset)
+    }
+
+    /// Returns a handle for reading the file with inode number `inode` at arbitrary offsets via
+    /// [`InodeReader::read_at`], caching its inode metadata so it is only fetched once no matter
+    /// how many reads the caller performs through the handle
+    pub fn open_reader(&self, inode: u32) -> Result<InodeReader<'_, V>, Error> {
+        Ok(InodeReader { parser: self, inode_metadata: self.get_inode(inode)? })
+    }
+
+    fn get_contents_with_offset_impl(&self, inode_metadata: &Inode, out_buffer: &mut [u8], offset: u64)
+        -> Result<usize, Error> {
         if out_buffer.len() == 0 {
-            return 0;
+            return Ok(0);
         }
 
-        // FIXME: Don't iterate from the start every time...
+        let file_size = self.file_size(inode_metadata);
+
+        if offset >= file_size {
+            return Ok(0);
+        }
 
-        let inode_metadata = self.get_inode(inode);
-        let file_size = inode_metadata.size_low as usize; // TODO: 64bit size
+        let block_size = self.block_size as u64;
 
         // total_read tracks the number of bytes we read into the buffer, and data_offset tracks the
         // number of bytes we went over from the start of the file
         let mut total_read = 0;
-        let mut data_offset = 0;
-        self.for_each_data_block(inode, &mut |data_block| {
-            // The last block may be less than the normal size if the block size does not divide the
-            // file size
-            let block_length = data_block.len().min(file_size - data_offset);
+        let mut data_offset = (offset / block_size) * block_size;
+        let mut logical_block = (data_offset / block_size) as u32;
+        let mut scratch = [0u8; MAX_BLOCK_SIZE];
 
-            // We check if part of this block is after the requested offset
-            if offset < data_offset + block_length {
-                // We might need to read from the middle of the first block we read
-                let block_offset = if offset > data_offset {
-                    offset - data_offset
-                } else {
-                    0
-                };
+        while data_offset < file_size && total_read < out_buffer.len() {
+            let block_addr = self.logical_block_to_addr(inode_metadata, logical_block)?;
+            let data_block = &mut scratch[..self.block_size];
+            self.read_block(block_addr, data_block)?;
 
-                
-                // The amount of bytes we need to read is the minimum between the number of bytes
-                // in the block we are interested in, and the space left in the out buffer
-                let left_in_block = block_length - block_offset;
-                let size_left = left_in_block.min(out_buffer.len() - total_read);
+            // The last block may be less than the normal size if the block size does not divide the
+            // file size
+            let block_length = (data_block.len() as u64).min(file_size - data_offset) as usize;
 
-                out_buffer[total_read..total_read+size_left].copy_from_slice(&data_block[..size_left]);
-                total_read += size_left;
+            // We might need to read from the middle of the first block we read
+            let block_offset = if offset > data_offset {
+                (offset - data_offset) as usize
+            } else {
+                0
+            };
 
-                // If we reached the end of the out buffer, we can finish
-                if total_read == out_buffer.len() {
-                    return IterationDecision::Break;
-                }
-            }
+            // The amount of bytes we need to read is the minimum between the number of bytes
+            // in the block we are interested in, and the space left in the out buffer
+            let left_in_block = block_length - block_offset;
+            let size_left = left_in_block.min(out_buffer.len() - total_read);
 
-            data_offset += self.block_size;
+            out_buffer[total_read..total_read+size_left]
+                .copy_from_slice(&data_block[block_offset..block_offset+size_left]);
+            total_read += size_left;
 
-            // If we reached the end of the logical file there is no need to continue
-            if data_offset >= file_size {
-                IterationDecision::Break
-            } else {
-                IterationDecision::Continue
-            }
-        });
+            data_offset += block_size;
+            logical_block += 1;
+        }
 
-        total_read
+        Ok(total_read)
     }
 
-    /// Calls the `callback` for each block allocated to inode whose number is `inode`. The callback
-    /// will be called with a byte slice of the block's content
-    pub fn for_each_data_block<F>(&self, inode: u32, callback: &mut F)
-        where F: FnMut(&'a [u8]) -> IterationDecision {
-        let inode_metadata = self.get_inode(inode);
+    /// Returns a lazy iterator over the data blocks allocated to the inode whose number is
+    /// `inode`, in logical order, stopping at the first hole (a logical block with no mapped
+    /// physical address). See [`for_each_data_block`](Self::for_each_data_block) for the
+    /// callback-driven equivalent
+    pub fn blocks(&self, inode: u32) -> Result<DataBlocks<'_, V>, Error> {
+        Ok(DataBlocks { parser: self, inode_metadata: self.get_inode(inode)?, next_logical_block: 0 })
+    }
 
-        for i in 0..INODE_DIRECT_PTR_COUNT {
-            if callback(self.get_block(inode_metadata.direct_pointers[i])) == IterationDecision::Break {
-                return;
+    /// Calls the `callback` for each block allocated to inode whose number is `inode`, with each
+    /// block read through [`blocks`](Self::blocks). `scratch` is not written to; it must still be
+    /// at least as long as the filesystem's block size
+    pub fn for_each_data_block<F>(&self, inode: u32, scratch: &mut [u8], callback: &mut F)
+        -> Result<(), Error>
+        where F: FnMut(&[u8]) -> IterationDecision {
+        assert!(scratch.len() >= self.block_size);
+
+        for data_block in self.blocks(inode)? {
+            if callback(&data_block?) == IterationDecision::Break {
+                break;
             }
         }
 
-        self.for_each_indirect_block(inode_metadata.singly_indirect_pointer, callback);
-        self.for_each_doubly_indirect_block(inode_metadata.doubly_indirect_pointer, callback);
-        self.for_each_triply_indirect_block(inode_metadata.triply_indirect_pointer, callback);
+        Ok(())
     }
 
-    /// Returns a reference to the inode metadata structure of the inode whose number is `inode`
-    pub fn get_inode(&self, inode: u32) -> &'a Inode {
+    /// Returns the inode metadata structure of the inode whose number is `inode`. Served out of the
+    /// [`InodeCache`] when possible
+    pub fn get_inode(&self, inode: u32) -> Result<Inode, Error> {
         // Inode numbers are start at 1
         assert!(inode >= 1);
         assert!(inode <= self.inode_count);
 
+        if let Some(metadata) = self.inode_cache.borrow_mut().get(inode) {
+            return Ok(metadata);
+        }
+
         // We calculate the block group of the inode, and the index inside the block group
         let block_group = ((inode - 1) / self.inodes_per_block_group) as usize;
         let inode_index = ((inode - 1) % self.inodes_per_block_group) as usize;
 
         // The block group table contains the block address of the inode table of the block group
-        let inode_table_block_addr = 
-            self.block_group_descriptor_table[block_group].inode_table_start_addr.0 as usize;
-        let inode_offset = 
-            (inode_table_block_addr * self.block_size) + (inode_index * core::mem::size_of::<Inode>());
-        
-        unsafe { 
-            &*(self.raw_bytes[inode_offset..].as_ptr() as *const Inode)
-        }
+        let bgd = self.read_block_group_descriptor(block_group)?;
+        let inode_table_block_addr = bgd.inode_table_start_addr.0 as u64;
+        let inode_offset =
+            (inode_table_block_addr * self.block_size as u64)
+            + (inode_index * core::mem::size_of::<Inode>()) as u64;
+
+        let mut buf = [0u8; core::mem::size_of::<Inode>()];
+        self.volume.read_at(inode_offset, &mut buf)?;
+        let metadata = unsafe { *(buf.as_ptr() as *const Inode) };
+
+        self.inode_cache.borrow_mut().insert(inode, metadata);
+        Ok(metadata)
+    }
+
+    /// Reads the block group descriptor of the block group numbered `group`
+    fn read_block_group_descriptor(&self, group: usize) -> Result<BlockGroupDescriptor, Error> {
+        let offset = self.block_group_descriptor_table_offset
+            + (group * core::mem::size_of::<BlockGroupDescriptor>()) as u64;
+
+        let mut buf = [0u8; core::mem::size_of::<BlockGroupDescriptor>()];
+        self.volume.read_at(offset, &mut buf)?;
+        Ok(unsafe { *(buf.as_ptr() as *const BlockGroupDescriptor) })
     }
 
-    /// Returns a byte slice of the data of the block at address `block`
-    fn get_block(&self, block: BlockAddr) -> &'a [u8] {
-        let offset = block.0 as usize * self.block_size;
-        &self.raw_bytes[offset..offset+self.block_size]
+    /// Reads the block at address `block` into `buf`, which must be at least `self.block_size` bytes
+    fn read_block(&self, block: BlockAddr, buf: &mut [u8]) -> Result<(), Error> {
+        let offset = block.0 as u64 * self.block_size as u64;
+        self.volume.read_at(offset, &mut buf[..self.block_size])
     }
 
-    // Returns a slice of the pointers inside the block at address `block`
-    fn get_ptrs_block(&self, block: BlockAddr) -> &'a [BlockAddr] {
-        unsafe { 
-            core::slice::from_raw_parts(
-                self.get_block(block).as_ptr() as *const BlockAddr,
-                self.num_ptrs_per_block
+    /// Reads the pointer block at address `block` into `out`, which must be at least
+    /// `self.num_ptrs_per_block` entries long. Served out of the [`PtrBlockCache`] when possible,
+    /// since large files and deep directories dereference the same pointer blocks repeatedly
+    fn read_ptrs_block(&self, block: BlockAddr, out: &mut [BlockAddr]) -> Result<(), Error> {
+        let out = &mut out[..self.num_ptrs_per_block];
+
+        // A zero pointer-block address means the whole range it would cover is unmapped
+        if block.0 == 0 {
+            for ptr in out.iter_mut() {
+                *ptr = BlockAddr(0);
+            }
+            return Ok(());
+        }
+
+        if let Some(ptrs) = self.ptr_block_cache.borrow_mut().get(block) {
+            out.copy_from_slice(&ptrs[..self.num_ptrs_per_block]);
+            return Ok(());
+        }
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(
+                out.as_mut_ptr() as *mut u8,
+                out.len() * core::mem::size_of::<BlockAddr>()
             )
+        };
+        self.read_block(block, bytes)?;
+
+        let mut ptrs = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+        ptrs[..self.num_ptrs_per_block].copy_from_slice(out);
+        self.ptr_block_cache.borrow_mut().insert(block, ptrs);
+
+        Ok(())
+    }
+
+    /// Translates a logical block index (the `n`th block belonging to an inode, as opposed to an
+    /// absolute address on the volume) into the physical [`BlockAddr`] it's stored at, walking the
+    /// inode's direct/indirect pointers (or its extent tree, if [`InodeFlags::ExtentsUsed`] is set)
+    /// directly to the relevant one instead of scanning from the start. Used both to jump straight
+    /// to an arbitrary offset in [`get_contents_with_offset`](Self::get_contents_with_offset) and to
+    /// resolve the logical leaf block numbers stored in an HTree index (see
+    /// [`lookup_via_htree`](Self::lookup_via_htree))
+    fn logical_block_to_addr(&self, inode_metadata: &Inode, logical_block: u32) -> Result<BlockAddr, Error> {
+        if (inode_metadata.flags & InodeFlags::ExtentsUsed as u32) != 0 {
+            let root_bytes = extent_tree_root_bytes(inode_metadata);
+            return Ok(self.extent_block_to_addr(&root_bytes, logical_block)?.unwrap_or(BlockAddr(0)));
+        }
+
+        let mut logical_block = logical_block as usize;
+        if logical_block < INODE_DIRECT_PTR_COUNT {
+            return Ok(inode_metadata.direct_pointers[logical_block]);
+        }
+        logical_block -= INODE_DIRECT_PTR_COUNT;
+
+        if logical_block < self.num_ptrs_per_block {
+            let mut ptrs = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+            self.read_ptrs_block(inode_metadata.singly_indirect_pointer, &mut ptrs)?;
+            return Ok(ptrs[logical_block]);
+        }
+        logical_block -= self.num_ptrs_per_block;
+
+        if logical_block < self.num_ptrs_per_block * self.num_ptrs_per_block {
+            let mut outer = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+            self.read_ptrs_block(inode_metadata.doubly_indirect_pointer, &mut outer)?;
+            let mut inner = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+            self.read_ptrs_block(outer[logical_block / self.num_ptrs_per_block], &mut inner)?;
+            return Ok(inner[logical_block % self.num_ptrs_per_block]);
         }
+        logical_block -= self.num_ptrs_per_block * self.num_ptrs_per_block;
+
+        let mut outer = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+        self.read_ptrs_block(inode_metadata.triply_indirect_pointer, &mut outer)?;
+        let mut middle = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+        self.read_ptrs_block(outer[logical_block / (self.num_ptrs_per_block * self.num_ptrs_per_block)], &mut middle)?;
+        let logical_block = logical_block % (self.num_ptrs_per_block * self.num_ptrs_per_block);
+        let mut inner = [BlockAddr(0); MAX_PTRS_PER_BLOCK];
+        self.read_ptrs_block(middle[logical_block / self.num_ptrs_per_block], &mut inner)?;
+        Ok(inner[logical_block % self.num_ptrs_per_block])
     }
 
-    /// Calls the `callback` for each block pointed to by the pointers in the pointer block `block`.
-    /// The callback will be called with a byte slice of the block's content
-    fn for_each_indirect_block<F>(&self, block: BlockAddr, callback: &mut F)
-        where F: FnMut(&'a [u8]) -> IterationDecision {
-        let ptrs = self.get_ptrs_block(block);
-        for &direct_ptr in ptrs {
-            if direct_ptr.0 == 0 {
-                return;
+    /// Finds the physical block that `logical_block` maps to by walking the extent tree rooted at
+    /// `node_bytes`, recursing into child index blocks as needed. Returns `None` if no extent
+    /// covers `logical_block` (a hole), or if a node's magic is invalid or the tree is deeper than
+    /// [`MAX_EXTENT_DEPTH`]
+    fn extent_block_to_addr(&self, node_bytes: &[u8], logical_block: u32) -> Result<Option<BlockAddr>, Error> {
+        self.extent_block_to_addr_impl(node_bytes, logical_block, 0)
+    }
+
+    fn extent_block_to_addr_impl(&self, node_bytes: &[u8], logical_block: u32, depth: u32)
+        -> Result<Option<BlockAddr>, Error> {
+        if depth > MAX_EXTENT_DEPTH {
+            return Ok(None);
+        }
+
+        let header = unsafe { &*(node_bytes.as_ptr() as *const ExtentHeader) };
+        if header.magic != EXTENT_MAGIC {
+            return Ok(None);
+        }
+        let entries_offset = core::mem::size_of::<ExtentHeader>();
+
+        if header.depth == 0 {
+            let count = match validated_extent_entry_count(
+                header, node_bytes, entries_offset, core::mem::size_of::<ExtentLeaf>()) {
+                Some(count) => count,
+                None => return Ok(None),
+            };
+            for i in 0..count {
+                let entry = unsafe {
+                    *(node_bytes[entries_offset + i * core::mem::size_of::<ExtentLeaf>()..].as_ptr()
+                        as *const ExtentLeaf)
+                };
+                if logical_block >= entry.logical_block
+                    && logical_block < entry.logical_block + entry.len as u32 {
+                    let start = ((entry.start_hi as u64) << 32) | entry.start_lo as u64;
+                    let addr = start + (logical_block - entry.logical_block) as u64;
+                    return Ok(Some(BlockAddr(addr as u32)));
+                }
             }
+            return Ok(None);
+        }
+
+        let count = match validated_extent_entry_count(
+            header, node_bytes, entries_offset, core::mem::size_of::<ExtentIndex>()) {
+            Some(count) => count,
+            None => return Ok(None),
+        };
 
-            if callback(self.get_block(direct_ptr)) == IterationDecision::Break {
-                return;
+        // Entries are sorted by logical_block, so the entry covering logical_block is the last one
+        // whose logical_block is not greater than it
+        let mut chosen: Option<ExtentIndex> = None;
+        for i in 0..count {
+            let entry = unsafe {
+                *(node_bytes[entries_offset + i * core::mem::size_of::<ExtentIndex>()..].as_ptr()
+                    as *const ExtentIndex)
+            };
+            if entry.logical_block > logical_block {
+                break;
             }
+            chosen = Some(entry);
         }
-    }
+        let entry = match chosen {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
 
-    /// Calls the `callback` for each block eventually pointed to by the pointers in the indirect
-    /// pointers block `block`. The callback will be called with a byte slice of the block's content
-    fn for_each_doubly_indirect_block<F>(&self, block: BlockAddr, callback: &mut F)
-        where F: FnMut(&'a [u8]) -> IterationDecision {
-        let ptrs = self.get_ptrs_block(block);
+        let child_addr = BlockAddr((((entry.leaf_hi as u64) << 32) | entry.leaf_lo as u64) as u32);
+        let mut child_block = [0u8; MAX_BLOCK_SIZE];
+        let child_block = &mut child_block[..self.block_size];
+        self.read_block(child_addr, child_block)?;
+
+        self.extent_block_to_addr_impl(child_block, logical_block, depth + 1)
+    }
 
-        for &ptr in ptrs {
-            if ptr.0 == 0 {
-                return;
+    /// Looks up `name` in the directory whose inode number is `dir_inode`, returning its inode
+    /// number and entry type if found. Uses the directory's HTree hash index for an O(log n)
+    /// lookup when the filesystem and the directory both support it, falling back to a linear scan
+    /// via [`for_each_directory_entry`](Self::for_each_directory_entry) otherwise
+    pub fn lookup_in_directory(&self, dir_inode: u32, name: &str) -> Result<Option<(u32, DirEntryType)>, Error> {
+        let inode_metadata = self.get_inode(dir_inode)?;
+        assert!(inode_metadata.get_type() == InodeType::Directory);
+
+        let htree_enabled =
+            (self.super_block_extension.optional_feature_flags & OptionalFeatureFlags::HashedDirectoryIndex as u32) != 0
+            && (inode_metadata.flags & InodeFlags::BTreeOrHashIndexedDirectory as u32) != 0;
+
+        if htree_enabled {
+            if let Some(result) = self.lookup_via_htree(&inode_metadata, name)? {
+                return Ok(result);
             }
-            self.for_each_indirect_block(ptr, callback);
+            // Fall through to a linear scan if we don't understand this directory's index layout
         }
+
+        self.directory(dir_inode)?
+            .find_map(|entry| match entry {
+                Ok((entry_inode, entry_name, entry_type)) if entry_name.as_str() == name =>
+                    Some(Ok((entry_inode, entry_type))),
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .transpose()
     }
 
-    /// Calls the `callback` for each block eventually pointed to by the pointers in the doubly
-    /// indirect pointers block `block`. The callback will be called with a byte slice of the
-    /// block's content
-    fn for_each_triply_indirect_block<F>(&self, block: BlockAddr, callback: &mut F)
-        where F: FnMut(&'a [u8]) -> IterationDecision {
-        let ptrs = self.get_ptrs_block(block);
+    /// Attempts an HTree index lookup of `name` in the directory described by `inode_metadata`.
+    /// Returns `Ok(None)` (note the outer `Option`) if the index uses a hash version or an
+    /// indirection depth we don't support, so the caller can fall back to a linear scan
+    fn lookup_via_htree(&self, inode_metadata: &Inode, name: &str)
+        -> Result<Option<Option<(u32, DirEntryType)>>, Error> {
+        let mut root_block = [0u8; MAX_BLOCK_SIZE];
+        let root_block = &mut root_block[..self.block_size];
+        self.read_block(self.logical_block_to_addr(inode_metadata, 0)?, root_block)?;
+
+        let root_info = unsafe {
+            &*(root_block[HTREE_ROOT_INFO_OFFSET..].as_ptr() as *const HTreeRootInfo)
+        };
+        if root_info.indirect_levels > 1 {
+            return Ok(None);
+        }
+
+        let target_hash = match compute_dir_hash(root_info.hash_version, name.as_bytes(), self.super_block_extension.hash_seed) {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+
+        let entries_offset = HTREE_ROOT_INFO_OFFSET + core::mem::size_of::<HTreeRootInfo>()
+            + core::mem::size_of::<HTreeEntryCountHeader>();
+        let count_header = unsafe {
+            &*(root_block[HTREE_ROOT_INFO_OFFSET + core::mem::size_of::<HTreeRootInfo>()..].as_ptr()
+                as *const HTreeEntryCountHeader)
+        };
+        let root_entries = match htree_entries(root_block, entries_offset, count_header) {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+
+        let mut leaf_block = find_htree_entry(root_entries, target_hash).block;
+
+        if root_info.indirect_levels == 1 {
+            let index_block_addr = self.logical_block_to_addr(inode_metadata, leaf_block)?;
+            let mut index_block = [0u8; MAX_BLOCK_SIZE];
+            let index_block = &mut index_block[..self.block_size];
+            self.read_block(index_block_addr, index_block)?;
+
+            let count_header = unsafe { &*(index_block.as_ptr() as *const HTreeEntryCountHeader) };
+            let entries = match htree_entries(
+                index_block, core::mem::size_of::<HTreeEntryCountHeader>(), count_header) {
+                Some(entries) => entries,
+                None => return Ok(None),
+            };
+            leaf_block = find_htree_entry(entries, target_hash).block;
+        }
+
+        let leaf_block_addr = self.logical_block_to_addr(inode_metadata, leaf_block)?;
+        let mut leaf = [0u8; MAX_BLOCK_SIZE];
+        let leaf = &mut leaf[..self.block_size];
+        self.read_block(leaf_block_addr, leaf)?;
+
+        let mut curr_offset = 0;
+        while curr_offset < leaf.len() {
+            let dir_entry = unsafe { &*(leaf[curr_offset..].as_ptr() as *const DirectoryEntry) };
+            if dir_entry.size == 0 {
+                break;
+            }
 
-        for &ptr in ptrs {
-            if ptr.0 == 0 {
-                return;
+            if dir_entry.inode != 0 {
+                let entry_name = read_entry_name(leaf, curr_offset, dir_entry.name_length);
+                if entry_name.as_str() == name {
+                    return Ok(Some(Some((dir_entry.inode, dir_entry.type_indicator))));
+                }
             }
-            self.for_each_doubly_indirect_block(ptr, callback);
+
+            curr_offset += dir_entry.size as usize;
         }
+
+        Ok(Some(None))
+    }
+
+}
+
+/// Copies a directory entry's name out of its containing data block into an owned [`FileName`]
+fn read_entry_name(data_block: &[u8], entry_offset: usize, name_length: u8) -> FileName {
+    let name_offset = entry_offset + core::mem::size_of::<DirectoryEntry>();
+    let mut bytes = [0u8; MAX_FILE_NAME_LEN];
+    bytes[..name_length as usize].copy_from_slice(&data_block[name_offset..name_offset+name_length as usize]);
+    FileName { bytes, len: name_length }
+}
+
+/// Header at the start of an extended attribute block, pointed to by [`Inode::extended_attributes_block`]
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct XattrHeader {
+    /// Must equal [`XATTR_BLOCK_MAGIC`] for the block to be a valid xattr block
+    magic: u32,
+    /// Number of inodes referencing this (possibly shared) xattr block
+    reference_count: u32,
+    /// Number of disk blocks used to hold this xattr block, currently always 1
+    block_count: u32,
+    hash: u32,
+    _reserved: [u32; 4],
+}
+
+/// A single entry in an extended attribute block's entry array, which immediately follows the
+/// [`XattrHeader`]. The entry's name is stored directly after it (`name_length` bytes, not
+/// null-terminated), and its value lives at `value_offset` bytes from the start of the block,
+/// growing down from the end of the block as more attributes are added
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct XattrEntry {
+    name_length: u8,
+    /// Selects the namespace prefix prepended to the stored name, see [`xattr_name_prefix`]
+    name_index: u8,
+    value_offset: u16,
+    value_block: u32,
+    value_size: u32,
+    hash: u32,
+}
+
+/// Returns the namespace prefix a [`XattrEntry::name_index`] maps to, prepended to the entry's
+/// stored name to reconstruct its full attribute name. Unrecognized indices map to an empty prefix
+fn xattr_name_prefix(name_index: u8) -> &'static str {
+    match name_index {
+        1 => "user.",
+        2 => "system.posix_acl_access",
+        3 => "system.posix_acl_default",
+        4 => "trusted.",
+        6 => "security.",
+        7 => "system.",
+        _ => "",
     }
 }
 
+/// Byte offset, from the start of a hash-indexed directory's first data block, of the HTree root
+/// info. The fake `.` and `..` entries that precede it always take up exactly 12 bytes each
+const HTREE_ROOT_INFO_OFFSET: usize = 24;
+
+/// The magic value at the start of an [`ExtentHeader`]
+const EXTENT_MAGIC: u16 = 0xF30A;
+/// The deepest an extent tree is allowed to be before we give up on it as malformed, guarding
+/// against cycles
+const MAX_EXTENT_DEPTH: u32 = 5;
+/// The size, in bytes, of the inode area an extent tree's root is stored in, in place of
+/// [`Inode::direct_pointers`] and the three indirect pointers that follow it
+const EXTENT_INLINE_AREA_LEN: usize =
+    (INODE_DIRECT_PTR_COUNT + 3) * core::mem::size_of::<BlockAddr>();
+
+/// Header at the start of an extent tree node (either the inline root stored in an inode's
+/// direct/indirect pointer area, or an index block pointed to by an [`ExtentIndex`])
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct ExtentHeader {
+    /// Must equal [`EXTENT_MAGIC`] for the node to be a valid extent node
+    magic: u16,
+    /// Number of valid entries ([`ExtentLeaf`] if `depth == 0`, otherwise [`ExtentIndex`])
+    /// following this header
+    entries: u16,
+    /// Maximum number of entries that could fit in this node
+    max: u16,
+    /// `0` if the entries following this header are leaves, otherwise the number of index levels
+    /// above the leaves
+    depth: u16,
+    generation: u32,
+}
+
+/// A leaf entry in an extent tree, present when its node's [`ExtentHeader::depth`] is `0`. Maps
+/// `len` contiguous logical blocks starting at `logical_block` to physical blocks starting at
+/// `(start_hi << 32) | start_lo`
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct ExtentLeaf {
+    logical_block: u32,
+    len: u16,
+    start_hi: u16,
+    start_lo: u32,
+}
+
+/// An index entry in an extent tree, present when its node's [`ExtentHeader::depth`] is greater
+/// than `0`. Points to the child node, itself starting with an [`ExtentHeader`], that covers
+/// logical blocks from `logical_block` onwards
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct ExtentIndex {
+    logical_block: u32,
+    leaf_lo: u32,
+    leaf_hi: u16,
+    _unused: u16,
+}
+
+/// Validates `header.entries` against `header.max` and against how many entries of `entry_size`
+/// bytes actually fit in `node_bytes` starting at `entries_offset`, returning the validated count,
+/// or `None` if a corrupted or hostile on-disk `entries` would read out of bounds. `entries` and
+/// `max` are untrusted on-disk fields, so [`Ext2Parser::extent_block_to_addr_impl`] must go through
+/// this instead of indexing `node_bytes` directly
+fn validated_extent_entry_count(header: &ExtentHeader, node_bytes: &[u8], entries_offset: usize, entry_size: usize)
+    -> Option<usize> {
+    let count = header.entries as usize;
+    if count > header.max as usize {
+        return None;
+    }
+
+    let entries_size = count.checked_mul(entry_size)?;
+    if entries_offset.checked_add(entries_size)? > node_bytes.len() {
+        return None;
+    }
+
+    Some(count)
+}
+
+/// Returns the 60-byte extent-tree root stored in `inode_metadata`'s direct/indirect pointer area,
+/// as raw bytes to be reinterpreted starting with an [`ExtentHeader`]
+fn extent_tree_root_bytes(inode_metadata: &Inode) -> [u8; EXTENT_INLINE_AREA_LEN] {
+    let mut bytes = [0u8; EXTENT_INLINE_AREA_LEN];
+    let src = unsafe {
+        core::slice::from_raw_parts(
+            &inode_metadata.direct_pointers as *const _ as *const u8,
+            EXTENT_INLINE_AREA_LEN
+        )
+    };
+    bytes.copy_from_slice(src);
+    bytes
+}
+
+/// Header of the HTree root info, present at [`HTREE_ROOT_INFO_OFFSET`] in a hash-indexed
+/// directory's first data block
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct HTreeRootInfo {
+    _reserved: u32,
+    /// The directory-hash algorithm used to build this tree, see [`compute_dir_hash`]
+    hash_version: u8,
+    /// The size, in bytes, of this structure, used to locate the entry-count header that follows it
+    info_length: u8,
+    /// `0` if the root block's entries point directly at leaf blocks, `1` if they point at another
+    /// level of index blocks
+    indirect_levels: u8,
+    _unused_flags: u8,
+}
+
+/// Header preceding the sorted array of [`HTreeIndexEntry`] in an HTree root or index block,
+/// giving the number of entries currently in use (`count`) out of the number that would fit
+/// (`limit`)
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct HTreeEntryCountHeader {
+    limit: u16,
+    count: u16,
+}
+
+/// A single entry in a sorted HTree index block. `block` is a *logical* block number within the
+/// directory inode's data blocks (see [`Ext2Parser::logical_block_to_addr`]), not an absolute
+/// address. The first entry of every index block is special: its `hash` is unused and it always
+/// matches, since it covers every hash smaller than the second entry's
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct HTreeIndexEntry {
+    hash: u32,
+    block: u32,
+}
+
+/// Computes the directory-hash of `name` used to navigate an HTree directory index. `version` is
+/// the directory's [`HTreeRootInfo::hash_version`] and `seed` is the filesystem's
+/// [`SuperBlockExtension::hash_seed`]. Returns `None` for hash versions we don't recognize
+fn compute_dir_hash(version: u8, name: &[u8], seed: [u32; 4]) -> Option<u32> {
+    match version {
+        0 => Some(hash_legacy(name)),
+        1 => Some(hash_half_md4(name, seed)),
+        2 => Some(hash_tea(name, seed)),
+        // Unrecognized hash version (including the unsigned variants 3-5): the caller falls back
+        // to a linear scan
+        _ => None,
+    }
+}
+
+/// The original, simple ext2 directory-hash algorithm (HTree hash version 0)
+fn hash_legacy(name: &[u8]) -> u32 {
+    let mut hash0: u32 = 0x12a3fe2d;
+    let mut hash1: u32 = 0x37abe8f9;
+
+    for &byte in name {
+        let hash = hash1.wrapping_add(hash0 ^ (byte as u32).wrapping_mul(7152373));
+        let hash = if hash & 0x80000000 != 0 { hash.wrapping_sub(0x7fffffff) } else { hash };
+        hash1 = hash0;
+        hash0 = hash;
+    }
+
+    hash0 << 1
+}
+
+/// Packs up to `num_words * 4` bytes of `name` into `num_words` big-endian-ish words the way
+/// `half_md4`/`tea` expect, padding the final word (and any words beyond the name's length) with a
+/// value derived from `name`'s length
+fn str_to_hash_buf(name: &[u8], out: &mut [u32]) {
+    let pad = (name.len() as u32) | ((name.len() as u32) << 8);
+    let pad = pad | (pad << 16);
+
+    let len = name.len().min(out.len() * 4);
+    let mut val = pad;
+    let mut word_idx = 0;
+    for (i, &byte) in name[..len].iter().enumerate() {
+        if i % 4 == 0 {
+            val = pad;
+        }
+        val = (byte as u32).wrapping_add(val << 8);
+        if i % 4 == 3 {
+            out[word_idx] = val;
+            word_idx += 1;
+            val = pad;
+        }
+    }
+    if word_idx < out.len() {
+        out[word_idx] = val;
+        word_idx += 1;
+    }
+    while word_idx < out.len() {
+        out[word_idx] = pad;
+        word_idx += 1;
+    }
+}
+
+/// The "half MD4" directory-hash algorithm (HTree hash version 1): MD4's core transform run over
+/// 8-word (32 byte) chunks of the name, seeded with the filesystem's hash seed
+fn hash_half_md4(name: &[u8], seed: [u32; 4]) -> u32 {
+    let mut buf = seed;
+
+    let mut offset = 0;
+    loop {
+        let chunk = &name[offset..];
+        let mut in_words = [0u32; 8];
+        str_to_hash_buf(chunk, &mut in_words);
+        half_md4_transform(&mut buf, &in_words);
+
+        if chunk.len() <= 32 {
+            break;
+        }
+        offset += 32;
+    }
+
+    buf[1]
+}
+
+/// The MD4-derived round functions used by [`half_md4_transform`]
+fn md4_f(x: u32, y: u32, z: u32) -> u32 { z ^ (x & (y ^ z)) }
+fn md4_g(x: u32, y: u32, z: u32) -> u32 { (x & y).wrapping_add((x ^ y) & z) }
+fn md4_h(x: u32, y: u32, z: u32) -> u32 { x ^ y ^ z }
+
+/// Three rounds of MD4's compression function, omitting its final output-mixing round (hence
+/// "half"), as used by ext2's HTree hash version 1
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    const K2: u32 = 0x5A827999;
+    const K3: u32 = 0x6ED9EBA1;
+
+    let (mut a, mut b, mut c, mut d) = (buf[0], buf[1], buf[2], buf[3]);
+
+    macro_rules! round {
+        ($f:ident, $a:ident, $b:ident, $c:ident, $d:ident, $x:expr, $s:expr) => {
+            $a = $a.wrapping_add($f($b, $c, $d)).wrapping_add($x).rotate_left($s);
+        };
+    }
+
+    round!(md4_f, a, b, c, d, input[0], 3);
+    round!(md4_f, d, a, b, c, input[1], 7);
+    round!(md4_f, c, d, a, b, input[2], 11);
+    round!(md4_f, b, c, d, a, input[3], 19);
+    round!(md4_f, a, b, c, d, input[4], 3);
+    round!(md4_f, d, a, b, c, input[5], 7);
+    round!(md4_f, c, d, a, b, input[6], 11);
+    round!(md4_f, b, c, d, a, input[7], 19);
+
+    round!(md4_g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    round!(md4_g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    round!(md4_g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    round!(md4_g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    round!(md4_g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    round!(md4_g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    round!(md4_g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    round!(md4_g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    round!(md4_h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    round!(md4_h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    round!(md4_h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    round!(md4_h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    round!(md4_h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    round!(md4_h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    round!(md4_h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    round!(md4_h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+/// The TEA (Tiny Encryption Algorithm) directory-hash algorithm (HTree hash version 2): TEA's
+/// block cipher run as a compression function over 4-word (16 byte) chunks of the name, seeded
+/// with the filesystem's hash seed
+fn hash_tea(name: &[u8], seed: [u32; 4]) -> u32 {
+    let mut buf = seed;
+
+    let mut offset = 0;
+    loop {
+        let chunk = &name[offset..];
+        let mut in_words = [0u32; 4];
+        str_to_hash_buf(chunk, &mut in_words);
+        tea_transform(&mut buf, &in_words);
+
+        if chunk.len() <= 16 {
+            break;
+        }
+        offset += 16;
+    }
+
+    buf[0]
+}
+
+/// TEA's round function, run as a compression function (rather than a cipher) by only keeping
+/// the first two words of the running state, as used by ext2's HTree hash version 2
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9E3779B9;
+
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let (a, b, c, d) = (input[0], input[1], input[2], input[3]);
+    let mut sum: u32 = 0;
+
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            (b1 << 4).wrapping_add(a) ^ b1.wrapping_add(sum) ^ (b1 >> 5).wrapping_add(b)
+        );
+        b1 = b1.wrapping_add(
+            (b0 << 4).wrapping_add(c) ^ b0.wrapping_add(sum) ^ (b0 >> 5).wrapping_add(d)
+        );
+    }
+
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+/// Validates `count_header.count` against `count_header.limit` and against how many
+/// [`HTreeIndexEntry`] actually fit in `node_bytes` starting at `entries_offset`, returning the
+/// validated slice, or `None` if a corrupted or hostile on-disk `count` would read out of bounds.
+/// `count` and `limit` are untrusted on-disk fields, so callers must go through this instead of
+/// building the slice directly - the caller falls back to a linear scan when this returns `None`
+fn htree_entries<'a>(node_bytes: &'a [u8], entries_offset: usize, count_header: &HTreeEntryCountHeader)
+    -> Option<&'a [HTreeIndexEntry]> {
+    let count = count_header.count as usize;
+    if count == 0 || count > count_header.limit as usize {
+        return None;
+    }
+
+    let entries_size = count.checked_mul(core::mem::size_of::<HTreeIndexEntry>())?;
+    if entries_offset.checked_add(entries_size)? > node_bytes.len() {
+        return None;
+    }
+
+    Some(unsafe {
+        core::slice::from_raw_parts(node_bytes[entries_offset..].as_ptr() as *const HTreeIndexEntry, count)
+    })
+}
+
+/// Binary-searches a sorted HTree index block's `entries` for the one with the largest `hash` that
+/// is `<= target`. Entry 0's hash is a sentinel that is always considered to match (see
+/// [`HTreeIndexEntry`]), so the search range always starts there and `entries` must be non-empty
+/// (guaranteed by [`htree_entries`], the only place that builds one)
+fn find_htree_entry(entries: &[HTreeIndexEntry], target: u32) -> HTreeIndexEntry {
+    let mut lo = 0usize;
+    let mut hi = entries.len();
+    while lo + 1 < hi {
+        let mid = lo + (hi - lo) / 2;
+        if entries[mid].hash <= target {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    entries[lo]
+}
+
 /// Calculates the integer division `x/y` while rounding towards the ceiling. Returns `None` if y is
 /// zero
 fn div_ceil(x: u32, y: u32) -> Option<u32> {
@@ -811,6 +2350,71 @@ fn div_ceil(x: u32, y: u32) -> Option<u32> {
     Some(1 + ((x - 1) / y))
 }
 
+/// Returns `true` if block group `group` (0-indexed) is one of the groups that keeps a backup
+/// superblock copy when the sparse superblock feature is enabled: groups 0 and 1, and every group
+/// that is a power of 3, 5 or 7
+fn is_backup_superblock_group(group: u32) -> bool {
+    group == 0 || group == 1
+        || is_power_of(group, 3) || is_power_of(group, 5) || is_power_of(group, 7)
+}
+
+/// Returns `true` if `n` is an integer power of `base` (`base >= 2`)
+fn is_power_of(n: u32, base: u32) -> bool {
+    if n == 0 {
+        return false;
+    }
+
+    let mut cur = 1u32;
+    while cur < n {
+        cur *= base;
+    }
+    cur == n
+}
+
+/// Joins a symlink's `target` with whatever path components (`rest`) still need to be resolved
+/// after it, the way [`Ext2Parser::resolve_path_to_inode_impl`] does when it follows a symlink in
+/// the middle of a path: `rest`, if any, is appended after a separating `/`. Writes into `out` and
+/// returns the number of bytes written. Panics if `out` is too small to hold both; callers pass a
+/// buffer of [`MAX_EXPANDED_SYMLINK_PATH_LEN`] bytes
+fn expand_symlink_path(target: &str, rest: &str, out: &mut [u8]) -> usize {
+    assert!(target.len() + 1 + rest.len() <= out.len());
+
+    let mut len = target.len();
+    out[..len].copy_from_slice(target.as_bytes());
+    if !rest.is_empty() {
+        out[len] = b'/';
+        len += 1;
+        out[len..len + rest.len()].copy_from_slice(rest.as_bytes());
+        len += rest.len();
+    }
+    len
+}
+
+/// Counts the number of clear (zero) bits among the first `num_bits` bits of `buf`, used by
+/// [`Ext2Parser::verify`] to cross-check a block group's usage bitmaps against its descriptor
+fn count_clear_bits(buf: &[u8], num_bits: u32) -> u32 {
+    let mut clear_count = 0u32;
+    for bit in 0..num_bits as usize {
+        if (buf[bit / 8] >> (bit % 8)) & 1 == 0 {
+            clear_count += 1;
+        }
+    }
+    clear_count
+}
+
+/// Metadata produced by successfully validating a superblock copy, carrying everything
+/// [`Ext2Parser::from_meta`] needs to finish building a parser, without requiring access to the
+/// [`Volume`] itself
+struct ParsedSuperBlockMeta {
+    super_block: SuperBlock,
+    super_block_extension: SuperBlockExtension,
+    block_group_descriptor_table_offset: u64,
+    block_size: usize,
+    block_group_count: u32,
+    read_only: bool,
+    source: SuperBlockSource,
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -820,24 +2424,166 @@ mod tests {
     #[test]
     fn it_works() {
         let file = std::fs::read("test_ext2_1024.fs").unwrap();
-        let parser = Ext2Parser::parse(&file).unwrap();
+        let parser = Ext2Parser::parse(RamVolume::new(&file)).unwrap();
 
         parser.for_each_directory_entry(2, |inode, name, entry_type| {
             std::println!("{:#?} {:#?} {:#?}", inode, name, entry_type);
             IterationDecision::Continue
-        });
+        }).unwrap();
         let mut buffer = [0u8; 4096];
-        let length = parser.get_contents(15, &mut buffer);
+        let length = parser.get_contents(15, &mut buffer).unwrap();
         let contents = &buffer[..length];
         std::println!("{:?}", contents);
         parser.for_each_directory_entry(12, |inode, name, entry_type| {
             std::println!("{:#?} {:#?} {:#?}", inode, name, entry_type);
             IterationDecision::Continue
-        });
+        }).unwrap();
 
         panic!();
 
         std::println!("{:#?}", parser);
         panic!();
     }
+
+    #[test]
+    fn count_clear_bits_counts_only_zero_bits() {
+        // 0b1010_1100 0b0000_1111: bits are read LSB-first within each byte
+        let buf = [0b1010_1100u8, 0b0000_1111u8];
+        assert!(count_clear_bits(&buf, 16) == 8);
+    }
+
+    #[test]
+    fn count_clear_bits_only_looks_at_num_bits() {
+        // The second byte is all clear, but it's past `num_bits` so it shouldn't count
+        let buf = [0b1010_1100u8, 0b0000_0000u8];
+        assert!(count_clear_bits(&buf, 8) == 4);
+    }
+
+    #[test]
+    fn compute_dir_hash_maps_known_versions() {
+        let seed = [0u32; 4];
+        assert!(compute_dir_hash(0, b"foo", seed).is_some());
+        assert!(compute_dir_hash(1, b"foo", seed).is_some());
+        assert!(compute_dir_hash(2, b"foo", seed).is_some());
+    }
+
+    #[test]
+    fn compute_dir_hash_rejects_unrecognized_versions() {
+        let seed = [0u32; 4];
+        // 3-5 are the unsigned-hash variants this implementation falls back to a linear scan for
+        for version in [3, 4, 5, 255] {
+            assert!(compute_dir_hash(version, b"foo", seed).is_none());
+        }
+    }
+
+    #[test]
+    fn hash_legacy_is_deterministic_and_sensitive_to_input() {
+        assert!(hash_legacy(b"foo") == hash_legacy(b"foo"));
+        assert!(hash_legacy(b"foo") != hash_legacy(b"bar"));
+    }
+
+    #[test]
+    fn hash_half_md4_is_deterministic_and_sensitive_to_seed() {
+        let a = hash_half_md4(b"foo", [1, 2, 3, 4]);
+        let b = hash_half_md4(b"foo", [1, 2, 3, 4]);
+        let c = hash_half_md4(b"foo", [5, 6, 7, 8]);
+        assert!(a == b);
+        assert!(a != c);
+    }
+
+    #[test]
+    fn hash_tea_is_deterministic_and_sensitive_to_seed() {
+        let a = hash_tea(b"foo", [1, 2, 3, 4]);
+        let b = hash_tea(b"foo", [1, 2, 3, 4]);
+        let c = hash_tea(b"foo", [5, 6, 7, 8]);
+        assert!(a == b);
+        assert!(a != c);
+    }
+
+    #[test]
+    fn expand_symlink_path_appends_remaining_components() {
+        let mut out = [0u8; MAX_EXPANDED_SYMLINK_PATH_LEN];
+        let len = expand_symlink_path("target/dir", "rest/of/path", &mut out);
+        assert!(core::str::from_utf8(&out[..len]).unwrap() == "target/dir/rest/of/path");
+    }
+
+    #[test]
+    fn expand_symlink_path_with_no_remaining_components_is_just_the_target() {
+        let mut out = [0u8; MAX_EXPANDED_SYMLINK_PATH_LEN];
+        let len = expand_symlink_path("target/dir", "", &mut out);
+        assert!(core::str::from_utf8(&out[..len]).unwrap() == "target/dir");
+    }
+
+    #[test]
+    fn expand_symlink_path_keeps_an_absolute_target_absolute() {
+        // `resolve_path_to_inode_impl` treats a leading `/` as absolute regardless of where the
+        // expanded path came from, so an absolute symlink target must stay untouched up front
+        let mut out = [0u8; MAX_EXPANDED_SYMLINK_PATH_LEN];
+        let len = expand_symlink_path("/abs/target", "rest", &mut out);
+        assert!(core::str::from_utf8(&out[..len]).unwrap() == "/abs/target/rest");
+    }
+
+    #[test]
+    fn htree_entries_accepts_a_count_that_fits() {
+        let node = [0u8; 64];
+        let count_header = HTreeEntryCountHeader { limit: 4, count: 2 };
+        let entries = htree_entries(&node, 0, &count_header);
+        assert!(entries.unwrap().len() == 2);
+    }
+
+    #[test]
+    fn htree_entries_rejects_a_count_over_limit() {
+        let node = [0u8; 64];
+        let count_header = HTreeEntryCountHeader { limit: 4, count: 5 };
+        assert!(htree_entries(&node, 0, &count_header).is_none());
+    }
+
+    #[test]
+    fn htree_entries_rejects_a_zero_count() {
+        let node = [0u8; 64];
+        let count_header = HTreeEntryCountHeader { limit: 4, count: 0 };
+        assert!(htree_entries(&node, 0, &count_header).is_none());
+    }
+
+    #[test]
+    fn htree_entries_rejects_a_count_that_overruns_the_buffer() {
+        // `limit` alone doesn't bound us here - a corrupt/hostile count within `limit` that still
+        // doesn't fit in the actual block must be rejected too
+        let node = [0u8; 16];
+        let count_header = HTreeEntryCountHeader { limit: 100, count: 100 };
+        assert!(htree_entries(&node, 0, &count_header).is_none());
+    }
+
+    fn extent_header(entries: u16, max: u16) -> ExtentHeader {
+        ExtentHeader { magic: EXTENT_MAGIC, entries, max, depth: 0, generation: 0 }
+    }
+
+    #[test]
+    fn validated_extent_entry_count_accepts_a_count_that_fits() {
+        let header = extent_header(2, 4);
+        let node = [0u8; 64];
+        let count = validated_extent_entry_count(
+            &header, &node, core::mem::size_of::<ExtentHeader>(), core::mem::size_of::<ExtentLeaf>());
+        assert!(count == Some(2));
+    }
+
+    #[test]
+    fn validated_extent_entry_count_rejects_a_count_over_max() {
+        let header = extent_header(5, 4);
+        let node = [0u8; 64];
+        let count = validated_extent_entry_count(
+            &header, &node, core::mem::size_of::<ExtentHeader>(), core::mem::size_of::<ExtentLeaf>());
+        assert!(count.is_none());
+    }
+
+    #[test]
+    fn validated_extent_entry_count_rejects_a_count_that_overruns_the_buffer() {
+        // `max` alone doesn't bound us here - a corrupt/hostile count within `max` that still
+        // doesn't fit in the actual block must be rejected too
+        let header = extent_header(100, 200);
+        let node = [0u8; 16];
+        let count = validated_extent_entry_count(
+            &header, &node, core::mem::size_of::<ExtentHeader>(), core::mem::size_of::<ExtentLeaf>());
+        assert!(count.is_none());
+    }
 }
@@ -1,10 +1,10 @@
-use ext2_parser::Ext2Parser;
+use ext2_parser::{Ext2Parser, RamVolume};
 use lock_cell::LockCell;
 
 use crate::RAM_EXT2_FS;
 
-pub static EXT2_PARSER: LockCell<Option<Ext2Parser>> = LockCell::new(None);
+pub static EXT2_PARSER: LockCell<Option<Ext2Parser<RamVolume<'static>>>> = LockCell::new(None);
 
 pub fn init() {
-	*EXT2_PARSER.lock() = Ext2Parser::parse(RAM_EXT2_FS);
-}
\ No newline at end of file
+	*EXT2_PARSER.lock() = Ext2Parser::parse(RamVolume::new(RAM_EXT2_FS));
+}
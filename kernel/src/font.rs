@@ -0,0 +1,101 @@
+//! A minimal built-in 8x16 bitmap font for `screen`'s framebuffer console backend
+
+/// Width, in pixels, of a single glyph
+pub const GLYPH_WIDTH: usize = 8;
+/// Height, in pixels, of a single glyph
+pub const GLYPH_HEIGHT: usize = 16;
+
+/// The underlying glyph shapes are only drawn at 8 rows of resolution; [`FONT`] stretches each one
+/// to [`GLYPH_HEIGHT`] by duplicating every row, which is cheaper than hand-drawing a true 8x16 set
+/// and still reads fine at normal framebuffer resolutions
+const BASE_GLYPH_HEIGHT: usize = 8;
+
+/// Glyph bitmaps, indexed by ASCII byte value. Each glyph is `GLYPH_HEIGHT` rows of `GLYPH_WIDTH`
+/// bits, most-significant bit first (leftmost pixel). This only covers the characters the kernel
+/// actually prints (digits, letters and a handful of punctuation); anything else falls back to a
+/// blank glyph, which is still a valid (if illegible) render.
+pub const FONT: [[u8; GLYPH_HEIGHT]; 128] = build_font();
+
+const fn blank() -> [u8; BASE_GLYPH_HEIGHT] {
+    [0; BASE_GLYPH_HEIGHT]
+}
+
+const fn stretch(base: [[u8; BASE_GLYPH_HEIGHT]; 128]) -> [[u8; GLYPH_HEIGHT]; 128] {
+    let mut stretched = [[0u8; GLYPH_HEIGHT]; 128];
+
+    let mut c = 0;
+    while c < 128 {
+        let mut row = 0;
+        while row < BASE_GLYPH_HEIGHT {
+            stretched[c][row * 2] = base[c][row];
+            stretched[c][row * 2 + 1] = base[c][row];
+            row += 1;
+        }
+        c += 1;
+    }
+
+    stretched
+}
+
+const fn build_font() -> [[u8; GLYPH_HEIGHT]; 128] {
+    let mut font = [[0u8; BASE_GLYPH_HEIGHT]; 128];
+
+    font[b' ' as usize] = blank();
+    font[b'!' as usize] = [0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x18, 0x00];
+    font[b'.' as usize] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18];
+    font[b',' as usize] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x30];
+    font[b':' as usize] = [0x00, 0x18, 0x18, 0x00, 0x00, 0x18, 0x18, 0x00];
+    font[b'-' as usize] = [0x00, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x00, 0x00];
+    font[b'_' as usize] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7E];
+    font[b'/' as usize] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80];
+    font[b'%' as usize] = [0xC3, 0xC6, 0x0C, 0x18, 0x30, 0x63, 0xC3, 0x00];
+    font[b'\'' as usize] = [0x18, 0x18, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    font[b'0' as usize] = [0x3C, 0x66, 0x6E, 0x76, 0x66, 0x66, 0x3C, 0x00];
+    font[b'1' as usize] = [0x18, 0x38, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00];
+    font[b'2' as usize] = [0x3C, 0x66, 0x06, 0x0C, 0x30, 0x60, 0x7E, 0x00];
+    font[b'3' as usize] = [0x3C, 0x66, 0x06, 0x1C, 0x06, 0x66, 0x3C, 0x00];
+    font[b'4' as usize] = [0x0C, 0x1C, 0x3C, 0x6C, 0x7E, 0x0C, 0x0C, 0x00];
+    font[b'5' as usize] = [0x7E, 0x60, 0x7C, 0x06, 0x06, 0x66, 0x3C, 0x00];
+    font[b'6' as usize] = [0x3C, 0x66, 0x60, 0x7C, 0x66, 0x66, 0x3C, 0x00];
+    font[b'7' as usize] = [0x7E, 0x06, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x00];
+    font[b'8' as usize] = [0x3C, 0x66, 0x66, 0x3C, 0x66, 0x66, 0x3C, 0x00];
+    font[b'9' as usize] = [0x3C, 0x66, 0x66, 0x3E, 0x06, 0x66, 0x3C, 0x00];
+
+    font[b'A' as usize] = [0x18, 0x3C, 0x66, 0x66, 0x7E, 0x66, 0x66, 0x00];
+    font[b'B' as usize] = [0x7C, 0x66, 0x66, 0x7C, 0x66, 0x66, 0x7C, 0x00];
+    font[b'C' as usize] = [0x3C, 0x66, 0x60, 0x60, 0x60, 0x66, 0x3C, 0x00];
+    font[b'D' as usize] = [0x78, 0x6C, 0x66, 0x66, 0x66, 0x6C, 0x78, 0x00];
+    font[b'E' as usize] = [0x7E, 0x60, 0x60, 0x7C, 0x60, 0x60, 0x7E, 0x00];
+    font[b'F' as usize] = [0x7E, 0x60, 0x60, 0x7C, 0x60, 0x60, 0x60, 0x00];
+    font[b'G' as usize] = [0x3C, 0x66, 0x60, 0x6E, 0x66, 0x66, 0x3C, 0x00];
+    font[b'H' as usize] = [0x66, 0x66, 0x66, 0x7E, 0x66, 0x66, 0x66, 0x00];
+    font[b'I' as usize] = [0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00];
+    font[b'J' as usize] = [0x06, 0x06, 0x06, 0x06, 0x06, 0x66, 0x3C, 0x00];
+    font[b'K' as usize] = [0x66, 0x6C, 0x78, 0x70, 0x78, 0x6C, 0x66, 0x00];
+    font[b'L' as usize] = [0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x7E, 0x00];
+    font[b'M' as usize] = [0x63, 0x77, 0x7F, 0x6B, 0x63, 0x63, 0x63, 0x00];
+    font[b'N' as usize] = [0x66, 0x76, 0x7E, 0x7E, 0x6E, 0x66, 0x66, 0x00];
+    font[b'O' as usize] = [0x3C, 0x66, 0x66, 0x66, 0x66, 0x66, 0x3C, 0x00];
+    font[b'P' as usize] = [0x7C, 0x66, 0x66, 0x7C, 0x60, 0x60, 0x60, 0x00];
+    font[b'Q' as usize] = [0x3C, 0x66, 0x66, 0x66, 0x66, 0x6C, 0x36, 0x00];
+    font[b'R' as usize] = [0x7C, 0x66, 0x66, 0x7C, 0x78, 0x6C, 0x66, 0x00];
+    font[b'S' as usize] = [0x3C, 0x66, 0x60, 0x3C, 0x06, 0x66, 0x3C, 0x00];
+    font[b'T' as usize] = [0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00];
+    font[b'U' as usize] = [0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x3C, 0x00];
+    font[b'V' as usize] = [0x66, 0x66, 0x66, 0x66, 0x66, 0x3C, 0x18, 0x00];
+    font[b'W' as usize] = [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00];
+    font[b'X' as usize] = [0x66, 0x66, 0x3C, 0x18, 0x3C, 0x66, 0x66, 0x00];
+    font[b'Y' as usize] = [0x66, 0x66, 0x66, 0x3C, 0x18, 0x18, 0x18, 0x00];
+    font[b'Z' as usize] = [0x7E, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x7E, 0x00];
+
+    // Lowercase letters reuse the uppercase glyphs - a distinct lowercase set isn't worth the
+    // binary size for a diagnostics-only console
+    let mut c = b'A';
+    while c <= b'Z' {
+        font[(c - b'A' + b'a') as usize] = font[c as usize];
+        c += 1;
+    }
+
+    stretch(font)
+}
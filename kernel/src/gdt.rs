@@ -2,12 +2,14 @@
 
 use exclusive_cell::ExclusiveCell;
 
-const GDT_ENTRIES: usize = 6;
+const GDT_ENTRIES: usize = 7;
 
 pub const KERNEL_CS_SELECTOR: u16 = 1*8;
 pub const KERNEL_DS_SELECTOR: u16 = 2*8;
 pub const USER_CS_SELECTOR: u16 = 3*8;
 pub const USER_DS_SELECTOR: u16 = 4*8;
+/// Selector of the dedicated TSS used by the double-fault task gate (see `interrupts::init`)
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 6*8;
 
 /// Struct to wrap GDT entries to so we can set the alignment to 8 bytes (best performance according
 /// to the Intel manual)
@@ -39,6 +41,8 @@ pub unsafe fn init() {
     gdt[4] = GDTEntry::new(0x0, 0xFFFFF, 0b0010, 0b1111, 0b1100);
     // TSS Descriptor
     gdt[5] = crate::tss::init();
+    // Double-fault TSS Descriptor
+    gdt[6] = crate::tss::init_double_fault_tss(crate::interrupts::double_fault_handler);
 
     // Load the GDT. There is no need to actually reload the segment selectors, as the GDT is the
     // same, but this will be important later on when the GDT is accessed (e.g. in interrupts)
@@ -3,28 +3,70 @@
 // FIXME: NOT THREAD SAFE
 
 use core::mem::size_of;
+use alloc::vec;
+use alloc::vec::Vec;
 
 use exclusive_cell::ExclusiveCell;
-use page_tables::{VirtAddr, PhysAddr};
+use page_tables::{CacheMode, VirtAddr, PhysAddr};
 
 const SCREEN_BUFFER_VADDR: u32 = 0xCB800000;
 
+/// VGA input status register 1. Bit 3 is set for the duration of the vertical retrace.
+const INPUT_STATUS_1_PORT: u16 = 0x3DA;
+const INPUT_STATUS_1_VBLANK_BIT: u8 = 1 << 3;
+
+/// A typical CRT/VESA refresh rate, used to give callers a rough per-frame time budget. We have no
+/// way to query the actual mode timing yet, so this is an approximation, not a guarantee.
+const TYPICAL_REFRESH_HZ: u32 = 70;
+
 pub struct FrameBuffer {
 	pub width: usize,
-	pub height: usize
+	pub height: usize,
+	/// Off-screen buffer that drawing code targets. Kept separate from the mapped screen buffer so
+	/// a caller can never observe a partially-drawn frame; `present` is what makes a frame visible.
+	back_buffer: Vec<u32>,
 }
 
 impl FrameBuffer {
-	/// Returns a slice of the screen buffer
+	/// Returns a slice of the off-screen back buffer. Changes are not visible on screen until the
+	/// next call to `present`.
 	pub fn get_buffer(&mut self) -> &mut [u32] {
-		unsafe {
-			core::slice::from_raw_parts_mut(SCREEN_BUFFER_VADDR as *mut u32, self.width*self.height)
-		}
+		&mut self.back_buffer
 	}
 
 	pub const fn get_size(&self) -> usize {
 		return self.width * self.height * size_of::<u32>();
 	}
+
+	/// Blits the back buffer onto the mapped screen buffer in one pass. The blit is gated on the
+	/// start of the vertical blanking interval (polling port 0x3DA) so it can never race an
+	/// in-progress scan-out and tear the frame.
+	pub fn present(&mut self) {
+		wait_for_vblank_start();
+
+		let screen_buffer = unsafe {
+			core::slice::from_raw_parts_mut(SCREEN_BUFFER_VADDR as *mut u32, self.width*self.height)
+		};
+		screen_buffer.copy_from_slice(&self.back_buffer);
+	}
+
+	/// The approximate time, in microseconds, between two vertical blanking intervals - a rough
+	/// per-frame budget callers can use to pace animation. We don't yet have a way to query the
+	/// display's actual mode timing, so this is derived from `TYPICAL_REFRESH_HZ` rather than the
+	/// mode's real scanline count and pixel clock.
+	pub const fn refresh_period_micros(&self) -> u32 {
+		1_000_000 / TYPICAL_REFRESH_HZ
+	}
+}
+
+/// Blocks until any in-progress vertical retrace ends, then blocks again until the next one
+/// begins, so the caller returns right at the start of a vblank window instead of partway through
+/// one that might end before a blit finishes
+fn wait_for_vblank_start() {
+	unsafe {
+		while (cpu::in8(INPUT_STATUS_1_PORT) & INPUT_STATUS_1_VBLANK_BIT) != 0 {}
+		while (cpu::in8(INPUT_STATUS_1_PORT) & INPUT_STATUS_1_VBLANK_BIT) == 0 {}
+	}
 }
 
 pub static FRAME_BUFFER: ExclusiveCell<Option<FrameBuffer>> = ExclusiveCell::new(None);
@@ -39,9 +81,17 @@ pub fn init(screen_buffer_paddr: PhysAddr, screen_width: u16, screen_height: u16
 		// Map the screen buffer so we can write to it
 		let buffer_size = (screen_width as usize) * (screen_height as usize) * size_of::<u32>();
 		let buffer_page_count = buffer_size.div_ceil(4096) as u32;
+		// Write-combining rather than the strongly uncacheable mapping this used to make: the back
+		// buffer is blitted over in one pass every frame, and WC's buffered/combined writes are
+		// dramatically faster for that pattern than UC's, at no cost since nothing ever reads this
+		// mapping back
+		//
+		// Marked global: this mapping lives for the whole life of the kernel, so it should survive
+		// a `mov cr3` process switch instead of being needlessly re-walked and re-cached
 		for i in 0..buffer_page_count {
 			page_dir.map_to_phys_page(phys_mem, VirtAddr(SCREEN_BUFFER_VADDR + 4096*i),
-				PhysAddr(screen_buffer_paddr.0 + 4096*i), true, false, true, false)
+				PhysAddr(screen_buffer_paddr.0 + 4096*i), true, false, true,
+				CacheMode::WriteCombining, true)
 				.expect("Failed to map screen buffer");
 		}
 	}
@@ -49,6 +99,8 @@ pub fn init(screen_buffer_paddr: PhysAddr, screen_width: u16, screen_height: u16
 	{
 		let mut fb = FRAME_BUFFER.acquire();
 		assert!(fb.is_none());
-		*fb = Some(FrameBuffer { width: screen_width as usize, height: screen_height as usize });
+		let back_buffer = vec![0u32; (screen_width as usize) * (screen_height as usize)];
+		*fb = Some(FrameBuffer { width: screen_width as usize, height: screen_height as usize,
+			back_buffer });
 	}
-}
\ No newline at end of file
+}
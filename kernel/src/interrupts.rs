@@ -1,14 +1,31 @@
 //! Interrupts initialization and handling
 
-mod pic_8259a;
-mod pit_8254;
+pub(crate) mod pic_8259a;
+pub(crate) mod pit_8254;
 
 use exclusive_cell::ExclusiveCell;
+use lock_cell::LockCell;
 use crate::gdt::KERNEL_CS_SELECTOR;
 use serial::println;
 
 const IDT_ENTRIES: usize = 256;
 
+/// The signature device drivers register their interrupt handlers with via `intr_register`
+pub(crate) type InterruptHandlerFn = fn(&mut PushADRegisterState);
+
+/// A table of device-driver-registered handlers, indexed by interrupt vector. This lets device
+/// drivers (the PIT, keyboard, mouse, ...) claim their own vector during their own `init` instead of
+/// the core dispatcher having to know about every device, mirroring how Pintos' `intr_handlers[]`
+/// table decouples its interrupt core from individual drivers.
+static INTR_HANDLERS: LockCell<[Option<InterruptHandlerFn>; IDT_ENTRIES]> =
+    LockCell::new([None; IDT_ENTRIES]);
+
+/// Registers `handler` to be invoked whenever interrupt vector `vector` fires. Overwrites any
+/// previously registered handler for that vector.
+pub(crate) fn intr_register(vector: u8, handler: InterruptHandlerFn) {
+    INTR_HANDLERS.lock()[vector as usize] = Some(handler);
+}
+
 /// Struct to wrap IDT entries to so we can set the alignment to 8 bytes (best performance according
 /// to the Intel manual)
 #[derive(Clone, Copy)]
@@ -41,9 +58,11 @@ pub fn init() {
     idt[7] = IDTEntry::new(KERNEL_CS_SELECTOR, interrupt_7_handler as u32, 0, true,
         DescriptorType::InterruptGate);
     
-    // TODO: Use a task gate for the double fault handler so we can handle kernel stack corruptino
-    idt[8] = IDTEntry::new(KERNEL_CS_SELECTOR, interrupt_8_handler as u32, 0, true,
-        DescriptorType::InterruptGate);
+    // Use a task gate for the double fault handler: it runs on its own TSS (own stack, own cr3
+    // snapshot) set up in `gdt::init`, so it can still run (and report the fault) even if what
+    // caused the double fault was kernel stack exhaustion
+    idt[8] = IDTEntry::new(crate::gdt::DOUBLE_FAULT_TSS_SELECTOR, 0, 0, true,
+        DescriptorType::TaskGate);
 
     idt[9] = IDTEntry::new(KERNEL_CS_SELECTOR, interrupt_9_handler as u32, 0, true,
         DescriptorType::InterruptGate);
@@ -138,25 +157,63 @@ impl IDTEntry {
     const fn new(segment: u16, entry_offset: u32, privilege: u32, protected_mode: bool,
         typ: DescriptorType) -> Self {
         assert!(privilege < 4);
-        
+
+        if let DescriptorType::TaskGate = typ {
+            // Task gates don't reference a handler by offset - the CPU fully switches to the task
+            // described by the TSS selector in `segment`, so the offset/protected_mode fields this
+            // function otherwise encodes don't apply here
+            let low_dword = (segment as u32) << 16;
+            let high_dword = (1 << 15) | (privilege << 13) | (0b0101 << 8);
+            return IDTEntry(((high_dword as u64) << 32) | (low_dword as u64));
+        }
+
         let type_bits = match typ {
             DescriptorType::InterruptGate => 0,
             DescriptorType::TrapGate => 1,
-            DescriptorType::TaskGate => unimplemented!()
+            DescriptorType::TaskGate => unreachable!()
         };
-        
+
         let low_dword = ((segment as u32) << 16) | (entry_offset & 0xFFFF);
         let high_dword = (entry_offset & 0xFFFF0000) | (1 << 15) | (privilege << 13) |
             ((protected_mode as u32) << 11) | (3 << 9) | (type_bits << 8);
-        
+
         IDTEntry(((high_dword as u64) << 32) | (low_dword as u64))
     }
 }
 
+/// Human-readable names for the vectors we set up in the IDT, indexed by vector number, the way
+/// Pintos' `intr_name` and viengoos' `interrupt_names[]` do. Index 15 is listed as "Reserved"
+/// because Intel reserves that vector and we never install a gate for it.
+const INTERRUPT_NAMES: [&str; 22] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "NMI Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "BOUND Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack Fault (#SS)",
+    "General Protection (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 FPU Floating-Point Error (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point (#XM)",
+    "Virtualization (#VE)",
+    "Control Protection (#CP)",
+];
+
 /// General interrupt handler, each interrupt lands here after going through its specific gate
-unsafe extern "cdecl" fn interrupt_handler(interrupt_number: u32, error_code: u32, eip: u32) {
+unsafe extern "cdecl" fn interrupt_handler(interrupt_number: u32, error_code: u32, eip: u32,
+    cs: u32, eflags: u32, esp: u32, ss: u32, regs: &mut PushADRegisterState) {
     let interrupt_number = interrupt_number as u8;
-    
+
     if interrupt_number >= pic_8259a::PIC_IRQ_OFFSET
         && interrupt_number < pic_8259a::PIC_IRQ_OFFSET + 8 {
         let irq = interrupt_number - pic_8259a::PIC_IRQ_OFFSET;
@@ -164,83 +221,134 @@ unsafe extern "cdecl" fn interrupt_handler(interrupt_number: u32, error_code: u3
             println!("WARNING: Spurious PIC IRQ {}!", irq);
             return;
         }
-        
-        if irq == 0 {
-            pit_8254::handle_interrupt();
-        } else if irq == 1 {
-            crate::ps2::keyboard::handle_interrupt();
-        } else if irq == 12 {
-            unimplemented!("Mouse interrupt");
+
+        // Dispatch through the registered-handler table if a driver claimed this vector, falling
+        // back to just printing the (otherwise unhandled) IRQ number
+        if let Some(handler) = INTR_HANDLERS.lock()[interrupt_number as usize] {
+            handler(regs);
         } else {
             println!("PIC IRQ {}", irq);
         }
-        
+
         pic_8259a::send_eoi(irq);
         return;
     }
-    
+
+    // #PF (vector 14): before treating this as a genuine fault, check whether the current page
+    // tables already grant the access the error code says was denied - if something else (lazy
+    // mapping, stack growth, ...) already fixed up the PTE and this CPU just faulted on a stale TLB
+    // entry, flushing the one translation and retrying the faulting instruction is enough
+    if interrupt_number == 14 {
+        let fault_addr = cpu::get_cr2();
+        if crate::memory_manager::is_page_fault_spurious(fault_addr, error_code) {
+            cpu::invlpg(fault_addr as usize);
+            return;
+        }
+
+        // Bit 1 (W/R) of the error code is set if the faulting access was a write - only a write
+        // can ever hit a copy-on-write mapping installed by `PageDirectory::clone_cow`
+        let write_access = error_code & 0x2 != 0;
+        if write_access && crate::memory_manager::handle_cow_fault(fault_addr) {
+            return;
+        }
+    }
+
+    report_fault(interrupt_number, error_code, eip, cs, eflags, esp, ss, regs);
+}
+
+/// Prints a named, structured fault report (full GP/segment/control-register state, plus a
+/// decoded selector for the error codes that carry one) and panics. Reached by every configured
+/// exception vector except #DB and #BP, which go to the kgdb stub, and #DF, which runs on its own
+/// task gate instead (see `tss.rs`) so it can survive a corrupted kernel stack.
+unsafe fn report_fault(interrupt_number: u8, error_code: u32, eip: u32, cs: u32, eflags: u32,
+    esp: u32, ss: u32, regs: &PushADRegisterState) -> ! {
+    let name = INTERRUPT_NAMES.get(interrupt_number as usize).copied()
+        .unwrap_or("Unrecognized Interrupt");
+
     // FIXME: This will dead-lock if the exception happened while the serial lock is held
-    println!("Handling interrupt {} with code={} eip={:#010x}", interrupt_number, error_code, eip);
-
-    match interrupt_number {
-        0 => panic!("Divide Error Exception (#DE)"),
-        1 => panic!("Debug Exception (#DB)"),
-        2 => panic!("NMI Interrupt"),
-        3 => panic!("Breakpoint Exception (#BP)"),
-        4 => panic!("Overflow Exception (#OF)"),
-        5 => panic!("BOUND Range Exceeded Exception (#BR)"),
-        6 => panic!("Invalid Opcode Exception (#UD)"),
-        7 => panic!("Device Not Available Exception (#NM)"),
-        8 => panic!("Double Fault Exception (#DF)"),
-        9 => panic!("Coprocessor Segment Overrun"),
-        10 => panic!("Invalid TSS Exception (#TS)"),
-        11 => panic!("Segment Not Present (#NP)"),
-        12 => panic!("Stack Fault Exception (#SS)"),
-        13 => panic!("General Protection Exception (#GP)"),
-        14 => panic!("Page-Fault Exception (#PF) CR2={:#010x}", cpu::get_cr2()),
-        16 => panic!("x87 FPU Floating-Point Error (#MF)"),
-        17 => panic!("Alignment Check Exception (#AC)"),
-        18 => panic!("Machine-Check Exception (#MC)"),
-        19 => panic!("SIMD Floating-Point Exception (#XM)"),
-        20 => panic!("Virtualization Exception (#VE)"),
-        21 => panic!("Control Protection Exception (#CP)"),
-        _ => panic!("Unrecognized Interrupt")
+    println!("=== Unhandled Fault: {} ===", name);
+    println!("eip={:#010x} cs={:#06x} eflags={:#010x} esp={:#010x} ss={:#06x}",
+        eip, cs, eflags, esp, ss);
+    println!("eax={:#010x} ebx={:#010x} ecx={:#010x} edx={:#010x}",
+        regs.eax, regs.ebx, regs.ecx, regs.edx);
+    println!("esi={:#010x} edi={:#010x} ebp={:#010x}", regs.esi, regs.edi, regs.ebp);
+    println!("cr0={:#010x} cr2={:#010x} cr3={:#010x}", cpu::get_cr0(), cpu::get_cr2(), cpu::get_cr3());
+
+    // #TS, #NP, #SS and #GP push a "selector error code": bit 0 is the EXT flag (set if the fault
+    // happened while the CPU was delivering an earlier event rather than executing normally), bit
+    // 1 is the IDT flag (the index addresses the IDT instead of the GDT/LDT), and bits 3-15 hold
+    // the index into whichever table that is
+    if matches!(interrupt_number, 10 | 11 | 12 | 13) {
+        let external = error_code & 0x1 != 0;
+        let idt = error_code & 0x2 != 0;
+        let index = (error_code >> 3) & 0x1FFF;
+        println!("error_code={:#x} (external={} idt={} index={})", error_code, external, idt, index);
+    } else {
+        println!("error_code={:#x}", error_code);
     }
+
+    panic!("{}", name);
 }
 
 #[derive(Debug)]
 #[repr(C)]
-struct PushADRegisterState {
-    edi: u32,
-    esi: u32,
-    ebp: u32,
-    esp: u32,
-    ebx: u32,
-    edx: u32,
-    ecx: u32,
-    eax: u32,
-}
-
-/// Syscall interrupt handler, int 0x67 lands here
+pub(crate) struct PushADRegisterState {
+    pub(crate) edi: u32,
+    pub(crate) esi: u32,
+    pub(crate) ebp: u32,
+    pub(crate) esp: u32,
+    pub(crate) ebx: u32,
+    pub(crate) edx: u32,
+    pub(crate) ecx: u32,
+    pub(crate) eax: u32,
+}
+
+/// Entry point for the double-fault task gate (IDT entry 8). The CPU reaches this via a hardware
+/// task switch rather than a normal call, on its own stack and with interrupts masked, so it can
+/// report the fault even if what triggered it was kernel stack exhaustion. There is no way back
+/// from here - a double fault always means the machine is in an unknown state.
+pub(crate) extern "cdecl" fn double_fault_handler() -> ! {
+    // The task switch that got us here saved the full register state of whatever was running into
+    // the main TSS, so that's where we read the faulting task's state from
+    crate::tss::print_main_tss_state();
+
+    unsafe { cpu::halt(); }
+}
+
+/// Syscall interrupt handler, int 0x67 lands here. By convention (mirroring `userland::syscalls`),
+/// `eax` holds the syscall number and `ebx`/`ecx`/`edx` hold up to three arguments; the result (or
+/// negated `SyscallError`) is written back into `eax` for the caller to pick up after `iretd`.
 unsafe extern "cdecl" fn syscall_interrupt_handler(register_state: &mut PushADRegisterState) {
-    crate::println!("Syscall {:?}", register_state);
+    let result = match crate::syscall::Syscall::from_u32(register_state.eax) {
+        Some(syscall) => crate::syscall::handle_syscall(syscall, register_state.ebx,
+            register_state.ecx, register_state.edx),
+        None => crate::syscall::SyscallError::UnknownSyscall.to_i32(),
+    };
+
+    register_state.eax = result as u32;
 }
 
 macro_rules! int_asm_no_err_code {
     ($x:literal) => {
         asm!("
-                push eax                // Save `cdecl` caller-saved registers on the stack
-                push ecx
-                push edx
-                mov eax, [esp + 12]     // Grab the return eip from the interrupt frame
+                pushad                  // Save the full register state, handlers may need it
+                mov eax, [esp + 32]     // Grab the hardware-pushed eip
+                mov ebx, [esp + 36]     // ... cs
+                mov ecx, [esp + 40]     // ... eflags
+                lea edx, [esp + 44]     // Compute esp as it was right before the fault
+                mov si, ss              // Grab ss (unchanged: none of our gates switch privilege)
+                movzx esi, si
+                push esp                // Push arg 8: pointer to the saved register state
+                push esi                // Push arg 7: ss
+                push edx                // Push arg 6: esp
+                push ecx                // Push arg 5: eflags
+                push ebx                // Push arg 4: cs
                 push eax                // Push arg 3: the interrupt's return eip
                 push dword ptr 0        // Push arg 2: the fake error code
                 push dword ptr {int_no} // Push arg 1: the interrupt number
                 call {int_handler}      // Call the handler function
-                add esp, 12             // Pop the interrupt number, the error code, and the ret eip
-                pop edx                 // Restore caller-saved registers
-                pop ecx
-                pop eax
+                add esp, 32             // Pop the 8 arguments we pushed above
+                popad                   // Restore the full register state
                 iretd                   // Return from the interrupt
             ",
             int_no = const $x,
@@ -253,20 +361,26 @@ macro_rules! int_asm_no_err_code {
 macro_rules! int_asm_err_code {
     ($x:literal) => {
         asm!("
-                push eax                // Save `cdecl` caller-saved registers on the stack
-                push ecx
-                push edx
-                mov eax, [esp + 16]     // Grab the return eip from the interrupt frame
-                mov ecx, [esp + 12]     // Grab the interrupt error code
+                pushad                  // Save the full register state, handlers may need it
+                mov edi, [esp + 32]     // Grab the hardware-pushed error code
+                mov eax, [esp + 36]     // ... eip
+                mov ebx, [esp + 40]     // ... cs
+                mov ecx, [esp + 44]     // ... eflags
+                lea edx, [esp + 48]     // Compute esp as it was right before the fault
+                mov si, ss              // Grab ss (unchanged: none of our gates switch privilege)
+                movzx esi, si
+                push esp                // Push arg 8: pointer to the saved register state
+                push esi                // Push arg 7: ss
+                push edx                // Push arg 6: esp
+                push ecx                // Push arg 5: eflags
+                push ebx                // Push arg 4: cs
                 push eax                // Push arg 3: the interrupt's return eip
-                push ecx                // Push arg 2: the error code
+                push edi                // Push arg 2: the error code
                 push dword ptr {int_no} // Push arg 1: the interrupt number
                 call {int_handler}      // Call the handler function
-                add esp, 8              // Pop the interrupt number and the error code
-                add esp, 12             // Pop the interrupt number, the error code, and the ret eip
-                pop edx                 // Restore caller-saved registers
-                pop ecx
-                pop eax
+                add esp, 32             // Pop the 8 arguments we pushed above
+                popad                   // Restore the full register state
+                add esp, 4              // Pop the hardware-pushed error code
                 iretd                   // Return from the interrupt
             ",
             int_no = const $x,
@@ -276,6 +390,44 @@ macro_rules! int_asm_err_code {
     }
 }
 
+// #DB and #BP are the kgdb stub's entry points rather than the shared fault reporter, and unlike
+// every other vector here the stub needs to be able to rewrite the CPU's actual resume state (to
+// rewind eip past a removed breakpoint, and to toggle the trap flag for single-step/continue) -
+// something `interrupt_handler`'s by-value eip/eflags can't do. This macro is identical to
+// `int_asm_no_err_code!` except it passes pointers into the hardware-pushed eip/eflags slots
+// instead of copies of their values.
+macro_rules! kgdb_asm_stub {
+    ($x:literal) => {
+        asm!("
+                pushad                  // Save the full register state, the stub may modify it
+                lea eax, [esp + 32]     // Pointer to the hardware-pushed eip - the stub rewrites
+                                        // this in place so the iretd below resumes wherever it
+                                        // decided (breakpoint rewind, a G write, ...)
+                mov ebx, [esp + 36]     // cs (not writable by the stub, passed by value)
+                lea ecx, [esp + 40]     // Pointer to the hardware-pushed eflags - same story, used
+                                        // to toggle the trap flag for single-step/continue
+                lea edx, [esp + 44]     // Compute esp as it was right before the fault
+                mov si, ss              // Grab ss (unchanged: none of our gates switch privilege)
+                movzx esi, si
+                push esp                // Push arg 7: pointer to the saved register state
+                push esi                // Push arg 6: ss
+                push edx                // Push arg 5: esp
+                push ecx                // Push arg 4: pointer to eflags
+                push ebx                // Push arg 3: cs
+                push eax                // Push arg 2: pointer to eip
+                push dword ptr {int_no} // Push arg 1: the interrupt number
+                call {int_handler}      // Call the stub's trap entry point
+                add esp, 28             // Pop the 7 arguments we pushed above
+                popad                   // Restore the full register state
+                iretd                   // Return from the interrupt
+            ",
+            int_no = const $x,
+            int_handler = sym crate::kgdb::trap_entry,
+            options(noreturn)
+        );
+    }
+}
+
 #[naked]
 unsafe extern fn interrupt_0_handler() -> ! {
     int_asm_no_err_code!(0);
@@ -283,7 +435,7 @@ unsafe extern fn interrupt_0_handler() -> ! {
 
 #[naked]
 unsafe extern fn interrupt_1_handler() -> ! {
-    int_asm_no_err_code!(1);
+    kgdb_asm_stub!(1);
 }
 
 #[naked]
@@ -293,7 +445,7 @@ unsafe extern fn interrupt_2_handler() -> ! {
 
 #[naked]
 unsafe extern fn interrupt_3_handler() -> ! {
-    int_asm_no_err_code!(3);
+    kgdb_asm_stub!(3);
 }
 
 #[naked]
@@ -316,11 +468,6 @@ unsafe extern fn interrupt_7_handler() -> ! {
     int_asm_no_err_code!(7);
 }
 
-#[naked]
-unsafe extern fn interrupt_8_handler() -> ! {
-    int_asm_err_code!(8);
-}
-
 #[naked]
 unsafe extern fn interrupt_9_handler() -> ! {
     int_asm_no_err_code!(9);
@@ -1,40 +1,66 @@
 //! 8254 PIT controller
 
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
 
 use exclusive_cell::ExclusiveCell;
+use cpu::WriteOnlyPort;
 
 // Reference: https://www.scs.stanford.edu/10wi-cs140/pintos/specs/8254.pdf
 
-const PIT_CHANNEL_0_DATA_PORT: u16 = 0x40;
-const PIT_CONTROL_WORD_REGISTER_PORT: u16 = 0x43;
+const PIT_CHANNEL_0_DATA_PORT: WriteOnlyPort<u8> = WriteOnlyPort::new(0x40);
+const PIT_CONTROL_WORD_REGISTER_PORT: WriteOnlyPort<u8> = WriteOnlyPort::new(0x43);
 
-/// The interrupt frequency we want to achieve (in Hz)
+/// The interrupt frequency channel 0 is programmed to on `init`, used for everything the rest of
+/// the kernel derives from the PIT (wall clock, monotonic tick, software timers below)
 const TARGET_FREQ_HZ: f64 = 100f64;
 /// The frequency the PIT's clock runs on
 const PIT_FREQ_HZ: f64 = 1_000_000f64 * 105f64 / 88f64;
-/// The calculated frequency divisor for the PIT
-// TODO: The PIT supports using a divisor of 0 as 2^16, so if we need a small frequency than we need
-// to add a case for that
-const PIT_FREQ_DIV: u16 = (PIT_FREQ_HZ / TARGET_FREQ_HZ) as u16;
-/// The actual interrupt frequency (it is different from the target frequency because we are forced
-/// to truncate the divisor to an integer)
-const REAL_FREQ_HZ: f64 = PIT_FREQ_HZ / (PIT_FREQ_DIV as f64);
-
-/// Initiailizes the PIT's first counter as a rate generator
+
+/// The actual interrupt frequency channel 0 is currently programmed to, stored as the bit pattern
+/// of an `f64` since there's no `AtomicF64`. Differs from whatever was requested of `set_rate`
+/// because the divisor has to be rounded to an integer (or clamped to the 65536 case).
+static REAL_FREQ_HZ_BITS: AtomicU64 = AtomicU64::new(0);
+
+/// Initializes the PIT's first counter as a rate generator at `TARGET_FREQ_HZ`, and registers its
+/// IRQ0 handler
 pub fn init() {
+	set_rate(TARGET_FREQ_HZ);
+
+	// Claim IRQ0 in the interrupt core's handler table instead of being hardcoded into it
+	super::intr_register(super::pic_8259a::PIC_IRQ_OFFSET, handle_irq);
+}
+
+/// Programs PIT channel 0 as a rate generator (mode 2) at (approximately) `hz`, returning the
+/// actual frequency achieved once the divisor is rounded to an integer. A requested rate low
+/// enough that the divisor would be 65536 or higher is programmed as a divisor of 0, which the PIT
+/// treats as 65536 - the case the old hardcoded-divisor version of this module left as a TODO.
+pub(crate) fn set_rate(hz: f64) -> f64 {
+	let divisor = (PIT_FREQ_HZ / hz).round();
+	let divisor = if divisor >= 65536f64 { 0u16 } else { divisor as u16 };
+
 	unsafe {
 		// Initialize counter 0 by writing a setup control-word:
 		// 00  - select counter 0
 		// 11  - write least signifcant byte first, then most significant byte
 		// 010 - mode 2 (rate generator)
 		// 0   - 16-bit binary (instead of BCD)
-		cpu::out8(PIT_CONTROL_WORD_REGISTER_PORT, 0b0011_0100);
+		PIT_CONTROL_WORD_REGISTER_PORT.write(0b0011_0100);
 
 		// Write the least sig and most sig bytes of the freq divisor
-		cpu::out8(PIT_CHANNEL_0_DATA_PORT, PIT_FREQ_DIV as u8);
-		cpu::out8(PIT_CHANNEL_0_DATA_PORT, (PIT_FREQ_DIV >> 8) as u8);
+		PIT_CHANNEL_0_DATA_PORT.write(divisor as u8);
+		PIT_CHANNEL_0_DATA_PORT.write((divisor >> 8) as u8);
 	}
+
+	let actual_divisor = if divisor == 0 { 65536f64 } else { divisor as f64 };
+	let real_freq_hz = PIT_FREQ_HZ / actual_divisor;
+	REAL_FREQ_HZ_BITS.store(real_freq_hz.to_bits(), Ordering::Relaxed);
+	real_freq_hz
+}
+
+/// Entry point invoked by the interrupt core for IRQ0
+fn handle_irq(_regs: &mut super::PushADRegisterState) {
+	unsafe { handle_interrupt(); }
 }
 
 // static mut time: f64 = 0f64; TODO: DEBUG CODE
@@ -42,11 +68,124 @@ pub fn init() {
 pub static CURRENT_UNIX_TIME: AtomicU32 = AtomicU32::new(0);
 static ONLINE_TIME: ExclusiveCell<f64> = ExclusiveCell::new(0.0);
 
+/// Total number of PIT ticks handled since `init`, used by `monotonic::init` to calibrate the TSC
+/// frequency against a known wall-clock interval, and as the time base for the software timers below
+static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// The callback signature for a software timer registered with `after`
+type TimerCallback = fn();
+
+/// Maximum number of pending software timers. A small fixed capacity keeps this allocation-free,
+/// matching the rest of the kernel's driver state.
+const MAX_TIMERS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Timer {
+	deadline_tick: u64,
+	callback: TimerCallback,
+}
+
+/// A fixed-capacity list of pending timers, kept sorted by ascending `deadline_tick` (entries
+/// `0..len` are populated, the rest unused) so `handle_interrupt` only ever has to look at the
+/// front to know whether anything is due
+struct TimerQueue {
+	entries: [Option<Timer>; MAX_TIMERS],
+	len: usize,
+}
+
+impl TimerQueue {
+	/// Inserts `timer` in sorted position. Returns false if the queue is full.
+	fn insert(&mut self, timer: Timer) -> bool {
+		if self.len >= MAX_TIMERS {
+			return false;
+		}
+
+		let pos = self.entries[..self.len].iter()
+			.position(|entry| entry.unwrap().deadline_tick > timer.deadline_tick)
+			.unwrap_or(self.len);
+
+		let mut i = self.len;
+		while i > pos {
+			self.entries[i] = self.entries[i - 1];
+			i -= 1;
+		}
+		self.entries[pos] = Some(timer);
+		self.len += 1;
+		true
+	}
+
+	/// Removes and returns the callback of the earliest-deadline timer, if one is due by `now`
+	fn pop_expired(&mut self, now: u64) -> Option<TimerCallback> {
+		if self.len == 0 || self.entries[0].unwrap().deadline_tick > now {
+			return None;
+		}
+
+		let callback = self.entries[0].unwrap().callback;
+		for i in 0..self.len - 1 {
+			self.entries[i] = self.entries[i + 1];
+		}
+		self.entries[self.len - 1] = None;
+		self.len -= 1;
+		Some(callback)
+	}
+}
+
+static TIMERS: ExclusiveCell<TimerQueue> =
+	ExclusiveCell::new(TimerQueue { entries: [None; MAX_TIMERS], len: 0 });
+
+/// Requests that `callback` be invoked once at least `delay` has elapsed, without the caller having
+/// to reprogram the PIT itself. Deadlines are tracked in a sorted software queue keyed off the
+/// existing periodic tick count and fired from `handle_interrupt` - channel 0 is already the
+/// kernel's only source of a hardware timer interrupt (the other two PIT channels aren't wired to
+/// the PIC), so giving it a true one-shot mode for this would mean losing the wall clock/monotonic
+/// tick everything else is built on for as long as the one-shot was pending. Returns false if the
+/// timer queue is full.
+pub(crate) fn after(delay: Duration, callback: TimerCallback) -> bool {
+	let delay_ticks = (delay.as_secs_f64() * real_freq_hz()).ceil() as u64;
+	let timer = Timer { deadline_tick: tick_count() + delay_ticks.max(1), callback };
+	TIMERS.acquire().insert(timer)
+}
+
 // Handles an interrupt from the PIT (should only be called when an interrupt happens)
 pub unsafe fn handle_interrupt() {
+	let now = TICK_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+
 	let mut online_time = ONLINE_TIME.acquire();
-	*online_time += 1f64/REAL_FREQ_HZ;
+	*online_time += 1f64 / real_freq_hz();
 
 	let boot_time = crate::time::BOOT_UNIX_TIME.load(Ordering::Relaxed);
 	CURRENT_UNIX_TIME.store(boot_time + (*online_time as u32), Ordering::Relaxed);
-}
\ No newline at end of file
+	drop(online_time);
+
+	// Fire every timer that's come due. Each iteration re-acquires the queue (rather than holding
+	// it across every callback) so a callback registering another `after` timer doesn't reenter the
+	// exclusive cell and panic.
+	loop {
+		let callback = TIMERS.acquire().pop_expired(now);
+		match callback {
+			Some(callback) => callback(),
+			None => break,
+		}
+	}
+}
+
+/// The number of PIT ticks that have elapsed since `init`
+pub(crate) fn tick_count() -> u64 {
+	TICK_COUNT.load(Ordering::Relaxed)
+}
+
+/// Corrects the PIT-derived wall clock to match an authoritative reading from the RTC, called once
+/// a second by `time`'s update-ended interrupt handler. Setting `BOOT_UNIX_TIME` back (rather than
+/// just overwriting `CURRENT_UNIX_TIME` once) keeps the next PIT tick's own `boot_time +
+/// online_time` computation in sync too, instead of drifting away from the RTC again immediately.
+pub(crate) fn resync(unix_time: u32) {
+	let online_time = *ONLINE_TIME.acquire();
+	crate::time::BOOT_UNIX_TIME.store(unix_time - (online_time as u32), Ordering::Relaxed);
+	CURRENT_UNIX_TIME.store(unix_time, Ordering::Relaxed);
+}
+
+/// The PIT's current actual interrupt frequency (see `set_rate`), exposed so calibration code can
+/// turn a tick count into an elapsed duration
+pub(crate) fn real_freq_hz() -> f64 {
+	f64::from_bits(REAL_FREQ_HZ_BITS.load(Ordering::Relaxed))
+}
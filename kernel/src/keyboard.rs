@@ -1,6 +1,10 @@
 //! General keyboard definitions and methods
 
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+use core::time::Duration;
+
 use exclusive_cell::ExclusiveCell;
+use producer_consumer::ProducerConsumer;
 
 // The order of keys is generally from top to bottom, left to right, first the main keys, then the
 // action keys, then arrows, and then the numpad and finally multimedia keys.
@@ -162,17 +166,293 @@ impl KeyCode {
 			_ => false
 		}
 	}
+
+	/// Whether or not this is a modifier or lock key - these never participate in typematic repeat
+	/// (see `set_repeat`), the same way a real keyboard doesn't "retype" Shift
+	fn is_modifier_or_lock(&self) -> bool {
+		matches!(self,
+			KeyCode::KeyLeftShift | KeyCode::KeyRightShift |
+			KeyCode::KeyLeftControl | KeyCode::KeyRightControl |
+			KeyCode::KeyLeftAlt | KeyCode::KeyRightAlt |
+			KeyCode::KeyLeftLogo | KeyCode::KeyRightLogo |
+			KeyCode::KeyCapsLock | KeyCode::KeyNumberLock | KeyCode::KeyScrollLock)
+	}
+}
+
+/// A physical key's ASCII output for one keyboard layout, with 0 meaning "this key has no ASCII
+/// representation". `shifted` is equal to `base` for keys whose output does not change with Shift
+/// (Enter, Tab, Space, the numpad operator keys, ...).
+#[derive(Clone, Copy)]
+struct KeymapEntry {
+	base: u8,
+	shifted: u8,
+}
+
+/// A `KeymapEntry` for a key with no ASCII representation at all, used to fill in every slot a
+/// layout table does not explicitly list
+const NO_ASCII: KeymapEntry = KeymapEntry { base: 0, shifted: 0 };
+
+/// Shorthand for a key whose Shift state doesn't change its output (Enter, Tab, numpad operators, ...)
+const fn same(c: u8) -> KeymapEntry {
+	KeymapEntry { base: c, shifted: c }
+}
+
+/// Shorthand for a key with distinct unshifted/shifted output
+const fn pair(base: u8, shifted: u8) -> KeymapEntry {
+	KeymapEntry { base, shifted }
+}
+
+/// Builds a full per-`KeyCode` table from a sparse list of `(KeyCode, KeymapEntry)` overrides,
+/// defaulting every key not mentioned to `NO_ASCII`
+const fn build_keymap(overrides: &[(KeyCode, KeymapEntry)]) -> [KeymapEntry; KeyCode::Count as usize] {
+	let mut entries = [NO_ASCII; KeyCode::Count as usize];
+
+	let mut i = 0;
+	while i < overrides.len() {
+		let (key_code, entry) = overrides[i];
+		entries[key_code as usize] = entry;
+		i += 1;
+	}
+
+	entries
+}
+
+/// A two-keystroke compose/dead-key rule: once the compose trigger (see `set_compose_trigger`) has
+/// been pressed, typing a key that resolves to `first` and then one that resolves to `second`
+/// produces `result` instead of either of their own characters
+#[derive(Clone, Copy)]
+struct ComposeRule {
+	first: u8,
+	second: u8,
+	result: u8,
+}
+
+/// Shorthand for a `ComposeRule` entry, matching `pair`/`same`'s terseness above
+const fn compose(first: u8, second: u8, result: u8) -> ComposeRule {
+	ComposeRule { first, second, result }
+}
+
+/// The accented/punctuation compose sequences common to all the Latin layouts below. A layout with
+/// its own dead-key conventions would give `Keymap::compose_rules` a different table instead.
+const STANDARD_COMPOSE_RULES: &[ComposeRule] = &[
+	compose(b'\'', b'e', 0xE9), compose(b'\'', b'E', 0xC9), // e acute
+	compose(b'\'', b'a', 0xE1), compose(b'\'', b'A', 0xC1), // a acute
+	compose(b'\'', b'o', 0xF3), compose(b'\'', b'O', 0xD3), // o acute
+	compose(b'\'', b'u', 0xFA), compose(b'\'', b'U', 0xDA), // u acute
+	compose(b'`', b'e', 0xE8), compose(b'`', b'E', 0xC8), // e grave
+	compose(b'`', b'a', 0xE0), compose(b'`', b'A', 0xC0), // a grave
+	compose(b'"', b'u', 0xFC), compose(b'"', b'U', 0xDC), // u diaeresis
+	compose(b'~', b'n', 0xF1), compose(b'~', b'N', 0xD1), // n tilde
+	compose(b'<', b'<', 0xAB), compose(b'>', b'>', 0xBB), // guillemets
+];
+
+/// A data-driven physical-key-to-ASCII table for one keyboard layout. Physical keys are still named
+/// after their US-QWERTY position (e.g. `KeyQ` is the key to the right of Tab, regardless of what it
+/// actually types), which is exactly what lets a layout remap that position to a different character.
+/// `KeyEvent::as_ascii` applies the CapsLock/NumLock/Shift rules against whichever layout is active;
+/// this table only says what a given physical key produces once those rules pick `base` or `shifted`.
+struct Keymap {
+	/// Name used to select this layout with `set_layout`
+	name: &'static str,
+	entries: [KeymapEntry; KeyCode::Count as usize],
+	/// This layout's compose/dead-key rules - see `ComposeRule`
+	compose_rules: &'static [ComposeRule],
+}
+
+impl Keymap {
+	fn entry(&self, key_code: KeyCode) -> KeymapEntry {
+		self.entries[key_code as usize]
+	}
+
+	/// Looks up what `first` followed by `second` composes to under this layout, if anything
+	fn resolve_compose(&self, first: u8, second: u8) -> Option<u8> {
+		self.compose_rules.iter()
+			.find(|rule| rule.first == first && rule.second == second)
+			.map(|rule| rule.result)
+	}
 }
 
+/// The standard US-QWERTY layout, matching the hardcoded mapping this module used to have
+static US_QWERTY: Keymap = Keymap {
+	name: "us-qwerty",
+	entries: build_keymap(&[
+		(KeyCode::KeyBackTick, pair(b'`', b'~')),
+		(KeyCode::Key1, pair(b'1', b'!')), (KeyCode::Key2, pair(b'2', b'@')),
+		(KeyCode::Key3, pair(b'3', b'#')), (KeyCode::Key4, pair(b'4', b'$')),
+		(KeyCode::Key5, pair(b'5', b'%')), (KeyCode::Key6, pair(b'6', b'^')),
+		(KeyCode::Key7, pair(b'7', b'&')), (KeyCode::Key8, pair(b'8', b'*')),
+		(KeyCode::Key9, pair(b'9', b'(')), (KeyCode::Key0, pair(b'0', b')')),
+		(KeyCode::KeyMinus, pair(b'-', b'_')), (KeyCode::KeyEquals, pair(b'=', b'+')),
+		(KeyCode::KeyBackspace, same(8)), (KeyCode::KeyTab, same(b'\t')),
+		(KeyCode::KeyQ, pair(b'q', b'Q')), (KeyCode::KeyW, pair(b'w', b'W')),
+		(KeyCode::KeyE, pair(b'e', b'E')), (KeyCode::KeyR, pair(b'r', b'R')),
+		(KeyCode::KeyT, pair(b't', b'T')), (KeyCode::KeyY, pair(b'y', b'Y')),
+		(KeyCode::KeyU, pair(b'u', b'U')), (KeyCode::KeyI, pair(b'i', b'I')),
+		(KeyCode::KeyO, pair(b'o', b'O')), (KeyCode::KeyP, pair(b'p', b'P')),
+		(KeyCode::KeyLeftSquareBracket, pair(b'[', b'{')),
+		(KeyCode::KeyRightSquareBracket, pair(b']', b'}')),
+		(KeyCode::KeyEnter, same(b'\n')),
+		(KeyCode::KeyA, pair(b'a', b'A')), (KeyCode::KeyS, pair(b's', b'S')),
+		(KeyCode::KeyD, pair(b'd', b'D')), (KeyCode::KeyF, pair(b'f', b'F')),
+		(KeyCode::KeyG, pair(b'g', b'G')), (KeyCode::KeyH, pair(b'h', b'H')),
+		(KeyCode::KeyJ, pair(b'j', b'J')), (KeyCode::KeyK, pair(b'k', b'K')),
+		(KeyCode::KeyL, pair(b'l', b'L')),
+		(KeyCode::KeySemicolon, pair(b';', b':')),
+		(KeyCode::KeyApostrophe, pair(b'\'', b'"')),
+		(KeyCode::KeyBackSlash, pair(b'\\', b'|')),
+		(KeyCode::KeyExtraBackSlash, pair(b'\\', b'|')),
+		(KeyCode::KeyZ, pair(b'z', b'Z')), (KeyCode::KeyX, pair(b'x', b'X')),
+		(KeyCode::KeyC, pair(b'c', b'C')), (KeyCode::KeyV, pair(b'v', b'V')),
+		(KeyCode::KeyB, pair(b'b', b'B')), (KeyCode::KeyN, pair(b'n', b'N')),
+		(KeyCode::KeyM, pair(b'm', b'M')),
+		(KeyCode::KeyComma, pair(b',', b'<')), (KeyCode::KeyPeriod, pair(b'.', b'>')),
+		(KeyCode::KeySlash, pair(b'/', b'?')),
+		(KeyCode::KeySpace, same(b' ')),
+		(KeyCode::KeyNumpadSlash, same(b'/')), (KeyCode::KeyNumpadAsterisk, same(b'*')),
+		(KeyCode::KeyNumpadMinus, same(b'-')), (KeyCode::KeyNumpadPlus, same(b'+')),
+		(KeyCode::KeyNumpadEnter, same(b'\n')), (KeyCode::KeyNumpadPeriod, same(b'.')),
+		(KeyCode::KeyNumpad0, same(b'0')), (KeyCode::KeyNumpad1, same(b'1')),
+		(KeyCode::KeyNumpad2, same(b'2')), (KeyCode::KeyNumpad3, same(b'3')),
+		(KeyCode::KeyNumpad4, same(b'4')), (KeyCode::KeyNumpad5, same(b'5')),
+		(KeyCode::KeyNumpad6, same(b'6')), (KeyCode::KeyNumpad7, same(b'7')),
+		(KeyCode::KeyNumpad8, same(b'8')), (KeyCode::KeyNumpad9, same(b'9')),
+	]),
+	compose_rules: STANDARD_COMPOSE_RULES,
+};
+
+/// The US-Dvorak layout. Physical keys keep their US-QWERTY name (see `Keymap`), but most of the
+/// letter/number-row keys produce a different character; the number row itself, the numpad, and the
+/// backslash key are unchanged from QWERTY.
+static US_DVORAK: Keymap = Keymap {
+	name: "us-dvorak",
+	entries: build_keymap(&[
+		(KeyCode::KeyBackTick, pair(b'`', b'~')),
+		(KeyCode::Key1, pair(b'1', b'!')), (KeyCode::Key2, pair(b'2', b'@')),
+		(KeyCode::Key3, pair(b'3', b'#')), (KeyCode::Key4, pair(b'4', b'$')),
+		(KeyCode::Key5, pair(b'5', b'%')), (KeyCode::Key6, pair(b'6', b'^')),
+		(KeyCode::Key7, pair(b'7', b'&')), (KeyCode::Key8, pair(b'8', b'*')),
+		(KeyCode::Key9, pair(b'9', b'(')), (KeyCode::Key0, pair(b'0', b')')),
+		(KeyCode::KeyMinus, pair(b'[', b'{')), (KeyCode::KeyEquals, pair(b']', b'}')),
+		(KeyCode::KeyBackspace, same(8)), (KeyCode::KeyTab, same(b'\t')),
+		(KeyCode::KeyQ, pair(b'\'', b'"')), (KeyCode::KeyW, pair(b',', b'<')),
+		(KeyCode::KeyE, pair(b'.', b'>')), (KeyCode::KeyR, pair(b'p', b'P')),
+		(KeyCode::KeyT, pair(b'y', b'Y')), (KeyCode::KeyY, pair(b'f', b'F')),
+		(KeyCode::KeyU, pair(b'g', b'G')), (KeyCode::KeyI, pair(b'c', b'C')),
+		(KeyCode::KeyO, pair(b'r', b'R')), (KeyCode::KeyP, pair(b'l', b'L')),
+		(KeyCode::KeyLeftSquareBracket, pair(b'/', b'?')),
+		(KeyCode::KeyRightSquareBracket, pair(b'=', b'+')),
+		(KeyCode::KeyEnter, same(b'\n')),
+		(KeyCode::KeyA, pair(b'a', b'A')), (KeyCode::KeyS, pair(b'o', b'O')),
+		(KeyCode::KeyD, pair(b'e', b'E')), (KeyCode::KeyF, pair(b'u', b'U')),
+		(KeyCode::KeyG, pair(b'i', b'I')), (KeyCode::KeyH, pair(b'd', b'D')),
+		(KeyCode::KeyJ, pair(b'h', b'H')), (KeyCode::KeyK, pair(b't', b'T')),
+		(KeyCode::KeyL, pair(b'n', b'N')),
+		(KeyCode::KeySemicolon, pair(b's', b'S')),
+		(KeyCode::KeyApostrophe, pair(b'-', b'_')),
+		(KeyCode::KeyBackSlash, pair(b'\\', b'|')),
+		(KeyCode::KeyExtraBackSlash, pair(b'\\', b'|')),
+		(KeyCode::KeyZ, pair(b';', b':')), (KeyCode::KeyX, pair(b'q', b'Q')),
+		(KeyCode::KeyC, pair(b'j', b'J')), (KeyCode::KeyV, pair(b'k', b'K')),
+		(KeyCode::KeyB, pair(b'x', b'X')), (KeyCode::KeyN, pair(b'b', b'B')),
+		(KeyCode::KeyM, pair(b'm', b'M')),
+		(KeyCode::KeyComma, pair(b'w', b'W')), (KeyCode::KeyPeriod, pair(b'v', b'V')),
+		(KeyCode::KeySlash, pair(b'z', b'Z')),
+		(KeyCode::KeySpace, same(b' ')),
+		(KeyCode::KeyNumpadSlash, same(b'/')), (KeyCode::KeyNumpadAsterisk, same(b'*')),
+		(KeyCode::KeyNumpadMinus, same(b'-')), (KeyCode::KeyNumpadPlus, same(b'+')),
+		(KeyCode::KeyNumpadEnter, same(b'\n')), (KeyCode::KeyNumpadPeriod, same(b'.')),
+		(KeyCode::KeyNumpad0, same(b'0')), (KeyCode::KeyNumpad1, same(b'1')),
+		(KeyCode::KeyNumpad2, same(b'2')), (KeyCode::KeyNumpad3, same(b'3')),
+		(KeyCode::KeyNumpad4, same(b'4')), (KeyCode::KeyNumpad5, same(b'5')),
+		(KeyCode::KeyNumpad6, same(b'6')), (KeyCode::KeyNumpad7, same(b'7')),
+		(KeyCode::KeyNumpad8, same(b'8')), (KeyCode::KeyNumpad9, same(b'9')),
+	]),
+	compose_rules: STANDARD_COMPOSE_RULES,
+};
+
+/// The Colemak layout. Only the letter keys and the semicolon key move from their QWERTY positions;
+/// the number row, the rest of the punctuation, and the numpad are unchanged.
+static US_COLEMAK: Keymap = Keymap {
+	name: "us-colemak",
+	entries: build_keymap(&[
+		(KeyCode::KeyBackTick, pair(b'`', b'~')),
+		(KeyCode::Key1, pair(b'1', b'!')), (KeyCode::Key2, pair(b'2', b'@')),
+		(KeyCode::Key3, pair(b'3', b'#')), (KeyCode::Key4, pair(b'4', b'$')),
+		(KeyCode::Key5, pair(b'5', b'%')), (KeyCode::Key6, pair(b'6', b'^')),
+		(KeyCode::Key7, pair(b'7', b'&')), (KeyCode::Key8, pair(b'8', b'*')),
+		(KeyCode::Key9, pair(b'9', b'(')), (KeyCode::Key0, pair(b'0', b')')),
+		(KeyCode::KeyMinus, pair(b'-', b'_')), (KeyCode::KeyEquals, pair(b'=', b'+')),
+		(KeyCode::KeyBackspace, same(8)), (KeyCode::KeyTab, same(b'\t')),
+		(KeyCode::KeyQ, pair(b'q', b'Q')), (KeyCode::KeyW, pair(b'w', b'W')),
+		(KeyCode::KeyE, pair(b'f', b'F')), (KeyCode::KeyR, pair(b'p', b'P')),
+		(KeyCode::KeyT, pair(b'g', b'G')), (KeyCode::KeyY, pair(b'j', b'J')),
+		(KeyCode::KeyU, pair(b'l', b'L')), (KeyCode::KeyI, pair(b'u', b'U')),
+		(KeyCode::KeyO, pair(b'y', b'Y')), (KeyCode::KeyP, pair(b';', b':')),
+		(KeyCode::KeyLeftSquareBracket, pair(b'[', b'{')),
+		(KeyCode::KeyRightSquareBracket, pair(b']', b'}')),
+		(KeyCode::KeyEnter, same(b'\n')),
+		(KeyCode::KeyA, pair(b'a', b'A')), (KeyCode::KeyS, pair(b'r', b'R')),
+		(KeyCode::KeyD, pair(b's', b'S')), (KeyCode::KeyF, pair(b't', b'T')),
+		(KeyCode::KeyG, pair(b'd', b'D')), (KeyCode::KeyH, pair(b'h', b'H')),
+		(KeyCode::KeyJ, pair(b'n', b'N')), (KeyCode::KeyK, pair(b'e', b'E')),
+		(KeyCode::KeyL, pair(b'i', b'I')),
+		(KeyCode::KeySemicolon, pair(b'o', b'O')),
+		(KeyCode::KeyApostrophe, pair(b'\'', b'"')),
+		(KeyCode::KeyBackSlash, pair(b'\\', b'|')),
+		(KeyCode::KeyExtraBackSlash, pair(b'\\', b'|')),
+		(KeyCode::KeyZ, pair(b'z', b'Z')), (KeyCode::KeyX, pair(b'x', b'X')),
+		(KeyCode::KeyC, pair(b'c', b'C')), (KeyCode::KeyV, pair(b'v', b'V')),
+		(KeyCode::KeyB, pair(b'b', b'B')), (KeyCode::KeyN, pair(b'k', b'K')),
+		(KeyCode::KeyM, pair(b'm', b'M')),
+		(KeyCode::KeyComma, pair(b',', b'<')), (KeyCode::KeyPeriod, pair(b'.', b'>')),
+		(KeyCode::KeySlash, pair(b'/', b'?')),
+		(KeyCode::KeySpace, same(b' ')),
+		(KeyCode::KeyNumpadSlash, same(b'/')), (KeyCode::KeyNumpadAsterisk, same(b'*')),
+		(KeyCode::KeyNumpadMinus, same(b'-')), (KeyCode::KeyNumpadPlus, same(b'+')),
+		(KeyCode::KeyNumpadEnter, same(b'\n')), (KeyCode::KeyNumpadPeriod, same(b'.')),
+		(KeyCode::KeyNumpad0, same(b'0')), (KeyCode::KeyNumpad1, same(b'1')),
+		(KeyCode::KeyNumpad2, same(b'2')), (KeyCode::KeyNumpad3, same(b'3')),
+		(KeyCode::KeyNumpad4, same(b'4')), (KeyCode::KeyNumpad5, same(b'5')),
+		(KeyCode::KeyNumpad6, same(b'6')), (KeyCode::KeyNumpad7, same(b'7')),
+		(KeyCode::KeyNumpad8, same(b'8')), (KeyCode::KeyNumpad9, same(b'9')),
+	]),
+	compose_rules: STANDARD_COMPOSE_RULES,
+};
+
+/// Looks up a layout by the name passed to `set_layout`, case-insensitively. Unknown names fall back
+/// to QWERTY, same as the Fuchsia keymaps library this is modeled after.
+fn find_keymap(name: &str) -> &'static Keymap {
+	if name.eq_ignore_ascii_case(US_DVORAK.name) {
+		&US_DVORAK
+	} else if name.eq_ignore_ascii_case(US_COLEMAK.name) {
+		&US_COLEMAK
+	} else {
+		&US_QWERTY
+	}
+}
+
+/// Selects the keyboard layout `as_ascii` decodes key presses against by name (`"us-qwerty"`,
+/// `"us-dvorak"`, `"us-colemak"`, ...). An unrecognized name falls back to QWERTY.
+pub fn set_layout(name: &str) {
+	KEYBOARD_STATE.acquire().active_keymap = find_keymap(name);
+}
+
+/// Returns the name of the currently active keyboard layout
+pub fn get_layout() -> &'static str {
+	KEYBOARD_STATE.acquire().active_keymap.name
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum KeyEventType {
 	KeyDown,
 	KeyUp,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct KeyEvent {
 	pub key_code: KeyCode,
 	pub event_type: KeyEventType,
-	
+
 	// Modifiers
 	pub shift_down: bool,
 	pub ctrl_down: bool,
@@ -180,179 +460,70 @@ pub struct KeyEvent {
 	pub logo_down: bool,
 	pub caps_lock_enabled: bool,
 	pub number_lock_enabled: bool,
+
+	/// Set on a synthetic KeyDown re-emitted by typematic auto-repeat (see `set_repeat`) instead of
+	/// a genuine physical press, so consumers that care (e.g. a game tracking distinct key presses)
+	/// can tell the two apart instead of seeing what looks like the key being mashed
+	pub repeat: bool,
 }
 
 impl KeyEvent {
-	/// Returns the ASCII representation of the pressed key, modifier keys are respected. `None` is
-	/// returned if the key press does not have an ASCII representation.
-	fn as_ascii(&self) -> Option<u8> {
+	/// Returns the ASCII representation of the pressed key under the currently active keyboard
+	/// layout (see `set_layout`), modifier keys are respected. `None` is returned if the key press
+	/// does not have an ASCII representation.
+	pub(crate) fn as_ascii(&self) -> Option<u8> {
 		// If Control/Alt/Logo is down, this is not a normal text key.
 		if self.ctrl_down || self.alt_down || self.logo_down {
 			return None;
 		}
 
+		self.layout_char()
+	}
+
+	/// Looks this key up in the active layout under the current Shift/CapsLock/NumLock state,
+	/// ignoring Ctrl/Alt/Logo. Used by `as_ascii` (which blocks out Ctrl/Alt/Logo-held keys entirely)
+	/// and by `encode_terminal`'s CSI-u encoding, which needs the underlying letter even when it was
+	/// pressed with Ctrl or Alt held (e.g. to report that Ctrl+C was pressed, not just "Ctrl").
+	fn layout_char(&self) -> Option<u8> {
+		self.layout_char_with(KEYBOARD_STATE.acquire().active_keymap)
+	}
+
+	/// The lookup behind `layout_char`, taking the keymap directly instead of acquiring
+	/// `KEYBOARD_STATE` for it - needed by `key_pressed_event`/`repeat_tick`/compose resolution, which
+	/// already hold the keyboard state guard and would panic re-acquiring it through `layout_char`
+	fn layout_char_with(&self, keymap: &Keymap) -> Option<u8> {
 		let ascii_code = if self.key_code.is_in_numpad() {
-			// If this is a numpad key, then the number lock has to be respected. If the number lock
-			// is not enabled, or if shift down (even if number lock is enabled), the numbers act as
-			// their action-counterpart, and not as text.
-			if !self.number_lock_enabled || self.shift_down {
-				match self.key_code {
-					KeyCode::KeyNumpadSlash => b'/',
-					KeyCode::KeyNumpadAsterisk => b'*',
-					KeyCode::KeyNumpadMinus => b'-',
-					KeyCode::KeyNumpadPlus => b'+',
-					KeyCode::KeyNumpadEnter => b'\n',
-					KeyCode::KeyNumpadPeriod => b'.',
-					_ => 0
-				}
+			// Numpad digits only act as text if number lock is enabled and shift is not held (shift
+			// turns them back into their navigation-key counterpart, same as a real keyboard); the
+			// operator keys (and Enter/the decimal point) always act as text. Both cases just look
+			// the character up in the table - which key is which is layout-independent, physical
+			// numpad geometry, so this branching stays as shared code.
+			let is_digit = matches!(self.key_code,
+				KeyCode::KeyNumpad0 | KeyCode::KeyNumpad1 | KeyCode::KeyNumpad2 |
+				KeyCode::KeyNumpad3 | KeyCode::KeyNumpad4 | KeyCode::KeyNumpad5 |
+				KeyCode::KeyNumpad6 | KeyCode::KeyNumpad7 | KeyCode::KeyNumpad8 |
+				KeyCode::KeyNumpad9);
+
+			if is_digit && (!self.number_lock_enabled || self.shift_down) {
+				0
 			} else {
-				match self.key_code {
-					KeyCode::KeyNumpad0 => b'0',
-					KeyCode::KeyNumpad1 => b'1',
-					KeyCode::KeyNumpad2 => b'2',
-					KeyCode::KeyNumpad3 => b'3',
-					KeyCode::KeyNumpad4 => b'4',
-					KeyCode::KeyNumpad5 => b'5',
-					KeyCode::KeyNumpad6 => b'6',
-					KeyCode::KeyNumpad7 => b'7',
-					KeyCode::KeyNumpad8 => b'8',
-					KeyCode::KeyNumpad9 => b'9',
-					KeyCode::KeyNumpadSlash => b'/',
-					KeyCode::KeyNumpadAsterisk => b'*',
-					KeyCode::KeyNumpadMinus => b'-',
-					KeyCode::KeyNumpadPlus => b'+',
-					KeyCode::KeyNumpadEnter => b'\n',
-					KeyCode::KeyNumpadPeriod => b'.',
-					_ => 0
-				}
+				keymap.entry(self.key_code).base
 			}
 		} else if self.key_code.is_letter() {
 			// If this is a letter key, caps lock has to be respected. Shift and caps lock both
 			// switch from lower-case letters to upper-case letters, but if both caps lock is
 			// enabled and shift down the effect is canceled and the letters are lower-case.
 			if self.shift_down ^ self.caps_lock_enabled {
-				match self.key_code {
-					KeyCode::KeyA => b'A',
-					KeyCode::KeyB => b'B',
-					KeyCode::KeyC => b'C',
-					KeyCode::KeyD => b'D',
-					KeyCode::KeyE => b'E',
-					KeyCode::KeyF => b'F',
-					KeyCode::KeyG => b'G',
-					KeyCode::KeyH => b'H',
-					KeyCode::KeyI => b'I',
-					KeyCode::KeyJ => b'J',
-					KeyCode::KeyK => b'K',
-					KeyCode::KeyL => b'L',
-					KeyCode::KeyM => b'M',
-					KeyCode::KeyN => b'N',
-					KeyCode::KeyO => b'O',
-					KeyCode::KeyP => b'P',
-					KeyCode::KeyQ => b'Q',
-					KeyCode::KeyR => b'R',
-					KeyCode::KeyS => b'S',
-					KeyCode::KeyT => b'T',
-					KeyCode::KeyU => b'U',
-					KeyCode::KeyV => b'V',
-					KeyCode::KeyW => b'W',
-					KeyCode::KeyX => b'X',
-					KeyCode::KeyY => b'Y',
-					KeyCode::KeyZ => b'Z',
-					_ => 0
-				}
+				keymap.entry(self.key_code).shifted
 			} else {
-				match self.key_code {
-					KeyCode::KeyA => b'a',
-					KeyCode::KeyB => b'b',
-					KeyCode::KeyC => b'c',
-					KeyCode::KeyD => b'd',
-					KeyCode::KeyE => b'e',
-					KeyCode::KeyF => b'f',
-					KeyCode::KeyG => b'g',
-					KeyCode::KeyH => b'h',
-					KeyCode::KeyI => b'i',
-					KeyCode::KeyJ => b'j',
-					KeyCode::KeyK => b'k',
-					KeyCode::KeyL => b'l',
-					KeyCode::KeyM => b'm',
-					KeyCode::KeyN => b'n',
-					KeyCode::KeyO => b'o',
-					KeyCode::KeyP => b'p',
-					KeyCode::KeyQ => b'q',
-					KeyCode::KeyR => b'r',
-					KeyCode::KeyS => b's',
-					KeyCode::KeyT => b't',
-					KeyCode::KeyU => b'u',
-					KeyCode::KeyV => b'v',
-					KeyCode::KeyW => b'w',
-					KeyCode::KeyX => b'x',
-					KeyCode::KeyY => b'y',
-					KeyCode::KeyZ => b'z',
-					_ => 0
-				}
+				keymap.entry(self.key_code).base
 			}
 		} else {
 			// Keys have different meaning if the shift key is down
 			if self.shift_down {
-				match self.key_code {
-					KeyCode::KeyBackTick => b'~',
-					KeyCode::Key1 => b'!',
-					KeyCode::Key2 => b'@',
-					KeyCode::Key3 => b'#',
-					KeyCode::Key4 => b'$',
-					KeyCode::Key5 => b'%',
-					KeyCode::Key6 => b'^',
-					KeyCode::Key7 => b'&',
-					KeyCode::Key8 => b'*',
-					KeyCode::Key9 => b'(',
-					KeyCode::Key0 => b')',
-					KeyCode::KeyMinus => b'_',
-					KeyCode::KeyEquals => b'+',
-					KeyCode::KeyBackspace => 8, // TODO: Should I really do this?
-					KeyCode::KeyTab => b'\t',
-					KeyCode::KeyLeftSquareBracket => b'{',
-					KeyCode::KeyRightSquareBracket => b'}',
-					KeyCode::KeyEnter => b'\n',
-					KeyCode::KeySemicolon => b':',
-					KeyCode::KeyApostrophe => b'"',
-					KeyCode::KeyBackSlash => b'|',
-					KeyCode::KeyExtraBackSlash => b'|',
-					KeyCode::KeyComma => b'<',
-					KeyCode::KeyPeriod => b'>',
-					KeyCode::KeySlash => b'?',
-					KeyCode::KeySpace => b' ',
-					_ => 0
-				}
+				keymap.entry(self.key_code).shifted
 			} else {
-				match self.key_code {
-					KeyCode::KeyBackTick => b'`',
-					KeyCode::Key1 => b'1',
-					KeyCode::Key2 => b'2',
-					KeyCode::Key3 => b'3',
-					KeyCode::Key4 => b'4',
-					KeyCode::Key5 => b'5',
-					KeyCode::Key6 => b'6',
-					KeyCode::Key7 => b'7',
-					KeyCode::Key8 => b'8',
-					KeyCode::Key9 => b'9',
-					KeyCode::Key0 => b'0',
-					KeyCode::KeyMinus => b'-',
-					KeyCode::KeyEquals => b'=',
-					KeyCode::KeyBackspace => 8, // TODO: Should I really do this?
-					KeyCode::KeyTab => b'\t',
-					KeyCode::KeyLeftSquareBracket => b'[',
-					KeyCode::KeyRightSquareBracket => b']',
-					KeyCode::KeyEnter => b'\n',
-					KeyCode::KeySemicolon => b';',
-					KeyCode::KeyApostrophe => b'\'',
-					KeyCode::KeyBackSlash => b'\\',
-					KeyCode::KeyExtraBackSlash => b'\\',
-					KeyCode::KeyComma => b',',
-					KeyCode::KeyPeriod => b'.',
-					KeyCode::KeySlash => b'/',
-					KeyCode::KeySpace => b' ',
-					_ => 0
-				}
+				keymap.entry(self.key_code).base
 			}
 		};
 
@@ -364,6 +535,362 @@ impl KeyEvent {
 			None
 		}
 	}
+
+	/// Encodes this key press as an xterm-compatible terminal escape sequence: always for arrows,
+	/// F-keys and Home/End/Insert/Delete/PageUp/PageDown, and, only when `modes.csi_u` is set, as
+	/// CSI-u for any other key with a layout character held with Shift, Ctrl or Alt down. Returns
+	/// `None` for releases (terminals only report presses), for every other key `as_ascii` covers,
+	/// and for keys with no terminal encoding at all (pure modifier/lock keys).
+	pub fn encode_terminal(&self, modes: TerminalModes) -> Option<TerminalSequence> {
+		if self.event_type != KeyEventType::KeyDown {
+			return None;
+		}
+
+		// xterm's parameterized modifier number: 1 (no modifiers) + 1 (Shift) + 2 (Alt) + 4 (Ctrl)
+		let modifier_param = 1
+			+ if self.shift_down { 1 } else { 0 }
+			+ if self.alt_down { 2 } else { 0 }
+			+ if self.ctrl_down { 4 } else { 0 };
+		let modified = modifier_param != 1;
+
+		if let Some(form) = terminal_key_form(self.key_code) {
+			let mut seq = TerminalSequence::empty();
+			match form {
+				TerminalKeyForm::Arrow(letter) => {
+					if modified {
+						seq.push_csi();
+						seq.push_decimal(1);
+						seq.push_byte(b';');
+						seq.push_decimal(modifier_param);
+					} else if modes.application_cursor {
+						seq.push_ss3();
+					} else {
+						seq.push_csi();
+					}
+					seq.push_byte(letter);
+				},
+				TerminalKeyForm::Bracket(letter) => {
+					seq.push_csi();
+					if modified {
+						seq.push_decimal(1);
+						seq.push_byte(b';');
+						seq.push_decimal(modifier_param);
+					}
+					seq.push_byte(letter);
+				},
+				TerminalKeyForm::Ss3(letter) => {
+					if modified {
+						seq.push_csi();
+						seq.push_decimal(1);
+						seq.push_byte(b';');
+						seq.push_decimal(modifier_param);
+					} else {
+						seq.push_ss3();
+					}
+					seq.push_byte(letter);
+				},
+				TerminalKeyForm::Tilde(number) => {
+					seq.push_csi();
+					seq.push_decimal(number as usize);
+					if modified {
+						seq.push_byte(b';');
+						seq.push_decimal(modifier_param);
+					}
+					seq.push_byte(b'~');
+				},
+			}
+
+			return Some(seq);
+		}
+
+		// No dedicated escape sequence for this key - if CSI-u is enabled and a modifier is held,
+		// fall back to reporting the underlying character with its modifiers, so Ctrl+letter doesn't
+		// just collapse to nothing the way it does through `as_ascii`.
+		if modes.csi_u && modified {
+			let codepoint = self.layout_char()?;
+			let mut seq = TerminalSequence::empty();
+			seq.push_csi();
+			seq.push_decimal(codepoint as usize);
+			seq.push_byte(b';');
+			seq.push_decimal(modifier_param);
+			seq.push_byte(b'u');
+			return Some(seq);
+		}
+
+		None
+	}
+}
+
+/// Terminal modes that affect how `KeyEvent::encode_terminal` renders a key. These are set by the
+/// host terminal session (e.g. in response to the application receiving a DECCKM escape sequence),
+/// not tracked as keyboard state, so they're passed in rather than living on `KeyboardState`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct TerminalModes {
+	/// DECCKM: arrow keys emit `ESC O <letter>` instead of `ESC [ <letter>` when unmodified
+	pub application_cursor: bool,
+	/// Encode a Ctrl/Alt/Shift-modified key that has no dedicated escape sequence as CSI-u
+	/// (`ESC [ <codepoint> ; <modifiers> u`) instead of dropping it
+	pub csi_u: bool,
+}
+
+/// Which escape-sequence family a key's terminal encoding belongs to, if it has one at all
+#[derive(Clone, Copy)]
+enum TerminalKeyForm {
+	/// `ESC O <letter>` unmodified (`ESC [ <letter>` instead if `application_cursor` isn't set) /
+	/// `ESC [ 1 ; M <letter>` modified - the arrow keys, the only ones application-cursor mode affects
+	Arrow(u8),
+	/// `ESC [ <letter>` unmodified / `ESC [ 1 ; M <letter>` modified - Home/End
+	Bracket(u8),
+	/// `ESC O <letter>` unmodified / `ESC [ 1 ; M <letter>` modified - F1-F4
+	Ss3(u8),
+	/// `ESC [ <n> ~` unmodified / `ESC [ <n> ; M ~` modified - F5-F12, Insert/Delete/PageUp/PageDown
+	Tilde(u8),
+}
+
+/// Maps a `KeyCode` to the terminal escape-sequence family it encodes as, if any - see `TerminalKeyForm`
+fn terminal_key_form(key_code: KeyCode) -> Option<TerminalKeyForm> {
+	Some(match key_code {
+		KeyCode::KeyUpArrow => TerminalKeyForm::Arrow(b'A'),
+		KeyCode::KeyDownArrow => TerminalKeyForm::Arrow(b'B'),
+		KeyCode::KeyRightArrow => TerminalKeyForm::Arrow(b'C'),
+		KeyCode::KeyLeftArrow => TerminalKeyForm::Arrow(b'D'),
+		KeyCode::KeyHome => TerminalKeyForm::Bracket(b'H'),
+		KeyCode::KeyEnd => TerminalKeyForm::Bracket(b'F'),
+		KeyCode::KeyF1 => TerminalKeyForm::Ss3(b'P'),
+		KeyCode::KeyF2 => TerminalKeyForm::Ss3(b'Q'),
+		KeyCode::KeyF3 => TerminalKeyForm::Ss3(b'R'),
+		KeyCode::KeyF4 => TerminalKeyForm::Ss3(b'S'),
+		KeyCode::KeyInsert => TerminalKeyForm::Tilde(2),
+		KeyCode::KeyDelete => TerminalKeyForm::Tilde(3),
+		KeyCode::KeyPageUp => TerminalKeyForm::Tilde(5),
+		KeyCode::KeyPageDown => TerminalKeyForm::Tilde(6),
+		KeyCode::KeyF5 => TerminalKeyForm::Tilde(15),
+		KeyCode::KeyF6 => TerminalKeyForm::Tilde(17),
+		KeyCode::KeyF7 => TerminalKeyForm::Tilde(18),
+		KeyCode::KeyF8 => TerminalKeyForm::Tilde(19),
+		KeyCode::KeyF9 => TerminalKeyForm::Tilde(20),
+		KeyCode::KeyF10 => TerminalKeyForm::Tilde(21),
+		KeyCode::KeyF11 => TerminalKeyForm::Tilde(23),
+		KeyCode::KeyF12 => TerminalKeyForm::Tilde(24),
+		_ => return None,
+	})
+}
+
+/// A small stack-allocated terminal escape sequence, as produced by `KeyEvent::encode_terminal`. 16
+/// bytes comfortably covers the longest sequence we build (a CSI-u report, `ESC [ 255 ; 8 u`).
+#[derive(Clone, Copy)]
+pub struct TerminalSequence {
+	bytes: [u8; 16],
+	len: u8,
+}
+
+impl TerminalSequence {
+	fn empty() -> Self {
+		Self { bytes: [0; 16], len: 0 }
+	}
+
+	fn push_byte(&mut self, byte: u8) {
+		self.bytes[self.len as usize] = byte;
+		self.len += 1;
+	}
+
+	/// Appends `n`'s decimal digits (no leading zeroes); every value we ever encode fits in three
+	/// digits (the largest is a CSI-u codepoint, at most 255)
+	fn push_decimal(&mut self, n: usize) {
+		let hundreds = n / 100;
+		let tens = (n % 100) / 10;
+		let ones = n % 10;
+
+		let mut printing = false;
+		if hundreds > 0 {
+			self.push_byte(b'0' + hundreds as u8);
+			printing = true;
+		}
+		if printing || tens > 0 {
+			self.push_byte(b'0' + tens as u8);
+		}
+		self.push_byte(b'0' + ones as u8);
+	}
+
+	fn push_csi(&mut self) {
+		self.push_byte(0x1B);
+		self.push_byte(b'[');
+	}
+
+	fn push_ss3(&mut self) {
+		self.push_byte(0x1B);
+		self.push_byte(b'O');
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.bytes[..self.len as usize]
+	}
+}
+
+/// A key event decoded into something an application can actually consume: either a character (for
+/// printable keys, with Shift/CapsLock/NumLock already folded in) or the raw key code (for
+/// everything else, e.g. arrows or function keys, which have no textual representation)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodedKey {
+	Unicode(char),
+	RawKey(KeyCode),
+}
+
+/// The number of decoded keys that can be queued up before being read by `next_decoded_key`
+const DECODED_KEY_QUEUE_CAPACITY: usize = 16;
+
+/// A small fixed-capacity ring buffer of decoded keys, written to by `key_pressed_event` and drained
+/// by `next_decoded_key`
+struct DecodedKeyQueue {
+	buffer: [Option<DecodedKey>; DECODED_KEY_QUEUE_CAPACITY],
+	head: usize,
+	len: usize,
+}
+
+impl DecodedKeyQueue {
+	const fn new() -> Self {
+		Self {
+			buffer: [None; DECODED_KEY_QUEUE_CAPACITY],
+			head: 0,
+			len: 0,
+		}
+	}
+
+	/// Pushes `key` onto the back of the queue. If the queue is already full, the oldest queued key
+	/// is silently dropped to make room, so a slow consumer only ever loses history, not liveness.
+	fn push(&mut self, key: DecodedKey) {
+		let tail = (self.head + self.len) % DECODED_KEY_QUEUE_CAPACITY;
+		self.buffer[tail] = Some(key);
+
+		if self.len < DECODED_KEY_QUEUE_CAPACITY {
+			self.len += 1;
+		} else {
+			// The queue was full, so we just overwrote the oldest entry - advance `head` past it
+			self.head = (self.head + 1) % DECODED_KEY_QUEUE_CAPACITY;
+		}
+	}
+
+	/// Pops the oldest queued key, if any
+	fn pop(&mut self) -> Option<DecodedKey> {
+		if self.len == 0 {
+			return None;
+		}
+
+		let key = self.buffer[self.head].take();
+		self.head = (self.head + 1) % DECODED_KEY_QUEUE_CAPACITY;
+		self.len -= 1;
+		key
+	}
+}
+
+/// The global queue of decoded keys awaiting consumption via `next_decoded_key`
+static DECODED_KEY_QUEUE: ExclusiveCell<DecodedKeyQueue> = ExclusiveCell::new(DecodedKeyQueue::new());
+
+/// Returns the next decoded key pressed by the user, if any are queued up. Printable keys are
+/// reported as `DecodedKey::Unicode`, with Shift/CapsLock/NumLock already applied; everything else
+/// (arrows, function keys, modifiers themselves, ...) is reported as `DecodedKey::RawKey` so
+/// applications can still react to keys with no textual representation.
+pub fn next_decoded_key() -> Option<DecodedKey> {
+	DECODED_KEY_QUEUE.acquire().pop()
+}
+
+/// A raw key code level event (no modifier/lock decoding applied), as pushed directly from
+/// `key_pressed_event`/`key_released_event` into `RAW_KEY_EVENT_QUEUE`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawKeyEvent {
+	pub key_code: KeyCode,
+	pub pressed: bool,
+}
+
+/// The number of raw key events that can be queued up before being read by `next_key_event`
+const RAW_KEY_EVENT_QUEUE_CAPACITY: usize = 128;
+
+/// A fixed-capacity ring buffer of raw key events, with overrun detection instead of
+/// `DecodedKeyQueue`'s drop-the-oldest behaviour: losing which keys were pressed is worse than
+/// losing which characters they decoded to, so a full queue here drops the *new* event and records
+/// an overrun instead, the same way the PS/2 controller's own scan code buffer reports an overrun
+/// with a dedicated code (0x00/0xFF) rather than silently discarding history.
+struct RawKeyEventQueue {
+	buffer: [Option<RawKeyEvent>; RAW_KEY_EVENT_QUEUE_CAPACITY],
+	head: usize,
+	len: usize,
+	/// Set when an incoming event had to be dropped because the queue was full. Cleared the next
+	/// time `pop` is called, which surfaces a synthetic overrun marker for it first.
+	overrun_pending: bool,
+}
+
+impl RawKeyEventQueue {
+	const fn new() -> Self {
+		Self {
+			buffer: [None; RAW_KEY_EVENT_QUEUE_CAPACITY],
+			head: 0,
+			len: 0,
+			overrun_pending: false,
+		}
+	}
+
+	/// Pushes `event` onto the back of the queue, or records an overrun if it is already full
+	fn push(&mut self, event: RawKeyEvent) {
+		if self.len == RAW_KEY_EVENT_QUEUE_CAPACITY {
+			self.overrun_pending = true;
+			return;
+		}
+
+		let tail = (self.head + self.len) % RAW_KEY_EVENT_QUEUE_CAPACITY;
+		self.buffer[tail] = Some(event);
+		self.len += 1;
+	}
+
+	/// Pops the oldest queued event, if any. A pending overrun is surfaced first, as a
+	/// `KeyCode::Unknown` pressed event, so the consumer knows events were lost before it sees the
+	/// next real one.
+	fn pop(&mut self) -> Option<RawKeyEvent> {
+		if self.overrun_pending {
+			self.overrun_pending = false;
+			return Some(RawKeyEvent { key_code: KeyCode::Unknown, pressed: true });
+		}
+
+		if self.len == 0 {
+			return None;
+		}
+
+		let event = self.buffer[self.head].take();
+		self.head = (self.head + 1) % RAW_KEY_EVENT_QUEUE_CAPACITY;
+		self.len -= 1;
+		event
+	}
+}
+
+/// The global queue of raw key events awaiting consumption via `next_key_event`
+static RAW_KEY_EVENT_QUEUE: ExclusiveCell<RawKeyEventQueue> = ExclusiveCell::new(RawKeyEventQueue::new());
+
+/// Returns the next raw key press/release event, if any are queued up, or a `KeyCode::Unknown`
+/// pressed event if the queue overran and some events were lost since the last call
+pub fn next_key_event() -> Option<RawKeyEvent> {
+	RAW_KEY_EVENT_QUEUE.acquire().pop()
+}
+
+/// The number of fully-decoded `KeyEvent`s (modifier/lock state and all) that can be queued up
+/// before being read by a process through `/dev/keyboard` or stdin - see `syscall::syscall_read`
+const KEYBOARD_EVENTS_QUEUE_CAPACITY: usize = 64;
+
+/// The queue backing both the stdin ASCII read path and `/dev/keyboard`'s raw event reads. Unlike
+/// `DECODED_KEY_QUEUE`/`RAW_KEY_EVENT_QUEUE` above, this one is a single-producer/single-consumer
+/// `ProducerConsumer` rather than an `ExclusiveCell`-guarded ring buffer, since the consumer (a
+/// blocking `read` syscall) needs to be able to spin waiting for an event without holding a lock the
+/// keyboard interrupt handler would then deadlock trying to acquire.
+pub(crate) static KEYBOARD_EVENTS_QUEUE: ProducerConsumer<KeyEvent, KEYBOARD_EVENTS_QUEUE_CAPACITY> =
+	ProducerConsumer::new();
+
+/// How far into a compose sequence (see `set_compose_trigger`) we currently are
+#[derive(Clone, Copy, PartialEq)]
+enum ComposeState {
+	/// No compose sequence in progress
+	Idle,
+	/// The compose trigger was pressed; waiting for the first key of the sequence
+	WaitingFirst,
+	/// The first key resolved to this character; waiting for the second key of the sequence
+	WaitingSecond(u8),
 }
 
 struct KeyboardState {
@@ -373,6 +900,17 @@ struct KeyboardState {
 	number_lock_enabled: bool,
 	caps_lock_enabled: bool,
 	scroll_lock_enabled: bool,
+
+	/// The keymap `as_ascii` currently decodes key presses against, changed via `set_layout`
+	active_keymap: &'static Keymap,
+
+	/// The non-modifier key typematic repeat is currently re-emitting KeyDowns for, if any - see
+	/// `set_repeat`. Only one key repeats at a time, same as a real keyboard: pressing a second key
+	/// while the first is still held takes over rather than repeating both.
+	repeating_key: Option<KeyCode>,
+
+	/// Where we are in a compose sequence - see `set_compose_trigger`
+	compose_state: ComposeState,
 }
 
 impl KeyboardState {
@@ -383,30 +921,120 @@ impl KeyboardState {
 			number_lock_enabled: true,
 			caps_lock_enabled: false,
 			scroll_lock_enabled: false,
+			active_keymap: &US_QWERTY,
+			repeating_key: None,
+			compose_state: ComposeState::Idle,
 		}
 	}
 }
 
+/// The key that starts a compose sequence, changed via `set_compose_trigger`. Defaults to the Menu
+/// key, since it produces no ASCII of its own in any layout and isn't otherwise used. Stored as the
+/// raw discriminant so it can live in an atomic; `compose_trigger` converts it back.
+static COMPOSE_TRIGGER: AtomicU8 = AtomicU8::new(KeyCode::KeyMenu as u8);
+
+/// Designates `key_code` as the key that starts a compose sequence (see `KeyboardState`'s
+/// `compose_state` field): pressing it, then up to two keys that resolve through the active
+/// layout's `ComposeRule` table, produces a single composed character instead of either key's own
+/// output. A common alternative to the default (Menu) is Right-Alt, used as an AltGr key.
+pub fn set_compose_trigger(key_code: KeyCode) {
+	COMPOSE_TRIGGER.store(key_code as u8, Ordering::Relaxed);
+}
+
+fn compose_trigger() -> KeyCode {
+	let raw = COMPOSE_TRIGGER.load(Ordering::Relaxed);
+	// Safety: only ever stored by `set_compose_trigger`, from a valid `KeyCode`
+	unsafe { core::mem::transmute(raw) }
+}
+
+/// Default typematic delay before a held key starts auto-repeating, and default repeat rate once
+/// it does - see `set_repeat`
+const DEFAULT_REPEAT_DELAY_MS: u32 = 500;
+const DEFAULT_REPEAT_RATE_HZ: u32 = 10;
+
+static REPEAT_DELAY_MS: AtomicU32 = AtomicU32::new(DEFAULT_REPEAT_DELAY_MS);
+static REPEAT_RATE_HZ: AtomicU32 = AtomicU32::new(DEFAULT_REPEAT_RATE_HZ);
+
+/// Configures typematic auto-repeat: once a held non-modifier, non-lock key has been down for
+/// `delay_ms` without being released, a synthetic KeyDown `KeyEvent` (with `repeat: true`) is
+/// re-emitted for it every `1000 / rate_hz` milliseconds until it's released. Takes effect the next
+/// time a key is pressed.
+pub fn set_repeat(delay_ms: u32, rate_hz: u32) {
+	REPEAT_DELAY_MS.store(delay_ms, Ordering::Relaxed);
+	REPEAT_RATE_HZ.store(rate_hz.max(1), Ordering::Relaxed);
+}
+
+/// Timer callback driving typematic repeat, scheduled through `crate::interrupts::pit_8254::after`
+/// (whose callback type is a plain `fn()`, so this reads the key to repeat from `KEYBOARD_STATE`
+/// rather than having it passed in). Reschedules itself for as long as some key is still marked as
+/// repeating; a released key (`repeating_key` back to `None`) just lets the pending call do nothing
+/// instead of rescheduling, which is also why `key_pressed_event` only kicks off a new timer chain
+/// when none is already running - a still-running chain will pick up whichever key is now held.
+fn repeat_tick() {
+	let keyboard_state = KEYBOARD_STATE.acquire();
+
+	let key_code = match keyboard_state.repeating_key {
+		Some(key_code) => key_code,
+		None => return,
+	};
+
+	// Calculate the modifier states by checking both left and right variants
+	let shift_down = keyboard_state.key_state[KeyCode::KeyLeftShift as usize]
+		|| keyboard_state.key_state[KeyCode::KeyRightShift as usize];
+	let ctrl_down = keyboard_state.key_state[KeyCode::KeyLeftControl as usize]
+		|| keyboard_state.key_state[KeyCode::KeyRightControl as usize];
+	let alt_down = keyboard_state.key_state[KeyCode::KeyLeftAlt as usize]
+		|| keyboard_state.key_state[KeyCode::KeyRightAlt as usize];
+	let logo_down = keyboard_state.key_state[KeyCode::KeyLeftLogo as usize]
+		|| keyboard_state.key_state[KeyCode::KeyRightLogo as usize];
+
+	let event = KeyEvent {
+		key_code,
+		event_type: KeyEventType::KeyDown,
+		shift_down,
+		ctrl_down,
+		alt_down,
+		logo_down,
+		caps_lock_enabled: keyboard_state.caps_lock_enabled,
+		number_lock_enabled: keyboard_state.number_lock_enabled,
+		repeat: true,
+	};
+
+	// Release the keyboard state before decoding: `as_ascii` acquires `KEYBOARD_STATE` itself, and
+	// the cell panics on reentrant access - see the matching comment in `key_pressed_event`
+	drop(keyboard_state);
+
+	// Re-decode and re-deliver it the same way a genuine press is, except for the raw event queue:
+	// that stream tracks physical press/release edges, and a repeat isn't a new one of those
+	let decoded_key = match event.as_ascii() {
+		Some(ascii) => DecodedKey::Unicode(ascii as char),
+		None => DecodedKey::RawKey(key_code),
+	};
+	DECODED_KEY_QUEUE.acquire().push(decoded_key);
+	let _ = KEYBOARD_EVENTS_QUEUE.produce(event);
+
+	let rate_hz = REPEAT_RATE_HZ.load(Ordering::Relaxed).max(1);
+	crate::interrupts::pit_8254::after(Duration::from_millis((1000 / rate_hz) as u64), repeat_tick);
+}
+
 /// The global keyboard state. Access should be exclusive: we do not expect to recieve two key
 /// events simultaneously
 static KEYBOARD_STATE: ExclusiveCell<KeyboardState> = ExclusiveCell::new(KeyboardState::new());
 
-/// Updates the keyboard state given that the key with code `key_code` was pressed down
-pub fn key_pressed_event(key_code: KeyCode) {
+/// Updates the keyboard state given that the key with code `key_code` was pressed down. The lock
+/// latch states are passed in rather than tracked here, since the PS/2 driver is the one that
+/// toggles them (it also has to keep the keyboard's LEDs in sync) - see `ps2::keyboard::lock_state`.
+pub fn key_pressed_event(key_code: KeyCode, caps_lock_enabled: bool, number_lock_enabled: bool,
+	scroll_lock_enabled: bool) {
 	// Acquire exclusive access to the keyboard state
 	let mut keyboard_state = KEYBOARD_STATE.acquire();
 
 	// Save the key as currently pressed
 	keyboard_state.key_state[key_code as usize] = true;
 
-	// Toggle the relevant lock state if the lock key is pressed
-	if key_code == KeyCode::KeyCapsLock {
-		keyboard_state.caps_lock_enabled = !keyboard_state.caps_lock_enabled;
-	} else if key_code == KeyCode::KeyNumberLock {
-		keyboard_state.number_lock_enabled = !keyboard_state.number_lock_enabled;
-	} else if key_code == KeyCode::KeyScrollLock {
-		keyboard_state.scroll_lock_enabled = !keyboard_state.scroll_lock_enabled;
-	}
+	keyboard_state.caps_lock_enabled = caps_lock_enabled;
+	keyboard_state.number_lock_enabled = number_lock_enabled;
+	keyboard_state.scroll_lock_enabled = scroll_lock_enabled;
 
 	// Calculate the modifier states by checking both left and right variants
 	let shift_down = keyboard_state.key_state[KeyCode::KeyLeftShift as usize]
@@ -428,25 +1056,110 @@ pub fn key_pressed_event(key_code: KeyCode) {
 		logo_down,
 		caps_lock_enabled: keyboard_state.caps_lock_enabled,
 		number_lock_enabled: keyboard_state.number_lock_enabled,
+		repeat: false,
 	};
 
-	// FIXME: REMOVE DEBUG
-	if let Some(chr) = event.as_ascii() {
-		crate::screen::print_char(chr, crate::screen::ATTR_WHITE_ON_BLACK);
-		if chr == b'\n' {
-			crate::screen::print("> ");
+	// Also push the raw, undecoded event for consumers that want the full press/release stream
+	RAW_KEY_EVENT_QUEUE.acquire().push(RawKeyEvent { key_code, pressed: true });
+
+	// And the fully-decoded event, for stdin/`/dev/keyboard` readers - dropped silently if nobody's
+	// draining it
+	let _ = KEYBOARD_EVENTS_QUEUE.produce(event);
+
+	// Start typematic auto-repeat for this key, unless it's a modifier/lock key (holding Shift
+	// should never itself "retype"), the compose trigger (same reasoning, and repeating it would
+	// also re-drive the compose state machine below on every tick), or a chain is already running
+	// for another held key - in the latter case just switching `repeating_key` is enough, since the
+	// running chain re-reads it on every tick and will pick up this key on its own (see `repeat_tick`).
+	if !key_code.is_modifier_or_lock() && key_code != compose_trigger() {
+		let already_repeating = keyboard_state.repeating_key.is_some();
+		keyboard_state.repeating_key = Some(key_code);
+		if !already_repeating {
+			let delay_ms = REPEAT_DELAY_MS.load(Ordering::Relaxed);
+			crate::interrupts::pit_8254::after(Duration::from_millis(delay_ms as u64), repeat_tick);
+		}
+	}
+
+	// Decode the event into something applications can consume, with the keyboard state guard still
+	// held (unlike `repeat_tick`, which has to re-acquire it, this function already has it) so the
+	// compose state machine below can read and update `compose_state` atomically with the decode it
+	// affects - a character if the key has a textual representation (with modifiers already applied)
+	// or the raw key code otherwise
+	let keymap = keyboard_state.active_keymap;
+	let modified = event.ctrl_down || event.alt_down || event.logo_down;
+	let text_char = if modified { None } else { event.layout_char_with(keymap) };
+
+	let decoded_key = if key_code == compose_trigger() && keyboard_state.compose_state == ComposeState::Idle {
+		// Start a new compose sequence. The trigger key itself has no ASCII representation in any
+		// layout, so this falls back to `RawKey` the same way any other non-text key would.
+		keyboard_state.compose_state = ComposeState::WaitingFirst;
+		DecodedKey::RawKey(key_code)
+	} else if keyboard_state.compose_state != ComposeState::Idle {
+		match (keyboard_state.compose_state, text_char) {
+			// Neither a modified key nor one with no ASCII representation can continue a compose
+			// sequence - discard it, same as a table-miss below
+			(_, None) => {
+				keyboard_state.compose_state = ComposeState::Idle;
+				DecodedKey::RawKey(key_code)
+			},
+			(ComposeState::WaitingFirst, Some(first)) => {
+				keyboard_state.compose_state = ComposeState::WaitingSecond(first);
+				DecodedKey::RawKey(key_code)
+			},
+			(ComposeState::WaitingSecond(first), Some(second)) => {
+				keyboard_state.compose_state = ComposeState::Idle;
+				match keymap.resolve_compose(first, second) {
+					Some(result) => DecodedKey::Unicode(result as char),
+					None => DecodedKey::RawKey(key_code),
+				}
+			},
+			(ComposeState::Idle, Some(_)) => unreachable!(),
+		}
+	} else {
+		match text_char {
+			Some(ascii) => DecodedKey::Unicode(ascii as char),
+			None => DecodedKey::RawKey(key_code),
 		}
+	};
+	DECODED_KEY_QUEUE.acquire().push(decoded_key);
+
+	// Release the keyboard state last: earlier `as_ascii`/`layout_char` versions of this decode step
+	// re-acquired `KEYBOARD_STATE` internally and had to run after a `drop` here, but
+	// `layout_char_with` takes the keymap directly, so the whole decode can happen under the one
+	// guard this function already holds
+	drop(keyboard_state);
+
+	let power_event = match key_code {
+		KeyCode::KeyACPIPower => Some(crate::power::PowerEvent::Power),
+		KeyCode::KeyACPISleep => Some(crate::power::PowerEvent::Sleep),
+		KeyCode::KeyACPIWake => Some(crate::power::PowerEvent::Wake),
+		_ => None,
+	};
+	if let Some(power_event) = power_event {
+		crate::power::notify(power_event);
 	}
 }
 
-/// Updates the keyboard state given that the key with code `key_code` was released
-pub fn key_released_event(key_code: KeyCode) {
+/// Updates the keyboard state given that the key with code `key_code` was released. See
+/// `key_pressed_event` for why the lock latch states are passed in.
+pub fn key_released_event(key_code: KeyCode, caps_lock_enabled: bool, number_lock_enabled: bool,
+	scroll_lock_enabled: bool) {
 	// Acquire exclusive rights to the keyboard state
 	let mut keyboard_state = KEYBOARD_STATE.acquire();
 
 	// Save the key as unpressed
 	keyboard_state.key_state[key_code as usize] = false;
 
+	// Stop typematic repeat if this was the key being repeated - the pending timer (if any) will
+	// see `repeating_key` is `None` and quietly stop rescheduling itself instead of being cancelled
+	if keyboard_state.repeating_key == Some(key_code) {
+		keyboard_state.repeating_key = None;
+	}
+
+	keyboard_state.caps_lock_enabled = caps_lock_enabled;
+	keyboard_state.number_lock_enabled = number_lock_enabled;
+	keyboard_state.scroll_lock_enabled = scroll_lock_enabled;
+
 	// Calculate the modifier states by checking both left and right variants
 	let shift_down = keyboard_state.key_state[KeyCode::KeyLeftShift as usize]
 		|| keyboard_state.key_state[KeyCode::KeyRightShift as usize];
@@ -458,7 +1171,7 @@ pub fn key_released_event(key_code: KeyCode) {
 		|| keyboard_state.key_state[KeyCode::KeyRightLogo as usize];
 
 	// Construct the KeyUp event
-	let _event = KeyEvent {
+	let event = KeyEvent {
 		key_code,
 		event_type: KeyEventType::KeyUp,
 		shift_down,
@@ -467,7 +1180,13 @@ pub fn key_released_event(key_code: KeyCode) {
 		logo_down,
 		caps_lock_enabled: keyboard_state.caps_lock_enabled,
 		number_lock_enabled: keyboard_state.number_lock_enabled,
+		repeat: false,
 	};
 
-	// TODO: Propagate this event somehow
+	// Unlike the decoded-character queue, the raw event stream propagates releases too, since a
+	// consumer tracking held keys (e.g. a game) needs to know when they go back up
+	RAW_KEY_EVENT_QUEUE.acquire().push(RawKeyEvent { key_code, pressed: false });
+
+	// Same for the fully-decoded event queue: `/dev/keyboard` readers need KeyUp too
+	let _ = KEYBOARD_EVENTS_QUEUE.produce(event);
 }
\ No newline at end of file
@@ -0,0 +1,353 @@
+//! GDB Remote Serial Protocol debug stub
+//!
+//! Lets a host `gdb` attach over the existing serial wire (`serial::init_with_ports` already owns
+//! it) and inspect a trapped kernel: read/write the general-purpose registers, read/write memory,
+//! single-step, and set/remove software breakpoints. Entry happens from the `#DB` (vector 1) and
+//! `#BP` (vector 3) exception gates, which hand the trap frame to `trap_entry` below instead of
+//! going through `interrupts::report_fault` like every other exception vector except `#DF` does.
+//!
+//! Both gates are interrupt gates, so the CPU clears IF before we ever get here - nothing else can
+//! run on this (single) core while a debug session is in progress, which is also what keeps our
+//! `serial::read_raw_byte`/`write_raw_byte` calls from interleaving with a concurrent `println!` or
+//! the panic handler.
+
+use exclusive_cell::ExclusiveCell;
+
+use crate::interrupts::PushADRegisterState;
+
+/// Whether to log stub entry/exit to serial for debugging the stub itself
+const PRINT_DEBUG_MESSAGES: bool = false;
+
+/// Largest command/response payload (the part between `$` and `#`) we'll buffer. Comfortably
+/// covers a full register dump (16 registers * 8 hex digits) and a reasonably-sized memory
+/// read/write.
+const MAX_PACKET_LEN: usize = 1024;
+
+/// The EFLAGS trap-flag bit: set to single-step, clear to run free
+const EFLAGS_TRAP_FLAG: u32 = 1 << 8;
+
+/// Maximum number of software breakpoints installed at once
+const MAX_BREAKPOINTS: usize = 16;
+
+/// Installed software breakpoints, as `(address, original byte)` so `z0` can restore what `Z0`
+/// overwrote with `int3` (0xCC)
+static BREAKPOINTS: ExclusiveCell<[Option<(u32, u8)>; MAX_BREAKPOINTS]> =
+	ExclusiveCell::new([None; MAX_BREAKPOINTS]);
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Entry point for the `#DB`/`#BP` exception gates (see `interrupts::kgdb_asm_stub!`). `eip`/
+/// `eflags` point directly into the hardware-pushed trap frame still sitting on the stack, so
+/// writing through them (to rewind `eip` past a breakpoint, or to toggle the trap flag for
+/// step/continue) changes what the `iretd` below us actually resumes into.
+pub(crate) unsafe extern "cdecl" fn trap_entry(vector: u32, eip: *mut u32, cs: u32,
+	eflags: *mut u32, esp: u32, ss: u32, regs: &mut PushADRegisterState) {
+	if PRINT_DEBUG_MESSAGES {
+		serial::println!("[kgdb] trapped (vector={}, eip={:#x})", vector, *eip);
+	}
+
+	// A breakpoint's int3 is one byte, so the CPU leaves eip pointing just past it. If this is a
+	// breakpoint we installed, rewind eip back to the instruction's real address so GDB (and
+	// anything resuming from here) sees/re-executes the original instruction rather than whatever
+	// comes after it.
+	if vector == 3 {
+		let fault_addr = (*eip).wrapping_sub(1);
+		if breakpoint_original_byte(fault_addr).is_some() {
+			*eip = fault_addr;
+		}
+	}
+
+	// SIGTRAP: every reason we end up in this stub (breakpoint, single-step) is a trap as far as
+	// GDB's signal numbering is concerned
+	send_packet(b"S05");
+
+	loop {
+		let mut buf = [0u8; MAX_PACKET_LEN];
+		let len = read_packet(&mut buf);
+		if len == 0 {
+			continue;
+		}
+		let packet = &buf[..len];
+
+		match packet[0] {
+			b'?' => send_packet(b"S05"),
+			b'g' => {
+				let mut reply = [0u8; MAX_PACKET_LEN];
+				let reply_len = encode_registers(*eip, cs, *eflags, esp, ss, regs, &mut reply);
+				send_packet(&reply[..reply_len]);
+			},
+			b'G' => {
+				decode_registers(&packet[1..], eip, eflags, regs);
+				send_packet(b"OK");
+			},
+			b'm' => handle_read_memory(packet),
+			b'M' => handle_write_memory(packet),
+			b'Z' | b'z' => handle_breakpoint(packet),
+			b'c' => {
+				*eflags &= !EFLAGS_TRAP_FLAG;
+				if PRINT_DEBUG_MESSAGES {
+					serial::println!("[kgdb] continuing at {:#x}", *eip);
+				}
+				return;
+			},
+			b's' => {
+				*eflags |= EFLAGS_TRAP_FLAG;
+				if PRINT_DEBUG_MESSAGES {
+					serial::println!("[kgdb] stepping from {:#x}", *eip);
+				}
+				return;
+			},
+			// Anything we don't implement is answered with an empty packet, which is the RSP's
+			// way of saying "unsupported" and lets gdb fall back gracefully
+			_ => send_packet(b""),
+		}
+	}
+}
+
+/// Handles a `m addr,len` read-memory command
+fn handle_read_memory(packet: &[u8]) {
+	let mut pos = 1;
+	let addr = parse_hex_field(packet, &mut pos);
+	pos += 1; // skip the comma
+	let len = (parse_hex_field(packet, &mut pos) as usize).min(MAX_PACKET_LEN / 2);
+
+	let mut reply = [0u8; MAX_PACKET_LEN];
+	let mut reply_len = 0;
+	for i in 0..len {
+		let byte = unsafe { *((addr as usize + i) as *const u8) };
+		push_hex_byte(&mut reply, &mut reply_len, byte);
+	}
+	send_packet(&reply[..reply_len]);
+}
+
+/// Handles a `M addr,len:data` write-memory command
+fn handle_write_memory(packet: &[u8]) {
+	let mut pos = 1;
+	let addr = parse_hex_field(packet, &mut pos);
+	pos += 1; // skip the comma
+	let len = parse_hex_field(packet, &mut pos) as usize;
+	pos += 1; // skip the colon
+
+	for i in 0..len {
+		if pos + 1 >= packet.len() {
+			break;
+		}
+		let byte = (hex_digit(packet[pos]) << 4) | hex_digit(packet[pos + 1]);
+		unsafe { *((addr as usize + i) as *mut u8) = byte; }
+		pos += 2;
+	}
+	send_packet(b"OK");
+}
+
+/// Handles a `Z0,addr,kind`/`z0,addr,kind` insert/remove software breakpoint command. Only
+/// breakpoint type 0 (software, i.e. an `int3` patched into the target byte) is supported; any
+/// other type is reported as unsupported rather than silently ignored.
+fn handle_breakpoint(packet: &[u8]) {
+	let insert = packet[0] == b'Z';
+	if packet.get(1) != Some(&b'0') {
+		send_packet(b"");
+		return;
+	}
+
+	let mut pos = 2; // skip the command byte and the breakpoint-type digit
+	pos += 1; // skip the comma before the address
+	let addr = parse_hex_field(packet, &mut pos);
+	// The trailing ",kind" field (breakpoint length) is intentionally left unparsed - x86 only has
+	// the one `int3` encoding for a software breakpoint, so there's nothing to branch on
+
+	let ok = if insert { insert_breakpoint(addr) } else { remove_breakpoint(addr) };
+	send_packet(if ok { b"OK" } else { b"E01" });
+}
+
+/// Patches `addr` with `int3` (0xCC), remembering the byte it overwrote so `remove_breakpoint` can
+/// put it back. Returns false if the breakpoint table is full.
+fn insert_breakpoint(addr: u32) -> bool {
+	let mut breakpoints = BREAKPOINTS.acquire();
+	let slot = match breakpoints.iter().position(|bp| bp.is_none()) {
+		Some(slot) => slot,
+		None => return false,
+	};
+
+	let original_byte = unsafe { *(addr as *const u8) };
+	breakpoints[slot] = Some((addr, original_byte));
+	unsafe { *(addr as *mut u8) = 0xCC; }
+	true
+}
+
+/// Restores the byte `insert_breakpoint` overwrote at `addr`. Returns false if there was no
+/// breakpoint installed there.
+fn remove_breakpoint(addr: u32) -> bool {
+	let mut breakpoints = BREAKPOINTS.acquire();
+	match breakpoints.iter().position(|bp| matches!(bp, Some((bp_addr, _)) if *bp_addr == addr)) {
+		Some(slot) => {
+			let (_, original_byte) = breakpoints[slot].take().unwrap();
+			unsafe { *(addr as *mut u8) = original_byte; }
+			true
+		},
+		None => false,
+	}
+}
+
+/// The byte a breakpoint at `addr` overwrote, if one is installed there
+fn breakpoint_original_byte(addr: u32) -> Option<u8> {
+	let breakpoints = BREAKPOINTS.acquire();
+	breakpoints.iter().find_map(|bp| match bp {
+		Some((bp_addr, original_byte)) if *bp_addr == addr => Some(*original_byte),
+		_ => None,
+	})
+}
+
+/// Encodes the `g` reply: the i386 general-purpose registers in the order gdb's `i386` target
+/// description expects (eax, ecx, edx, ebx, esp, ebp, esi, edi, eip, eflags, cs, ss, ds, es, fs,
+/// gs), each as 8 hex digits in the target's (little-endian) byte order. Our interrupt gates never
+/// switch privilege, so ds/es/fs/gs are always the flat kernel data selector.
+fn encode_registers(eip: u32, cs: u32, eflags: u32, esp: u32, ss: u32, regs: &PushADRegisterState,
+	out: &mut [u8; MAX_PACKET_LEN]) -> usize {
+	let kernel_ds = crate::gdt::KERNEL_DS_SELECTOR as u32;
+	let mut pos = 0;
+	for value in [regs.eax, regs.ecx, regs.edx, regs.ebx, esp, regs.ebp, regs.esi, regs.edi, eip,
+		eflags, cs, ss, kernel_ds, kernel_ds, kernel_ds, kernel_ds] {
+		push_hex_u32_le(out, &mut pos, value);
+	}
+	pos
+}
+
+/// Decodes a `G` command's payload (the same 16-register layout `encode_registers` produces) back
+/// into the trap frame and register state. Segment registers are accepted but not written back -
+/// this stub has no use for a debugger changing them.
+fn decode_registers(payload: &[u8], eip: *mut u32, eflags: *mut u32, regs: &mut PushADRegisterState) {
+	if payload.len() < 16 * 8 {
+		return;
+	}
+
+	regs.eax = parse_hex_u32_le(&payload[0..8]);
+	regs.ecx = parse_hex_u32_le(&payload[8..16]);
+	regs.edx = parse_hex_u32_le(&payload[16..24]);
+	regs.ebx = parse_hex_u32_le(&payload[24..32]);
+	// esp (32..40) isn't writable through `PushADRegisterState` post-facto without moving the
+	// saved frame, so it's intentionally left alone
+	regs.ebp = parse_hex_u32_le(&payload[40..48]);
+	regs.esi = parse_hex_u32_le(&payload[48..56]);
+	regs.edi = parse_hex_u32_le(&payload[56..64]);
+	unsafe {
+		*eip = parse_hex_u32_le(&payload[64..72]);
+		*eflags = parse_hex_u32_le(&payload[72..80]);
+	}
+}
+
+/// Reads one GDB Remote Serial Protocol packet (`$<payload>#<checksum>`) into `buf`, acknowledging
+/// it with `+`/`-` as the checksum dictates, and returns the payload length. Bytes outside of a
+/// `$...#cc` frame (e.g. a stray ack) are silently discarded.
+fn read_packet(buf: &mut [u8; MAX_PACKET_LEN]) -> usize {
+	loop {
+		loop {
+			match serial::read_raw_byte() {
+				Some(b'$') => break,
+				Some(_) => continue,
+				None => return 0,
+			}
+		}
+
+		let mut len = 0;
+		let mut checksum: u8 = 0;
+		loop {
+			let byte = match serial::read_raw_byte() {
+				Some(byte) => byte,
+				None => return 0,
+			};
+			if byte == b'#' {
+				break;
+			}
+			if len < buf.len() {
+				buf[len] = byte;
+				len += 1;
+			}
+			checksum = checksum.wrapping_add(byte);
+		}
+
+		let checksum_hi = serial::read_raw_byte().map(hex_digit).unwrap_or(0);
+		let checksum_lo = serial::read_raw_byte().map(hex_digit).unwrap_or(0);
+		let received_checksum = (checksum_hi << 4) | checksum_lo;
+
+		if received_checksum == checksum {
+			serial::write_raw_byte(b'+');
+			return len;
+		}
+
+		// Checksum mismatch - NAK it and wait for the host to resend
+		serial::write_raw_byte(b'-');
+	}
+}
+
+/// Sends `payload` as a framed `$<payload>#<checksum>` packet, resending until the host ACKs it
+fn send_packet(payload: &[u8]) {
+	loop {
+		serial::write_raw_byte(b'$');
+		let mut checksum: u8 = 0;
+		for &byte in payload {
+			serial::write_raw_byte(byte);
+			checksum = checksum.wrapping_add(byte);
+		}
+		serial::write_raw_byte(b'#');
+		write_hex_byte(checksum);
+
+		match serial::read_raw_byte() {
+			Some(b'+') | None => return,
+			_ => continue,
+		}
+	}
+}
+
+fn write_hex_byte(byte: u8) {
+	serial::write_raw_byte(HEX_DIGITS[(byte >> 4) as usize]);
+	serial::write_raw_byte(HEX_DIGITS[(byte & 0xF) as usize]);
+}
+
+fn push_hex_byte(out: &mut [u8], pos: &mut usize, byte: u8) {
+	out[*pos] = HEX_DIGITS[(byte >> 4) as usize];
+	out[*pos + 1] = HEX_DIGITS[(byte & 0xF) as usize];
+	*pos += 2;
+}
+
+/// Appends `value`'s 4 bytes as 8 hex digits in little-endian (target) byte order, the way GDB's
+/// `g`/`G` packets encode every register
+fn push_hex_u32_le(out: &mut [u8], pos: &mut usize, value: u32) {
+	for byte in value.to_le_bytes() {
+		push_hex_byte(out, pos, byte);
+	}
+}
+
+/// Decodes 8 hex digits in little-endian (target) byte order back into a `u32`, the inverse of
+/// `push_hex_u32_le`
+fn parse_hex_u32_le(digits: &[u8]) -> u32 {
+	let mut bytes = [0u8; 4];
+	for (i, byte) in bytes.iter_mut().enumerate() {
+		*byte = (hex_digit(digits[i * 2]) << 4) | hex_digit(digits[i * 2 + 1]);
+	}
+	u32::from_le_bytes(bytes)
+}
+
+/// Parses a plain big-endian hex number (as used for `addr`/`len` fields, unlike the
+/// target-byte-order register encoding) starting at `buf[*pos]`, stopping at the first non-hex
+/// byte. Advances `*pos` past the digits it consumed.
+fn parse_hex_field(buf: &[u8], pos: &mut usize) -> u32 {
+	let mut value = 0u32;
+	while *pos < buf.len() && is_hex_digit(buf[*pos]) {
+		value = (value << 4) | (hex_digit(buf[*pos]) as u32);
+		*pos += 1;
+	}
+	value
+}
+
+fn is_hex_digit(byte: u8) -> bool {
+	matches!(byte, b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F')
+}
+
+fn hex_digit(byte: u8) -> u8 {
+	match byte {
+		b'0'..=b'9' => byte - b'0',
+		b'a'..=b'f' => byte - b'a' + 10,
+		b'A'..=b'F' => byte - b'A' + 10,
+		_ => 0,
+	}
+}
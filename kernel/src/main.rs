@@ -21,6 +21,9 @@ mod tss;
 mod gdt;
 mod interrupts;
 mod screen;
+mod graphics_screen;
+mod text_terminal;
+mod font;
 mod keyboard;
 mod mouse;
 mod ps2;
@@ -30,6 +33,9 @@ mod syscall;
 mod process;
 mod ext2;
 mod time;
+mod power;
+mod monotonic;
+mod kgdb;
 
 /// Entry point of the kernel. `boot_args_ptr` is a a physical address below 1MiB which points to a
 /// `BootArgs` structure.
@@ -51,6 +57,15 @@ pub extern fn entry(boot_args_ptr: *const BootArgs) -> ! {
 
     println!("Initialized memory manager");
 
+    // Program the PAT so `CacheMode::WriteCombining`/`CacheMode::WriteProtect` mappings (e.g. the
+    // screen buffer `screen::init`/`graphics_screen::init` map below) select the right memory type
+    unsafe { page_tables::init_pat(); }
+
+    // Set CR4.PGE so the `global: true` mappings `screen::init`/`graphics_screen::init` make below
+    // (permanent for the life of the kernel) survive a `mov cr3` reload instead of being flushed
+    // from the TLB on every process switch along with everything else
+    unsafe { page_tables::enable_global_pages(); }
+
     // Initialize the GDT and the TSS
     unsafe { gdt::init(); }
 
@@ -61,12 +76,24 @@ pub extern fn entry(boot_args_ptr: *const BootArgs) -> ! {
     interrupts::init();
     println!("Enabled interrupts");
 
+    // Calibrate the TSC-based monotonic clock against the now-ticking PIT
+    monotonic::init();
+
     // Initialize the PS/2 controller (which will in turn initialize a keyboard driver if a PS/2
     // keyboard is connected)
     ps2::controller::init();
 
     // Initialize and clear the screen
-    screen::init();
+    screen::init(&boot_args);
+
+    // Stdout (see `syscall::syscall_write`) is served by the newer graphics-framebuffer-backed
+    // `text_terminal`, not `screen` above, so it needs its own framebuffer and grid set up too
+    if boot_args.frame_buffer_paddr.0 != 0 {
+        graphics_screen::init(boot_args.frame_buffer_paddr, boot_args.frame_buffer_width,
+            boot_args.frame_buffer_height);
+        text_terminal::init(boot_args.frame_buffer_width as usize,
+            boot_args.frame_buffer_height as usize);
+    }
 
     // Test syscall TODO: REMOVE
     // unsafe {
@@ -92,11 +119,11 @@ pub extern fn entry(boot_args_ptr: *const BootArgs) -> ! {
     let user_program = {
         let ext2_parser = ext2::EXT2_PARSER.lock();
         let ext2_parser = ext2_parser.as_ref().unwrap();
-        let (user_program_inode, _) = ext2_parser.resolve_path_to_inode("/bin/shell", ext2_parser::ROOT_INODE).unwrap();
-        let user_program_metadata = ext2_parser.get_inode(user_program_inode);
-        let user_program_size = user_program_metadata.size_low as usize;
+        let (user_program_inode, _) = ext2_parser.resolve_path_to_inode("/bin/shell", ext2_parser::ROOT_INODE).unwrap().unwrap();
+        let user_program_metadata = ext2_parser.get_inode(user_program_inode).unwrap();
+        let user_program_size = ext2_parser.file_size(&user_program_metadata) as usize;
         let mut user_program = vec![0u8; user_program_size];
-        assert!(ext2_parser.get_contents(user_program_inode, &mut user_program) == user_program_size);
+        assert!(ext2_parser.get_contents(user_program_inode, &mut user_program).unwrap() == user_program_size);
         user_program
     };
 
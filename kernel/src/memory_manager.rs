@@ -1,14 +1,23 @@
 //! Responisble for physical and virtual memory management
 
 use core::convert::TryInto;
-use core::sync::atomic::{AtomicUsize, Ordering};
 use core::alloc::{GlobalAlloc, Layout};
 
 use range_set::{RangeSet, InclusiveRange};
-use page_tables::{PAGE_ENTRY_PRESENT, PAGE_ENTRY_WRITE, PageDirectory, PhysAddr, PhysMem, VirtAddr};
+use page_tables::{
+    CacheMode, PAGE_ENTRY_PRESENT, PAGE_ENTRY_WRITE, Frame, PageDirectory, PhysAddr, PhysMem,
+    VirtAddr,
+};
 use lock_cell::LockCell;
 use boot_args::{BootArgs, LAST_PAGE_TABLE_VADDR, KERNEL_ALLOCATIONS_BASE_VADDR};
 
+use buddy_allocator::BuddyAllocator;
+use slab_allocator::SlabAllocator;
+
+mod buddy_allocator;
+mod slab_allocator;
+mod mmio;
+
 /// Global to hold the `RangeSet` of available physical memory and the `PageDirectory` which manages
 /// page mappings.
 /// IMPORTANT: While maskable hardware interrupts are masked while this lock is held, care must be
@@ -16,6 +25,21 @@ use boot_args::{BootArgs, LAST_PAGE_TABLE_VADDR, KERNEL_ALLOCATIONS_BASE_VADDR};
 /// exceptions.
 pub static PHYS_MEM: LockCell<Option<(PhysicalMemory, PageDirectory)>> = LockCell::new(None);
 
+/// Maximum number of distinct physical frames that can be tracked as copy-on-write-shared (see
+/// `PageDirectory::clone_cow`) at once. Bounded to a fixed-size table rather than a heap-allocated
+/// map: `PhysicalMemory` backs the kernel's `GlobalAlloc`, so a structure here that needs to
+/// allocate would try to re-lock `PHYS_MEM` from inside itself and deadlock against the ticket
+/// lock it's already held under.
+const MAX_COW_FRAMES: usize = 256;
+
+/// The reference count of a physical frame currently shared copy-on-write by more than one page
+/// directory
+#[derive(Clone, Copy)]
+struct CowRefCount {
+    frame: PhysAddr,
+    count: u32,
+}
+
 /// A struct that implements `PhysMem` for use in mappings
 pub struct PhysicalMemory{
     /// Actual usable ranges of physical memory
@@ -25,7 +49,20 @@ pub struct PhysicalMemory{
     last_page_table_paddr: PhysAddr,
 
     /// The current physical mapping in the last page (That is used to access physical memory)
-    current_phys_mapping: Option<PhysAddr>
+    current_phys_mapping: Option<PhysAddr>,
+
+    /// Reference counts for frames shared copy-on-write by `PageDirectory::clone_cow`. A frame
+    /// with no entry here is implicitly exclusively owned (refcount of one) - see
+    /// `PhysMem::inc_ref`/`PhysMem::dec_ref`.
+    cow_ref_counts: [Option<CowRefCount>; MAX_COW_FRAMES],
+}
+
+impl PhysicalMemory {
+    /// Finds the COW-tracking slot for `phys_addr`'s frame, if it's currently shared
+    fn find_cow_slot(&self, phys_addr: PhysAddr) -> Option<usize> {
+        self.cow_ref_counts.iter()
+            .position(|slot| matches!(slot, Some(entry) if entry.frame == phys_addr))
+    }
 }
 
 impl PhysMem for PhysicalMemory {
@@ -49,21 +86,21 @@ impl PhysMem for PhysicalMemory {
             && phys_addr.0 <= self.last_page_table_paddr.0 + 4095 {
             // This page table is mapped at `LAST_PAGE_TABLE_VADDR`, so the translation of the
             // requested physical address is just at the relevant offset of that virtual address
-            let page_offset = phys_addr.0 - self.last_page_table_paddr.0;
+            let page_offset = (phys_addr - self.last_page_table_paddr) as usize;
 
             // Check that the requested physical window resides entirely inside the page directory
-            if page_offset.checked_add(size as u32 - 1)? > 4095 {
+            if page_offset.checked_add(size - 1)? > 4095 {
                 return None;
             }
 
-            return Some((LAST_PAGE_TABLE_VADDR + page_offset) as *mut u8);
+            return Some((LAST_PAGE_TABLE_VADDR + page_offset as u32) as *mut u8);
         }
 
         // Calculate the address of the page containing the physical address
-        let phys_addr_page = phys_addr.0 & !0xFFF;
+        let phys_addr_page = phys_addr.page_down();
 
         if self.current_phys_mapping.is_none()
-            || self.current_phys_mapping.unwrap().0 != phys_addr_page {
+            || self.current_phys_mapping.unwrap() != phys_addr_page {
             // If the physical address is not already mapped in, we must make a mapping for it, so we
             // need access to the page directory struct
             let page_dir = page_dir?;
@@ -71,7 +108,7 @@ impl PhysMem for PhysicalMemory {
             // Make sure the requested physical window does not extend beyond this one page. This should
             // not be a problem: the page table functions only ever use this to read and write to page
             // tables which are one page long.
-            if phys_addr.0.checked_add(size as u32 - 1)? > (phys_addr_page + 4095) {
+            if phys_addr.checked_add(size as u32 - 1)? > phys_addr_page + 4095 {
                 return None;
             }
 
@@ -79,23 +116,23 @@ impl PhysMem for PhysicalMemory {
             // It is critical we use the `map_raw_directly` method, which uses the virtual address we
             // provide to it, instead of asking for a virtual address from this function, which would
             // cause an inifnite loop
-            let raw_pte = PAGE_ENTRY_PRESENT | PAGE_ENTRY_WRITE | phys_addr_page;
+            let raw_pte = PAGE_ENTRY_PRESENT | PAGE_ENTRY_WRITE | phys_addr_page.0;
             page_dir.map_raw_directly(VirtAddr(0xFFFFF000), raw_pte, true,
-                VirtAddr(LAST_PAGE_TABLE_VADDR));   
-            self.current_phys_mapping = Some(PhysAddr(phys_addr_page));
+                VirtAddr(LAST_PAGE_TABLE_VADDR));
+            self.current_phys_mapping = Some(phys_addr_page);
         }
-        
+
 
         // Calculate the virtual address based on the offset from the start of the page
-        let virt_addr = 0xFFFFF000 + (phys_addr.0 - phys_addr_page);
+        let virt_addr = 0xFFFFF000 + (phys_addr - phys_addr_page);
         Some(virt_addr as *mut u8)
     }
 
-    fn allocate_phys_mem(&mut self, layout: Layout) -> Option<PhysAddr> {
+    fn allocate_phys_mem(&mut self, layout: Layout) -> Option<Frame> {
         let addr = self.memory_ranges.allocate(layout.size().try_into().ok()?,
-            layout.align().try_into().ok()?);
-        
-        addr.map(|x| PhysAddr(x))
+            layout.align().try_into().ok()?)?;
+
+        Some(Frame { addr: PhysAddr(addr), size: layout.size() })
     }
 
     fn release_phys_mem(&mut self, phys_addr: PhysAddr, size: usize) {
@@ -103,20 +140,59 @@ impl PhysMem for PhysicalMemory {
             return;
         }
 
+        // A single page might be shared copy-on-write with other directories; only actually free
+        // it once every owner has dropped its reference. `dec_ref` returns 0 for a frame that was
+        // never shared in the first place, so this is a no-op for every ordinary (non-COW) release.
+        if size == 4096 && self.dec_ref(phys_addr) > 0 {
+            return;
+        }
+
         self.memory_ranges.insert(InclusiveRange {
             start: phys_addr.0,
             end: phys_addr.0.saturating_add((size - 1) as u32)
         });
     }
-}
 
-struct FreePagesEntry {
-    page_count: usize,
-    next: Option<*mut FreePagesEntry>,
+    fn inc_ref(&mut self, phys_addr: PhysAddr) {
+        if let Some(index) = self.find_cow_slot(phys_addr) {
+            self.cow_ref_counts[index].as_mut().unwrap().count += 1;
+            return;
+        }
+
+        // Wasn't shared before: it had one implicit owner already, so sharing it with one more
+        // directory makes two
+        let free_slot = self.cow_ref_counts.iter().position(Option::is_none)
+            .expect("Out of copy-on-write frame tracking slots");
+        self.cow_ref_counts[free_slot] = Some(CowRefCount { frame: phys_addr, count: 2 });
+    }
+
+    fn dec_ref(&mut self, phys_addr: PhysAddr) -> u32 {
+        let index = match self.find_cow_slot(phys_addr) {
+            Some(index) => index,
+            // Was never shared, so this was its only owner
+            None => return 0,
+        };
+
+        let entry = self.cow_ref_counts[index].as_mut().unwrap();
+        entry.count -= 1;
+        let remaining = entry.count;
+
+        if remaining <= 1 {
+            // Back down to a single (implicit) owner - stop tracking it explicitly
+            self.cow_ref_counts[index] = None;
+        }
+
+        remaining
+    }
 }
 
-static NEXT_AVAILABLE_VADDR: AtomicUsize = AtomicUsize::new(KERNEL_ALLOCATIONS_BASE_VADDR as usize);
-static FREE_PAGES_LIST: LockCell<Option<*mut FreePagesEntry>> = LockCell::new(None);
+/// The buddy allocator managing the kernel heap's virtual address arena, starting at
+/// `KERNEL_ALLOCATIONS_BASE_VADDR`
+static BUDDY_ALLOCATOR: LockCell<BuddyAllocator> = LockCell::new(BuddyAllocator::empty());
+
+/// The slab tier that serves allocations smaller than a page out of pages drawn from
+/// `BUDDY_ALLOCATOR`
+static SLAB_ALLOCATOR: LockCell<SlabAllocator> = LockCell::new(SlabAllocator::empty());
 
 /// The global allocator for the bootloader
 #[global_allocator]
@@ -126,103 +202,37 @@ static GLOBAL_ALLOCATOR: GlobalAllocator = GlobalAllocator;
 struct GlobalAllocator;
 
 impl GlobalAllocator {
-    /// Tries to satisfy the allocation of page-aligned size `aligned_size` using the free list.
-    /// Returns `None` if not successful
-    fn alloc_from_free_list(&self, aligned_size: usize) -> Option<*mut u8> {
-        assert!(aligned_size & 0xFFF == 0);
-
-        let mut start_of_free_list = FREE_PAGES_LIST.lock();
-        // Check if there are any entries in the free list
-        if let Some(free_list) = *start_of_free_list {
-            // Calculate the number of pages we need to fit the allocation
-            let num_pages_needed = aligned_size / 4096;
-
-            let mut last_entry: Option<*mut FreePagesEntry> = None;
-            let mut entry = free_list;
-            loop {
-                let free_pages = unsafe { core::ptr::read(entry) };
-
-                // We check if we can fit the allocation in this entry
-                if num_pages_needed <= free_pages.page_count {
-                    if num_pages_needed < free_pages.page_count {
-                        // If the allocation is smaller than the size of entry, we just shorten it
-                        let new_page_count = free_pages.page_count - num_pages_needed;
-                        unsafe {
-                            core::ptr::write(entry, FreePagesEntry {
-                                page_count: new_page_count,
-                                next: free_pages.next
-                            });
-                        }
-
-                        // And finally we return a pointer to the end of the updated free area
-                        return Some(((entry as usize) + (new_page_count*4096)) as *mut u8);
-                    } else {
-                        // Else, if the entry is completely used up, we need to update the last
-                        // entry's next pointer
-                        if let Some(last_entry) = last_entry {
-                            unsafe {
-                                let mut last = core::ptr::read(last_entry);
-                                last.next = free_pages.next;
-                                core::ptr::write(last_entry, last);
-                            }
-                        } else {
-                            // If we are using the first entry in the list, we need to update the
-                            // start-of-the-list pointer
-                            *start_of_free_list = free_pages.next;
-                        }
-
-                        return Some(entry as *mut u8);
-                    }
-                } else if free_pages.next.is_some() {
-                    // If we can't, but there are more entries in the list, we advance to the next
-                    last_entry = Some(entry);
-                    entry = free_pages.next.unwrap();
-                } else {
-                    // If this is the end of the list, we exit the loop
-                    break;
-                }
-            }
-        }
-
-        // If we didn't find any free entry that we can use there is nothing to do
-        None
+    fn alloc_internal(&self, layout: Layout) -> Option<*mut u8> {
+        self.alloc_internal_maybe_zeroed(layout, false)
     }
 
-    fn alloc_internal(&self, layout: Layout) -> Option<*mut u8> {
+    /// Shared implementation of `alloc_internal`/`alloc_zeroed_internal`. If `zeroed` is set, the
+    /// returned memory is guaranteed to be cleared, whether it came from a freshly-mapped page or
+    /// was recycled from a previous allocation.
+    fn alloc_internal_maybe_zeroed(&self, layout: Layout, zeroed: bool) -> Option<*mut u8> {
         // The `RangeSet` allocator only supports 32-bit
         let _size: u32 = layout.size().try_into().ok()?;
         let _align: u32 = layout.align().try_into().ok()?;
 
-        // We currently just rely on the fact that we allocate pages which are page-aligned, so any
-        // request with alignment larger than 4096 can not actually be fulfilled.
-        assert!(layout.align() <= 4096);
-
-        // Round up the size to the next multiple of a page
-        let aligned_size = (layout.size().checked_add(4095)?) & !0xFFF;
-
-        // If the free pages list is not empty, we check if we can reuse an existing mapping
-        if let Some(allocation) = self.alloc_from_free_list(aligned_size) {
-            return Some(allocation);
-        }
-
-        // Grab a virtual address for this allocation
-        let virt_addr = NEXT_AVAILABLE_VADDR.fetch_add(aligned_size, Ordering::SeqCst);
+        // Get access to physical memory and the page directory, needed to back any never-before-used
+        // page handed out by either tier below
+        let mut pmem = PHYS_MEM.lock();
+        let (phys_mem, page_dir) = pmem.as_mut()?;
 
-        // Check we have enough room for the allocation
-        if virt_addr.checked_add(aligned_size - 1)? >=
-            KERNEL_ALLOCATIONS_BASE_VADDR as usize + 0x200000 {
-            // TODO: Move the size to a better place
-            return None;
+        // Small allocations are served out of the slab tier so they don't each burn a whole page
+        if SlabAllocator::fits(layout) {
+            return SLAB_ALLOCATOR.lock().alloc(phys_mem, page_dir, layout, zeroed);
         }
 
-        // Get access to physical memory and the page directory
-        let mut pmem = PHYS_MEM.lock();
-        let (phys_mem, page_dir) = pmem.as_mut()?;
+        // Anything too large for the slab tier falls through to the page-granular buddy allocator.
+        // It only ever hands out naturally-aligned power-of-two-sized blocks of pages, so any
+        // request with alignment larger than a single page can not actually be fulfilled.
+        assert!(layout.align() <= 4096);
 
-        // Map the memory for the allocation
-        page_dir.map(phys_mem, VirtAddr(virt_addr as u32), aligned_size as u32, true, false)?;
+        // Round up the size to a whole number of pages
+        let num_pages = (layout.size().checked_add(4095)?) / 4096;
 
-        Some(virt_addr as *mut u8)
+        BUDDY_ALLOCATOR.lock().alloc(phys_mem, page_dir, num_pages, zeroed)
     }
 
     fn dealloc_internal(&self, ptr: *mut u8, layout: Layout) -> Option<()> {
@@ -230,76 +240,16 @@ impl GlobalAllocator {
             panic!("Attempt to dealloc a zero sized allocation");
         }
 
+        if SlabAllocator::fits(layout) {
+            SLAB_ALLOCATOR.lock().dealloc(ptr, layout);
+            return Some(());
+        }
+
         // Round up the size to the next multiple of a page (which is the actual allocation size
         // our allocator provides)
-        let aligned_size = (layout.size().checked_add(4095)?) & !0xFFF;
-
-        let mut start_of_free_list = FREE_PAGES_LIST.lock();
+        let num_pages = (layout.size().checked_add(4095)?) / 4096;
 
-        let mut new_entry_ptr = ptr as *mut FreePagesEntry;
-        let mut new_entry = FreePagesEntry {
-            page_count: aligned_size / 4096,
-            next: *start_of_free_list
-        };
-
-        // If the free list is not empty, we need to check if the freed allocation is adjacent to
-        // any of the existing free entries and merge them
-        if let Some(free_list) = *start_of_free_list {
-            let mut last_entry: Option<*mut FreePagesEntry> = None;
-            let mut entry = free_list;
-            loop {
-                let free_pages = unsafe { core::ptr::read(entry) };
-
-                if ptr as usize + aligned_size == entry as usize {
-                    // If the freed allocation ends at the start of this free entry, we remove the
-                    // existing entry and update our new one
-                    new_entry.page_count += free_pages.page_count;
-
-                    if let Some(last_entry) = last_entry {
-                        unsafe {
-                            let mut last = core::ptr::read(last_entry);
-                            last.next = free_pages.next;
-                            core::ptr::write(last_entry, last);
-                        }
-                    } else {
-                        // If this is the free entry, we update the list heads
-                        *start_of_free_list = free_pages.next;
-                    }
-                } else if ptr as usize == entry as usize + (free_pages.page_count * 4096) {
-                    // Else, if the freed allocation start at the end of this free entry, we remove
-                    // the existing entry and update our new one
-                    new_entry.page_count += free_pages.page_count;
-                    new_entry_ptr = entry;
-
-                    if let Some(last_entry) = last_entry {
-                        unsafe {
-                            let mut last = core::ptr::read(last_entry);
-                            last.next = free_pages.next;
-                            core::ptr::write(last_entry, last);
-                        }
-                    } else {
-                        // If this is the free entry, we update the list heads
-                        *start_of_free_list = free_pages.next;
-                    }
-                }
-
-                // If there is another entry in the list we continue to it, else we finish
-                if free_pages.next.is_some() {
-                    last_entry = Some(entry);
-                    entry = free_pages.next.unwrap();
-                } else {
-                    break;
-                }
-            }
-        }
-        
-        // Save the list entry at the start of the allocation
-        unsafe {
-            core::ptr::write(new_entry_ptr, new_entry);
-        }
-        
-        // Update the head of the free list
-        *start_of_free_list = Some(new_entry_ptr);
+        BUDDY_ALLOCATOR.lock().dealloc(ptr, num_pages);
 
         Some(())
     }
@@ -313,6 +263,78 @@ unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         assert!(self.dealloc_internal(ptr, layout).is_some());
     }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_internal_maybe_zeroed(layout, true).unwrap_or(core::ptr::null_mut())
+    }
+}
+
+/// Maps `size` bytes of physical memory starting at `phys_addr` into a fresh run of kernel virtual
+/// addresses, for access to a device's registers or framebuffer, and returns a pointer to the start
+/// of the mapping. `cache_mode` selects the mapping's memory type (see `CacheMode`) - ordinary MMIO
+/// registers should use `CacheMode::Uncacheable`, while a write-heavy linear frame buffer should use
+/// `CacheMode::WriteCombining`.
+pub fn ioremap(phys_addr: PhysAddr, size: usize, cache_mode: CacheMode) -> Option<*mut u8> {
+    let mut pmem = PHYS_MEM.lock();
+    let (phys_mem, page_dir) = pmem.as_mut()?;
+
+    mmio::ioremap(phys_mem, page_dir, phys_addr, size, cache_mode)
+}
+
+/// Tears down a mapping previously made by `ioremap`. `phys_addr`/`size` must match the values
+/// passed to the corresponding `ioremap` call.
+pub fn iounmap(ptr: *mut u8, phys_addr: PhysAddr, size: usize) {
+    let mut pmem = PHYS_MEM.lock();
+    let (phys_mem, page_dir) = match pmem.as_mut() {
+        Some(pmem) => pmem,
+        None => return,
+    };
+
+    mmio::iounmap(phys_mem, page_dir, ptr, phys_addr, size)
+}
+
+/// Checks whether a #PF at `fault_addr` (whose error code is `error_code`, in the same bit layout
+/// the CPU pushes) is actually spurious: whether the current page tables already grant the access
+/// the fault says was denied. This happens when something else (lazy mapping, stack growth, ...)
+/// already fixed up the page table entry but a stale TLB entry on this CPU triggered the fault
+/// anyway. The caller is expected to `invlpg` the address and resume instead of reporting a fault.
+///
+/// FIXME: This will dead-lock if the fault happened while `PHYS_MEM` was already locked
+pub(crate) fn is_page_fault_spurious(fault_addr: u32, error_code: u32) -> bool {
+    let mut pmem = PHYS_MEM.lock();
+    let (phys_mem, page_dir) = match pmem.as_mut() {
+        Some(pmem) => pmem,
+        None => return false,
+    };
+
+    let (user, writable) = match page_dir.page_permissions(phys_mem, VirtAddr(fault_addr)) {
+        Some(permissions) => permissions,
+        // Not mapped at all, so there's nothing that could have just fixed this up
+        None => return false,
+    };
+
+    // Bit 1 (W/R) and bit 2 (U/S) of the error code are set if the access that faulted was a write,
+    // or from user mode, respectively - only relevant if the permission table actually denies them
+    let write_access = error_code & 0x2 != 0;
+    let user_access = error_code & 0x4 != 0;
+
+    (!write_access || writable) && (!user_access || user)
+}
+
+/// Attempts to resolve `fault_addr` as a copy-on-write fault against the currently active page
+/// directory (see `PageDirectory::clone_cow`/`handle_cow_fault`). Returns `true` if the fault was
+/// one and has been resolved, so the caller can just retry the faulting instruction; `false` if
+/// there's no copy-on-write mapping there and the fault is a genuine one to report.
+///
+/// FIXME: This will dead-lock if the fault happened while `PHYS_MEM` was already locked
+pub(crate) fn handle_cow_fault(fault_addr: u32) -> bool {
+    let mut pmem = PHYS_MEM.lock();
+    let (phys_mem, page_dir) = match pmem.as_mut() {
+        Some(pmem) => pmem,
+        None => return false,
+    };
+
+    page_dir.handle_cow_fault(phys_mem, VirtAddr(fault_addr)).is_some()
 }
 
 /// Initializes the memory manager and unmaps the temp identity map
@@ -327,7 +349,8 @@ pub fn init(boot_args: &BootArgs) {
     let mut phys_mem = PhysicalMemory{
         memory_ranges: boot_args.free_memory,
         last_page_table_paddr: boot_args.last_page_table_paddr,
-        current_phys_mapping: None
+        current_phys_mapping: None,
+        cow_ref_counts: [None; MAX_COW_FRAMES],
     };
     
     // Setup the page directory
@@ -338,6 +361,9 @@ pub fn init(boot_args: &BootArgs) {
     for paddr in (0..(1024*1024)).step_by(4096) {
         page_directory.unmap(&mut phys_mem, VirtAddr(paddr), false);
     }
-    
+
     *pmem = Some((phys_mem, page_directory));
+
+    // Seed the heap allocator with the whole (as of yet entirely unmapped) arena as a single block
+    BUDDY_ALLOCATOR.lock().init(KERNEL_ALLOCATIONS_BASE_VADDR as usize);
 }
\ No newline at end of file
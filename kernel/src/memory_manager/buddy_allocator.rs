@@ -0,0 +1,175 @@
+//! A buddy allocator over the kernel heap's virtual address arena
+//!
+//! `free_lists[k]` holds naturally-aligned blocks of `2^k` pages. Allocating splits the smallest
+//! available block down to the requested order, pushing the unused upper half of each split onto
+//! the free list one order down; freeing repeatedly merges a block with its buddy while the buddy
+//! is free and of the same order. This bounds external fragmentation and gives power-of-two
+//! alignments up to `MAX_ORDER` pages directly, unlike a plain linear free list.
+//!
+//! Unlike a physical frame allocator, the blocks here are *virtual* address ranges that are not
+//! necessarily backed by physical memory yet, so we additionally track which pages have ever been
+//! mapped in. A page is only mapped the first time it is actually handed out by `alloc`; once
+//! mapped it is left mapped even after being freed, so it can be reused without remapping.
+
+use page_tables::{PageDirectory, VirtAddr};
+use super::PhysicalMemory;
+
+/// Total number of 4 KiB pages in the heap arena. Must be a power of two.
+const ARENA_PAGES: usize = 512;
+/// Highest order tracked; `2^MAX_ORDER == ARENA_PAGES`, so the whole arena starts as one block
+const MAX_ORDER: usize = 9;
+
+/// Intrusive free-list node, written directly into the first bytes of a free block
+struct FreeBlock {
+    next: Option<*mut FreeBlock>,
+}
+
+/// Buddy allocator state. Must be `init`-ed with the arena's base address before use.
+pub(super) struct BuddyAllocator {
+    /// Base virtual address of the arena
+    base: usize,
+    /// `free_lists[k]` is the head of the free list of order-`k` blocks (`2^k` pages each)
+    free_lists: [Option<*mut FreeBlock>; MAX_ORDER + 1],
+    /// Whether each page in the arena has ever been backed by a physical mapping
+    mapped: [bool; ARENA_PAGES],
+}
+
+impl BuddyAllocator {
+    /// Constructs an allocator with nothing initialized yet; `init` must be called before use
+    pub(super) const fn empty() -> Self {
+        Self {
+            base: 0,
+            free_lists: [None; MAX_ORDER + 1],
+            mapped: [false; ARENA_PAGES],
+        }
+    }
+
+    /// Seeds the allocator with the whole arena starting at `base` as a single free block. Should
+    /// only be called once.
+    pub(super) fn init(&mut self, base: usize) {
+        self.base = base;
+        self.push_free(base, MAX_ORDER);
+    }
+
+    /// Allocates `num_pages` contiguous pages (rounded up to the smallest containing power of two),
+    /// mapping in any page of the returned block that has never been backed by physical memory
+    /// before. Returns `None` if the request can't be satisfied or a new mapping could not be made.
+    /// If `zeroed` is set, the whole block is cleared before being handed back - this is needed even
+    /// for pages that were already mapped, since they may be a recycled block that previously held
+    /// another allocation's data.
+    pub(super) fn alloc(&mut self, phys_mem: &mut PhysicalMemory, page_dir: &mut PageDirectory,
+        num_pages: usize, zeroed: bool) -> Option<*mut u8> {
+        let order = order_for(num_pages)?;
+
+        // Find the smallest non-empty order at or above the one we need
+        let found_order = (order..=MAX_ORDER).find(|&j| self.free_lists[j].is_some())?;
+
+        let mut j = found_order;
+        let addr = self.pop_free(j)?;
+
+        // Split the block down to the order we need, pushing each split's upper buddy back onto the
+        // free list one order down
+        while j > order {
+            j -= 1;
+            self.push_free(addr + (1 << (j + 12)), j);
+        }
+
+        // Map in any page of this block that has never been backed by physical memory before
+        for page_index in 0..(1usize << order) {
+            let page_addr = addr + page_index * 4096;
+            let bitmap_index = (page_addr - self.base) / 4096;
+
+            if !self.mapped[bitmap_index] {
+                page_dir.map(phys_mem, VirtAddr(page_addr as u32), 4096, true, false)?;
+                self.mapped[bitmap_index] = true;
+            }
+        }
+
+        if zeroed {
+            unsafe {
+                core::ptr::write_bytes(addr as *mut u8, 0, (1usize << order) * 4096);
+            }
+        }
+
+        Some(addr as *mut u8)
+    }
+
+    /// Frees `num_pages` contiguous pages (rounded up to the smallest containing power of two)
+    /// starting at `ptr`, merging with the buddy block repeatedly while it is free and of the same
+    /// order
+    pub(super) fn dealloc(&mut self, ptr: *mut u8, num_pages: usize) {
+        let mut order = order_for(num_pages).expect("invalid size passed to buddy dealloc");
+        let mut addr = ptr as usize;
+
+        while order < MAX_ORDER {
+            let buddy_addr = self.buddy_of(addr, order);
+            if !self.remove_free(buddy_addr, order) {
+                break;
+            }
+
+            // The buddy was free at the same order: merge, keeping the lower address, and try to
+            // merge again one order up
+            addr = addr.min(buddy_addr);
+            order += 1;
+        }
+
+        self.push_free(addr, order);
+    }
+
+    /// Computes the address of the buddy of the order-`order` block at `addr`
+    fn buddy_of(&self, addr: usize, order: usize) -> usize {
+        self.base + ((addr - self.base) ^ (1 << (order + 12)))
+    }
+
+    /// Pushes the block at `addr` onto `free_lists[order]`
+    fn push_free(&mut self, addr: usize, order: usize) {
+        let node = addr as *mut FreeBlock;
+        unsafe {
+            core::ptr::write(node, FreeBlock { next: self.free_lists[order] });
+        }
+        self.free_lists[order] = Some(node);
+    }
+
+    /// Pops and returns the head of `free_lists[order]`, if any
+    fn pop_free(&mut self, order: usize) -> Option<usize> {
+        let node = self.free_lists[order]?;
+        self.free_lists[order] = unsafe { core::ptr::read(node).next };
+        Some(node as usize)
+    }
+
+    /// Removes the block at `addr` from `free_lists[order]`. Returns whether it was found.
+    fn remove_free(&mut self, addr: usize, order: usize) -> bool {
+        let mut last_entry: Option<*mut FreeBlock> = None;
+        let mut entry = self.free_lists[order];
+
+        while let Some(node) = entry {
+            let block = unsafe { core::ptr::read(node) };
+
+            if node as usize == addr {
+                match last_entry {
+                    Some(last_entry) => unsafe {
+                        let mut last = core::ptr::read(last_entry);
+                        last.next = block.next;
+                        core::ptr::write(last_entry, last);
+                    },
+                    None => self.free_lists[order] = block.next,
+                }
+                return true;
+            }
+
+            last_entry = Some(node);
+            entry = block.next;
+        }
+
+        false
+    }
+}
+
+/// Computes the order (`ceil(log2(num_pages))`) of the smallest block that holds `num_pages` pages
+fn order_for(num_pages: usize) -> Option<usize> {
+    if num_pages == 0 || num_pages > (1 << MAX_ORDER) {
+        return None;
+    }
+
+    Some((usize::BITS - (num_pages - 1).leading_zeros()) as usize)
+}
@@ -0,0 +1,152 @@
+//! `ioremap`-style mapping of device memory (MMIO) into the kernel's virtual address space
+//!
+//! `PhysicalMemory::translate_phys` only ever exposes physical memory one page at a time, through a
+//! single permanently-mapped window, and always maps it cacheable and read/write - fine for reading
+//! and writing page tables, but wrong for a multi-page device region (a framebuffer, local APIC
+//! registers, an AHCI BAR) that needs a stable, contiguous mapping, usually marked uncacheable.
+//! `ioremap` reserves a run of virtual addresses out of the dedicated MMIO region and maps the whole
+//! physical range into it; `iounmap` tears the mapping back down without releasing the physical
+//! memory, which is device memory owned by the hardware, not by `PhysicalMemory`'s allocator.
+
+use boot_args::{IOREMAP_BASE_VADDR, IOREMAP_REGION_SIZE};
+use page_tables::{CacheMode, PageDirectory, PhysAddr, VirtAddr};
+use lock_cell::LockCell;
+use super::PhysicalMemory;
+
+/// A free virtual-address run, threaded as an intrusive list through its own unused pages. `ioremap`
+/// mappings are coarse and created/torn down rarely, so a simple free list (rather than the heap's
+/// buddy allocator) is all this region needs.
+struct FreeRun {
+    page_count: usize,
+    next: Option<*mut FreeRun>,
+}
+
+/// Allocator state for the MMIO virtual address region
+struct MmioRegion {
+    /// Next never-before-used virtual address in the region
+    next_vaddr: usize,
+    /// Head of the list of freed, reusable runs
+    free_runs: Option<*mut FreeRun>,
+}
+
+static MMIO_REGION: LockCell<MmioRegion> = LockCell::new(MmioRegion {
+    next_vaddr: IOREMAP_BASE_VADDR as usize,
+    free_runs: None,
+});
+
+/// Maps `size` bytes of physical memory starting at `phys_addr` into a fresh run of kernel virtual
+/// addresses, and returns a pointer to the start of the mapping. `cache_mode` selects the memory
+/// type of the mapping (see `CacheMode`).
+pub(super) fn ioremap(phys_mem: &mut PhysicalMemory, page_dir: &mut PageDirectory,
+    phys_addr: PhysAddr, size: usize, cache_mode: CacheMode) -> Option<*mut u8> {
+    let num_pages = num_pages_for(phys_addr, size)?;
+    let phys_page_base = phys_addr.page_down();
+    let phys_offset = phys_addr.offset_in_page() as usize;
+
+    let mut region = MMIO_REGION.lock();
+    let virt_addr = alloc_run(&mut region, num_pages)?;
+
+    for page_index in 0..num_pages {
+        let page_virt_addr = VirtAddr((virt_addr + page_index * 4096) as u32);
+        let page_phys_addr = phys_page_base + (page_index * 4096) as u32;
+
+        if page_dir.map_to_phys_page(phys_mem, page_virt_addr, page_phys_addr, true, false, true,
+            cache_mode, false).is_none() {
+            // Roll back whatever we already mapped before giving up
+            for rollback_index in 0..page_index {
+                let rollback_vaddr = VirtAddr((virt_addr + rollback_index * 4096) as u32);
+                page_dir.unmap(phys_mem, rollback_vaddr, false);
+            }
+            free_run(&mut region, virt_addr, num_pages);
+            return None;
+        }
+    }
+
+    Some((virt_addr + phys_offset) as *mut u8)
+}
+
+/// Tears down a mapping previously made by `ioremap`. `phys_addr`/`size` must match the values
+/// passed to the corresponding `ioremap` call.
+pub(super) fn iounmap(phys_mem: &mut PhysicalMemory, page_dir: &mut PageDirectory, ptr: *mut u8,
+    phys_addr: PhysAddr, size: usize) {
+    let num_pages = match num_pages_for(phys_addr, size) {
+        Some(num_pages) => num_pages,
+        None => return,
+    };
+    let virt_addr = (ptr as usize) & !0xFFF;
+
+    // Never releases the backing physical memory: it belongs to the device, not to `phys_mem`'s
+    // allocator
+    page_dir.unmap_range(phys_mem, VirtAddr(virt_addr as u32), (num_pages * 4096) as u32, false);
+
+    free_run(&mut MMIO_REGION.lock(), virt_addr, num_pages);
+}
+
+/// Number of pages needed to cover `size` bytes starting at `phys_addr`, accounting for its
+/// within-page offset
+fn num_pages_for(phys_addr: PhysAddr, size: usize) -> Option<usize> {
+    if size == 0 {
+        return None;
+    }
+
+    let phys_offset = phys_addr.offset_in_page() as usize;
+    Some((phys_offset.checked_add(size)?.checked_add(4095)?) / 4096)
+}
+
+/// Finds `num_pages` contiguous virtual pages, reusing a freed run if one is large enough, and
+/// otherwise carving never-before-used space out of the end of the region
+fn alloc_run(region: &mut MmioRegion, num_pages: usize) -> Option<usize> {
+    let mut last_entry: Option<*mut FreeRun> = None;
+    let mut entry = region.free_runs;
+
+    while let Some(run_ptr) = entry {
+        let run = unsafe { core::ptr::read(run_ptr) };
+
+        if num_pages <= run.page_count {
+            if num_pages < run.page_count {
+                // Keep the front of the entry on the free list and hand out its tail
+                let new_page_count = run.page_count - num_pages;
+                unsafe {
+                    core::ptr::write(run_ptr, FreeRun { page_count: new_page_count, next: run.next });
+                }
+                return Some((run_ptr as usize) + new_page_count * 4096);
+            }
+
+            // The entry is exactly the size we need; unlink it entirely
+            match last_entry {
+                Some(last_ptr) => unsafe {
+                    let mut last = core::ptr::read(last_ptr);
+                    last.next = run.next;
+                    core::ptr::write(last_ptr, last);
+                },
+                None => region.free_runs = run.next,
+            }
+            return Some(run_ptr as usize);
+        }
+
+        last_entry = Some(run_ptr);
+        entry = run.next;
+    }
+
+    // Nothing free was big enough; bump into never-before-used space
+    let virt_addr = region.next_vaddr;
+    let size = num_pages.checked_mul(4096)?;
+    let end = virt_addr.checked_add(size)?;
+    if end > IOREMAP_BASE_VADDR as usize + IOREMAP_REGION_SIZE as usize {
+        return None;
+    }
+
+    region.next_vaddr = end;
+    Some(virt_addr)
+}
+
+/// Returns a run of `num_pages` pages starting at `virt_addr` to the free list. Runs are not
+/// coalesced with adjacent free neighbours: `ioremap` mappings are few and long-lived enough that
+/// the extra bookkeeping isn't worth it.
+fn free_run(region: &mut MmioRegion, virt_addr: usize, num_pages: usize) {
+    let run_ptr = virt_addr as *mut FreeRun;
+    unsafe {
+        core::ptr::write(run_ptr, FreeRun { page_count: num_pages, next: region.free_runs });
+    }
+    region.free_runs = Some(run_ptr);
+}
@@ -0,0 +1,194 @@
+//! A slab allocator for allocations smaller than a page
+//!
+//! `alloc_internal` otherwise rounds every request up to a whole page, so a small `Box` would burn
+//! an entire page (and its backing physical frame) on its own. Instead, requests that fit are
+//! served out of a per-size-class cache of slabs, where a slab is a single page (obtained from the
+//! page allocator) carved into equal-sized objects threaded together into a free list. Each slab's
+//! metadata (its place in the cache's slab list, free count, and free list head) lives in a small
+//! header at the start of the page itself, so there is no separate bookkeeping allocation.
+
+use core::alloc::Layout;
+
+use super::{PhysicalMemory, BUDDY_ALLOCATOR};
+use page_tables::PageDirectory;
+
+/// The size classes served by the slab tier; requests larger than the biggest class fall through
+/// to the page-granular allocator
+const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// An intrusive free-list node, written directly into the first bytes of a free object
+struct FreeObject {
+    next: Option<*mut FreeObject>,
+}
+
+/// Per-slab metadata, stored in a reserved region at the start of the slab's page
+struct SlabHeader {
+    /// Next slab in this size class's list
+    next: Option<*mut SlabHeader>,
+    /// Head of this slab's free object list
+    free_list: Option<*mut FreeObject>,
+    /// Number of free objects remaining in this slab
+    free_count: usize,
+}
+
+/// A slab allocator: one list of slabs per size class in `SIZE_CLASSES`
+pub(super) struct SlabAllocator {
+    caches: [Option<*mut SlabHeader>; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+    /// Constructs an allocator with no slabs yet; slabs are carved out lazily on first use
+    pub(super) const fn empty() -> Self {
+        Self { caches: [None; SIZE_CLASSES.len()] }
+    }
+
+    /// Returns whether `layout` is small enough to be served by the slab tier
+    pub(super) fn fits(layout: Layout) -> bool {
+        size_class_index_for(layout).is_some()
+    }
+
+    /// Allocates an object fitting `layout` out of the smallest size class it fits in, carving a
+    /// fresh slab out of a newly-allocated page if every existing slab in that class is full. If
+    /// `zeroed` is set, the object is cleared before being handed back - necessary even for objects
+    /// out of an existing slab, since they may be recycled from a previous allocation.
+    pub(super) fn alloc(&mut self, phys_mem: &mut PhysicalMemory, page_dir: &mut PageDirectory,
+        layout: Layout, zeroed: bool) -> Option<*mut u8> {
+        let class_index = size_class_index_for(layout)?;
+        let size_class = SIZE_CLASSES[class_index];
+
+        // Look for an existing slab in this class that still has a free object
+        let mut candidate = self.caches[class_index];
+        while let Some(header_ptr) = candidate {
+            let header = unsafe { core::ptr::read(header_ptr) };
+            if header.free_count > 0 {
+                break;
+            }
+            candidate = header.next;
+        }
+
+        // If none had room, allocate a fresh page and carve a new slab out of it
+        let header_ptr = match candidate {
+            Some(header_ptr) => header_ptr,
+            None => {
+                let page = BUDDY_ALLOCATOR.lock().alloc(phys_mem, page_dir, 1, false)?;
+                let header_ptr = init_slab(page, size_class);
+
+                unsafe {
+                    let mut header = core::ptr::read(header_ptr);
+                    header.next = self.caches[class_index];
+                    core::ptr::write(header_ptr, header);
+                }
+                self.caches[class_index] = Some(header_ptr);
+
+                header_ptr
+            }
+        };
+
+        // Pop a free object off of the slab
+        let mut header = unsafe { core::ptr::read(header_ptr) };
+        let object_ptr = header.free_list?;
+        let object = unsafe { core::ptr::read(object_ptr) };
+        header.free_list = object.next;
+        header.free_count -= 1;
+        unsafe { core::ptr::write(header_ptr, header); }
+
+        if zeroed {
+            unsafe { core::ptr::write_bytes(object_ptr as *mut u8, 0, size_class); }
+        }
+
+        Some(object_ptr as *mut u8)
+    }
+
+    /// Returns an object at `ptr` (previously handed out for the same `layout`) to its slab, and
+    /// frees the slab's page back to the page allocator if it is now completely empty
+    pub(super) fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let class_index = size_class_index_for(layout).expect("invalid layout passed to slab dealloc");
+        let size_class = SIZE_CLASSES[class_index];
+
+        // Slabs are exactly one page, so the header sits at the start of the page containing `ptr`
+        let page = (ptr as usize & !0xFFF) as *mut u8;
+        let header_ptr = page as *mut SlabHeader;
+        let mut header = unsafe { core::ptr::read(header_ptr) };
+
+        let object_ptr = ptr as *mut FreeObject;
+        unsafe { core::ptr::write(object_ptr, FreeObject { next: header.free_list }); }
+        header.free_list = Some(object_ptr);
+        header.free_count += 1;
+
+        if header.free_count == objects_per_slab(size_class) {
+            // The slab is now completely empty: unlink it and release its page
+            self.remove_slab(class_index, header_ptr);
+            BUDDY_ALLOCATOR.lock().dealloc(page, 1);
+            return;
+        }
+
+        unsafe { core::ptr::write(header_ptr, header); }
+    }
+
+    /// Unlinks the slab at `target` from `caches[class_index]`'s list
+    fn remove_slab(&mut self, class_index: usize, target: *mut SlabHeader) {
+        let mut last_entry: Option<*mut SlabHeader> = None;
+        let mut entry = self.caches[class_index];
+
+        while let Some(header_ptr) = entry {
+            let header = unsafe { core::ptr::read(header_ptr) };
+
+            if header_ptr == target {
+                match last_entry {
+                    Some(last_ptr) => unsafe {
+                        let mut last_header = core::ptr::read(last_ptr);
+                        last_header.next = header.next;
+                        core::ptr::write(last_ptr, last_header);
+                    },
+                    None => self.caches[class_index] = header.next,
+                }
+                return;
+            }
+
+            last_entry = Some(header_ptr);
+            entry = header.next;
+        }
+    }
+}
+
+/// Number of bytes reserved for the `SlabHeader` at the start of a slab, rounded up to a whole
+/// number of `size_class`-sized object slots so the objects that follow stay aligned to their size
+fn header_reserved_bytes(size_class: usize) -> usize {
+    let header_size = core::mem::size_of::<SlabHeader>();
+    ((header_size + size_class - 1) / size_class) * size_class
+}
+
+/// Number of objects of `size_class` bytes that fit in a page alongside the slab header
+fn objects_per_slab(size_class: usize) -> usize {
+    (4096 - header_reserved_bytes(size_class)) / size_class
+}
+
+/// Initializes a freshly-allocated page as a slab of `size_class`-sized objects, threading all of
+/// its objects onto a free list, and returns a pointer to its header
+fn init_slab(page: *mut u8, size_class: usize) -> *mut SlabHeader {
+    let reserved = header_reserved_bytes(size_class);
+
+    let mut free_list = None;
+    for index in (0..objects_per_slab(size_class)).rev() {
+        let object_ptr = unsafe { page.add(reserved + index * size_class) } as *mut FreeObject;
+        unsafe { core::ptr::write(object_ptr, FreeObject { next: free_list }); }
+        free_list = Some(object_ptr);
+    }
+
+    let header_ptr = page as *mut SlabHeader;
+    unsafe {
+        core::ptr::write(header_ptr, SlabHeader {
+            next: None,
+            free_list,
+            free_count: objects_per_slab(size_class),
+        });
+    }
+
+    header_ptr
+}
+
+/// Finds the index into `SIZE_CLASSES` of the smallest class that can satisfy `layout`
+fn size_class_index_for(layout: Layout) -> Option<usize> {
+    let needed = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| class >= needed)
+}
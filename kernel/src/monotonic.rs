@@ -0,0 +1,77 @@
+//! Calibrated TSC-based monotonic clock
+//!
+//! The PIT handler in `crate::interrupts::pit_8254` only tracks wall-clock time at one-second
+//! resolution (`CURRENT_UNIX_TIME`) and otherwise just counts its ~100 Hz ticks. This module calibrates the TSC's frequency against the PIT's known tick
+//! rate once at boot, then uses `cpu::serializing_rdtsc()` to provide a much finer-grained
+//! monotonic clock for callers that need to measure short intervals (e.g. the commented-out heap
+//! allocator benchmark in `main.rs`).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::interrupts::pit_8254;
+
+/// How many PIT ticks to wait while calibrating. At the PIT's ~100 Hz tick rate this is roughly a
+/// 50ms calibration window - long enough to average out rdtsc/interrupt jitter, short enough to not
+/// noticeably delay boot.
+const CALIBRATION_TICKS: u64 = 5;
+
+/// The TSC value read at the start of calibration, i.e. as close to boot as this module gets
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+/// The calibrated TSC frequency, in Hz. Zero until `init` has run.
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Calibrates `TSC_HZ` against the PIT. Must be called after `crate::interrupts::init` has started
+/// the PIT ticking and unmasked interrupts, and before anything calls `now_ns`/`busy_sleep`.
+pub fn init() {
+	// Wait for a tick boundary so the window we measure starts right as a tick lands, rather than
+	// part way through one
+	let start_ticks = pit_8254::tick_count();
+	while pit_8254::tick_count() == start_ticks {
+		core::hint::spin_loop();
+	}
+
+	let target_ticks = pit_8254::tick_count() + CALIBRATION_TICKS;
+	let start_tsc = cpu::serializing_rdtsc();
+	let start_ticks = pit_8254::tick_count();
+
+	while pit_8254::tick_count() < target_ticks {
+		core::hint::spin_loop();
+	}
+
+	let end_tsc = cpu::serializing_rdtsc();
+	let ticks_elapsed = pit_8254::tick_count() - start_ticks;
+
+	let tsc_hz = (end_tsc - start_tsc) as f64 * pit_8254::real_freq_hz() / (ticks_elapsed as f64);
+
+	BOOT_TSC.store(start_tsc, Ordering::Relaxed);
+	TSC_HZ.store(tsc_hz as u64, Ordering::Relaxed);
+
+	serial::println!("Calibrated TSC frequency: {} Hz", tsc_hz as u64);
+}
+
+/// Nanoseconds elapsed since `init` recorded `BOOT_TSC`. Panics if `init` hasn't run yet.
+pub fn now_ns() -> u64 {
+	let tsc_hz = TSC_HZ.load(Ordering::Relaxed);
+	assert!(tsc_hz != 0, "monotonic::now_ns called before monotonic::init");
+
+	let elapsed_cycles = cpu::serializing_rdtsc() - BOOT_TSC.load(Ordering::Relaxed);
+
+	// Intermediate product can overflow a u64 well before `elapsed_cycles` does, so we widen to
+	// u128 for the multiply and narrow back down afterwards
+	((elapsed_cycles as u128) * 1_000_000_000 / (tsc_hz as u128)) as u64
+}
+
+/// The calibrated TSC frequency in Hz, for callers that want to convert a raw `serializing_rdtsc`
+/// cycle delta into real time themselves
+pub fn tsc_hz() -> u64 {
+	TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// Busy-waits for at least `ns` nanoseconds by spinning on the TSC, instead of the magic
+/// `busy_loop(0x6c600)` constant used by `time::read_cmos_reg`
+pub fn busy_sleep(ns: u64) {
+	let target = now_ns() + ns;
+	while now_ns() < target {
+		core::hint::spin_loop();
+	}
+}
@@ -0,0 +1,49 @@
+//! Power management event dispatch
+
+use lock_cell::LockCell;
+
+/// A notification raised by one of the ACPI power keys a PS/2 keyboard can report
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerEvent {
+	/// The power button was pressed
+	Power,
+	/// The sleep button was pressed
+	Sleep,
+	/// The system was woken up
+	Wake,
+}
+
+/// The signature a power event handler registers with via `set_notification_handler`
+pub type PowerNotificationHandler = fn(PowerEvent);
+
+/// The currently registered power event handler, defaulting to `default_handler`
+static NOTIFICATION_HANDLER: LockCell<PowerNotificationHandler> = LockCell::new(default_handler);
+
+/// Registers `handler` to be invoked whenever a `PowerEvent` is raised, replacing whatever handler
+/// (default or otherwise) was previously registered. This gives the rest of the kernel (or, once
+/// userspace gains a power-management daemon, a syscall-driven hook) a single choke point to
+/// observe hardware power buttons, mirroring how `crate::interrupts::intr_register` lets
+/// individual drivers claim a notification instead of being hardcoded into the dispatcher.
+pub fn set_notification_handler(handler: PowerNotificationHandler) {
+	*NOTIFICATION_HANDLER.lock() = handler;
+}
+
+/// Raises `event`, invoking the currently registered handler
+pub fn notify(event: PowerEvent) {
+	(*NOTIFICATION_HANDLER.lock())(event);
+}
+
+/// The handler installed until something calls `set_notification_handler`. Sleep/Wake are logged
+/// and otherwise ignored, since this kernel has no power states to transition between yet; Power
+/// halts the CPU, since that's the best a kernel with no ACPI shutdown support can do for a user
+/// who just pressed the power button.
+fn default_handler(event: PowerEvent) {
+	match event {
+		PowerEvent::Power => {
+			serial::println!("Power button pressed, halting");
+			unsafe { cpu::halt(); }
+		},
+		PowerEvent::Sleep => serial::println!("Sleep button pressed (ignored, no sleep support)"),
+		PowerEvent::Wake => serial::println!("Wake event received (ignored, no sleep support)"),
+	}
+}
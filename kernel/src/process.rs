@@ -30,29 +30,40 @@ pub struct Process {
 }
 
 impl Process {
-	pub fn new(kernel_intr_stack: VirtAddr) -> Self {
+	/// Copies the kernel half (the top 256 PDEs, covering the shared 0xC0000000+ region) of the
+	/// currently active page directory into `page_dir`, and gives `page_dir` its own private
+	/// mapping of `kernel_intr_stack`. Every process needs both set up the same way, whether
+	/// `page_dir` starts out completely empty (`new`) or already has a copy-on-write user half
+	/// installed by `clone_cow` (`new_from_fork`) - `clone_cow` only ever walks present, user PDEs,
+	/// so it never touches the kernel half either way.
+	fn install_kernel_half(page_dir: &mut PageDirectory, phys_mem: &mut PhysicalMemory,
+		cur_page_dir: &PageDirectory, kernel_intr_stack: VirtAddr) {
 		let mut pd_buffer = box[0u8; 1024];
 
-		let mut pmem = memory_manager::PHYS_MEM.lock();
-		let (phys_mem, cur_page_dir) = pmem.as_mut().unwrap();
-
 		let old_cr3 = cur_page_dir.get_directory_addr();
 		let cur_pd = unsafe {
 			core::slice::from_raw_parts(phys_mem.translate_phys(old_cr3, 4096).unwrap(), 4096)
 		};
 		pd_buffer.copy_from_slice(&cur_pd[3072..]);
-		
-		let mut proc_page_dir = PageDirectory::new(phys_mem).unwrap();
-		let new_cr3 = proc_page_dir.get_directory_addr();
+
+		let new_cr3 = page_dir.get_directory_addr();
 		let new_pd = unsafe {
 			core::slice::from_raw_parts_mut(phys_mem.translate_phys(new_cr3, 4096).unwrap(), 4096)
 		};
 		(&mut new_pd[3072..]).copy_from_slice(&pd_buffer[..]);
-			
+
 		// FIXME: Temp hack because we dont free the kernel stack yet
-		let _ = proc_page_dir.unmap(phys_mem, kernel_intr_stack, true);
+		let _ = page_dir.unmap(phys_mem, kernel_intr_stack, true);
 		// TODO: How does this get updates in other processes' page directories?
-		proc_page_dir.map(phys_mem, kernel_intr_stack, KERNEL_INTR_STACK_SIZE, true, false).unwrap();
+		page_dir.map(phys_mem, kernel_intr_stack, KERNEL_INTR_STACK_SIZE, true, false).unwrap();
+	}
+
+	pub fn new(kernel_intr_stack: VirtAddr) -> Self {
+		let mut pmem = memory_manager::PHYS_MEM.lock();
+		let (phys_mem, cur_page_dir) = pmem.as_mut().unwrap();
+
+		let mut proc_page_dir = PageDirectory::new(phys_mem).unwrap();
+		Self::install_kernel_half(&mut proc_page_dir, phys_mem, cur_page_dir, kernel_intr_stack);
 
 		Self {
 			page_directory: proc_page_dir,
@@ -75,14 +86,17 @@ impl Process {
 		self.registers.esp = USER_STACK_VADDR.0 + USER_STACK_SIZE;
 
 		let mut virt_mem_range_idx = 1;
-		elf.for_segment(|seg_vaddr, seg_size, init_bytes, _read, write, exec| {
+		elf.for_segment(|seg_vaddr, init_bytes, bss_len, flags| {
+			let write = flags & elf_parser::SEGMENT_FLAGS_PF_W != 0;
+			let exec = flags & elf_parser::SEGMENT_FLAGS_PF_X != 0;
+
 			let (first_page_vaddr, num_pages) = self.page_directory.map_init(
 				phys_mem,
 				VirtAddr(seg_vaddr as u32),
-				seg_size as u32,
+				(init_bytes.len() + bss_len) as u32,
 				write,
 				true,
-				|off| { 
+				|off| {
 					if off < init_bytes.len() {
 						init_bytes[off]
 					} else {
@@ -109,39 +123,32 @@ impl Process {
 		proc
 	}
 
-	pub fn new_from_fork(kernel_intr_stack: VirtAddr, parent: &Process) -> Self {
-		let mut proc = Self::new(kernel_intr_stack);
-
-		proc.file_descriptors = parent.file_descriptors;
-		proc.cwd_inode = parent.cwd_inode;
-		proc.registers = parent.registers;
-		proc.registers.eax = 0; // The fork-syscall return value is 0 for the child
-		proc.eip = parent.eip;
-		proc.eflags = parent.eflags;
+	pub fn new_from_fork(kernel_intr_stack: VirtAddr, parent: &mut Process) -> Self {
+		let mut pmem = memory_manager::PHYS_MEM.lock();
+		let (phys_mem, cur_page_dir) = pmem.as_mut().unwrap();
 
-		// TODO: Copy on write
-		proc.virtual_memory_ranges = parent.virtual_memory_ranges;
-		
-		let mut temp_buf = box[0u8; 4096];
+		// Shares every one of the parent's user pages with it copy-on-write instead of eagerly
+		// copying them; a later write to one takes a fault `memory_manager::handle_cow_fault`
+		// resolves. `clone_cow` only ever walks present, user PDEs, so the kernel half still needs
+		// to be installed separately below, same as `new` does for a brand new directory.
+		let mut proc_page_dir = parent.page_directory.clone_cow(phys_mem).unwrap();
+		Self::install_kernel_half(&mut proc_page_dir, phys_mem, cur_page_dir, kernel_intr_stack);
 
-		let mut pmem = memory_manager::PHYS_MEM.lock();
-		let (phys_mem, _) = pmem.as_mut().unwrap();
+		let mut registers = parent.registers;
+		registers.eax = 0; // The fork-syscall return value is 0 for the child
 
-		for mem_range in parent.virtual_memory_ranges {
-			if let Some((first_page_vaddr, num_pages, write, _exec)) = mem_range {
-				for page in 0..num_pages {
-					let page_vaddr = first_page_vaddr.0 + page*4096;
-					let page_slice = unsafe {
-						core::slice::from_raw_parts(page_vaddr as *const u8, 4096)
-					};
-					temp_buf.copy_from_slice(page_slice);
-					proc.page_directory.map_init(phys_mem, VirtAddr(page_vaddr), 4096, write, true,
-						|offset| temp_buf[offset]).unwrap(); // TODO: This is slow, use memcpy
-				}
-			}
+		Self {
+			page_directory: proc_page_dir,
+			virtual_memory_ranges: parent.virtual_memory_ranges,
+			kernel_intr_stack,
+			file_descriptors: parent.file_descriptors,
+			cwd_inode: parent.cwd_inode,
+			registers,
+			eip: parent.eip,
+			eflags: parent.eflags,
+			in_kernel: false,
+			exit_code: None,
 		}
-
-		proc
 	}
 
 	fn unmap_user_virtual_memory(&mut self, phys_mem: &mut PhysicalMemory) {
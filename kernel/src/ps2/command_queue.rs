@@ -3,24 +3,41 @@
 const PS2_MSG_ACK: u8 = 0xFA;
 const PS2_MSG_RESEND: u8 = 0xFE;
 /// Maximum amount of command retries when receiving a RESEND response
-const MAX_COMMAND_RETRIES: usize = 3;
+const MAX_COMMAND_RETRIES: u8 = 3;
+/// The longest response any queued command expects back (e.g. a mouse status/Get-Info response)
+const MAX_RESPONSE_LEN: usize = 3;
 
 #[derive(Clone, Copy, Debug)]
 pub struct PS2Command {
 	pub command: u8,
-	pub data: Option<u8>
+	pub data: Option<u8>,
+	/// How many bytes the device sends back after acknowledging this command, beyond the ACK
+	/// itself (e.g. 2 for a keyboard identify). These are collected by the queue and made
+	/// available through `last_response` once the command completes.
+	pub response_len: u8,
+}
+
+/// An entry in the command queue: the command itself, plus how many times it's been resent so far
+#[derive(Clone, Copy, Debug)]
+struct QueuedCommand {
+	command: PS2Command,
+	retries: u8,
 }
 
 /// A struct that handles a queue of commands to send to a PS/2 device
 pub struct PS2CommandQueue {
 	/// Command queue for sending and resending commands as needed
-	queue: [PS2Command; 5],
+	queue: [QueuedCommand; 5],
 	/// Number of commands in the queue
 	queue_length: usize,
-	/// Number of retries of the current queued command
-	command_retries: usize,
 	/// Whether or not we are waiting for an ACK of the command's data byte
 	waiting_for_data_ack: bool,
+	/// Whether we're past the head command's ACK and are now collecting its response bytes
+	collecting_response: bool,
+	/// How many of the head command's response bytes have arrived so far
+	response_received: u8,
+	/// The head command's response bytes, as they arrive. Valid range is `[..response_received]`
+	response_buffer: [u8; MAX_RESPONSE_LEN],
 	/// Whether the command should be send to the second port of the first port
 	second_port: bool,
 }
@@ -28,23 +45,29 @@ pub struct PS2CommandQueue {
 impl PS2CommandQueue {
 	pub const fn new(second_port: bool) -> Self {
 		PS2CommandQueue {
-			queue: [PS2Command {command: 0, data: None }; 5],
+			queue: [QueuedCommand {
+				command: PS2Command { command: 0, data: None, response_len: 0 },
+				retries: 0,
+			}; 5],
 			queue_length: 0,
-			command_retries: 0,
 			waiting_for_data_ack: false,
+			collecting_response: false,
+			response_received: 0,
+			response_buffer: [0u8; MAX_RESPONSE_LEN],
 			second_port
 		}
 	}
 
 	/// Queues the specified command and dispatches it immediately if it is the first in the queue
-	pub fn queue(&mut self, command: impl Into<PS2Command>) {
+	pub fn append_command(&mut self, command: impl Into<PS2Command>) {
 		let command: PS2Command = command.into();
+		assert!(command.response_len as usize <= MAX_RESPONSE_LEN);
 
 		// Assert we have enough space left in the queue
 		assert!(self.queue_length < self.queue.len());
 
 		// Append the command to the end of the queue and update the queue length
-		self.queue[self.queue_length] = command;
+		self.queue[self.queue_length] = QueuedCommand { command, retries: 0 };
 		self.queue_length += 1;
 
 		// If this is the first command in the queue we can dispatch it immediately
@@ -53,21 +76,42 @@ impl PS2CommandQueue {
 		}
 	}
 
-	/// Uses the provided keyboard message to update the command queue. Returns true if the queue is
+	/// Uses the provided device message to update the command queue. Returns true if the queue is
 	/// empty after the message is handled
-	pub fn handle_message(&mut self, message: u8) -> bool {
+	pub fn update_command_queue(&mut self, message: u8) -> bool {
 		// If no commands are queued this is not a response to a queued command
 		if self.queue_length == 0 {
 			return true;
 		}
 
+		// While we're past the head command's ACK, every byte that comes in is one of its response
+		// bytes, not a fresh ACK/RESEND
+		if self.collecting_response {
+			self.response_buffer[self.response_received as usize] = message;
+			self.response_received += 1;
+
+			if self.response_received == self.queue[0].command.response_len {
+				self.collecting_response = false;
+				self.pop_head();
+			}
+
+			return self.queue_length == 0;
+		}
+
 		if message == PS2_MSG_RESEND {
 			// If this is a RESEND message, we retry the first command in the queue a few times
-			if self.command_retries < MAX_COMMAND_RETRIES {
-				self.command_retries += 1;
-				self.send_command_to_device(self.queue[0]);
+			let head = &mut self.queue[0];
+			if head.retries < MAX_COMMAND_RETRIES {
+				head.retries += 1;
+				let command = head.command;
+				self.send_command_to_device(command);
 			} else {
-				panic!("[PS2CommandQueue]: Failed to send command {:?} (Too many retries)",	self.queue[0]);
+				// The device keeps garbling this command - give up on it rather than panicking the
+				// kernel over what might just be a flaky connection, and let the rest of the queue
+				// (if any) carry on
+				serial::println!("WARNING: [PS2CommandQueue] Giving up on command {:?} after {} \
+					RESENDs", head.command, MAX_COMMAND_RETRIES);
+				self.pop_head();
 			}
 		} else if message == PS2_MSG_ACK {
 			// If this is an acknowledge message, we first check if the command is also expect an
@@ -77,48 +121,66 @@ impl PS2CommandQueue {
 				return false;
 			}
 
-			// We reset the retry counter for the next command
-			self.command_retries = 0;
-
-			// We pop the first element in the queue by shifting all elements back one place
-			for i in 1..self.queue_length {
-				self.queue[i-1] = self.queue[i];
-			}
-
-			// We decrement the queue length
-			self.queue_length -= 1;
-
-			// If the queue is not empty, we dispatch the next command
-			if self.queue_length > 0 {
-				self.send_command_to_device(self.queue[0]);
+			// Forget the previous command's response, so `last_response` only reports this one's
+			self.response_received = 0;
+			if self.queue[0].command.response_len > 0 {
+				// The command isn't done yet - wait for its response bytes to arrive too
+				self.collecting_response = true;
+			} else {
+				self.pop_head();
 			}
 		} else {
 			// If the queue is not empty, but the message we received is not an ACK or a RESEND, the
-			// command has a response byte which is discarded. This shouldn't happen(?)
-			panic!("[PS2CommandQueue] Discarded command result {:#X}", message);
+			// device sent something we didn't ask for (e.g. a stray self-test/failure byte). Drop the
+			// stuck command instead of panicking - whatever is wrong with it, blocking the rest of the
+			// queue on it forever would be worse.
+			serial::println!("WARNING: [PS2CommandQueue] Discarded unexpected byte {:#X} while \
+				waiting on command {:?}", message, self.queue[0].command);
+			self.pop_head();
 		}
 
 		self.queue_length == 0
 	}
-	
+
+	/// The response bytes collected for the command that was just completed, if it had any. Only
+	/// meaningful immediately after `update_command_queue` reports the queue emptied out.
+	pub fn last_response(&self) -> &[u8] {
+		&self.response_buffer[..self.response_received as usize]
+	}
+
+	/// Pops the head of the queue (whose ACK, and any response bytes, have now fully arrived), and
+	/// dispatches the next entry if there is one
+	fn pop_head(&mut self) {
+		for i in 1..self.queue_length {
+			self.queue[i-1] = self.queue[i];
+		}
+		self.queue_length -= 1;
+
+		if self.queue_length > 0 {
+			self.send_command_to_device(self.queue[0].command);
+		}
+	}
+
 	/// Sends the specified command to the keyboard
 	fn send_command_to_device(&mut self, command: PS2Command) {
 		// We first send the command byte
-		if self.second_port {
-			super::controller::send_data_to_second_port(command.command);
+		let result = if self.second_port {
+			super::controller::send_data_to_second_port(command.command)
 		}else{
-			super::controller::send_data(command.command);
-		}
+			super::controller::send_data(command.command)
+		};
+		result.expect("PS/2 I/O error while sending command byte");
 
 		if let Some(data_byte) = command.data {
 			// If the command also has a data byte, we send it as well and remember we need to
 			// ignore the first ACK because the keyboard will also ACK the data byte
-			if self.second_port {
-				super::controller::send_data_to_second_port(data_byte);
+			let result = if self.second_port {
+				super::controller::send_data_to_second_port(data_byte)
 			}else{
-				super::controller::send_data(data_byte);
-			}
+				super::controller::send_data(data_byte)
+			};
+			result.expect("PS/2 I/O error while sending command data byte");
 			self.waiting_for_data_ack = true;
 		}
 	}
-}
\ No newline at end of file
+}
@@ -13,6 +13,10 @@ const PS2_CTRL_WRITE_CMD_PORT: u16 = 0x64;
 const PS2_CTRL_STATUS_OUTPUT_FULL_MASK: u8 = 0x1;
 /// PS/2 controller status mask for the input buffer full bit
 const PS2_CTRL_STATUS_INPUT_FULL_MASK: u8 = 0x2;
+/// PS/2 controller status mask for the timeout-error bit
+const PS2_CTRL_STATUS_TIMEOUT_ERROR_MASK: u8 = 1 << 6;
+/// PS/2 controller status mask for the parity-error bit
+const PS2_CTRL_STATUS_PARITY_ERROR_MASK: u8 = 1 << 7;
 
 /// PS/2 controller config mask for the first port interrupt enable bit
 const PS2_CTRL_CONFIG_FIRST_INTERRUPT_ENABLE_MASK: u8 = 1 << 0;
@@ -22,6 +26,8 @@ const PS2_CTRL_CONFIG_SECOND_INTERRUPT_ENABLE_MASK: u8 = 1 << 1;
 const PS2_CTRL_CONFIG_SECOND_PORT_CLOCK_DISABLE_MASK: u8 = 1 << 5;
 /// PS/2 controller config mask for the first port translation enable bit
 const PS2_CTRL_CONFIG_FIRST_PORT_TRANSLATE_MASK: u8 = 1 << 6;
+/// PS/2 controller config mask for the A20 gate bit
+const PS2_CTRL_CONFIG_A20_GATE_MASK: u8 = 1 << 4;
 
 /// The value returned by the PS/2 controller when the self-test passes
 const PS2_CTRL_SELF_TEST_PASSED: u8 = 0x55;
@@ -30,10 +36,41 @@ const PS2_CTRL_PORT_TEST_PASSED: u8 = 0x0;
 
 /// The universal reset command that all PS/2 devices support
 const PS2_DEVICE_RESET_CMD: u8 = 0xFF;
+/// Command acknowledged response, common to all PS/2 devices
+const PS2_DEVICE_MSG_ACK: u8 = 0xFA;
+/// The value a device sends after a reset once its self-test passes
+const PS2_DEVICE_MSG_SELF_TEST_PASSED: u8 = 0xAA;
+/// The universal identify command that all PS/2 devices support
+const PS2_DEVICE_CMD_IDENTIFY: u8 = 0xF2;
+/// The universal echo command that all PS/2 devices support: the device just sends the same byte
+/// back, without the usual ACK framing
+const PS2_DEVICE_CMD_ECHO: u8 = 0xEE;
 
 /// Timeout for receiving and sending PS/2 controller data
 const PS2_TIMEOUT: usize = 0x30000000;
 
+/// Errors from a single PS/2 controller data-port transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ps2Error {
+	/// The expected status bit never flipped before `PS2_TIMEOUT` spin iterations elapsed
+	Timeout,
+	/// The controller's status register reported a device timeout (status bit 6)
+	DeviceTimeout,
+	/// The controller's status register reported a parity error (status bit 7)
+	Parity,
+}
+
+/// The kind of device found attached to a PS/2 port, as determined by `reset_and_identify_port`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PortDevice {
+	/// The port didn't respond as expected to reset/identify, or reported an ID we don't recognize
+	Unknown,
+	StandardMouse,
+	ScrollMouse,
+	FiveButtonMouse,
+	Mf2Keyboard,
+}
+
 /// Possible commands for the PS/2 controller
 #[repr(u8)]
 enum PS2Command {
@@ -49,6 +86,30 @@ enum PS2Command {
 	WriteToSecondPort = 0xD4,
 }
 
+/// Enables or disables the A20 gate by read-modify-writing the corresponding bit in the PS/2
+/// controller's config byte, then re-reading the config byte to confirm the change actually took.
+/// Useful as a fallback on real hardware, where BIOS/bootloader A20 handling can't always be
+/// trusted.
+pub fn set_a20(enable: bool) -> Result<(), Ps2Error> {
+	let mut config_byte = send_command_with_response(PS2Command::ReadConfigByte)?;
+	if enable {
+		config_byte |= PS2_CTRL_CONFIG_A20_GATE_MASK;
+	} else {
+		config_byte &= !PS2_CTRL_CONFIG_A20_GATE_MASK;
+	}
+	send_command_with_arg(PS2Command::WriteConfigByte, config_byte)?;
+
+	// Give the controller a moment to settle before reading the config byte back
+	wait_for_input_ready()?;
+
+	let new_config_byte = send_command_with_response(PS2Command::ReadConfigByte)?;
+	if (new_config_byte & PS2_CTRL_CONFIG_A20_GATE_MASK != 0) != enable {
+		panic!("PS/2 controller did not latch the requested A20 gate state");
+	}
+
+	Ok(())
+}
+
 /// Initializes the PS/2 controller and tries to enable and reset both PS/2 ports
 pub fn init() {
 	// NOTE: OSDEV wiki says that USB controller init must happen before PS/2 init (and USB legacy
@@ -58,26 +119,34 @@ pub fn init() {
 	let mut first_port_avail = true;
 	let mut second_port_avail = false;
 
-	// PS/2 init sequence
+	// PS/2 init sequence. Each step here is expected to succeed on a working controller, so a
+	// `Ps2Error` bubbling out of one of these is treated the same as the pre-existing hard
+	// failures below: something is badly wrong with the controller, not just a single device.
 	unsafe {
 
 		// Initially disable both ports (if a second port does not exist disabling it is a NOP)
-		send_command(PS2Command::DisableFirstPort);
-		send_command(PS2Command::DisableSecondPort);
+		send_command(PS2Command::DisableFirstPort).expect("Failed to disable PS/2 first port");
+		send_command(PS2Command::DisableSecondPort).expect("Failed to disable PS/2 second port");
 
 		// Flush the output buffer
 		cpu::in8(PS2_CTRL_DATA_PORT);
 
+		// Make sure A20 is on, regardless of what the bootloader already did
+		set_a20(true).expect("Failed to enable A20 gate");
+
 		// Run controller self-test
-		if send_command_with_response(PS2Command::SelfTest) != PS2_CTRL_SELF_TEST_PASSED {
+		if send_command_with_response(PS2Command::SelfTest).expect("PS/2 Controller self-test I/O error")
+			!= PS2_CTRL_SELF_TEST_PASSED {
 			panic!("PS/2 Controller self-test failed!");
 		}
 
 		// Configure the controller for the init sequence: no interrupts for either port
-		let mut ctrl_config_byte = send_command_with_response(PS2Command::ReadConfigByte);
+		let mut ctrl_config_byte = send_command_with_response(PS2Command::ReadConfigByte)
+			.expect("Failed to read PS/2 controller config byte");
 		ctrl_config_byte &= !PS2_CTRL_CONFIG_FIRST_INTERRUPT_ENABLE_MASK;
 		ctrl_config_byte &= !PS2_CTRL_CONFIG_SECOND_INTERRUPT_ENABLE_MASK;
-		send_command_with_arg(PS2Command::WriteConfigByte, ctrl_config_byte);
+		send_command_with_arg(PS2Command::WriteConfigByte, ctrl_config_byte)
+			.expect("Failed to write PS/2 controller config byte");
 
 		// To check if the controller has/supports a second port, we check the config bit which
 		// is cleared/set by the enable/disable commands. Because the value of the bit is
@@ -87,16 +156,18 @@ pub fn init() {
 		if (ctrl_config_byte & PS2_CTRL_CONFIG_SECOND_PORT_CLOCK_DISABLE_MASK) == 0 {
 			second_port_avail = false;
 		} else {
-			send_command(PS2Command::EnableSecondPort);
-			let new_config_byte = send_command_with_response(PS2Command::ReadConfigByte);
+			send_command(PS2Command::EnableSecondPort).expect("Failed to enable PS/2 second port");
+			let new_config_byte = send_command_with_response(PS2Command::ReadConfigByte)
+				.expect("Failed to read PS/2 controller config byte");
 			if (new_config_byte & PS2_CTRL_CONFIG_SECOND_PORT_CLOCK_DISABLE_MASK) != 0 {
 				second_port_avail = false;
 			}
-			send_command(PS2Command::DisableSecondPort);
+			send_command(PS2Command::DisableSecondPort).expect("Failed to disable PS/2 second port");
 		}
 
 		// Run interface test for the first port
-		let first_port_test_result = send_command_with_response(PS2Command::FirstPortTest);
+		let first_port_test_result = send_command_with_response(PS2Command::FirstPortTest)
+			.expect("Failed to run PS/2 first port test");
 		if first_port_test_result != PS2_CTRL_PORT_TEST_PASSED {
 			serial::println!("ERROR: PS/2 first port test failed with error code {:#x}!",
 				first_port_test_result);
@@ -105,7 +176,8 @@ pub fn init() {
 
 		// Run interface test for the second port
 		if second_port_avail {
-			let second_port_test_result = send_command_with_response(PS2Command::SecondPortTest);
+			let second_port_test_result = send_command_with_response(PS2Command::SecondPortTest)
+				.expect("Failed to run PS/2 second port test");
 			if second_port_test_result != PS2_CTRL_PORT_TEST_PASSED {
 				serial::println!("ERROR: PS/2 second port test failed with error code {:#x}!",
 					second_port_test_result);
@@ -114,7 +186,8 @@ pub fn init() {
 		}
 
 		// Configure devices (enabling interrupts and disabling legacy translation)
-		let mut ctrl_config_byte = send_command_with_response(PS2Command::ReadConfigByte);
+		let mut ctrl_config_byte = send_command_with_response(PS2Command::ReadConfigByte)
+			.expect("Failed to read PS/2 controller config byte");
 		if first_port_avail {
 			ctrl_config_byte |= PS2_CTRL_CONFIG_FIRST_INTERRUPT_ENABLE_MASK;
 			ctrl_config_byte &= !PS2_CTRL_CONFIG_FIRST_PORT_TRANSLATE_MASK;
@@ -122,86 +195,175 @@ pub fn init() {
 		if second_port_avail {
 			ctrl_config_byte |= PS2_CTRL_CONFIG_SECOND_INTERRUPT_ENABLE_MASK;
 		}
-		send_command_with_arg(PS2Command::WriteConfigByte, ctrl_config_byte);
-		
-		// Enable and reset devices
+		send_command_with_arg(PS2Command::WriteConfigByte, ctrl_config_byte)
+			.expect("Failed to write PS/2 controller config byte");
+
+		// Enable both ports, then reset and identify whatever is attached to them. Unlike the
+		// steps above, a `Ps2Error` here just means the attached device (if any) didn't respond
+		// as expected, so `reset_and_identify_port` folds it into `PortDevice::Unknown` instead of
+		// panicking the whole controller.
+		let mut first_port_device = PortDevice::Unknown;
+		let mut second_port_device = PortDevice::Unknown;
 		if first_port_avail {
-			send_command(PS2Command::EnableFirstPort);
-			
-			send_data(PS2_DEVICE_RESET_CMD);
+			send_command(PS2Command::EnableFirstPort).expect("Failed to enable PS/2 first port");
+			first_port_device = reset_and_identify_port(false);
 		}
 		if second_port_avail {
-			send_command(PS2Command::EnableSecondPort);
+			send_command(PS2Command::EnableSecondPort).expect("Failed to enable PS/2 second port");
+			second_port_device = reset_and_identify_port(true);
+		}
 
-			send_data_to_second_port(PS2_DEVICE_RESET_CMD);
+		serial::println!("Enabled PS/2 Controller [{:?}, {:?}]", first_port_device,
+			second_port_device);
+
+		// Dispatch the driver that matches each port's device. The drivers are hardcoded to their
+		// conventional port (keyboard on the first port, mice on the second), so a device that
+		// doesn't match what we expect of its port is left unhandled rather than guessed at.
+		match first_port_device {
+			// Identify already confirmed this is an MF2 keyboard, but reset/identify is also the
+			// step most likely to have been garbled on flaky hardware. Echo it once more before
+			// attaching the driver and trusting whatever scan codes show up next.
+			PortDevice::Mf2Keyboard if echo(false) => super::keyboard::init(),
+			PortDevice::Mf2Keyboard =>
+				serial::println!("WARNING: PS/2 first port keyboard did not respond to Echo, \
+					treating it as absent"),
+			PortDevice::Unknown => {},
+			other => serial::println!("WARNING: Unexpected device on PS/2 first port: {:?}", other),
+		}
+		match second_port_device {
+			PortDevice::StandardMouse | PortDevice::ScrollMouse | PortDevice::FiveButtonMouse => {
+				super::mouse::init();
+			},
+			PortDevice::Unknown => {},
+			other => serial::println!("WARNING: Unexpected device on PS/2 second port: {:?}", other),
 		}
 	}
+}
+
+/// Resets the device attached to the given port and, if it comes back up successfully, identifies
+/// it via the universal Identify command. Returns `PortDevice::Unknown` if the port doesn't
+/// respond as expected at any step (e.g. nothing is plugged in, or a `Ps2Error` occurs)
+fn reset_and_identify_port(second_port: bool) -> PortDevice {
+	let send = |byte: u8| if second_port { send_data_to_second_port(byte) } else { send_data(byte) };
+
+	if send(PS2_DEVICE_RESET_CMD).is_err() || receive_data() != Ok(PS2_DEVICE_MSG_ACK) {
+		return PortDevice::Unknown;
+	}
+	if receive_data() != Ok(PS2_DEVICE_MSG_SELF_TEST_PASSED) {
+		return PortDevice::Unknown;
+	}
+
+	if send(PS2_DEVICE_CMD_IDENTIFY).is_err() || receive_data() != Ok(PS2_DEVICE_MSG_ACK) {
+		return PortDevice::Unknown;
+	}
 
-	serial::println!("Enabled PS/2 Controller [{}, {}]", first_port_avail, second_port_avail);
+	// The device sends zero, one, or two ID bytes depending on its kind
+	let id_byte_a = receive_data().ok();
+	let id_byte_b = id_byte_a.and_then(|_| receive_data().ok());
+
+	match (id_byte_a, id_byte_b) {
+		(None, _) | (Some(0x00), None) => PortDevice::StandardMouse,
+		(Some(0x03), None) => PortDevice::ScrollMouse,
+		(Some(0x04), None) => PortDevice::FiveButtonMouse,
+		(Some(0xAB), Some(_)) => PortDevice::Mf2Keyboard,
+		_ => PortDevice::Unknown,
+	}
+}
+
+/// Sends the Echo liveness probe to the device on the given port and checks that it echoed the
+/// same byte back. Used as one last sanity check before trusting a device's scan/packet data,
+/// since it doesn't rely on the ACK framing every other command goes through - a device that's
+/// wedged enough to not even echo certainly isn't going to stream good data either.
+fn echo(second_port: bool) -> bool {
+	let send = |byte: u8| if second_port { send_data_to_second_port(byte) } else { send_data(byte) };
+
+	send(PS2_DEVICE_CMD_ECHO).is_ok() && receive_data() == Ok(PS2_DEVICE_CMD_ECHO)
 }
 
 /// Sends a command the PS/2 controller
-fn send_command(command: PS2Command)  {
+fn send_command(command: PS2Command) -> Result<(), Ps2Error> {
+	wait_for_input_ready()?;
 	unsafe {
 		cpu::out8(PS2_CTRL_WRITE_CMD_PORT, command as u8);
 	}
+	Ok(())
 }
 
 /// Sends a command that takes an extra argument byte to the PS/2 controller
-fn send_command_with_arg(command: PS2Command, arg: u8) {
-	send_command(command);
-	send_data(arg);
+fn send_command_with_arg(command: PS2Command, arg: u8) -> Result<(), Ps2Error> {
+	send_command(command)?;
+	send_data(arg)
 }
 
 /// Sends a command to the PS/2 controller and waits for a response
-fn send_command_with_response(command: PS2Command) -> u8 {
-	send_command(command);
+fn send_command_with_response(command: PS2Command) -> Result<u8, Ps2Error> {
+	send_command(command)?;
 	receive_data()
 }
 
-/// Waits for and returns the value in the PS/2 controller's output buffer. Panics on timeout
-pub fn receive_data() -> u8 {
-	recieve_data_with_timeout().expect("Timeout in `receive_data()` of PS/2 controller")
-}
-
-/// Waits for and returns the value in the PS/2 controller's output buffer. Returns `None` on timeout
-pub fn recieve_data_with_timeout() -> Option<u8> {
-	let mut timeout = PS2_TIMEOUT;
-	while (get_status_register() & PS2_CTRL_STATUS_OUTPUT_FULL_MASK) == 0 && timeout > 0 {
-		timeout -= 1;
-		spin_loop();
-	}
-
-	if timeout == 0 {
-		return None;
-	}
+/// Waits for and returns the value in the PS/2 controller's output buffer, checking the status
+/// register's timeout/parity error bits before trusting the byte that comes back
+pub fn receive_data() -> Result<u8, Ps2Error> {
+	let status = wait_for_status(PS2_CTRL_STATUS_OUTPUT_FULL_MASK, true)?;
+	check_status_errors(status)?;
 
 	unsafe {
-		Some(cpu::in8(PS2_CTRL_DATA_PORT))
+		Ok(cpu::in8(PS2_CTRL_DATA_PORT))
 	}
 }
 
 /// Waits for and sends a value to the PS/2 controller's input buffer. Unless the PS/2 controller
 /// expects an argument for a command, this is sent to the device connected to the first port
-pub fn send_data(byte: u8) {
-	let mut timeout = PS2_TIMEOUT;
-	while (get_status_register() & PS2_CTRL_STATUS_INPUT_FULL_MASK != 0) && timeout > 0 {
-		timeout -= 1;
-		spin_loop();
-	}
-
-	if timeout == 0 {
-		panic!("Timeout in `send_data({:#x})` of PS/2 controller", byte);
-	}
+pub fn send_data(byte: u8) -> Result<(), Ps2Error> {
+	wait_for_input_ready()?;
 
 	unsafe {
 		cpu::out8(PS2_CTRL_DATA_PORT, byte);
 	}
+	Ok(())
 }
 
 /// Waits for and sends a value to the device connected to the second port
-pub fn send_data_to_second_port(byte: u8) {
-	send_command_with_arg(PS2Command::WriteToSecondPort, byte);
+pub fn send_data_to_second_port(byte: u8) -> Result<(), Ps2Error> {
+	send_command_with_arg(PS2Command::WriteToSecondPort, byte)
+}
+
+/// Waits for the input buffer to be empty, re-validating the status register's error bits first so
+/// a stale timeout/parity error from the previous transaction isn't silently carried into this one
+fn wait_for_input_ready() -> Result<(), Ps2Error> {
+	let status = wait_for_status(PS2_CTRL_STATUS_INPUT_FULL_MASK, false)?;
+	check_status_errors(status)
+}
+
+/// Spins on the status register until `mask` is set (if `set` is true) or clear (if false),
+/// returning the status register's final value. Returns `Ps2Error::Timeout` if `PS2_TIMEOUT` spin
+/// iterations elapse first.
+fn wait_for_status(mask: u8, set: bool) -> Result<u8, Ps2Error> {
+	let mut timeout = PS2_TIMEOUT;
+	loop {
+		let status = get_status_register();
+		if ((status & mask) != 0) == set {
+			return Ok(status);
+		}
+
+		timeout -= 1;
+		if timeout == 0 {
+			return Err(Ps2Error::Timeout);
+		}
+		spin_loop();
+	}
+}
+
+/// Checks the status register's timeout/parity error bits, which the controller sets to flag a
+/// corrupted transmission to/from a device
+fn check_status_errors(status: u8) -> Result<(), Ps2Error> {
+	if status & PS2_CTRL_STATUS_TIMEOUT_ERROR_MASK != 0 {
+		Err(Ps2Error::DeviceTimeout)
+	} else if status & PS2_CTRL_STATUS_PARITY_ERROR_MASK != 0 {
+		Err(Ps2Error::Parity)
+	} else {
+		Ok(())
+	}
 }
 
 /// Reads the PS/2 controller's status register
@@ -1,14 +1,12 @@
 //! PS/2 mouse driver
 
+use core::time::Duration;
+
 use exclusive_cell::ExclusiveCell;
 use super::command_queue::{PS2CommandQueue, PS2Command};
 
 /// Command acknowledged response
 const MOUSE_MSG_ACK: u8 = 0xFA;
-/// Self-test successful response
-const MOUSE_MSG_SELF_TEST_PASSED: u8 = 0xAA;
-/// Self-test failed response
-const MOUSE_MSG_SELF_TEST_FAILED: u8 = 0xFC;
 /// The initial device ID of a PS/2 mouse
 const MOUSE_ID_STANDARD: u8 = 0x00;
 /// The device ID of a PS/2 mouse which supports a scroll wheel
@@ -21,16 +19,86 @@ const MOUSE_CMD_ENABLE_STREAMING: u8 = 0xF4;
 const MOUSE_CMD_SET_SAMPLE_RATE: u8 = 0xF3;
 /// Command to get the mouse device ID
 const MOUSE_CMD_GET_MOUSE_ID: u8 = 0xF2;
+/// Get-Info/status command: returns a 3-byte status packet (status flags, resolution, sample
+/// rate), which the reference psm driver also uses to tell vendor variants apart that share a
+/// device ID with a genuine Microsoft IntelliMouse
+const MOUSE_CMD_GET_STATUS: u8 = 0xE9;
 /// Mouse sample rate
 const MOUSE_SAMPLE_RATE: u8 = 10;
 
-/// Mouse driver state-machine states
+/// How long a partial packet can sit with no further bytes arriving before we give up on it and
+/// resync - same threshold the reference psm driver uses
+const RESYNC_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Genius NetScroll/NetMouse's status-packet sample-rate byte (status[2]) after the scroll-wheel
+/// knock, distinguishing it from a plain IntelliMouse clone reporting the same device ID
+const GENIUS_NETSCROLL_STATUS_SAMPLE_RATE: u8 = 0x08;
+/// Logitech MouseMan+'s status-packet resolution byte (status[1]) after the scroll-wheel knock
+const LOGITECH_MOUSEMAN_PLUS_STATUS_RESOLUTION: u8 = 0x03;
+
+/// `accel_factor_q8` value meaning "no acceleration" - see `set_pointer_acceleration`
+const ACCEL_FACTOR_DISABLED_Q8: u32 = 256;
+/// Default movement-magnitude threshold (in packet delta units) above which acceleration kicks in
+const DEFAULT_ACCEL_THRESHOLD: u32 = 8;
+/// `x_delta`/`y_delta` are sign-extended from a single packet byte (plus the overflow bits), so
+/// clamp the accelerated result back to the same range rather than letting it run away
+const MAX_ACCELERATED_DELTA: i32 = 255;
+
+/// A recognized PS/2 mouse model, identified during `PS2MouseState::Identifying` from the device
+/// ID returned by the scroll-wheel/5-button "secret knock" sequences plus, where that ID is
+/// ambiguous, the vendor-specific bytes of the Get-Info/status response. Packet decoding and
+/// button counts key off this instead of separate feature booleans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MouseModel {
+	/// The original 3-button, no-wheel PS/2 mouse protocol
+	Standard,
+	/// Microsoft IntelliMouse: adds a scroll wheel, still 3 buttons
+	IntelliMouse,
+	/// Microsoft IntelliMouse Explorer: scroll wheel plus two extra (4th/5th) buttons
+	IntelliMouseExplorer,
+	/// Genius NetScroll/NetMouse: scroll wheel, 3 buttons, identified by its status-packet
+	/// signature rather than a distinct device ID
+	GeniusNetScroll,
+	/// Logitech MouseMan+: scroll wheel, 3 buttons, identified by its status-packet signature
+	LogitechMouseManPlus,
+}
+
+impl MouseModel {
+	/// Whether this model's packets carry a 4th (Z axis/wheel) byte
+	fn has_scroll_wheel(&self) -> bool {
+		!matches!(self, MouseModel::Standard)
+	}
+
+	/// Whether this model's 4th packet byte also carries 4th/5th button bits, as opposed to just
+	/// the wheel delta
+	fn has_5_buttons(&self) -> bool {
+		matches!(self, MouseModel::IntelliMouseExplorer)
+	}
+}
+
+/// How `PS2MouseDriver::dispatch_packet` delivers Z-axis (scroll wheel) movement to
+/// `crate::mouse::mouse_event`, following the `moused` convention of letting a wheel be remapped
+/// for consumers that don't understand a dedicated Z axis - see `set_z_axis_mapping`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZAxisMapping {
+	/// Deliver the Z delta as-is
+	Axis,
+	/// Report no Z delta; instead, a positive Z delta presses the 4th button and a negative one
+	/// presses the 5th button for that one packet, the same "virtual scroll buttons" `moused` falls
+	/// back to for clients with no concept of a wheel
+	VirtualButtons,
+	/// Report no Z delta; instead, fold it into the X delta, for horizontal-scroll-style wheels
+	HorizontalAxis,
+}
+
+/// Mouse driver state-machine states. The controller has already reset, self-tested and
+/// identified the device as some kind of PS/2 mouse before this driver is ever attached, so the
+/// sequence starts straight at probing for the scroll wheel.
 #[derive(Debug)]
 enum PS2MouseState {
-	Uninitialized,
-	PassedSelfTest,
 	TryInitScrollWheel,
 	TryInit5Buttons,
+	Identifying,
 	Initialized,
 }
 
@@ -40,34 +108,66 @@ struct PS2MouseDriver {
 	state: PS2MouseState,
 	/// Command queue for command sequences
 	command_queue: PS2CommandQueue,
-	/// Whether or not the mouse has a scroll wheel
-	supports_scroll_wheel: bool,
-	/// Whether or not the mouse has two extra side buttons
-	supports_5_buttons: bool,
+	/// The model identified so far - `Standard` until `Identifying` completes
+	model: MouseModel,
 	/// Accumalated packet data
 	packet_data: [u8; 4],
 	/// Amount of packet bytes accumualted
 	packet_sequence: usize,
+	/// PIT tick count as of the last byte received while mid-packet, used to resync if the stream
+	/// stalls for longer than `RESYNC_TIMEOUT` - see `handle_interrupt`'s `Initialized` arm
+	last_byte_tick: u64,
+	/// How `dispatch_packet` delivers Z-axis movement - see `set_z_axis_mapping`
+	z_axis_mapping: ZAxisMapping,
+	/// Movement-magnitude threshold above which pointer acceleration kicks in - see
+	/// `set_pointer_acceleration`
+	accel_threshold: u32,
+	/// Acceleration factor applied to `x_delta`/`y_delta` once `accel_threshold` is exceeded,
+	/// as a fixed-point multiplier scaled by 256 - see `set_pointer_acceleration`
+	accel_factor_q8: u32,
 }
 
 impl PS2MouseDriver {
 	/// Construct an uninitialized mouse driver
 	const fn new() -> Self {
 		PS2MouseDriver {
-			state: PS2MouseState::Uninitialized,
+			state: PS2MouseState::TryInitScrollWheel,
 			command_queue: PS2CommandQueue::new(true),
-			supports_scroll_wheel: false,
-			supports_5_buttons: false,
+			model: MouseModel::Standard,
 			packet_data: [0; 4],
 			packet_sequence: 0,
+			last_byte_tick: 0,
+			z_axis_mapping: ZAxisMapping::Axis,
+			accel_threshold: DEFAULT_ACCEL_THRESHOLD,
+			accel_factor_q8: ACCEL_FACTOR_DISABLED_Q8,
 		}
 	}
 
+	/// Queues the "secret knock" sample-rate sequence and device ID query that starts the probe
+	/// for a scroll wheel. Should only be called once the PS/2 controller has identified some kind
+	/// of mouse on the second port - the reset/self-test/identify steps are handled there.
+	fn begin_initializing(&mut self) {
+		self.command_queue.append_command(PS2Command {
+			command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(200), response_len: 0
+		});
+		self.command_queue.append_command(PS2Command {
+			command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(100), response_len: 0
+		});
+		self.command_queue.append_command(PS2Command {
+			command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(80), response_len: 0
+		});
+		// We then inquire about the device ID, which should change to reflect the scroll wheel
+		// being enabled
+		self.command_queue.append_command(PS2Command {
+			command: MOUSE_CMD_GET_MOUSE_ID, data: None, response_len: 1
+		});
+	}
+
 	/// Handle a mouse IRQ
 	pub fn handle_interrupt(&mut self, mouse_message: u8) {
 		// We first check if this is a response to a command we queued, and handle the response if
 		// it is
-		let queue_empty = self.command_queue.handle_message(mouse_message);
+		let queue_empty = self.command_queue.update_command_queue(mouse_message);
 
 		// If there no commands on in the queue then we need to handle the message based on the
 		// current state. On the other hand, if the receiving of this message acknowledged the last
@@ -75,97 +175,86 @@ impl PS2MouseDriver {
 		// need to take the relevant action for the new state.
 		if queue_empty {
 			match self.state {
-				PS2MouseState::Uninitialized => {
-					// If the mouse is uninitialized because we sent a `reset` command, it will first send
-					// an ACK response. If it is uninitialized because it was just plugged in, it will not
-					// send an ACK first, so we just discard an ACK if we see it.
-					if mouse_message == MOUSE_MSG_ACK {
-						return;
-					}
-
-					// We first expect a message with the result of the self-test
-					if mouse_message == MOUSE_MSG_SELF_TEST_PASSED {
-						self.state = PS2MouseState::PassedSelfTest;
-					} else if mouse_message == MOUSE_MSG_SELF_TEST_FAILED {
-						panic!("Mouse failed Basic Assurance Test, what should we do here?");
-					} else {
-						panic!("Unexpected mouse message before initialization");
-					}
-				},
-				PS2MouseState::PassedSelfTest => {
-					// We then expect a message with the mouse's device ID
-					assert!(mouse_message == MOUSE_ID_STANDARD);
-
-					// We send the "secret knock" to try and enable the scroll wheel
-					self.command_queue.queue(PS2Command {
-						command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(200)
-					});
-					self.command_queue.queue(PS2Command {
-						command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(100)
-					});
-					self.command_queue.queue(PS2Command {
-						command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(80)
-					});
-					// We then inquire about the device ID, which should change to reflect the
-					// scroll wheel being enabled
-					self.command_queue.queue(PS2Command {
-						command: MOUSE_CMD_GET_MOUSE_ID, data: None
-					});
-
-					self.state = PS2MouseState::TryInitScrollWheel;
-				},
 				PS2MouseState::TryInitScrollWheel => {
-					let device_id = super::controller::receive_data();
+					// The device ID command's response byte was collected by the command queue as
+					// it arrived, so we can just read it back out here
+					let device_id = self.command_queue.last_response()[0];
 					if device_id == MOUSE_ID_STANDARD {
-						// If the device ID did not change, the mouse does not have a scroll wheel
-						// We set the sample rate and then enable packet streaming
-						self.command_queue.queue(PS2Command {
-							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(MOUSE_SAMPLE_RATE)
-						});
-						self.command_queue.queue(PS2Command {
-							command: MOUSE_CMD_ENABLE_STREAMING, data: None
+						// If the device ID did not change, the mouse does not have a scroll wheel -
+						// nothing more to identify, so go straight to the status probe
+						self.command_queue.append_command(PS2Command {
+							command: MOUSE_CMD_GET_STATUS, data: None, response_len: 3
 						});
-						self.state = PS2MouseState::Initialized;
+						self.state = PS2MouseState::Identifying;
 					} else if device_id == MOUSE_ID_INTELLIMOUSE {
 						// The device ID changed, so scroll wheel is now enabled
-						self.supports_scroll_wheel = true;
+						self.model = MouseModel::IntelliMouse;
 
 						// We send the "secret knock" to try and enable the two extra buttons
-						self.command_queue.queue(PS2Command {
-							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(200)
+						self.command_queue.append_command(PS2Command {
+							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(200), response_len: 0
 						});
-						self.command_queue.queue(PS2Command {
-							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(200)
+						self.command_queue.append_command(PS2Command {
+							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(200), response_len: 0
 						});
-						self.command_queue.queue(PS2Command {
-							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(80)
+						self.command_queue.append_command(PS2Command {
+							command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(80), response_len: 0
 						});
 						// We then inquire about the device ID, which should change to reflect the
 						// buttons being enabled
-						self.command_queue.queue(PS2Command {
-							command: MOUSE_CMD_GET_MOUSE_ID, data: None
+						self.command_queue.append_command(PS2Command {
+							command: MOUSE_CMD_GET_MOUSE_ID, data: None, response_len: 1
 						});
-	
+
 						self.state = PS2MouseState::TryInit5Buttons;
 					} else {
-						panic!("Unrecognized mouse device id {:#X}", mouse_message);
+						panic!("Unrecognized mouse device id {:#X}", device_id);
 					}
 				},
 				PS2MouseState::TryInit5Buttons => {
-					let device_id = super::controller::receive_data();
+					// The device ID command's response byte was collected by the command queue as
+					// it arrived, so we can just read it back out here
+					let device_id = self.command_queue.last_response()[0];
 					if device_id == MOUSE_ID_INTELLIMOUSE_EXPLORER {
 						// The device ID changed, so the buttons are now enabled
-						self.supports_5_buttons = true;
+						self.model = MouseModel::IntelliMouseExplorer;
 					} else if device_id != MOUSE_ID_INTELLIMOUSE {
-						panic!("Unrecognized mouse device id {:#X}", mouse_message);
+						panic!("Unrecognized mouse device id {:#X}", device_id);
 					}
 
+					// Whether or not the buttons enabled, probe the status packet next - a plain
+					// IntelliMouse's device ID is also what several vendor clones report, and those
+					// are only distinguishable by their status response
+					self.command_queue.append_command(PS2Command {
+						command: MOUSE_CMD_GET_STATUS, data: None, response_len: 3
+					});
+					self.state = PS2MouseState::Identifying;
+				},
+				PS2MouseState::Identifying => {
+					// The status command's 3 response bytes were collected by the command queue as
+					// they arrived: [0] status flags, [1] resolution, [2] sample rate
+					let status = self.command_queue.last_response();
+
+					// Vendor clones share a device ID with a genuine IntelliMouse/IntelliMouse
+					// Explorer, so only look for their signature when we haven't already confirmed
+					// the 5-button knock (which only a genuine Explorer responds to correctly)
+					if self.model == MouseModel::IntelliMouse {
+						if status[2] == GENIUS_NETSCROLL_STATUS_SAMPLE_RATE {
+							self.model = MouseModel::GeniusNetScroll;
+						} else if status[1] == LOGITECH_MOUSEMAN_PLUS_STATUS_RESOLUTION {
+							self.model = MouseModel::LogitechMouseManPlus;
+						}
+					}
+
+					serial::println!("PS/2 mouse identified as {:?}", self.model);
+
 					// We set the sample rate and then enable packet streaming
-					self.command_queue.queue(PS2Command {
-						command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(MOUSE_SAMPLE_RATE)
+					self.command_queue.append_command(PS2Command {
+						command: MOUSE_CMD_SET_SAMPLE_RATE, data: Some(MOUSE_SAMPLE_RATE),
+						response_len: 0
 					});
-					self.command_queue.queue(PS2Command {
-						command: MOUSE_CMD_ENABLE_STREAMING, data: None
+					self.command_queue.append_command(PS2Command {
+						command: MOUSE_CMD_ENABLE_STREAMING, data: None, response_len: 0
 					});
 					self.state = PS2MouseState::Initialized;
 				},
@@ -175,10 +264,24 @@ impl PS2MouseDriver {
 						return;
 					}
 
-					// FIXME: It seems that for some reason the mouse packets seem to get out of
-					// sync sometimes. We need to figure out why that happens. Currently, we use the
-					// fact that the fourth bit in the first packet byte is always zero, to try and
-					// re-sync.
+					// The mouse occasionally drops a byte, which leaves every packet boundary after it
+					// permanently misread. If we're mid-packet and too long has passed since the
+					// previous byte, assume whatever we've accumulated is stale and start over -
+					// combined with the bit-3 check below, this makes the stream self-correcting
+					// instead of staying misaligned forever.
+					let now = crate::interrupts::pit_8254::tick_count();
+					if self.packet_sequence != 0 {
+						let resync_timeout_ticks =
+							(RESYNC_TIMEOUT.as_secs_f64() * crate::interrupts::pit_8254::real_freq_hz())
+								.ceil() as u64;
+						if now.saturating_sub(self.last_byte_tick) > resync_timeout_ticks {
+							self.packet_sequence = 0;
+						}
+					}
+					self.last_byte_tick = now;
+
+					// The fourth bit of the first packet byte is always one, so after a stall (or on
+					// a normal packet boundary) only accept a byte as byte 0 if that bit is set
 					if self.packet_sequence == 0 && (mouse_message & 0x8) == 0 {
 						return;
 					}
@@ -188,7 +291,7 @@ impl PS2MouseDriver {
 					self.packet_sequence += 1;
 
 					// If we recieved the entire packet, dispatch it and restart the sequence
-					if (!self.supports_scroll_wheel && self.packet_sequence == 3)
+					if (!self.model.has_scroll_wheel() && self.packet_sequence == 3)
 						|| self.packet_sequence == 4 {
 						self.dispatch_packet();
 						self.packet_sequence = 0;
@@ -227,25 +330,48 @@ impl PS2MouseDriver {
 			y_delta = 0;
 		}
 
-		if self.supports_scroll_wheel {
+		// Pointer acceleration: once the movement magnitude for this packet exceeds
+		// `accel_threshold`, scale both deltas by `accel_factor_q8`/256, the same linear/threshold
+		// model the reference moused acceleration profile uses. Left at `ACCEL_FACTOR_DISABLED_Q8`
+		// this is a no-op; tunable (or disableable) via `set_pointer_acceleration`.
+		let magnitude = x_delta.unsigned_abs() + y_delta.unsigned_abs();
+		if magnitude > self.accel_threshold {
+			x_delta = (x_delta * self.accel_factor_q8 as i32 / 256)
+				.clamp(-MAX_ACCELERATED_DELTA, MAX_ACCELERATED_DELTA);
+			y_delta = (y_delta * self.accel_factor_q8 as i32 / 256)
+				.clamp(-MAX_ACCELERATED_DELTA, MAX_ACCELERATED_DELTA);
+		}
+
+		let (mut fourth_down, mut fifth_down, mut z_delta) = (false, false, 0);
+		if self.model.has_scroll_wheel() {
 			let extended_data = self.packet_data[3];
-			if self.supports_5_buttons {
+			if self.model.has_5_buttons() {
 				// Sign extend the z delta
-				let z_delta = (((extended_data & 0xF) as i32) << 28) >> 28;
-				
-				let fourth_down = extended_data & 0x10 != 0;
-				let fifth_down = extended_data & 0x20 != 0;
-				crate::mouse::mouse_event(left_down, right_down, middle_down, fourth_down,
-					fifth_down, x_delta, y_delta, z_delta);
+				z_delta = (((extended_data & 0xF) as i32) << 28) >> 28;
+				fourth_down = extended_data & 0x10 != 0;
+				fifth_down = extended_data & 0x20 != 0;
 			} else {
-				let z_delta = extended_data as i8 as i32;
-				crate::mouse::mouse_event(left_down, right_down, middle_down, false, false, x_delta,
-					y_delta, z_delta);
+				z_delta = extended_data as i8 as i32;
 			}
-		} else {
-			crate::mouse::mouse_event(left_down, right_down, middle_down, false, false, x_delta,
-				y_delta, 0);
 		}
+
+		// Apply the configured Z-axis mapping before handing off to the general mouse layer, so a
+		// consumer that only understands buttons/X still gets something usable out of the wheel
+		match self.z_axis_mapping {
+			ZAxisMapping::Axis => {},
+			ZAxisMapping::VirtualButtons => {
+				fourth_down |= z_delta > 0;
+				fifth_down |= z_delta < 0;
+				z_delta = 0;
+			},
+			ZAxisMapping::HorizontalAxis => {
+				x_delta += z_delta;
+				z_delta = 0;
+			},
+		}
+
+		crate::mouse::mouse_event(left_down, right_down, middle_down, fourth_down, fifth_down,
+			x_delta, y_delta, z_delta);
 	}
 }
 
@@ -256,4 +382,35 @@ static MOUSE_DRIVER: ExclusiveCell<PS2MouseDriver> = ExclusiveCell::new(PS2Mouse
 /// Handles an interrupt from the PS/2 mouse (should only be called when an interrupt happens)
 pub fn handle_interrupt(mouse_message: u8) {
 	MOUSE_DRIVER.acquire().handle_interrupt(mouse_message);
+}
+
+/// Selects how `dispatch_packet` delivers scroll-wheel movement - see `ZAxisMapping`
+pub fn set_z_axis_mapping(mapping: ZAxisMapping) {
+	MOUSE_DRIVER.acquire().z_axis_mapping = mapping;
+}
+
+/// Configures pointer acceleration: once a packet's movement magnitude (`|x_delta| + |y_delta|`)
+/// exceeds `threshold`, both deltas are scaled by `factor_q8`/256 (a fixed-point multiplier, since
+/// there's no FPU support to rely on here) before being clamped and handed off to
+/// `crate::mouse::mouse_event`. Pass `factor_q8 = 256` to disable acceleration entirely.
+pub fn set_pointer_acceleration(threshold: u32, factor_q8: u32) {
+	let mut driver = MOUSE_DRIVER.acquire();
+	driver.accel_threshold = threshold;
+	driver.accel_factor_q8 = factor_q8;
+}
+
+/// Registers this driver's IRQ12 handler with the interrupt core and queues the scroll wheel/extra
+/// button probe sequence. Should only be called once the PS/2 controller has identified some kind
+/// of mouse on the second port - the reset/self-test/identify steps are handled there, not by this
+/// driver.
+pub fn init() {
+	crate::interrupts::intr_register(crate::interrupts::pic_8259a::PIC_IRQ_OFFSET + 12, handle_irq);
+	MOUSE_DRIVER.acquire().begin_initializing();
+}
+
+/// Entry point invoked by the interrupt core for IRQ12; reads the pending byte out of the PS/2
+/// controller's data port and forwards it to the driver's state machine
+fn handle_irq(_regs: &mut crate::interrupts::PushADRegisterState) {
+	let message = super::controller::receive_data().expect("PS/2 I/O error during mouse IRQ");
+	handle_interrupt(message);
 }
\ No newline at end of file
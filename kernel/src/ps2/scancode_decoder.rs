@@ -0,0 +1,542 @@
+//! Scan code interpretation, factored out of the keyboard driver so the same state machine shape
+//! can be reused for every scan code set the controller might hand us. QEMU, VirtualBox and real
+//! AT-class hardware don't all agree on a single set, so the driver picks a decoder to match
+//! whatever set it configured the keyboard to use (see `CONFIGURED_SCAN_CODE_SET`).
+
+use crate::keyboard::KeyCode;
+
+/// A fully decoded scan code: a key going down or up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodedScanCode {
+	Pressed(KeyCode),
+	Released(KeyCode),
+}
+
+/// Interprets a stream of raw scan code bytes from a single keyboard scan code set into key
+/// press/release events. Implementations keep whatever multibyte-sequence state they need between
+/// calls to `process`
+pub trait ScanCodeDecoder {
+	/// Feed the next raw byte from the keyboard into the decoder. Returns a decoded event once a
+	/// full (possibly multibyte) scan code has been recognized, or `None` while a multibyte
+	/// sequence is still in progress
+	fn process(&mut self, byte: u8) -> Option<DecodedScanCode>;
+
+	/// Resets the decoder back to its initial state, discarding any partially-received multibyte
+	/// sequence
+	fn reset(&mut self);
+}
+
+/// Scan code set 2 decoder state-machine states
+#[derive(Debug)]
+enum Set2State {
+	ScanningKey,
+	ScanningExtendedKey,
+	ScanningReleasedKey,
+	ScanningReleasedExtendedKey,
+	ScanningPrintScreenPressedMultibyte(u8),
+	ScanningPrintScreenReleasedMultibyte(u8),
+	ScanningPausePressedMultibyte(u8),
+}
+
+/// Message sent before a scan code to indicate the next key is an extended scan code
+const SET2_MSG_EXTENDED_KEY: u8 = 0xE0;
+/// Message sent before a scan code to indicate the next key is released (default is pressed)
+const SET2_MSG_RELEASED_KEY: u8 = 0xF0;
+
+/// The multi-byte scan code that represents a PrtScn press
+const SET2_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE: [u8; 3] = [0x12, 0xE0, 0x7C];
+/// The multi-byte scan code that represents a PrtScn release
+const SET2_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE: [u8; 4] = [0x7C, 0xE0, 0xF0, 0x12];
+/// The multi-byte scan code that represents a Pause press (and immediate release)
+const SET2_PAUSE_PRESSED_MULTIBYTE_SCANCODE: [u8; 8] = [0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77];
+
+/// Decodes scan code set 2, the set every PS/2 keyboard speaks natively on power-up
+pub struct Set2Decoder {
+	state: Set2State,
+}
+
+impl Set2Decoder {
+	pub const fn new() -> Self {
+		Self { state: Set2State::ScanningKey }
+	}
+}
+
+impl ScanCodeDecoder for Set2Decoder {
+	fn process(&mut self, byte: u8) -> Option<DecodedScanCode> {
+		match self.state {
+			Set2State::ScanningKey => {
+				if byte == SET2_MSG_EXTENDED_KEY {
+					self.state = Set2State::ScanningExtendedKey;
+					None
+				} else if byte == SET2_MSG_RELEASED_KEY {
+					self.state = Set2State::ScanningReleasedKey;
+					None
+				} else if byte == SET2_PAUSE_PRESSED_MULTIBYTE_SCANCODE[0] {
+					self.state = Set2State::ScanningPausePressedMultibyte(1);
+					None
+				} else {
+					Some(DecodedScanCode::Pressed(set2_simple_scancode_to_keycode(byte)))
+				}
+			},
+			Set2State::ScanningExtendedKey => {
+				if byte == SET2_MSG_RELEASED_KEY {
+					self.state = Set2State::ScanningReleasedExtendedKey;
+					None
+				} else if byte == SET2_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE[0] {
+					self.state = Set2State::ScanningPrintScreenPressedMultibyte(1);
+					None
+				} else {
+					self.state = Set2State::ScanningKey;
+					Some(DecodedScanCode::Pressed(set2_extended_scancode_to_keycode(byte)))
+				}
+			},
+			Set2State::ScanningReleasedKey => {
+				self.state = Set2State::ScanningKey;
+				Some(DecodedScanCode::Released(set2_simple_scancode_to_keycode(byte)))
+			},
+			Set2State::ScanningReleasedExtendedKey => {
+				if byte == SET2_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE[0] {
+					self.state = Set2State::ScanningPrintScreenReleasedMultibyte(1);
+					None
+				} else {
+					self.state = Set2State::ScanningKey;
+					Some(DecodedScanCode::Released(set2_extended_scancode_to_keycode(byte)))
+				}
+			},
+			Set2State::ScanningPrintScreenPressedMultibyte(byte_idx) => {
+				let idx = byte_idx as usize;
+				if byte == SET2_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE[idx] {
+					if idx < SET2_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE.len() - 1 {
+						self.state = Set2State::ScanningPrintScreenPressedMultibyte((idx + 1) as u8);
+						None
+					} else {
+						self.state = Set2State::ScanningKey;
+						Some(DecodedScanCode::Pressed(KeyCode::KeyPrintScreen))
+					}
+				} else {
+					self.state = Set2State::ScanningKey;
+					Some(DecodedScanCode::Pressed(KeyCode::Unknown))
+				}
+			},
+			Set2State::ScanningPrintScreenReleasedMultibyte(byte_idx) => {
+				let idx = byte_idx as usize;
+				if byte == SET2_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE[idx] {
+					if idx < SET2_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE.len() - 1 {
+						self.state = Set2State::ScanningPrintScreenReleasedMultibyte((idx + 1) as u8);
+						None
+					} else {
+						self.state = Set2State::ScanningKey;
+						Some(DecodedScanCode::Released(KeyCode::KeyPrintScreen))
+					}
+				} else {
+					self.state = Set2State::ScanningKey;
+					Some(DecodedScanCode::Released(KeyCode::Unknown))
+				}
+			},
+			Set2State::ScanningPausePressedMultibyte(byte_idx) => {
+				let idx = byte_idx as usize;
+				if byte == SET2_PAUSE_PRESSED_MULTIBYTE_SCANCODE[idx] {
+					if idx < SET2_PAUSE_PRESSED_MULTIBYTE_SCANCODE.len() - 1 {
+						self.state = Set2State::ScanningPausePressedMultibyte((idx + 1) as u8);
+						None
+					} else {
+						// A pause key does not have a distinct release scan code, so we report it
+						// pressed here; the driver immediately follows up with a release
+						self.state = Set2State::ScanningKey;
+						Some(DecodedScanCode::Pressed(KeyCode::KeyPause))
+					}
+				} else {
+					self.state = Set2State::ScanningKey;
+					Some(DecodedScanCode::Pressed(KeyCode::Unknown))
+				}
+			},
+		}
+	}
+
+	fn reset(&mut self) {
+		self.state = Set2State::ScanningKey;
+	}
+}
+
+/// Converts a simple 1-byte set 2 scan code to the corresponding key code
+fn set2_simple_scancode_to_keycode(scan_code: u8) -> KeyCode {
+	match scan_code {
+		0x01 => KeyCode::KeyF9,
+		0x03 => KeyCode::KeyF5,
+		0x04 => KeyCode::KeyF3,
+		0x05 => KeyCode::KeyF1,
+		0x06 => KeyCode::KeyF2,
+		0x07 => KeyCode::KeyF12,
+		0x09 => KeyCode::KeyF10,
+		0x0A => KeyCode::KeyF8,
+		0x0B => KeyCode::KeyF6,
+		0x0C => KeyCode::KeyF4,
+		0x0D => KeyCode::KeyTab,
+		0x0E => KeyCode::KeyBackTick,
+		0x11 => KeyCode::KeyLeftAlt,
+		0x12 => KeyCode::KeyLeftShift,
+		0x14 => KeyCode::KeyLeftControl,
+		0x15 => KeyCode::KeyQ,
+		0x16 => KeyCode::Key1,
+		0x1A => KeyCode::KeyZ,
+		0x1B => KeyCode::KeyS,
+		0x1C => KeyCode::KeyA,
+		0x1D => KeyCode::KeyW,
+		0x1E => KeyCode::Key2,
+		0x21 => KeyCode::KeyC,
+		0x22 => KeyCode::KeyX,
+		0x23 => KeyCode::KeyD,
+		0x24 => KeyCode::KeyE,
+		0x25 => KeyCode::Key4,
+		0x26 => KeyCode::Key3,
+		0x29 => KeyCode::KeySpace,
+		0x2A => KeyCode::KeyV,
+		0x2B => KeyCode::KeyF,
+		0x2C => KeyCode::KeyT,
+		0x2D => KeyCode::KeyR,
+		0x2E => KeyCode::Key5,
+		0x31 => KeyCode::KeyN,
+		0x32 => KeyCode::KeyB,
+		0x33 => KeyCode::KeyH,
+		0x34 => KeyCode::KeyG,
+		0x35 => KeyCode::KeyY,
+		0x36 => KeyCode::Key6,
+		0x3A => KeyCode::KeyM,
+		0x3B => KeyCode::KeyJ,
+		0x3C => KeyCode::KeyU,
+		0x3D => KeyCode::Key7,
+		0x3E => KeyCode::Key8,
+		0x41 => KeyCode::KeyComma,
+		0x42 => KeyCode::KeyK,
+		0x43 => KeyCode::KeyI,
+		0x44 => KeyCode::KeyO,
+		0x45 => KeyCode::Key0,
+		0x46 => KeyCode::Key9,
+		0x49 => KeyCode::KeyPeriod,
+		0x4A => KeyCode::KeySlash,
+		0x4B => KeyCode::KeyL,
+		0x4C => KeyCode::KeySemicolon,
+		0x4D => KeyCode::KeyP,
+		0x4E => KeyCode::KeyMinus,
+		0x52 => KeyCode::KeyApostrophe,
+		0x54 => KeyCode::KeyLeftSquareBracket,
+		0x55 => KeyCode::KeyEquals,
+		0x58 => KeyCode::KeyCapsLock,
+		0x59 => KeyCode::KeyRightShift,
+		0x5A => KeyCode::KeyEnter,
+		0x5B => KeyCode::KeyRightSquareBracket,
+		0x5D => KeyCode::KeyBackSlash,
+		0x61 => KeyCode::KeyExtraBackSlash,
+		0x66 => KeyCode::KeyBackspace,
+		0x69 => KeyCode::KeyNumpad1,
+		0x6B => KeyCode::KeyNumpad4,
+		0x6C => KeyCode::KeyNumpad7,
+		0x70 => KeyCode::KeyNumpad0,
+		0x71 => KeyCode::KeyNumpadPeriod,
+		0x72 => KeyCode::KeyNumpad2,
+		0x73 => KeyCode::KeyNumpad5,
+		0x74 => KeyCode::KeyNumpad6,
+		0x75 => KeyCode::KeyNumpad8,
+		0x76 => KeyCode::KeyEscape,
+		0x77 => KeyCode::KeyNumberLock,
+		0x78 => KeyCode::KeyF11,
+		0x79 => KeyCode::KeyNumpadPlus,
+		0x7A => KeyCode::KeyNumpad3,
+		0x7B => KeyCode::KeyNumpadMinus,
+		0x7C => KeyCode::KeyNumpadAsterisk,
+		0x7D => KeyCode::KeyNumpad9,
+		0x7E => KeyCode::KeyScrollLock,
+		0x83 => KeyCode::KeyF7,
+		_ => KeyCode::Unknown,
+	}
+}
+
+/// Converts an extended set 2 scan code to the corresponding key code
+fn set2_extended_scancode_to_keycode(scan_code: u8) -> KeyCode {
+	match scan_code {
+		0x10 => KeyCode::KeyMultimediaSearch,
+		0x11 => KeyCode::KeyRightAlt,
+		0x14 => KeyCode::KeyRightControl,
+		0x15 => KeyCode::KeyMultimediaPreviousTrack,
+		0x18 => KeyCode::KeyMultimediaFavorites,
+		0x1F => KeyCode::KeyLeftLogo,
+		0x20 => KeyCode::KeyMultimediaRefresh,
+		0x21 => KeyCode::KeyMultimediaVolumeDown,
+		0x23 => KeyCode::KeyMultimediaMute,
+		0x27 => KeyCode::KeyRightLogo,
+		0x28 => KeyCode::KeyMultimediaWebStop,
+		0x2B => KeyCode::KeyMultimediaCalculator,
+		0x2F => KeyCode::KeyMenu,
+		0x30 => KeyCode::KeyMultimediaWebForward,
+		0x32 => KeyCode::KeyMultimediaVolumeUp,
+		0x34 => KeyCode::KeyMultimediaPlayPause,
+		0x37 => KeyCode::KeyACPIPower,
+		0x38 => KeyCode::KeyMultimediaWebBack,
+		0x3A => KeyCode::KeyMultimediaWebHome,
+		0x3B => KeyCode::KeyMultimediaStop,
+		0x3F => KeyCode::KeyACPISleep,
+		0x40 => KeyCode::KeyMultimediaMyComputer,
+		0x48 => KeyCode::KeyMultimediaEmail,
+		0x4A => KeyCode::KeyNumpadSlash,
+		0x4D => KeyCode::KeyMultimediaNextTrack,
+		0x50 => KeyCode::KeyMultimediaMediaSelect,
+		0x5A => KeyCode::KeyNumpadEnter,
+		0x5E => KeyCode::KeyACPIWake,
+		0x69 => KeyCode::KeyEnd,
+		0x6B => KeyCode::KeyLeftArrow,
+		0x6C => KeyCode::KeyHome,
+		0x70 => KeyCode::KeyInsert,
+		0x71 => KeyCode::KeyDelete,
+		0x72 => KeyCode::KeyDownArrow,
+		0x74 => KeyCode::KeyRightArrow,
+		0x75 => KeyCode::KeyUpArrow,
+		0x7A => KeyCode::KeyPageDown,
+		0x7D => KeyCode::KeyPageUp,
+		_ => KeyCode::Unknown,
+	}
+}
+
+/// Scan code set 1 decoder state-machine states. Unlike set 2, releases aren't a separate prefix
+/// byte - they're the same scan code with the high bit (0x80) set
+#[derive(Debug)]
+enum Set1State {
+	ScanningKey,
+	ScanningExtendedKey,
+	ScanningPrintScreenPressedMultibyte(u8),
+	ScanningPrintScreenReleasedMultibyte(u8),
+	ScanningPausePressedMultibyte(u8),
+}
+
+/// Message sent before a scan code to indicate the next key is an extended scan code
+const SET1_MSG_EXTENDED_KEY: u8 = 0xE0;
+/// The bit set in an otherwise-ordinary scan code byte to indicate the key was released
+const SET1_RELEASED_KEY_BIT: u8 = 0x80;
+
+/// The multi-byte scan code that represents a PrtScn press
+const SET1_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE: [u8; 4] = [0xE0, 0x2A, 0xE0, 0x37];
+/// The multi-byte scan code that represents a PrtScn release
+const SET1_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE: [u8; 4] = [0xE0, 0xB7, 0xE0, 0xAA];
+/// The multi-byte scan code that represents a Pause press (and immediate release)
+const SET1_PAUSE_PRESSED_MULTIBYTE_SCANCODE: [u8; 6] = [0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5];
+
+/// Decodes scan code set 1, the original IBM XT/AT set still spoken by a lot of firmware and
+/// emulators after the keyboard is told to switch to it
+pub struct Set1Decoder {
+	state: Set1State,
+}
+
+impl Set1Decoder {
+	pub const fn new() -> Self {
+		Self { state: Set1State::ScanningKey }
+	}
+}
+
+impl ScanCodeDecoder for Set1Decoder {
+	fn process(&mut self, byte: u8) -> Option<DecodedScanCode> {
+		match self.state {
+			Set1State::ScanningKey => {
+				if byte == SET1_MSG_EXTENDED_KEY {
+					self.state = Set1State::ScanningExtendedKey;
+					None
+				} else if byte == SET1_PAUSE_PRESSED_MULTIBYTE_SCANCODE[0] {
+					self.state = Set1State::ScanningPausePressedMultibyte(1);
+					None
+				} else if byte & SET1_RELEASED_KEY_BIT != 0 {
+					Some(DecodedScanCode::Released(
+						set1_simple_scancode_to_keycode(byte & !SET1_RELEASED_KEY_BIT)))
+				} else {
+					Some(DecodedScanCode::Pressed(set1_simple_scancode_to_keycode(byte)))
+				}
+			},
+			Set1State::ScanningExtendedKey => {
+				if byte == SET1_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE[1] {
+					self.state = Set1State::ScanningPrintScreenPressedMultibyte(2);
+					None
+				} else if byte == SET1_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE[1] {
+					self.state = Set1State::ScanningPrintScreenReleasedMultibyte(2);
+					None
+				} else if byte & SET1_RELEASED_KEY_BIT != 0 {
+					self.state = Set1State::ScanningKey;
+					Some(DecodedScanCode::Released(
+						set1_extended_scancode_to_keycode(byte & !SET1_RELEASED_KEY_BIT)))
+				} else {
+					self.state = Set1State::ScanningKey;
+					Some(DecodedScanCode::Pressed(set1_extended_scancode_to_keycode(byte)))
+				}
+			},
+			Set1State::ScanningPrintScreenPressedMultibyte(byte_idx) => {
+				let idx = byte_idx as usize;
+				if byte == SET1_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE[idx] {
+					if idx < SET1_PRINT_SCREEN_PRESSED_MULTIBYTE_SCANCODE.len() - 1 {
+						self.state = Set1State::ScanningPrintScreenPressedMultibyte((idx + 1) as u8);
+						None
+					} else {
+						self.state = Set1State::ScanningKey;
+						Some(DecodedScanCode::Pressed(KeyCode::KeyPrintScreen))
+					}
+				} else {
+					self.state = Set1State::ScanningKey;
+					Some(DecodedScanCode::Pressed(KeyCode::Unknown))
+				}
+			},
+			Set1State::ScanningPrintScreenReleasedMultibyte(byte_idx) => {
+				let idx = byte_idx as usize;
+				if byte == SET1_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE[idx] {
+					if idx < SET1_PRINT_SCREEN_RELEASED_MULTIBYTE_SCANCODE.len() - 1 {
+						self.state = Set1State::ScanningPrintScreenReleasedMultibyte((idx + 1) as u8);
+						None
+					} else {
+						self.state = Set1State::ScanningKey;
+						Some(DecodedScanCode::Released(KeyCode::KeyPrintScreen))
+					}
+				} else {
+					self.state = Set1State::ScanningKey;
+					Some(DecodedScanCode::Released(KeyCode::Unknown))
+				}
+			},
+			Set1State::ScanningPausePressedMultibyte(byte_idx) => {
+				let idx = byte_idx as usize;
+				if byte == SET1_PAUSE_PRESSED_MULTIBYTE_SCANCODE[idx] {
+					if idx < SET1_PAUSE_PRESSED_MULTIBYTE_SCANCODE.len() - 1 {
+						self.state = Set1State::ScanningPausePressedMultibyte((idx + 1) as u8);
+						None
+					} else {
+						// Same as set 2, Pause has no distinct release code
+						self.state = Set1State::ScanningKey;
+						Some(DecodedScanCode::Pressed(KeyCode::KeyPause))
+					}
+				} else {
+					self.state = Set1State::ScanningKey;
+					Some(DecodedScanCode::Pressed(KeyCode::Unknown))
+				}
+			},
+		}
+	}
+
+	fn reset(&mut self) {
+		self.state = Set1State::ScanningKey;
+	}
+}
+
+/// Converts a simple (non-extended, release bit already masked off) set 1 scan code to the
+/// corresponding key code
+fn set1_simple_scancode_to_keycode(scan_code: u8) -> KeyCode {
+	match scan_code {
+		0x01 => KeyCode::KeyEscape,
+		0x02 => KeyCode::Key1,
+		0x03 => KeyCode::Key2,
+		0x04 => KeyCode::Key3,
+		0x05 => KeyCode::Key4,
+		0x06 => KeyCode::Key5,
+		0x07 => KeyCode::Key6,
+		0x08 => KeyCode::Key7,
+		0x09 => KeyCode::Key8,
+		0x0A => KeyCode::Key9,
+		0x0B => KeyCode::Key0,
+		0x0C => KeyCode::KeyMinus,
+		0x0D => KeyCode::KeyEquals,
+		0x0E => KeyCode::KeyBackspace,
+		0x0F => KeyCode::KeyTab,
+		0x10 => KeyCode::KeyQ,
+		0x11 => KeyCode::KeyW,
+		0x12 => KeyCode::KeyE,
+		0x13 => KeyCode::KeyR,
+		0x14 => KeyCode::KeyT,
+		0x15 => KeyCode::KeyY,
+		0x16 => KeyCode::KeyU,
+		0x17 => KeyCode::KeyI,
+		0x18 => KeyCode::KeyO,
+		0x19 => KeyCode::KeyP,
+		0x1A => KeyCode::KeyLeftSquareBracket,
+		0x1B => KeyCode::KeyRightSquareBracket,
+		0x1C => KeyCode::KeyEnter,
+		0x1D => KeyCode::KeyLeftControl,
+		0x1E => KeyCode::KeyA,
+		0x1F => KeyCode::KeyS,
+		0x20 => KeyCode::KeyD,
+		0x21 => KeyCode::KeyF,
+		0x22 => KeyCode::KeyG,
+		0x23 => KeyCode::KeyH,
+		0x24 => KeyCode::KeyJ,
+		0x25 => KeyCode::KeyK,
+		0x26 => KeyCode::KeyL,
+		0x27 => KeyCode::KeySemicolon,
+		0x28 => KeyCode::KeyApostrophe,
+		0x29 => KeyCode::KeyBackTick,
+		0x2A => KeyCode::KeyLeftShift,
+		0x2B => KeyCode::KeyBackSlash,
+		0x2C => KeyCode::KeyZ,
+		0x2D => KeyCode::KeyX,
+		0x2E => KeyCode::KeyC,
+		0x2F => KeyCode::KeyV,
+		0x30 => KeyCode::KeyB,
+		0x31 => KeyCode::KeyN,
+		0x32 => KeyCode::KeyM,
+		0x33 => KeyCode::KeyComma,
+		0x34 => KeyCode::KeyPeriod,
+		0x35 => KeyCode::KeySlash,
+		0x36 => KeyCode::KeyRightShift,
+		0x37 => KeyCode::KeyNumpadAsterisk,
+		0x38 => KeyCode::KeyLeftAlt,
+		0x39 => KeyCode::KeySpace,
+		0x3A => KeyCode::KeyCapsLock,
+		0x3B => KeyCode::KeyF1,
+		0x3C => KeyCode::KeyF2,
+		0x3D => KeyCode::KeyF3,
+		0x3E => KeyCode::KeyF4,
+		0x3F => KeyCode::KeyF5,
+		0x40 => KeyCode::KeyF6,
+		0x41 => KeyCode::KeyF7,
+		0x42 => KeyCode::KeyF8,
+		0x43 => KeyCode::KeyF9,
+		0x44 => KeyCode::KeyF10,
+		0x45 => KeyCode::KeyNumberLock,
+		0x46 => KeyCode::KeyScrollLock,
+		0x47 => KeyCode::KeyNumpad7,
+		0x48 => KeyCode::KeyNumpad8,
+		0x49 => KeyCode::KeyNumpad9,
+		0x4A => KeyCode::KeyNumpadMinus,
+		0x4B => KeyCode::KeyNumpad4,
+		0x4C => KeyCode::KeyNumpad5,
+		0x4D => KeyCode::KeyNumpad6,
+		0x4E => KeyCode::KeyNumpadPlus,
+		0x4F => KeyCode::KeyNumpad1,
+		0x50 => KeyCode::KeyNumpad2,
+		0x51 => KeyCode::KeyNumpad3,
+		0x52 => KeyCode::KeyNumpad0,
+		0x53 => KeyCode::KeyNumpadPeriod,
+		0x56 => KeyCode::KeyExtraBackSlash,
+		0x57 => KeyCode::KeyF11,
+		0x58 => KeyCode::KeyF12,
+		_ => KeyCode::Unknown,
+	}
+}
+
+/// Converts an extended (0xE0-prefixed, release bit already masked off) set 1 scan code to the
+/// corresponding key code.
+///
+/// Note: unlike the core table above, firmware/emulators don't agree on exactly which extended
+/// codes the multimedia/ACPI keys send in set 1 - those keys are deliberately left unmapped here
+/// (falling through to `Unknown`) rather than guessing at vendor-specific codes
+fn set1_extended_scancode_to_keycode(scan_code: u8) -> KeyCode {
+	match scan_code {
+		0x1C => KeyCode::KeyNumpadEnter,
+		0x1D => KeyCode::KeyRightControl,
+		0x35 => KeyCode::KeyNumpadSlash,
+		0x38 => KeyCode::KeyRightAlt,
+		0x47 => KeyCode::KeyHome,
+		0x48 => KeyCode::KeyUpArrow,
+		0x49 => KeyCode::KeyPageUp,
+		0x4B => KeyCode::KeyLeftArrow,
+		0x4D => KeyCode::KeyRightArrow,
+		0x4F => KeyCode::KeyEnd,
+		0x50 => KeyCode::KeyDownArrow,
+		0x51 => KeyCode::KeyPageDown,
+		0x52 => KeyCode::KeyInsert,
+		0x53 => KeyCode::KeyDelete,
+		0x5B => KeyCode::KeyLeftLogo,
+		0x5C => KeyCode::KeyRightLogo,
+		0x5D => KeyCode::KeyMenu,
+		_ => KeyCode::Unknown,
+	}
+}
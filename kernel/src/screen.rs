@@ -1,11 +1,16 @@
-//! Basic VGA text-mode print functions
+//! Basic VGA text-mode print functions, plus a framebuffer-backed console used instead whenever the
+//! boot args report a linear frame buffer. `print_char` also interprets a small subset of
+//! ANSI/VT100 CSI escape sequences (colors, cursor positioning, clearing), so formatted log output
+//! gets uniform colored, cursor-addressable output on either backend.
 
 // For future reference:
 // http://web.stanford.edu/class/cs140/projects/pintos/specs/freevga/vga/vga.htm#register
 
-// FIXME: NOT THREAD SAFE
+use page_tables::{CacheMode, VirtAddr, PhysAddr};
+use lock_cell::LockCell;
+use boot_args::BootArgs;
 
-use page_tables::{VirtAddr, PhysAddr};
+use crate::font;
 
 const SCREEN_BUFFER_PADDR: u32 = 0xB8000;
 const SCREEN_BUFFER_VADDR: u32 = 0xB8000000;
@@ -20,27 +25,85 @@ const CURSOR_END_REG_INDEX: u8 = 11;
 const CURSOR_HIGH_REG_INDEX: u8 = 14;
 const CURSOR_LOW_REG_INDEX: u8 = 15;
 
-/// Initializes the screen
-pub fn init() {
+/// The number of bytes per pixel the framebuffer console assumes. The bootloader only ever hands us
+/// a 32bpp linear frame buffer (see `vbe::setup_vesa`'s preferences), so there is no need to track a
+/// bits-per-pixel field in `BootArgs` just to recompute this
+const FRAMEBUFFER_BYTES_PER_PIXEL: usize = 4;
+
+/// The active framebuffer console, if `init` was handed a `BootArgs` reporting a linear frame
+/// buffer. `print`/`print_char`/`clear_screen`/`scroll_one_line` transparently dispatch here instead
+/// of the VGA text buffer whenever this is populated.
+static FRAMEBUFFER_CONSOLE: LockCell<Option<FramebufferConsole>> = LockCell::new(None);
+
+/// The active VGA text-buffer console, populated by `init` whenever there is no linear frame
+/// buffer to use instead. Behind a `LockCell` exactly like `serial::SERIAL`, so concurrent
+/// printers can't interleave writes or race on the cached cursor below.
+static CONSOLE: LockCell<Option<Console>> = LockCell::new(None);
+
+/// The maximum number of numeric parameters tracked in an in-flight CSI sequence; parameters
+/// beyond this are parsed (so the sequence still terminates correctly) but discarded
+const MAX_CSI_PARAMS: usize = 4;
+
+/// Tracks progress through an in-flight ANSI/VT100 CSI escape sequence (`ESC [ params final`).
+/// Anything other than `ESC` immediately followed by `[` is not a CSI sequence at all, so the
+/// escape is abandoned and the byte that broke the pattern is printed normally.
+enum EscapeState {
+    Normal,
+    SawEscape,
+    Csi { params: [u16; MAX_CSI_PARAMS], count: usize },
+}
+
+static ESCAPE_STATE: LockCell<EscapeState> = LockCell::new(EscapeState::Normal);
+
+/// The VGA attribute byte applied to characters printed via `print`; updated in place by SGR
+/// (`ESC[...m`) escape sequences. `print_with_attributes` bypasses this entirely and always uses
+/// its caller-given attribute.
+static ACTIVE_ATTRIBUTE: LockCell<u8> = LockCell::new(ATTR_WHITE_ON_BLACK);
+
+/// Initializes the screen. If `boot_args` reports a linear frame buffer, maps it and brings up the
+/// framebuffer console instead of the VGA text buffer
+pub fn init(boot_args: &BootArgs) {
+    if boot_args.frame_buffer_paddr.0 != 0 {
+        init_framebuffer_console(boot_args);
+        clear_screen();
+        return;
+    }
+
     let mut pmem = crate::memory_manager::PHYS_MEM.lock();
     let phys_mem = pmem.as_mut().unwrap();
-    
+
     let mut pages = crate::memory_manager::PAGES.lock();
     let page_dir = pages.as_mut().unwrap();
 
-    // Map the screen buffer so we can write to it
+    // Map the screen buffer so we can write to it. Marked global: this mapping lives for the whole
+    // life of the kernel, so it should survive a `mov cr3` process switch instead of being
+    // needlessly re-walked and re-cached on the next access
     page_dir.map_to_phys_page(phys_mem, VirtAddr(SCREEN_BUFFER_VADDR),
-        PhysAddr(SCREEN_BUFFER_PADDR), true, false, true, false)
+        PhysAddr(SCREEN_BUFFER_PADDR), true, false, true, CacheMode::Uncacheable, true)
         .expect("Failed to map screen buffer");
-    
-    // Reset the screen
+
+    *CONSOLE.lock() = Some(Console::new());
+
+    // Reset the screen (also resets the cursor position)
     clear_screen();
-    // Reset the cursor position
-    set_cursor_offset(0);
     // Reset the cursor shape
     enable_cursor(13, 14);
 }
 
+/// Maps the linear frame buffer `boot_args` describes via `ioremap` and installs it as the active
+/// `FRAMEBUFFER_CONSOLE`
+fn init_framebuffer_console(boot_args: &BootArgs) {
+    let width = boot_args.frame_buffer_width as usize;
+    let height = boot_args.frame_buffer_height as usize;
+    let pitch = width * FRAMEBUFFER_BYTES_PER_PIXEL;
+    let size = pitch * height;
+
+    let framebuffer_ptr = crate::memory_manager::ioremap(boot_args.frame_buffer_paddr, size,
+        CacheMode::WriteCombining).expect("Failed to map the linear frame buffer");
+
+    *FRAMEBUFFER_CONSOLE.lock() = Some(FramebufferConsole::new(framebuffer_ptr, width, height, pitch));
+}
+
 /// Returns a slice to the screen buffer
 fn get_screen_buffer() -> &'static mut [u16] {
     unsafe {
@@ -49,10 +112,14 @@ fn get_screen_buffer() -> &'static mut [u16] {
 }
 
 
-/// Prints `message` on screen at the cursor
+/// Prints `message` on screen at the cursor, using the active attribute (see `ACTIVE_ATTRIBUTE`)
 pub fn print(message: &str) {
     for &ch in message.as_bytes() {
-        print_char(ch, ATTR_WHITE_ON_BLACK);
+        // Read the attribute into a local before calling print_char: an SGR escape sequence
+        // inside `ch` would otherwise try to re-lock ACTIVE_ATTRIBUTE while this temporary guard
+        // was still held
+        let attributes = *ACTIVE_ATTRIBUTE.lock();
+        print_char(ch, attributes);
     }
 }
 
@@ -64,47 +131,157 @@ pub fn print_with_attributes(message: &str, attributes: u8) {
 }
 
 /// Prints one `character` to the screen with the specified `attributes` at the cursor, and then
-/// advances the cursor. Also handles new lines.
+/// advances the cursor. Also handles new lines. If the framebuffer console is active, this draws
+/// into it instead of the VGA text buffer; `attributes` is ignored in that case, since the
+/// framebuffer console only draws in a fixed foreground/background color.
+///
+/// `character` is first fed through the ANSI/VT100 CSI escape-sequence state machine: bytes that
+/// are part of an (in-progress or just-completed) escape sequence are consumed here and never
+/// reach the screen.
 pub fn print_char(character: u8, attributes: u8) {
-    let screen_buffer = get_screen_buffer();
+    if consume_escape_byte(character) {
+        return;
+    }
 
-    let cursor_offset = get_cursor_offset();
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.print_char(character);
+        return;
+    }
+    core::mem::drop(framebuffer_console);
 
-    // Check if we got a new line
-    if character == b'\n' {
-        // Get the actual row
-        let cursor_row = cursor_offset / SCREEN_WIDTH;
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.print_char(character, attributes);
+    }
+}
 
-        // If we get a new line at the last row we need to scroll the screen
-        if cursor_row == SCREEN_HEIGHT - 1 {
-            scroll_one_line();
-            // Actually set the cursor offset to the start of this row
-            set_cursor_offset(cursor_row * SCREEN_WIDTH);
-        } else {
-            // Set the cursor offset to the start of the next row
-            set_cursor_offset((cursor_row + 1) * SCREEN_WIDTH);
-        }
-    } else {
-        // Combine the character and attribute
-        let char_and_attr = ((attributes as u16) << 8) | (character as u16);
-        screen_buffer[cursor_offset] = char_and_attr;
-
-        // If we just set the last character of the screen we need to scroll
-        if cursor_offset == (SCREEN_WIDTH * SCREEN_HEIGHT) - 1 {
-            scroll_one_line();
-            // Set the cursor offset to the start of the last row
-            set_cursor_offset((SCREEN_HEIGHT - 1) * SCREEN_WIDTH);
-        } else {
-            // Advance the cursor
-            set_cursor_offset(cursor_offset + 1);
+/// Feeds `character` through the CSI escape-sequence state machine. Returns `true` if the byte was
+/// consumed as part of an escape sequence (and so must not be rendered), or `false` if it's a
+/// normal character that should be printed as usual.
+fn consume_escape_byte(character: u8) -> bool {
+    let mut state = ESCAPE_STATE.lock();
+    match &mut *state {
+        EscapeState::Normal => {
+            if character == 0x1B {
+                *state = EscapeState::SawEscape;
+                return true;
+            }
+            false
+        },
+        EscapeState::SawEscape => {
+            if character == b'[' {
+                *state = EscapeState::Csi { params: [0; MAX_CSI_PARAMS], count: 0 };
+                true
+            } else {
+                // Not a CSI sequence after all; the lone ESC is swallowed (it has no glyph of its
+                // own anyway) but this byte falls through and prints normally
+                *state = EscapeState::Normal;
+                false
+            }
+        },
+        EscapeState::Csi { params, count } => {
+            match character {
+                b'0'..=b'9' => {
+                    if *count < MAX_CSI_PARAMS {
+                        let digit = (character - b'0') as u16;
+                        params[*count] = params[*count].saturating_mul(10).saturating_add(digit);
+                    }
+                },
+                b';' => {
+                    if *count < MAX_CSI_PARAMS {
+                        *count += 1;
+                    }
+                },
+                b'm' | b'H' | b'J' | b'K' => {
+                    let params = *params;
+                    let param_count = (*count + 1).min(MAX_CSI_PARAMS);
+                    *state = EscapeState::Normal;
+                    core::mem::drop(state);
+                    execute_csi(character, &params[..param_count]);
+                    return true;
+                },
+                _ => {
+                    // Not a sequence we recognize; abandon it rather than getting stuck forever
+                    *state = EscapeState::Normal;
+                },
+            }
+            true
+        },
+    }
+}
+
+/// Executes a completed CSI escape sequence: `final_byte` is one of `m`/`H`/`J`/`K`, and `params`
+/// holds the (defaulted-to-0 where omitted) numeric parameters parsed between `ESC[` and it
+fn execute_csi(final_byte: u8, params: &[u16]) {
+    match final_byte {
+        b'm' => apply_sgr(params),
+        b'H' => {
+            let row = *params.first().unwrap_or(&0);
+            let col = params.get(1).copied().unwrap_or(0);
+            // Row/column are 1-indexed; a 0 (omitted) parameter also means "the first one"
+            set_cursor_position(row.max(1) as usize, col.max(1) as usize);
+        },
+        b'J' => clear_screen(),
+        b'K' => clear_to_end_of_line(),
+        _ => unreachable!(),
+    }
+}
+
+/// Applies SGR (Select Graphic Rendition) parameters to `ACTIVE_ATTRIBUTE`: `0` resets to the
+/// default white-on-black, `30`-`37` set the foreground color nibble, `40`-`47` set the background
+fn apply_sgr(params: &[u16]) {
+    let mut attribute = *ACTIVE_ATTRIBUTE.lock();
+    for &param in params {
+        match param {
+            0 => attribute = ATTR_WHITE_ON_BLACK,
+            30..=37 => attribute = (attribute & 0xF0) | (param - 30) as u8,
+            40..=47 => attribute = (attribute & 0x0F) | (((param - 40) as u8) << 4),
+            _ => {},
         }
     }
+    *ACTIVE_ATTRIBUTE.lock() = attribute;
+}
+
+/// Moves the cursor to 1-indexed (`row`, `col`), clamped to the screen bounds
+fn set_cursor_position(row: usize, col: usize) {
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.set_cursor_position(row, col);
+        return;
+    }
+    core::mem::drop(framebuffer_console);
+
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.set_cursor_position(row, col);
+    }
+}
+
+/// Clears from the cursor to the end of its row, without moving the cursor
+fn clear_to_end_of_line() {
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.clear_to_end_of_line();
+        return;
+    }
+    core::mem::drop(framebuffer_console);
+
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.clear_to_end_of_line();
+    }
 }
 
 /// Clears the entire screen
 pub fn clear_screen() {
-    // We must include an attribute or else the cursor won't show up
-    get_screen_buffer().fill((ATTR_WHITE_ON_BLACK as u16) << 8);
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.clear();
+        return;
+    }
+    core::mem::drop(framebuffer_console);
+
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.clear();
+    }
 }
 
 /// Sets the character cursor offset of the VGA device.
@@ -124,26 +301,22 @@ pub fn set_cursor_offset(offset: usize) {
 
 /// Scrolls the screen one line by memmoving the rows up one row, and clearing the last row
 pub fn scroll_one_line() {
-    let screen_buffer = get_screen_buffer();
-
-    // We get a reference to the rows following the first row, this is the source of the copy
-    let second_row_onward = &screen_buffer[SCREEN_WIDTH..];
-
-    // Calculate how many u16s we need to copy for the entire screen except for one row
-    let num_elements = SCREEN_WIDTH * (SCREEN_HEIGHT - 1);
-
-    unsafe {
-        core::ptr::copy(second_row_onward.as_ptr(), screen_buffer.as_mut_ptr(), num_elements);
+    let mut framebuffer_console = FRAMEBUFFER_CONSOLE.lock();
+    if let Some(console) = framebuffer_console.as_mut() {
+        console.scroll_one_line();
+        return;
     }
+    core::mem::drop(framebuffer_console);
 
-    // Clear the last row (We must include an attribute or else the cursor won't show up)
-    screen_buffer[num_elements..].fill((ATTR_WHITE_ON_BLACK as u16) << 8);
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.scroll_one_line();
+    }
 }
 
-/// Retrieves the character cursor offset from the VGA device.
+/// Retrieves the character cursor offset from the VGA device by reading it back from the CRTC
+/// ports. `Console` caches its own cursor rather than calling this on every character printed;
+/// this remains for callers that want the hardware's current, authoritative position.
 pub fn get_cursor_offset() -> usize {
-    // TODO: We are the only one controlling the screen, we can just save the cursor location
-    // instead of accessing the ports which is slow
     unsafe {
         // The control port is used as an index into the registers
         // Index 14 is the high byte of the cursor offset
@@ -176,4 +349,241 @@ pub fn disable_cursor() {
         cpu::out8(REG_SCREEN_CTRL_PORT, CURSOR_START_REG_INDEX);
         cpu::out8(REG_SCREEN_DATA_PORT, 0b00100000);
     }
+}
+
+/// A simple character-cell text console drawn on top of a linear frame buffer mapped via
+/// `ioremap`, using the embedded 8x16 bitmap font in the `font` module. Mirrors the VGA text
+/// console's behavior (cursor advance, newline handling, scroll-on-last-row) but blits pixels into
+/// the frame buffer instead of writing `(character, attribute)` cells
+struct FramebufferConsole {
+    buffer: &'static mut [u8],
+    pitch: usize,
+    columns: usize,
+    rows: usize,
+    cursor_col: usize,
+    cursor_row: usize,
+}
+
+impl FramebufferConsole {
+    fn new(buffer_ptr: *mut u8, width: usize, height: usize, pitch: usize) -> Self {
+        let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_ptr, pitch * height) };
+        FramebufferConsole {
+            buffer,
+            pitch,
+            columns: width / font::GLYPH_WIDTH,
+            rows: height / font::GLYPH_HEIGHT,
+            cursor_col: 0,
+            cursor_row: 0,
+        }
+    }
+
+    /// Packs (white) into the 32bpp pixel format and writes it at `(x, y)`
+    fn put_pixel(&mut self, x: usize, y: usize, white: bool) {
+        let color: u32 = if white { 0x00FFFFFF } else { 0x00000000 };
+        let offset = y * self.pitch + x * FRAMEBUFFER_BYTES_PER_PIXEL;
+        self.buffer[offset..offset + FRAMEBUFFER_BYTES_PER_PIXEL]
+            .copy_from_slice(&color.to_le_bytes());
+    }
+
+    /// Blits the glyph for `character` at the current cursor cell, without advancing the cursor
+    fn draw_glyph(&mut self, character: u8) {
+        let glyph = font::FONT[if character < 128 { character as usize } else { b'?' as usize }];
+        let base_x = self.cursor_col * font::GLYPH_WIDTH;
+        let base_y = self.cursor_row * font::GLYPH_HEIGHT;
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..font::GLYPH_WIDTH {
+                let set = (bits & (1 << (font::GLYPH_WIDTH - 1 - col))) != 0;
+                self.put_pixel(base_x + col, base_y + row, set);
+            }
+        }
+    }
+
+    /// Clears the entire frame buffer and resets the cursor
+    fn clear(&mut self) {
+        self.buffer.fill(0);
+        self.cursor_col = 0;
+        self.cursor_row = 0;
+    }
+
+    /// Moves the cursor to the start of the next row, scrolling if it was on the last row
+    fn newline(&mut self) {
+        self.cursor_col = 0;
+        if self.cursor_row == self.rows - 1 {
+            self.scroll_one_line();
+        } else {
+            self.cursor_row += 1;
+        }
+    }
+
+    /// Scrolls the frame buffer up by one character row's worth of scanlines, by memmoving the
+    /// trailing scanlines up and clearing the last row - the pixel equivalent of the VGA text
+    /// console's `scroll_one_line`
+    fn scroll_one_line(&mut self) {
+        let row_bytes = self.pitch * font::GLYPH_HEIGHT;
+        let scroll_bytes = row_bytes * (self.rows - 1);
+
+        unsafe {
+            core::ptr::copy(self.buffer.as_ptr().add(row_bytes), self.buffer.as_mut_ptr(),
+                scroll_bytes);
+        }
+
+        self.buffer[scroll_bytes..].fill(0);
+    }
+
+    /// Draws one `character`, advancing the cursor and handling new lines/scrolling
+    fn print_char(&mut self, character: u8) {
+        if character == b'\n' {
+            self.newline();
+            return;
+        }
+
+        self.draw_glyph(character);
+
+        self.cursor_col += 1;
+        if self.cursor_col == self.columns {
+            self.newline();
+        }
+    }
+
+    /// Moves the cursor to 1-indexed (`row`, `col`), clamped to the screen bounds
+    fn set_cursor_position(&mut self, row: usize, col: usize) {
+        self.cursor_row = (row - 1).min(self.rows - 1);
+        self.cursor_col = (col - 1).min(self.columns - 1);
+    }
+
+    /// Clears from the cursor to the end of its row, without moving the cursor
+    fn clear_to_end_of_line(&mut self) {
+        let saved_col = self.cursor_col;
+        for col in saved_col..self.columns {
+            self.cursor_col = col;
+            self.draw_glyph(b' ');
+        }
+        self.cursor_col = saved_col;
+    }
+}
+
+/// A character-cell VGA text console. Caches the cursor offset and last-used attribute byte
+/// instead of reading the cursor back from the CRTC ports on every character, so `print_char` only
+/// ever needs to write the hardware cursor once per call
+struct Console {
+    buffer: &'static mut [u16],
+    cursor_offset: usize,
+    attribute: u8,
+}
+
+impl Console {
+    fn new() -> Self {
+        Console {
+            buffer: get_screen_buffer(),
+            cursor_offset: 0,
+            attribute: ATTR_WHITE_ON_BLACK,
+        }
+    }
+
+    /// Draws one `character` with `attributes`, advancing the cached cursor and handling new
+    /// lines/scrolling, then writes the final cursor position to the hardware once
+    fn print_char(&mut self, character: u8, attributes: u8) {
+        self.attribute = attributes;
+
+        if character == b'\n' {
+            // Get the actual row
+            let cursor_row = self.cursor_offset / SCREEN_WIDTH;
+
+            // If we get a new line at the last row we need to scroll the screen
+            if cursor_row == SCREEN_HEIGHT - 1 {
+                self.scroll_one_line();
+                // Actually set the cursor offset to the start of this row
+                self.cursor_offset = cursor_row * SCREEN_WIDTH;
+            } else {
+                // Set the cursor offset to the start of the next row
+                self.cursor_offset = (cursor_row + 1) * SCREEN_WIDTH;
+            }
+        } else {
+            // Combine the character and attribute
+            let char_and_attr = ((attributes as u16) << 8) | (character as u16);
+            self.buffer[self.cursor_offset] = char_and_attr;
+
+            // If we just set the last character of the screen we need to scroll
+            if self.cursor_offset == (SCREEN_WIDTH * SCREEN_HEIGHT) - 1 {
+                self.scroll_one_line();
+                // Set the cursor offset to the start of the last row
+                self.cursor_offset = (SCREEN_HEIGHT - 1) * SCREEN_WIDTH;
+            } else {
+                // Advance the cursor
+                self.cursor_offset += 1;
+            }
+        }
+
+        set_cursor_offset(self.cursor_offset);
+    }
+
+    /// Clears the entire screen and resets the cached cursor
+    fn clear(&mut self) {
+        // We must include an attribute or else the cursor won't show up
+        self.buffer.fill((self.attribute as u16) << 8);
+        self.cursor_offset = 0;
+        set_cursor_offset(self.cursor_offset);
+    }
+
+    /// Scrolls the screen one line by memmoving the rows up one row, and clearing the last row
+    fn scroll_one_line(&mut self) {
+        // We get a reference to the rows following the first row, this is the source of the copy
+        let second_row_onward = &self.buffer[SCREEN_WIDTH..];
+
+        // Calculate how many u16s we need to copy for the entire screen except for one row
+        let num_elements = SCREEN_WIDTH * (SCREEN_HEIGHT - 1);
+
+        unsafe {
+            core::ptr::copy(second_row_onward.as_ptr(), self.buffer.as_mut_ptr(), num_elements);
+        }
+
+        // Clear the last row (We must include an attribute or else the cursor won't show up)
+        self.buffer[num_elements..].fill((self.attribute as u16) << 8);
+    }
+
+    /// Moves the cursor to 1-indexed (`row`, `col`), clamped to the screen bounds
+    fn set_cursor_position(&mut self, row: usize, col: usize) {
+        let row = (row - 1).min(SCREEN_HEIGHT - 1);
+        let col = (col - 1).min(SCREEN_WIDTH - 1);
+        self.cursor_offset = row * SCREEN_WIDTH + col;
+        set_cursor_offset(self.cursor_offset);
+    }
+
+    /// Clears from the cursor to the end of its row, without moving the cursor, using the active
+    /// attribute
+    fn clear_to_end_of_line(&mut self) {
+        let row_end = (self.cursor_offset / SCREEN_WIDTH + 1) * SCREEN_WIDTH;
+        self.buffer[self.cursor_offset..row_end].fill((self.attribute as u16) << 8);
+    }
+}
+
+/// Dummy struct to implement `core::fmt::Write` on, mirroring `serial::SerialWriter`
+pub struct ScreenWriter;
+
+impl core::fmt::Write for ScreenWriter {
+    fn write_str(&mut self, msg: &str) -> core::fmt::Result {
+        print(msg);
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        {
+            use core::fmt::Write;
+            let _ = write!($crate::screen::ScreenWriter, $($arg)*);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {
+        {
+            use core::fmt::Write;
+            let _ = writeln!($crate::screen::ScreenWriter, $($arg)*);
+        }
+    };
 }
\ No newline at end of file
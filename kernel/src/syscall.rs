@@ -3,12 +3,14 @@ use alloc::vec::Vec;
 use elf_parser::ElfParser;
 use ext2_parser::{DirEntryType, IterationDecision};
 use page_tables::VirtAddr;
-use syscall_interface::{SyscallString, SyscallFileStat, SyscallArray, SyscallDirectoryEntry};
+use syscall_interface::{
+	SyscallString, SyscallFileStat, SyscallArray, SyscallDirectoryEntry, SyscallKeyEvent, O_NONBLOCK,
+};
 pub use syscall_interface::{Syscall, SyscallError};
 use crate::ext2;
-use crate::keyboard::{KEYBOARD_EVENTS_QUEUE, KeyEventType};
+use crate::keyboard::{KEYBOARD_EVENTS_QUEUE, KeyEvent, KeyEventType};
 use crate::process::{Process, SCHEDULER_STATE};
-use crate::vfs::{FILE_DESCRIPTIONS, FileDescription, FileType};
+use crate::vfs::{FILE_DESCRIPTIONS, FileDescription, FileType, DeviceId};
 use crate::userspace::{UserVaddr};
 
 macro_rules! unwrap_or_return {
@@ -34,6 +36,7 @@ pub fn handle_syscall(syscall: Syscall, arg0: u32, arg1: u32, arg2: u32) -> i32
 		Syscall::Stat => syscall_stat(UserVaddr::new(&arg0), UserVaddr::new(&arg1)),
 		Syscall::GetCWD => syscall_getcwd(UserVaddr::new(&arg0), arg1),
 		Syscall::ChangeCWD => syscall_changecwd(UserVaddr::new(&arg0)),
+		Syscall::IoPerm => syscall_ioperm(arg0, arg1, arg2 != 0),
 		Syscall::Count => SyscallError::UnknownSyscall.to_i32(),
 	}
 }
@@ -74,12 +77,12 @@ fn syscall_read(fd: u32, buf: UserVaddr<u8>, num_bytes: u32) -> i32 {
 
 		match description.file_type {
 			FileType::File => {
-				let num_read = ext2_parser.get_contents_with_offset(description.inode, buf, description.offset as usize);
+				let num_read = ext2_parser.get_contents_with_offset(description.inode, buf, description.offset as u64).unwrap();
 				description.offset += num_read as u32;
 				num_read as i32
 			},
 			FileType::Directory => {
-				let entry = ext2_parser.get_next_directory_entry(description.inode, description.offset);
+				let entry = ext2_parser.get_next_directory_entry(description.inode, description.offset).unwrap();
 				if entry.is_none() {
 					// No more entries
 					return 0;
@@ -92,7 +95,7 @@ fn syscall_read(fd: u32, buf: UserVaddr<u8>, num_bytes: u32) -> i32 {
 				let (next_opaque_offset, entry_inode, entry_name, entry_type) = entry.unwrap();
 				description.offset = next_opaque_offset;
 
-				let name_len = entry_name.as_bytes().len();
+				let name_len = entry_name.as_str().as_bytes().len();
 				assert!(name_len < u8::MAX as usize);
 				let mut syscall_struct = SyscallDirectoryEntry {
 					inode: entry_inode,
@@ -100,7 +103,7 @@ fn syscall_read(fd: u32, buf: UserVaddr<u8>, num_bytes: u32) -> i32 {
 					name_length: name_len as u8,
 					name: [0u8; 256]
 				};
-				syscall_struct.name[..name_len].copy_from_slice(entry_name.as_bytes());
+				syscall_struct.name[..name_len].copy_from_slice(entry_name.as_str().as_bytes());
 
 				buf[..core::mem::size_of::<SyscallDirectoryEntry>()].copy_from_slice(unsafe {
 					core::slice::from_raw_parts(
@@ -112,10 +115,65 @@ fn syscall_read(fd: u32, buf: UserVaddr<u8>, num_bytes: u32) -> i32 {
 				assert!(core::mem::size_of::<SyscallDirectoryEntry>() < i32::MAX as usize);
 				core::mem::size_of::<SyscallDirectoryEntry>() as i32
 			},
+			FileType::Device(DeviceId::Keyboard) => {
+				let record_size = core::mem::size_of::<SyscallKeyEvent>();
+				if (num_bytes as usize) < record_size {
+					return SyscallError::BufferTooSmall.to_i32();
+				}
+
+				// Block for the first event unless the descriptor was opened O_NONBLOCK; once at
+				// least one event is ready, drain whatever else already fits without blocking again
+				let first_event = if description.status & O_NONBLOCK != 0 {
+					match KEYBOARD_EVENTS_QUEUE.consume() {
+						Some(event) => event,
+						None => return 0,
+					}
+				} else {
+					KEYBOARD_EVENTS_QUEUE.consume_blocking()
+				};
+
+				let mut written = 0;
+				write_key_event(buf, &mut written, first_event);
+				while written + record_size <= buf.len() {
+					match KEYBOARD_EVENTS_QUEUE.consume() {
+						Some(event) => write_key_event(buf, &mut written, event),
+						None => break,
+					}
+				}
+
+				written as i32
+			},
 		}
 	}
 }
 
+/// Serializes `event` as a `SyscallKeyEvent` and appends it to `buf` at `*written`, advancing
+/// `*written` past it. The caller is responsible for making sure `buf` has room.
+fn write_key_event(buf: &mut [u8], written: &mut usize, event: KeyEvent) {
+	let mut modifiers = 0;
+	if event.shift_down { modifiers |= syscall_interface::KEY_EVENT_SHIFT_DOWN; }
+	if event.ctrl_down { modifiers |= syscall_interface::KEY_EVENT_CTRL_DOWN; }
+	if event.alt_down { modifiers |= syscall_interface::KEY_EVENT_ALT_DOWN; }
+	if event.logo_down { modifiers |= syscall_interface::KEY_EVENT_LOGO_DOWN; }
+	if event.caps_lock_enabled { modifiers |= syscall_interface::KEY_EVENT_CAPS_LOCK_ENABLED; }
+	if event.number_lock_enabled { modifiers |= syscall_interface::KEY_EVENT_NUMBER_LOCK_ENABLED; }
+
+	let syscall_struct = SyscallKeyEvent {
+		key_code: event.key_code as u8,
+		event_type: match event.event_type {
+			KeyEventType::KeyDown => syscall_interface::KEY_EVENT_TYPE_DOWN,
+			KeyEventType::KeyUp => syscall_interface::KEY_EVENT_TYPE_UP,
+		},
+		modifiers,
+	};
+
+	let record_size = core::mem::size_of::<SyscallKeyEvent>();
+	buf[*written..*written + record_size].copy_from_slice(unsafe {
+		core::slice::from_raw_parts(&syscall_struct as *const SyscallKeyEvent as *const u8, record_size)
+	});
+	*written += record_size;
+}
+
 fn syscall_write(fd: u32, buf: UserVaddr<u8>, num_bytes: u32) -> i32 {
 	let num_bytes = if num_bytes > i32::MAX as u32 {
 		i32::MAX as u32
@@ -139,20 +197,36 @@ fn syscall_open(path: UserVaddr<SyscallString>, flags: u32) -> i32 {
 	let mut sched_state = SCHEDULER_STATE.lock();
 	let cur_proc = sched_state.get_current_process();
 
-	let (inode, entry_type) = unwrap_or_return!(
-		ext2::EXT2_PARSER.lock().as_ref().unwrap().resolve_path_to_inode(path, cur_proc.cwd_inode),
-		SyscallError::InvalidPath
-	);
+	// `/dev/keyboard` isn't backed by an ext2 inode, so it's special-cased here instead of going
+	// through `resolve_path_to_inode` - see `FileType::Device`
+	let desc = if path == "/dev/keyboard" {
+		FileDescription {
+			inode: 0,
+			offset: 0,
+			status: flags,
+			file_type: FileType::Device(DeviceId::Keyboard),
+		}
+	} else {
+		let (inode, entry_type) = unwrap_or_return!(
+			ext2::EXT2_PARSER.lock().as_ref().unwrap().resolve_path_to_inode(path, cur_proc.cwd_inode).unwrap(),
+			SyscallError::InvalidPath
+		);
 
-	let desc_idx = unwrap_or_return!(FILE_DESCRIPTIONS.lock().add_description(FileDescription {
-		inode,
-		offset: 0,
-		status: flags,
-		file_type: match entry_type {
-			ext2_parser::DirEntryType::Directory => FileType::Directory,
-			_ => FileType::File,
-		},
-	}), SyscallError::OpenFileLimitReached);
+		FileDescription {
+			inode,
+			offset: 0,
+			status: flags,
+			file_type: match entry_type {
+				ext2_parser::DirEntryType::Directory => FileType::Directory,
+				_ => FileType::File,
+			},
+		}
+	};
+
+	let desc_idx = unwrap_or_return!(
+		FILE_DESCRIPTIONS.lock().add_description(desc),
+		SyscallError::OpenFileLimitReached
+	);
 
 	let fd = unwrap_or_return!(
 		cur_proc.alloc_file_descriptor(desc_idx),
@@ -191,17 +265,17 @@ fn syscall_execve(path: UserVaddr<SyscallString>, argv: UserVaddr<SyscallArray<S
 			let ext2_parser = ext2::EXT2_PARSER.lock();
 			let ext2_parser = ext2_parser.as_ref().unwrap();
 			let (inode, entry_type) = unwrap_or_return!(
-				ext2_parser.resolve_path_to_inode(path, cur_proc.cwd_inode),
+				ext2_parser.resolve_path_to_inode(path, cur_proc.cwd_inode).unwrap(),
 				SyscallError::InvalidPath
 			);
 			if entry_type != DirEntryType::RegularFile {
 				return SyscallError::PathIsDirectory.to_i32();
 			}
 
-			let user_program_metadata = ext2_parser.get_inode(inode);
-			let user_program_size = user_program_metadata.size_low as usize;
+			let user_program_metadata = ext2_parser.get_inode(inode).unwrap();
+			let user_program_size = ext2_parser.file_size(&user_program_metadata) as usize;
 			let mut user_program = crate::vec![0u8; user_program_size];
-			assert!(ext2_parser.get_contents(inode, &mut user_program) == user_program_size);
+			assert!(ext2_parser.get_contents(inode, &mut user_program).unwrap() == user_program_size);
 			user_program
 		};
 
@@ -260,11 +334,11 @@ fn syscall_stat(path: UserVaddr<SyscallString>, stat_buf: UserVaddr<SyscallFileS
 	let ext2_parser = ext2_parser.as_ref().unwrap();
 
 	let (inode, _) = unwrap_or_return!(
-		ext2_parser.resolve_path_to_inode(path, cur_proc.cwd_inode),
+		ext2_parser.resolve_path_to_inode(path, cur_proc.cwd_inode).unwrap(),
 		SyscallError::InvalidPath
 	);
 
-	let inode_metadata = ext2_parser.get_inode(inode);
+	let inode_metadata = ext2_parser.get_inode(inode).unwrap();
 
 	let stat_result = SyscallFileStat {
 		containing_device_id: 0,
@@ -273,7 +347,7 @@ fn syscall_stat(path: UserVaddr<SyscallString>, stat_buf: UserVaddr<SyscallFileS
 		num_hard_links: inode_metadata.hard_link_count,
 		owner_user_id: inode_metadata.user_id,
 		owner_group_id: inode_metadata.group_id,
-		total_size: inode_metadata.size_low, // FIXME: 64-bit size
+		total_size: ext2_parser.file_size(&inode_metadata) as u32, // FIXME: total_size is only 32bit in this ABI
 		last_access_time: inode_metadata.last_access_time,
 		last_modification_time: inode_metadata.last_modification_time,
 		last_status_change_time: 0, // TODO:
@@ -312,14 +386,14 @@ fn syscall_getcwd(buf: UserVaddr<u8>, size: u32) -> i32 {
 
 		ext2_parser.for_each_directory_entry(inode_walk[walk_index],
 			|entry_inode, entry_name, _| {
-				if entry_name == ".." {
+				if entry_name.as_str() == ".." {
 					inode_walk[walk_index + 1] = entry_inode;
 					IterationDecision::Break
 				} else {
 					IterationDecision::Continue
 				}
 			}
-		);
+		).unwrap();
 
 		walk_index += 1;
 	}
@@ -332,11 +406,12 @@ fn syscall_getcwd(buf: UserVaddr<u8>, size: u32) -> i32 {
 		ext2_parser.for_each_directory_entry(inode_walk[i],
 			|entry_inode, entry_name, _| {
 				if entry_inode == inode_walk[i-1] {
+					let entry_name = entry_name.as_str();
 					if write_index + entry_name.len() + 1 > size {
 						success = false;
 						return IterationDecision::Break;
 					}
-					
+
 					buf[write_index] = b'/';
 					write_index += 1;
 					buf[write_index..write_index + entry_name.len()].copy_from_slice(entry_name.as_bytes());
@@ -347,7 +422,7 @@ fn syscall_getcwd(buf: UserVaddr<u8>, size: u32) -> i32 {
 					IterationDecision::Continue
 				}
 			}
-		);
+		).unwrap();
 
 		if !success {
 			return SyscallError::BufferTooSmall.to_i32();
@@ -364,7 +439,7 @@ fn syscall_changecwd(path: UserVaddr<SyscallString>) -> i32 {
 	let cur_proc = sched_state.get_current_process();
 
 	let (inode, entry_type) = unwrap_or_return!(
-		ext2::EXT2_PARSER.lock().as_ref().unwrap().resolve_path_to_inode(path, cur_proc.cwd_inode),
+		ext2::EXT2_PARSER.lock().as_ref().unwrap().resolve_path_to_inode(path, cur_proc.cwd_inode).unwrap(),
 		SyscallError::InvalidPath
 	);
 
@@ -374,5 +449,26 @@ fn syscall_changecwd(path: UserVaddr<SyscallString>) -> i32 {
 
 	cur_proc.cwd_inode = inode;
 
+	0
+}
+
+/// Grants (`turn_on`) or revokes direct ring-3 access to the `num_ports` I/O ports starting at
+/// `port`, via the TSS I/O permission bitmap (see `tss::allow_io_port`/`tss::deny_io_port`).
+/// There's a single shared TSS (see `tss::TSS`), so this is a single system-wide grant, not scoped
+/// to the calling process.
+fn syscall_ioperm(port: u32, num_ports: u32, turn_on: bool) -> i32 {
+	let end_port = unwrap_or_return!(
+		port.checked_add(num_ports).filter(|&end| end <= 65536),
+		SyscallError::InvalidIoPortRange
+	);
+
+	for p in port..end_port {
+		if turn_on {
+			crate::tss::allow_io_port(p as u16);
+		} else {
+			crate::tss::deny_io_port(p as u16);
+		}
+	}
+
 	0
 }
\ No newline at end of file
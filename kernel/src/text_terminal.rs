@@ -1,11 +1,21 @@
 //! Text-terminal emulation for basic printing in graphics mode
+//!
+//! `print` never touches the terminal or framebuffer itself: it only pushes bytes into
+//! `PRINT_QUEUE`, a single-producer/single-consumer `ProducerConsumer` (the same primitive
+//! `keyboard::KEYBOARD_EVENTS_QUEUE` uses). `pump_print_queue` is the queue's one consumer; since
+//! `print` itself never acquires `TEXT_TERMINAL`/`FRAME_BUFFER`, callers of `print` (including
+//! interrupt context) never risk deadlocking against the code that draws. This
+//! kernel has no preemptible kernel threads to run a dedicated consumer loop on, so `pump_print_queue`
+//! instead drains the queue periodically off the PIT, the same self-rescheduling
+//! `pit_8254::after` chain `toggle_cursor_blink` below and `keyboard::repeat_tick` use.
 
-// FIXME: NOT THREAD SAFE
+use core::time::Duration;
 
-use exclusive_cell::ExclusiveCell;
+use alloc::vec;
+use alloc::vec::Vec;
 
-const TERMINAL_COLS: usize = 120;
-const TERMINAL_ROWS: usize = 50;
+use exclusive_cell::ExclusiveCell;
+use producer_consumer::ProducerConsumer;
 
 const FONT_DATA: &'static [u8] = include_bytes!("../../font/compact_font.bin");
 const FONT_FIRST_CHAR: u8 = 32;
@@ -13,31 +23,222 @@ const FONT_LAST_CHAR: u8 = 126;
 const FONT_WIDTH: usize = 12;
 const FONT_HEIGHT: usize = 18;
 
+/// The default foreground/background colors, as 0x00RRGGBB - matches the plain white-on-black
+/// look printing had before `Cell` carried its own colors
+const DEFAULT_FG: u32 = 0x00FFFFFF;
+const DEFAULT_BG: u32 = 0x00000000;
+
+/// The classic 8-color ANSI palette selected by SGR params 30-37 (foreground) / 40-47
+/// (background), indexed by `param - 30`/`param - 40`
+const ANSI_COLORS: [u32; 8] = [
+	0x00000000, // black
+	0x00AA0000, // red
+	0x0000AA00, // green
+	0x00AA5500, // yellow
+	0x000000AA, // blue
+	0x00AA00AA, // magenta
+	0x0000AAAA, // cyan
+	0x00AAAAAA, // white
+];
+
+/// The maximum number of numeric parameters tracked in an in-flight CSI sequence. 5 covers the
+/// longest sequence this parser understands, truecolor SGR (`38;2;r;g;b`); parameters beyond this
+/// are parsed (so the sequence still terminates correctly) but discarded.
+const MAX_CSI_PARAMS: usize = 5;
+
+/// How many rows `scroll_one_line` keeps around after evicting them from the live grid, letting
+/// `scroll_up` bring them back into view
+const SCROLLBACK_LINES: usize = 1000;
+
+/// How many bytes `print` can have queued up awaiting `pump_print_queue` before `produce_blocking` starts
+/// stalling the caller
+const PRINT_QUEUE_CAPACITY: usize = 4096;
+
+/// How often the cursor blink state flips; see `toggle_cursor_blink`
+const CURSOR_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often `pump_print_queue` drains `PRINT_QUEUE`; bounds how long `print`'s `produce_blocking`
+/// can ever actually be stuck waiting for room once the queue fills up
+const PRINT_PUMP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The cursor's visual appearance, mirroring Alacritty's `CursorShape`
+#[derive(Clone, Copy, PartialEq)]
+pub enum CursorStyle {
+	/// An outline around the cell, like the terminal's original hardcoded look
+	Block,
+	/// A line under the cell
+	Underline,
+	/// A thin line down the cell's left edge
+	Bar,
+	/// Never drawn
+	Hidden,
+}
+
+/// Tracks progress through an in-flight ANSI/VT100 CSI escape sequence (`ESC [ params final`).
+/// Anything other than `ESC` immediately followed by `[` is not a CSI sequence at all, so the
+/// escape is abandoned and the byte that broke the pattern is printed normally.
+enum EscapeState {
+	Normal,
+	SawEscape,
+	Csi { params: [u16; MAX_CSI_PARAMS], count: usize },
+}
+
+/// A single character cell: a glyph plus the foreground/background colors (0x00RRGGBB) it was
+/// printed with
+#[derive(Clone, Copy)]
+struct Cell {
+	glyph: u8,
+	fg: u32,
+	bg: u32,
+}
+
+impl Cell {
+	const fn blank() -> Self {
+		Self { glyph: 0, fg: DEFAULT_FG, bg: DEFAULT_BG }
+	}
+}
+
+/// A ring buffer of rows evicted from the live grid by `scroll_one_line`, so `scroll_up` has
+/// something to bring back into view. Holds at most `SCROLLBACK_LINES` rows, oldest-first;
+/// pushing past capacity overwrites the oldest surviving row. Rows may vary in width across a
+/// resize, so each is stored as its own `Vec`.
+struct Scrollback {
+	lines: Vec<Vec<Cell>>,
+	/// Slot the next pushed row will overwrite. Only meaningful once `lines` has grown to
+	/// `SCROLLBACK_LINES`; before that, `push` is still growing `lines` instead.
+	next: usize,
+}
+
+impl Scrollback {
+	const fn new() -> Self {
+		Self { lines: Vec::new(), next: 0 }
+	}
+
+	/// Stores `row`, evicting the oldest row once `SCROLLBACK_LINES` is exceeded
+	fn push(&mut self, row: Vec<Cell>) {
+		if self.lines.len() < SCROLLBACK_LINES {
+			self.lines.push(row);
+		} else {
+			self.lines[self.next] = row;
+			self.next = (self.next + 1) % SCROLLBACK_LINES;
+		}
+	}
+
+	/// How many rows are currently stored
+	fn count(&self) -> usize {
+		self.lines.len()
+	}
+
+	/// Returns the `i`-th oldest surviving row (`i` == 0 is the oldest), padded or truncated to
+	/// `cols` cells in case it was captured at a different width by an earlier `resize`. `i` must
+	/// be `< count()`.
+	fn nth(&self, i: usize, cols: usize) -> Vec<Cell> {
+		let oldest_slot = if self.lines.len() < SCROLLBACK_LINES { 0 } else { self.next };
+		let row = &self.lines[(oldest_slot + i) % self.lines.len()];
+		let mut out = vec![Cell::blank(); cols];
+		let copy_len = row.len().min(cols);
+		out[..copy_len].copy_from_slice(&row[..copy_len]);
+		out
+	}
+}
 
 struct TextTerminal {
+	/// Grid dimensions, in cells - computed from the framebuffer's pixel size by `init`, and kept
+	/// up to date by `resize`
+	cols: usize,
+	rows: usize,
 	cursor_row: usize,
 	cursor_col: usize,
-	text: [u8; TERMINAL_COLS * TERMINAL_ROWS],
+	text: Vec<Cell>,
+	escape_state: EscapeState,
+	/// The colors applied to every `Cell` printed from now on; updated in place by SGR sequences
+	active_fg: u32,
+	active_bg: u32,
+	/// Rows evicted from `text` by scrolling, available to be brought back into view
+	scrollback: Scrollback,
+	/// How many lines back from the bottom `redraw` is currently showing. 0 means the live grid is
+	/// fully in view, as if there were no scrollback at all.
+	view_offset: usize,
+	/// Which cells of the current view need to be rasterized on the next `redraw()`. Indexed the
+	/// same way as `text` while `view_offset == 0` (the common case); whenever the composited view
+	/// changes wholesale (scrolling, a full clear, a resize) every cell is marked dirty instead of
+	/// tracking the change precisely.
+	dirty: Vec<bool>,
+	/// Where the cursor was the last time `redraw()` ran, so the cell it used to highlight can be
+	/// repainted (without the highlight) once it moves away
+	prev_cursor_row: usize,
+	prev_cursor_col: usize,
+	/// `wrapped[row]` is `true` if `row` ran out of columns and auto-advanced onto the next row,
+	/// and `false` if it ended at an explicit `\n` (or hasn't been written to at all). Backspace at
+	/// column 0 and `resize`'s reflow both need to tell these two cases apart, and unlike scanning
+	/// for the last non-blank column (which can't tell a row that's full of real content from one
+	/// that wrapped), this is set precisely at the point the wrap or newline happens.
+	wrapped: Vec<bool>,
+	/// The cursor's visual style, set by `set_cursor_style`
+	cursor_style: CursorStyle,
+	/// Flipped on every `toggle_cursor_blink`; the cursor is only actually drawn while this is
+	/// `true`, so a `Hidden`-style cursor aside, it blinks at `CURSOR_BLINK_INTERVAL`
+	cursor_blink_on: bool,
 }
 
 impl TextTerminal {
-	const fn new() -> Self {
+	fn new(cols: usize, rows: usize) -> Self {
 		Self {
+			cols,
+			rows,
 			cursor_row: 0,
 			cursor_col: 0,
-			text: [0u8; TERMINAL_COLS * TERMINAL_ROWS],
+			text: vec![Cell::blank(); cols * rows],
+			escape_state: EscapeState::Normal,
+			active_fg: DEFAULT_FG,
+			active_bg: DEFAULT_BG,
+			scrollback: Scrollback::new(),
+			view_offset: 0,
+			dirty: vec![true; cols * rows],
+			prev_cursor_row: 0,
+			prev_cursor_col: 0,
+			wrapped: vec![false; rows],
+			cursor_style: CursorStyle::Block,
+			cursor_blink_on: true,
 		}
 	}
 
-	fn char_at(&mut self, col: usize, row: usize) -> &mut u8 {
-		&mut self.text[(row * TERMINAL_COLS) + col]
+	/// Marks the cell at (`col`, `row`) of the current view as needing to be rasterized again
+	fn mark_dirty(&mut self, col: usize, row: usize) {
+		self.dirty[(row * self.cols) + col] = true;
+	}
+
+	/// Marks every cell of the current view as needing to be rasterized again, for changes (a
+	/// scroll, a full clear, a resize) too wholesale to track cell-by-cell
+	fn mark_all_dirty(&mut self) {
+		self.dirty.fill(true);
+	}
+
+	/// Copies live grid row `row` out of `text`
+	fn live_row(&self, row: usize) -> Vec<Cell> {
+		let start = row * self.cols;
+		self.text[start..start + self.cols].to_vec()
+	}
+
+	fn char_at(&mut self, col: usize, row: usize) -> &mut Cell {
+		&mut self.text[(row * self.cols) + col]
 	}
 
 	/// Prints one `character` to the screen at the cursor, and then advances the cursor.
 	/// Also handles new lines.
+	///
+	/// `character` is first fed through the ANSI/VT100 CSI escape-sequence state machine: bytes
+	/// that are part of an (in-progress or just-completed) escape sequence are consumed here and
+	/// never reach the grid.
 	fn print_char(&mut self, character: u8) {
-		if character == b'\n' {	
-			if self.cursor_row == TERMINAL_ROWS - 1 {
+		if self.consume_escape_byte(character) {
+			return;
+		}
+
+		if character == b'\n' {
+			// An explicit newline always ends the current row outright, never a wrap
+			self.wrapped[self.cursor_row] = false;
+			if self.cursor_row == self.rows - 1 {
 				// If we get a new line at the last row we need to scroll the screen
 				self.scroll_one_line();
 				// Actually set the cursor offset to the start of this row
@@ -52,43 +253,55 @@ impl TextTerminal {
 			self.cursor_col = 0;
 		} else if character == 8 {
 			// If this is a backspace character, we clear the last character by setting it to zero
-			
+
 			// If we are not at the start of the screen, we move the cursor back
 			if self.cursor_col != 0 || self.cursor_row != 0 {
 				if self.cursor_col > 0 {
 					self.cursor_col -= 1;
-					*self.char_at(self.cursor_col, self.cursor_row) = 0;
+					*self.char_at(self.cursor_col, self.cursor_row) = Cell::blank();
+					self.mark_dirty(self.cursor_col, self.cursor_row);
 				} else {
 					self.cursor_row -= 1;
-					// Find the last character in the previous line
-					let mut last_char_col = 0;
-					for i in (1..TERMINAL_COLS).rev() {
-						if *self.char_at(i, self.cursor_row) != 0 {
-							last_char_col = i;
-							break;
-						}
-					}
-					// We only remove a character if the line extended all the way to the end,
-					// otherwise we treat the backspace as if it removed the 'newline'
-					if last_char_col == TERMINAL_COLS - 1 {
-						self.cursor_col = last_char_col;
-						*self.char_at(self.cursor_col, self.cursor_row) = 0;
+					if self.wrapped[self.cursor_row] {
+						// The previous row auto-wrapped into this one, so backspace deletes its
+						// last glyph and un-wraps it
+						self.cursor_col = self.cols - 1;
+						*self.char_at(self.cursor_col, self.cursor_row) = Cell::blank();
+						self.mark_dirty(self.cursor_col, self.cursor_row);
+						self.wrapped[self.cursor_row] = false;
 					} else {
+						// The previous row ended with an explicit newline; backspace just
+						// collapses onto it, landing right after its last character
+						let mut last_char_col = 0;
+						for i in (1..self.cols).rev() {
+							if self.char_at(i, self.cursor_row).glyph != 0 {
+								last_char_col = i;
+								break;
+							}
+						}
 						self.cursor_col = last_char_col + 1;
 					}
 				}
 			}
 		} else {
-			*self.char_at(self.cursor_col, self.cursor_row) = character;
+			*self.char_at(self.cursor_col, self.cursor_row) =
+				Cell { glyph: character, fg: self.active_fg, bg: self.active_bg };
+			self.mark_dirty(self.cursor_col, self.cursor_row);
+
+			// Filling the last column means this row auto-advances onto the next one, i.e. wraps,
+			// rather than ending at an explicit newline
+			if self.cursor_col == self.cols - 1 {
+				self.wrapped[self.cursor_row] = true;
+			}
 
 			// If this was the last character of the screen we need to scroll
-			if self.cursor_row == TERMINAL_ROWS - 1 && self.cursor_col == TERMINAL_COLS - 1 {
+			if self.cursor_row == self.rows - 1 && self.cursor_col == self.cols - 1 {
 				self.scroll_one_line();
 				// Set the cursor offset to the start of the last row
 				self.cursor_col = 0;
 			} else {
 				// Advance the cursor
-				if self.cursor_col == TERMINAL_COLS - 1 {
+				if self.cursor_col == self.cols - 1 {
 					self.cursor_col = 0;
 					self.cursor_row += 1;
 				} else {
@@ -98,38 +311,347 @@ impl TextTerminal {
 		}
 	}
 
+	/// Feeds `character` through the CSI escape-sequence state machine. Returns `true` if the
+	/// byte was consumed as part of an escape sequence (and so must not be printed as a glyph),
+	/// or `false` if it's a normal character that should fall through to `print_char`.
+	fn consume_escape_byte(&mut self, character: u8) -> bool {
+		match &mut self.escape_state {
+			EscapeState::Normal => {
+				if character == 0x1B {
+					self.escape_state = EscapeState::SawEscape;
+					return true;
+				}
+				false
+			},
+			EscapeState::SawEscape => {
+				if character == b'[' {
+					self.escape_state = EscapeState::Csi { params: [0; MAX_CSI_PARAMS], count: 0 };
+					true
+				} else {
+					// Not a CSI sequence after all; the lone ESC is swallowed (it has no glyph of
+					// its own anyway) but this byte falls through and prints normally
+					self.escape_state = EscapeState::Normal;
+					false
+				}
+			},
+			EscapeState::Csi { params, count } => {
+				match character {
+					b'0'..=b'9' => {
+						if *count < MAX_CSI_PARAMS {
+							let digit = (character - b'0') as u16;
+							params[*count] = params[*count].saturating_mul(10).saturating_add(digit);
+						}
+					},
+					b';' => {
+						if *count < MAX_CSI_PARAMS {
+							*count += 1;
+						}
+					},
+					b'H' | b'J' | b'K' | b'm' => {
+						let params = *params;
+						let param_count = (*count + 1).min(MAX_CSI_PARAMS);
+						self.escape_state = EscapeState::Normal;
+						self.execute_csi(character, &params[..param_count]);
+						return true;
+					},
+					_ => {
+						// Not a sequence we recognize; abandon it rather than getting stuck
+						// forever, and silently swallow the byte that broke it
+						self.escape_state = EscapeState::Normal;
+					},
+				}
+				true
+			},
+		}
+	}
+
+	/// Executes a completed CSI escape sequence: `final_byte` is one of `H`/`J`/`K`/`m`, and
+	/// `params` holds the (defaulted-to-0 where omitted) numeric parameters parsed between `ESC[`
+	/// and it
+	fn execute_csi(&mut self, final_byte: u8, params: &[u16]) {
+		match final_byte {
+			b'H' => {
+				let row = *params.first().unwrap_or(&0);
+				let col = params.get(1).copied().unwrap_or(0);
+				// Row/column are 1-indexed; a 0 (omitted) parameter also means "the first one"
+				self.cursor_row = (row.max(1) as usize - 1).min(self.rows - 1);
+				self.cursor_col = (col.max(1) as usize - 1).min(self.cols - 1);
+			},
+			b'J' => self.clear(),
+			b'K' => self.clear_to_end_of_line(),
+			b'm' => self.apply_sgr(params),
+			_ => unreachable!(),
+		}
+	}
+
+	/// Applies SGR (Select Graphic Rendition) parameters to `active_fg`/`active_bg`: `0` resets
+	/// to the default white-on-black, `30`-`37`/`40`-`47` select the 8 ANSI colors, and
+	/// `38;2;r;g;b`/`48;2;r;g;b` set a truecolor foreground/background
+	fn apply_sgr(&mut self, params: &[u16]) {
+		let mut i = 0;
+		while i < params.len() {
+			match params[i] {
+				0 => {
+					self.active_fg = DEFAULT_FG;
+					self.active_bg = DEFAULT_BG;
+				},
+				30..=37 => self.active_fg = ANSI_COLORS[(params[i] - 30) as usize],
+				40..=47 => self.active_bg = ANSI_COLORS[(params[i] - 40) as usize],
+				38 | 48 if params.get(i + 1) == Some(&2) => {
+					let r = params.get(i + 2).copied().unwrap_or(0).min(255);
+					let g = params.get(i + 3).copied().unwrap_or(0).min(255);
+					let b = params.get(i + 4).copied().unwrap_or(0).min(255);
+					let color = ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
+					if params[i] == 38 {
+						self.active_fg = color;
+					} else {
+						self.active_bg = color;
+					}
+					i += 4;
+				},
+				_ => {},
+			}
+			i += 1;
+		}
+	}
+
+	/// Clears the whole screen, resetting every cell to blank
 	fn clear(&mut self) {
-		self.text.fill(0);
+		self.text.fill(Cell::blank());
+		self.wrapped.fill(false);
+		self.mark_all_dirty();
+	}
+
+	/// Clears from the cursor to the end of its row, without moving the cursor
+	fn clear_to_end_of_line(&mut self) {
+		for col in self.cursor_col..self.cols {
+			*self.char_at(col, self.cursor_row) = Cell::blank();
+			self.mark_dirty(col, self.cursor_row);
+		}
 	}
 
 	/// Scrolls the screen one line by memmoving the rows up one row, and clearing the last row
 	fn scroll_one_line(&mut self) {
+		// The row about to be scrolled off the top is about to be overwritten; keep it around in
+		// the scrollback history before it's gone
+		self.scrollback.push(self.live_row(0));
+		// Every row shifts up, so every cell's content changes
+		self.mark_all_dirty();
+
 		// We get a reference to the rows following the first row, this is the source of the copy
-		let second_row_onward = &self.text[TERMINAL_COLS..];
+		let second_row_onward = &self.text[self.cols..];
 
-		// Calculate how many u8s we need to copy for the entire screen except for one row
-		let num_elements = TERMINAL_COLS * (TERMINAL_ROWS - 1);
+		// Calculate how many cells we need to copy for the entire screen except for one row
+		let num_elements = self.cols * (self.rows - 1);
 
 		unsafe {
 			core::ptr::copy(second_row_onward.as_ptr(), self.text.as_mut_ptr(), num_elements);
 		}
 
 		// Clear the last row
-		self.text[num_elements..].fill(0);
+		self.text[num_elements..].fill(Cell::blank());
+
+		// The wrapped flags shift up right along with their rows, and the new last row starts out
+		// unwrapped until something fills it
+		self.wrapped.copy_within(1.., 0);
+		self.wrapped[self.rows - 1] = false;
+	}
+
+	/// Reflows the grid to `new_cols`x`new_rows`, the way a real terminal emulator does on
+	/// resize: the rows making up each logical line (a run joined by soft-wrap, per `wrapped`)
+	/// are concatenated and then re-split at the new width, so a line that no longer fits one row
+	/// spills onto another, and vice-versa. The cursor's logical position (which logical line, and
+	/// how far into it) is preserved across the reflow.
+	fn resize(&mut self, new_cols: usize, new_rows: usize) {
+		if new_cols == self.cols && new_rows == self.rows {
+			return;
+		}
+
+		// Rebuild the logical lines making up the current grid, remembering which one the cursor
+		// falls in and how far into it
+		let mut logical_lines: Vec<Vec<Cell>> = Vec::new();
+		let mut current_line: Vec<Cell> = Vec::new();
+		let mut cursor_logical_line = 0;
+		let mut cursor_offset_in_line = 0;
+		for row in 0..self.rows {
+			if row == self.cursor_row {
+				cursor_logical_line = logical_lines.len();
+				cursor_offset_in_line = current_line.len() + self.cursor_col;
+			}
+			current_line.extend_from_slice(&self.text[row * self.cols..(row + 1) * self.cols]);
+			if !self.wrapped[row] {
+				logical_lines.push(core::mem::take(&mut current_line));
+			}
+		}
+		// A trailing row that wrapped with nothing after it still ends a logical line
+		if !current_line.is_empty() {
+			logical_lines.push(current_line);
+		}
+		if logical_lines.is_empty() {
+			logical_lines.push(Vec::new());
+		}
+
+		// Re-wrap every logical line into new_cols-wide rows, tracking where the cursor lands. Every
+		// chunk but a logical line's last wraps into the one after it.
+		let mut new_text_rows: Vec<Vec<Cell>> = Vec::new();
+		let mut new_wrapped: Vec<bool> = Vec::new();
+		let mut new_cursor_row = 0;
+		let mut new_cursor_col = 0;
+		for (line_idx, line) in logical_lines.iter().enumerate() {
+			let chunk_start_row = new_text_rows.len();
+			let mut offset = 0;
+			loop {
+				let end = (offset + new_cols).min(line.len());
+				let mut chunk = line[offset..end].to_vec();
+				chunk.resize(new_cols, Cell::blank());
+				new_text_rows.push(chunk);
+				offset = end;
+				if offset >= line.len() {
+					new_wrapped.push(false);
+					break;
+				}
+				new_wrapped.push(true);
+			}
+			if line_idx == cursor_logical_line {
+				new_cursor_row = chunk_start_row + (cursor_offset_in_line / new_cols);
+				new_cursor_col = cursor_offset_in_line % new_cols;
+			}
+		}
+
+		// If reflowing produced more rows than now fit on screen, the oldest become scrollback
+		// history instead of being discarded
+		let overflow = new_text_rows.len().saturating_sub(new_rows);
+		for row in new_text_rows.drain(..overflow) {
+			self.scrollback.push(row);
+		}
+		new_wrapped.drain(..overflow);
+		new_cursor_row = new_cursor_row.saturating_sub(overflow);
+
+		// Pad with blank rows if there's now more room than content
+		while new_text_rows.len() < new_rows {
+			new_text_rows.push(vec![Cell::blank(); new_cols]);
+			new_wrapped.push(false);
+		}
+
+		self.cols = new_cols;
+		self.rows = new_rows;
+		self.text = new_text_rows.into_iter().flatten().collect();
+		self.wrapped = new_wrapped;
+		self.cursor_row = new_cursor_row.min(new_rows - 1);
+		self.cursor_col = new_cursor_col.min(new_cols - 1);
+		self.prev_cursor_row = self.cursor_row;
+		self.prev_cursor_col = self.cursor_col;
+		self.view_offset = self.view_offset.min(self.scrollback.count());
+		self.dirty = vec![true; new_cols * new_rows];
 	}
 }
 
-static TEXT_TERMINAL: ExclusiveCell<TextTerminal> = ExclusiveCell::new(TextTerminal::new());
+static TEXT_TERMINAL: ExclusiveCell<Option<TextTerminal>> = ExclusiveCell::new(None);
+
+/// The queue backing `print`: the only thing producers ever touch, so they never need to contend
+/// for `TEXT_TERMINAL`/`FRAME_BUFFER` themselves - `pump_print_queue` is this queue's single consumer
+static PRINT_QUEUE: ProducerConsumer<u8, PRINT_QUEUE_CAPACITY> = ProducerConsumer::new();
+
+/// Blends `fg` over `bg` by `gray` (0-255, a font pixel's grayscale intensity), one color channel
+/// at a time. Both colors are 0x00RRGGBB.
+fn blend_pixel(bg: u32, fg: u32, gray: u32) -> u32 {
+	let mut result = 0u32;
+	for shift in [16, 8, 0] {
+		let bg_channel = ((bg >> shift) & 0xFF) as i32;
+		let fg_channel = ((fg >> shift) & 0xFF) as i32;
+		let blended = bg_channel + (fg_channel - bg_channel) * gray as i32 / 255;
+		result |= (blended as u32) << shift;
+	}
+	result
+}
+
+/// Sizes the grid to fit a `width`x`height` (in pixels) framebuffer, at `FONT_WIDTH`x`FONT_HEIGHT`
+/// per cell. Must be called once before any other function in this module.
+pub fn init(width: usize, height: usize) {
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		assert!(terminal.is_none());
+		*terminal = Some(TextTerminal::new(width / FONT_WIDTH, height / FONT_HEIGHT));
+	}
+	crate::interrupts::pit_8254::after(CURSOR_BLINK_INTERVAL, toggle_cursor_blink);
+	crate::interrupts::pit_8254::after(PRINT_PUMP_INTERVAL, pump_print_queue);
+}
+
+/// Sets the cursor's visual style, taking effect on the next `redraw()`
+pub fn set_cursor_style(style: CursorStyle) {
+	let mut terminal = TEXT_TERMINAL.acquire();
+	let terminal = terminal.as_mut().unwrap();
+	terminal.cursor_style = style;
+	terminal.mark_dirty(terminal.cursor_col, terminal.cursor_row);
+}
+
+/// Flips the cursor's blink state and reschedules itself through `pit_8254::after`, the same
+/// self-rescheduling chain `keyboard::repeat_tick` uses for typematic repeat
+fn toggle_cursor_blink() {
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		let terminal = terminal.as_mut().unwrap();
+		terminal.cursor_blink_on = !terminal.cursor_blink_on;
+		terminal.mark_dirty(terminal.cursor_col, terminal.cursor_row);
+	}
+	redraw();
+	crate::interrupts::pit_8254::after(CURSOR_BLINK_INTERVAL, toggle_cursor_blink);
+}
+
+/// Reflows the grid to fit a new `width`x`height` (in pixels) framebuffer - see
+/// `TextTerminal::resize` for how existing content is preserved
+pub fn resize(width: usize, height: usize) {
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		terminal.as_mut().unwrap().resize(width / FONT_WIDTH, height / FONT_HEIGHT);
+	}
+	redraw();
+}
 
 pub fn redraw() {
-	let terminal = TEXT_TERMINAL.acquire();
+	let mut terminal = TEXT_TERMINAL.acquire();
+	let terminal = terminal.as_mut().unwrap();
+
+	// The cursor highlight isn't part of any `Cell`'s content, so if the cursor moved since the
+	// last frame both the cell it left and the cell it entered need repainting even though
+	// neither cell's content actually changed
+	if terminal.cursor_row != terminal.prev_cursor_row || terminal.cursor_col != terminal.prev_cursor_col {
+		terminal.mark_dirty(terminal.prev_cursor_col, terminal.prev_cursor_row);
+		terminal.mark_dirty(terminal.cursor_col, terminal.cursor_row);
+		terminal.prev_cursor_row = terminal.cursor_row;
+		terminal.prev_cursor_col = terminal.cursor_col;
+	}
 
 	let mut fb = crate::graphics_screen::FRAME_BUFFER.acquire();
 	let frame_buffer = fb.as_mut().unwrap();
 
-	for y in 0..TERMINAL_ROWS {
-		for x in 0..TERMINAL_COLS {
-			let mut chr = terminal.text[(y * TERMINAL_COLS) + x];
+	// Clamp in case the history available has shrunk since `view_offset` was last set (it can't
+	// actually grow smaller once written, but this keeps the arithmetic below honest either way)
+	let offset = terminal.view_offset.min(terminal.scrollback.count());
+
+	// The cursor lives in the live grid, so it's only visible once its row has scrolled into the
+	// window - this is the view row it would land on were that the case
+	let cursor_view_row = offset + terminal.cursor_row;
+
+	for y in 0..terminal.rows {
+		// Row `y` of the view shows scrollback history while it's still below the live grid
+		// (`y < offset`), and the live grid once the window has scrolled past all of history
+		let abs_row = terminal.scrollback.count() - offset + y;
+		let row_cells = if abs_row < terminal.scrollback.count() {
+			terminal.scrollback.nth(abs_row, terminal.cols)
+		} else {
+			terminal.live_row(abs_row - terminal.scrollback.count())
+		};
+
+		for x in 0..terminal.cols {
+			let dirty_idx = (y * terminal.cols) + x;
+			if !terminal.dirty[dirty_idx] {
+				continue;
+			}
+			terminal.dirty[dirty_idx] = false;
+
+			let cell = row_cells[x];
+			let mut chr = cell.glyph;
 			if chr == 0 {
 				chr = 32;
 			}
@@ -144,39 +666,134 @@ pub fn redraw() {
 					let frame_y = (y * FONT_HEIGHT) + row;
 					let frame_idx = (frame_y * frame_buffer.width) + frame_x;
 
-					let mut gray_val = FONT_DATA[font_off] as u32;
+					let gray_val = FONT_DATA[font_off] as u32;
 
-					if terminal.cursor_col == x && terminal.cursor_row == y
-						&& (row == 0 || row == FONT_HEIGHT - 1 || col == 0 || col == FONT_WIDTH - 1) {
-						gray_val = 255;
-					}
+					let at_cursor = terminal.cursor_col == x && y == cursor_view_row
+						&& terminal.cursor_blink_on;
+					let cursor_lit = at_cursor && match terminal.cursor_style {
+						CursorStyle::Hidden => false,
+						CursorStyle::Block =>
+							row == 0 || row == FONT_HEIGHT - 1 || col == 0 || col == FONT_WIDTH - 1,
+						CursorStyle::Underline => row == FONT_HEIGHT - 1,
+						CursorStyle::Bar => col == 0,
+					};
+					let pixel = if cursor_lit {
+						0x00FFFFFF
+					} else {
+						blend_pixel(cell.bg, cell.fg, gray_val)
+					};
 
-					let color_splat = gray_val | (gray_val << 8) | (gray_val << 16) | (gray_val << 24);
-					frame_buffer.get_buffer()[frame_idx] = color_splat;
+					frame_buffer.get_buffer()[frame_idx] = pixel;
 				}
 			}
 		}
 	}
+
+	// `present()` waits for vblank and blits the whole back buffer, so it belongs once per
+	// completed frame here, not once per row above
+	frame_buffer.present();
 }
 
-/// Prints `message` on screen at the cursor
+/// Queues `message` to be printed at the cursor. Never touches the terminal or framebuffer itself -
+/// `pump_print_queue` is what actually prints and redraws, asynchronously, once it drains
+/// `PRINT_QUEUE`.
 pub fn print(message: &str) {
+	for &ch in message.as_bytes() {
+		PRINT_QUEUE.produce_blocking(ch);
+	}
+}
+
+/// The single consumer of `PRINT_QUEUE`, run periodically off the PIT (kicked off by `init`, see
+/// the module doc comment for why a timer chain stands in for a dedicated consumer thread here):
+/// drains everything queued up so far (coalescing a burst of `print` calls into one `redraw()`
+/// instead of one per byte), prints it, redraws if anything was actually printed, and reschedules
+/// itself for `PRINT_PUMP_INTERVAL` later.
+fn pump_print_queue() {
+	let mut printed = false;
 	{
 		let mut terminal = TEXT_TERMINAL.acquire();
-		for &ch in message.as_bytes() {
+		let terminal = terminal.as_mut().unwrap();
+		while let Some(ch) = PRINT_QUEUE.consume() {
+			// Snap the view back to the bottom first, so newly printed output is never hidden
+			// behind whatever scrollback the user was looking at
+			if terminal.view_offset != 0 {
+				terminal.view_offset = 0;
+				terminal.mark_all_dirty();
+			}
 			terminal.print_char(ch);
+			printed = true;
 		}
 	}
+	if printed {
+		redraw();
+	}
+	crate::interrupts::pit_8254::after(PRINT_PUMP_INTERVAL, pump_print_queue);
+}
+
+/// Marks every cell as needing to be rasterized again and redraws - e.g. any time the
+/// framebuffer's prior contents can't be trusted
+pub fn force_redraw() {
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		terminal.as_mut().unwrap().mark_all_dirty();
+	}
 	redraw();
 }
 
+/// Scrolls the view `lines` further back into scrollback history, clamped to however much history
+/// is actually available. Does not touch the live grid or cursor.
+pub fn scroll_up(lines: usize) {
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		let terminal = terminal.as_mut().unwrap();
+		let new_offset = (terminal.view_offset + lines).min(terminal.scrollback.count());
+		if new_offset != terminal.view_offset {
+			terminal.view_offset = new_offset;
+			terminal.mark_all_dirty();
+		}
+	}
+	redraw();
+}
+
+/// Scrolls the view `lines` back towards the bottom, clamped at offset 0 (the live grid)
+pub fn scroll_down(lines: usize) {
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		let terminal = terminal.as_mut().unwrap();
+		let new_offset = terminal.view_offset.saturating_sub(lines);
+		if new_offset != terminal.view_offset {
+			terminal.view_offset = new_offset;
+			terminal.mark_all_dirty();
+		}
+	}
+	redraw();
+}
+
+/// Snaps the view straight back to the bottom (offset 0), as if nothing had ever been scrolled
+pub fn scroll_to_bottom() {
+	let mut changed = false;
+	{
+		let mut terminal = TEXT_TERMINAL.acquire();
+		let terminal = terminal.as_mut().unwrap();
+		if terminal.view_offset != 0 {
+			terminal.view_offset = 0;
+			terminal.mark_all_dirty();
+			changed = true;
+		}
+	}
+	if changed {
+		redraw();
+	}
+}
+
 pub fn debug_offset_cursor(mut off: isize) {
 	off /= 10;
 	{
 		let mut terminal = TEXT_TERMINAL.acquire();
-		terminal.cursor_col = (terminal.cursor_col as isize + off).min(TERMINAL_COLS as isize - 1).max(0) as usize;
+		let terminal = terminal.as_mut().unwrap();
+		terminal.cursor_col = (terminal.cursor_col as isize + off).min(terminal.cols as isize - 1).max(0) as usize;
 	}
 	if off != 0 {
 		redraw();
 	}
-}
\ No newline at end of file
+}
@@ -1,7 +1,9 @@
 use core::sync::atomic::{AtomicU32, Ordering};
 
-const CMOS_ADDRESS_PORT: u16 = 0x70;
-const CMOS_DATA_PORT: u16 = 0x71;
+use cpu::Port;
+
+const CMOS_ADDRESS_PORT: Port<u8> = Port::new(0x70);
+const CMOS_DATA_PORT: Port<u8> = Port::new(0x71);
 const CMOS_RTC_SECONDS_REGISTER:  u8 = 0x0;
 const CMOS_RTC_MINUTES_REGISTER:  u8 = 0x2;
 const CMOS_RTC_HOURS_REGISTER:    u8 = 0x4;
@@ -10,11 +12,44 @@ const CMOS_RTC_MONTH_REGISTER:    u8 = 0x8;
 const CMOS_RTC_YEAR_REGISTER:     u8 = 0x9;
 const CMOS_RTC_STATUS_A_REGISTER: u8 = 0xA;
 const CMOS_RTC_STATUS_B_REGISTER: u8 = 0xB;
+const CMOS_RTC_STATUS_C_REGISTER: u8 = 0xC;
+
+/// Status register B bit that makes the RTC raise IRQ8 once a second, right after it finishes an
+/// update of the time registers
+const STATUS_B_UPDATE_ENDED_INTERRUPT_ENABLE: u8 = 1 << 4;
+/// Status register C bit that is set when the interrupt that just fired was the update-ended one
+/// (as opposed to the alarm or periodic interrupts, which this module doesn't enable)
+const STATUS_C_UPDATE_ENDED_FLAG: u8 = 1 << 4;
+
+cpu::typed_register! {
+	/// The CMOS RTC's status register A. Bit 7 (`updating`) is set while the RTC is in the middle
+	/// of updating its time registers, which is the only bit `time::init` cares about.
+	struct CmosStatusA: u8 {
+		updating: 1 << 7,
+	}
+}
+
+cpu::typed_register! {
+	/// The CMOS RTC's status register B, which describes the format the time registers are stored
+	/// in
+	struct CmosStatusB: u8 {
+		hour_format_24: 1 << 1,
+		binary_mode: 1 << 2,
+	}
+}
 
 /// The unix timestamp on system boot
 pub static BOOT_UNIX_TIME: AtomicU32 = AtomicU32::new(0);
 
-/// Initializes the `BOOT_UNIX_TIME` global using the RTC on the CMOS
+/// Initializes the `BOOT_UNIX_TIME` global using the RTC on the CMOS, and enables the RTC's
+/// update-ended interrupt (IRQ8) to keep the PIT-derived wall clock from drifting afterwards.
+///
+/// This runs before `interrupts::init`, so the IDT isn't loaded and the PIC hasn't been remapped
+/// off its real-mode vectors yet. That's fine: the CPU's interrupt flag stays clear (interrupts
+/// are globally disabled) until `interrupts::init` calls `cpu::sti` at the very end, so nothing can
+/// actually be delivered to a wrong vector in the meantime - an RTC edge would just sit pending at
+/// the PIC. Unmasking IRQ8 here only needs the IMR (OCW1), which existing hardware exposes
+/// independently of the ICW init sequence `pic_8259a::init` performs later.
 pub fn init() {
 	// TODO: Get century from ACPI century register
 
@@ -26,14 +61,14 @@ pub fn init() {
 	// is not updating, and read the time twice, and retry if the times do not match
 	loop {
 		// Wait until the RTC is not upating
-		while (read_cmos_reg(CMOS_RTC_STATUS_A_REGISTER) & (1 << 7)) != 0 {
+		while CmosStatusA::from(read_cmos_reg(CMOS_RTC_STATUS_A_REGISTER)).updating() {
 			core::hint::spin_loop();
 		}
 
 		let current_time = read_current_time();
 
 		// Make sure an update did not start
-		if (read_cmos_reg(CMOS_RTC_STATUS_A_REGISTER) & (1 << 7)) != 0 {
+		if CmosStatusA::from(read_cmos_reg(CMOS_RTC_STATUS_A_REGISTER)).updating() {
 			continue;
 		}
 
@@ -46,14 +81,47 @@ pub fn init() {
 			break;
 		}
 	}
+
+	// The PIT's own wall clock (see `pit_8254::handle_interrupt`) drifts over time because
+	// `REAL_FREQ_HZ` is a rounded frequency divisor. Enable the RTC's update-ended interrupt so we
+	// get a precise once-a-second edge to correct that drift against. The periodic interrupt (status
+	// B bit 6) could additionally give us a hardware-accurate sub-second tick, but nothing in the
+	// kernel needs finer than one-second wall-clock resolution yet, so it's left disabled.
+	let status_b = read_cmos_reg(CMOS_RTC_STATUS_B_REGISTER);
+	write_cmos_reg(CMOS_RTC_STATUS_B_REGISTER, status_b | STATUS_B_UPDATE_ENDED_INTERRUPT_ENABLE);
+
+	// Reading status C acknowledges (and re-arms) whichever RTC interrupt is currently pending, so
+	// we don't immediately get a spurious edge left over from before we enabled the interrupt
+	read_cmos_reg(CMOS_RTC_STATUS_C_REGISTER);
+
+	crate::interrupts::intr_register(crate::interrupts::pic_8259a::PIC_IRQ_OFFSET + 8, handle_irq);
+
+	// IRQ8 is on the slave PIC and masked off by default; unmask it without touching any other
+	// IRQ's mask bit
+	let mask = crate::interrupts::pic_8259a::get_interrupt_mask();
+	crate::interrupts::pic_8259a::set_interrupt_mask(mask & !(1 << 8));
+}
+
+/// Entry point invoked by the interrupt core for IRQ8 (the RTC)
+fn handle_irq(_regs: &mut crate::interrupts::PushADRegisterState) {
+	// Reading status C acknowledges whichever RTC interrupt just fired and re-arms it - without
+	// this the RTC never raises IRQ8 again after the first one
+	let status_c = read_cmos_reg(CMOS_RTC_STATUS_C_REGISTER);
+
+	// Only resync on the update-ended edge: the RTC's registers are guaranteed freshly-updated at
+	// that point (no double-read race like `init` has to guard against), and it's the only RTC
+	// interrupt this module enables anyway
+	if status_c & STATUS_C_UPDATE_ENDED_FLAG != 0 {
+		crate::interrupts::pit_8254::resync(read_current_time());
+	}
 }
 
 /// Reads the current unix timestamp from the RTC
 fn read_current_time() -> u32 {
 	// The flags in status define the format of the values the RTC provides
-	let status_b = read_cmos_reg(CMOS_RTC_STATUS_B_REGISTER);
-	let hour_format_24 = (status_b & (1 << 1)) != 0;
-	let binary_mode = (status_b & (1 << 2)) != 0;
+	let status_b = CmosStatusB::from(read_cmos_reg(CMOS_RTC_STATUS_B_REGISTER));
+	let hour_format_24 = status_b.hour_format_24();
+	let binary_mode = status_b.binary_mode();
 
 	let seconds = read_cmos_reg(CMOS_RTC_SECONDS_REGISTER);
 	let minutes = read_cmos_reg(CMOS_RTC_MINUTES_REGISTER);
@@ -157,8 +225,17 @@ fn read_cmos_reg(reg: u8) -> u8 {
 		// Accessing a CMOS register is done by writing the register address into the address port,
 		// and then reading the value from the data port. The OSDev wiki advises to have a small
 		// delay between the operations
-		cpu::out8(CMOS_ADDRESS_PORT, reg);
+		CMOS_ADDRESS_PORT.write(reg);
+		cpu::busy_loop(0x6c600);
+		CMOS_DATA_PORT.read()
+	}
+}
+
+/// Writes `value` into CMOS register `reg`. See `read_cmos_reg`.
+fn write_cmos_reg(reg: u8, value: u8) {
+	unsafe {
+		CMOS_ADDRESS_PORT.write(reg);
 		cpu::busy_loop(0x6c600);
-		cpu::in8(CMOS_DATA_PORT)
+		CMOS_DATA_PORT.write(value);
 	}
 }
\ No newline at end of file
@@ -3,9 +3,30 @@
 use exclusive_cell::ExclusiveCell;
 use crate::gdt::GDTEntry;
 
-/// Global that holds the single TSS we use for all tasks. Should only ever be accessed by the
-/// scheduler which is not multi-threaded.
-static TSS: ExclusiveCell<TaskStateSegment> = ExclusiveCell::new(TaskStateSegment::empty());
+/// Number of bytes needed for one bit per I/O port
+const IO_BITMAP_BYTES: usize = 65536 / 8;
+/// The I/O permission bitmap must be followed by one extra all-ones byte: the CPU's port access
+/// check for port N can read the bit for port N+7 when N isn't byte-aligned, so without this the
+/// check for the topmost ports would read past the bitmap (see Intel SDM Vol. 3 8.7, "Note" on the
+/// I/O permission bit map).
+const IO_BITMAP_SIZE: usize = IO_BITMAP_BYTES + 1;
+
+/// Global that holds the single TSS we use for all tasks, together with its I/O permission bitmap.
+/// Should only ever be accessed by the scheduler which is not multi-threaded.
+static TSS: ExclusiveCell<TssWithIoBitmap> = ExclusiveCell::new(TssWithIoBitmap::empty());
+
+/// The TSS for the double-fault handler task. Unlike `TSS`, this one is fully populated up-front
+/// (cr3/eip/esp/segment selectors) because we reach it via a hardware task switch (the CPU loads
+/// every field straight from this TSS), not via a regular call
+static DF_TSS: ExclusiveCell<TaskStateSegment> = ExclusiveCell::new(TaskStateSegment::empty());
+
+/// Size of the dedicated stack used while handling a double fault
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096;
+
+/// A stack reserved purely for the double-fault handler task, so a double fault caused by kernel
+/// stack exhaustion still has somewhere to run. Never touched by Rust code directly - the CPU
+/// switches `esp`/`ss` to point into it as part of the hardware task switch.
+static DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
 
 /// Represents an x86 32-bit TSS
 #[repr(C)]
@@ -98,22 +119,113 @@ impl TaskStateSegment {
     }
 }
 
+/// The main TSS, followed immediately by its I/O permission bitmap - kept in one `#[repr(C)]`
+/// struct so the two are always contiguous, as `io_map_base_addr` requires.
+#[repr(C)]
+struct TssWithIoBitmap {
+    tss: TaskStateSegment,
+    /// One bit per I/O port (port N is bit N%8 of byte N/8); a set bit denies ring-3 access to
+    /// that port, a clear bit allows it. `deny_io_port`/`allow_io_port` toggle individual ports;
+    /// `clear_io_permissions` resets every port back to denied. The mandatory trailing byte (see
+    /// `IO_BITMAP_SIZE`) is never toggled and stays 0xFF.
+    io_bitmap: [u8; IO_BITMAP_SIZE],
+}
+
+impl TssWithIoBitmap {
+    /// Constructs an empty TSS with every I/O port denied
+    const fn empty() -> Self {
+        Self { tss: TaskStateSegment::empty(), io_bitmap: [0xFF; IO_BITMAP_SIZE] }
+    }
+}
+
 /// Initializes the TSS and returns a GDT entry that refrences the TSS. Should only be called once
 pub unsafe fn init() -> GDTEntry {
 	// The TSS is only used for stack-switching during interrupt handling while in ring 3, so we
 	// only care about the ss0 and esp0 fields (ss:esp for ring 0).
 	let mut tss = TSS.acquire();
-    tss.ss0 = crate::gdt::KERNEL_DS_SELECTOR;
+    tss.tss.ss0 = crate::gdt::KERNEL_DS_SELECTOR;
 	// We initially set esp0 to an invalid value so that we hopefully fault if for some reason the
 	// stack pointer was not set before jumping to user land
-    tss.esp0 = 0xdeadbeef;
+    tss.tss.esp0 = 0xdeadbeef;
+
+    // The I/O permission bitmap immediately follows the TSS proper, so its offset from the TSS
+    // base is just the offset of `io_bitmap` within `TssWithIoBitmap`
+    let tss_base = &*tss as *const TssWithIoBitmap as usize;
+    let io_bitmap_base = tss.io_bitmap.as_ptr() as usize;
+    tss.tss.io_map_base_addr = (io_bitmap_base - tss_base) as u16;
 
-	// Calculating the GDT entry's limit field. The limit is (size_of - 1)
-	let tss_limit = core::mem::size_of::<crate::tss::TaskStateSegment>() as u32 - 1;
+	// Calculating the GDT entry's limit field. The limit is (size_of - 1), extended to cover the
+	// I/O permission bitmap appended after the TSS proper.
+	let tss_limit = core::mem::size_of::<TssWithIoBitmap>() as u32 - 1;
     GDTEntry::new(&*tss as *const _ as u32, tss_limit, 0b1001, 0b1000, 0b0000)
 }
 
 /// Sets the esp that will be used for the kernel when handling interrupts while in ring 3
 pub fn set_kernel_esp(esp: u32) {
-    TSS.acquire().esp0 = esp;
+    TSS.acquire().tss.esp0 = esp;
+}
+
+/// Grants a ring-3 task direct access to `port`, without requiring IOPL to be raised
+pub fn allow_io_port(port: u16) {
+    let mut tss = TSS.acquire();
+    let (byte, bit) = io_bitmap_location(port);
+    tss.io_bitmap[byte] &= !(1 << bit);
+}
+
+/// Revokes a ring-3 task's direct access to `port`, granted earlier via `allow_io_port`
+pub fn deny_io_port(port: u16) {
+    let mut tss = TSS.acquire();
+    let (byte, bit) = io_bitmap_location(port);
+    tss.io_bitmap[byte] |= 1 << bit;
+}
+
+/// Resets every I/O port back to denied, undoing any earlier `allow_io_port` calls
+pub fn clear_io_permissions() {
+    TSS.acquire().io_bitmap[..IO_BITMAP_BYTES].fill(0xFF);
+}
+
+/// The byte index and bit-within-that-byte that `port` occupies in the I/O permission bitmap
+fn io_bitmap_location(port: u16) -> (usize, u8) {
+    ((port / 8) as usize, (port % 8) as u8)
+}
+
+/// Initializes the double-fault handler's TSS and returns a GDT entry referencing it. `entry` is
+/// the function the CPU will jump to (via a task switch) whenever IDT entry 8's task gate fires.
+/// Should only be called once, after the main TSS/GDT entries have been set up.
+pub unsafe fn init_double_fault_tss(entry: extern "cdecl" fn() -> !) -> GDTEntry {
+    let mut df_tss = DF_TSS.acquire();
+
+    // We run the handler with interrupts masked and paging left as-is, on its own stack - this way
+    // it can run (and print diagnostics) even if the regular kernel stack has overflowed
+    df_tss.cr3 = cpu::get_cr3() as u32;
+    df_tss.eip = entry as u32;
+    df_tss.eflags = 0x2; // Bit 1 is always set, every other flag (including IF) stays cleared
+    df_tss.esp = DOUBLE_FAULT_STACK.as_ptr() as u32 + DOUBLE_FAULT_STACK_SIZE as u32;
+    df_tss.ss = crate::gdt::KERNEL_DS_SELECTOR;
+    df_tss.cs = crate::gdt::KERNEL_CS_SELECTOR;
+    df_tss.ds = crate::gdt::KERNEL_DS_SELECTOR;
+    df_tss.es = crate::gdt::KERNEL_DS_SELECTOR;
+    df_tss.fs = crate::gdt::KERNEL_DS_SELECTOR;
+    df_tss.gs = crate::gdt::KERNEL_DS_SELECTOR;
+
+    let tss_limit = core::mem::size_of::<TaskStateSegment>() as u32 - 1;
+    GDTEntry::new(&*df_tss as *const _ as u32, tss_limit, 0b1001, 0b1000, 0b0000)
+}
+
+/// Prints the register state the CPU saved into the main TSS when it task-switched away from
+/// whatever was running at the moment a double fault fired. Only meaningful to call from the
+/// double-fault handler task, immediately after the switch.
+pub(crate) fn print_main_tss_state() {
+    let guard = TSS.acquire();
+    let tss = &guard.tss;
+    serial::println!(
+        "Double Fault! Faulting task state: cr3={:#010x} eip={:#010x} eflags={:#010x}\n\
+         eax={:#010x} ebx={:#010x} ecx={:#010x} edx={:#010x}\n\
+         esp={:#010x} ebp={:#010x} esi={:#010x} edi={:#010x}\n\
+         cs={:#06x} ss={:#06x} ds={:#06x} es={:#06x} fs={:#06x} gs={:#06x}",
+        tss.cr3, tss.eip, tss.eflags,
+        tss.eax, tss.ebx, tss.ecx, tss.edx,
+        tss.esp, tss.ebp, tss.esi, tss.edi,
+        tss.cs, tss.ss, tss.ds, tss.es, tss.fs, tss.gs
+    );
 }
@@ -1,6 +1,8 @@
 use core::marker::PhantomData;
 
 use alloc::{string::String, vec::Vec, borrow::ToOwned};
+use boot_args::KERNEL_VADDR_SPLIT;
+use page_tables::VirtAddr;
 use syscall_interface::{SyscallString, SyscallArray};
 
 pub struct UserVaddr<'a, T>(u32, PhantomData<&'a T>);
@@ -69,12 +71,56 @@ impl<'a> UserVaddr<'a, SyscallArray<'a, SyscallString<'a>>> {
 	}
 }
 
-fn is_valid_for_reading(_vaddr: usize, _num_bytes: usize) -> bool {
-	//FIXME: Validity Checks
-	true
+fn is_valid_for_reading(vaddr: usize, num_bytes: usize) -> bool {
+	is_valid_range(vaddr, num_bytes, false)
+}
+
+fn is_valid_for_writing(vaddr: usize, num_bytes: usize) -> bool {
+	is_valid_range(vaddr, num_bytes, true)
 }
 
-fn is_valid_for_writing(_vaddr: usize, _num_bytes: usize) -> bool {
-	//FIXME: Validity Checks
+/// Checks that `[vaddr, vaddr+num_bytes)` is entirely user address space backed by present
+/// mappings, according to the currently active page tables - and, if `require_write`, that every
+/// page in the range is writable too. This is what keeps a malicious user pointer from making the
+/// kernel dereference arbitrary kernel addresses while servicing a syscall.
+fn is_valid_range(vaddr: usize, num_bytes: usize, require_write: bool) -> bool {
+	if num_bytes == 0 {
+		return vaddr < KERNEL_VADDR_SPLIT as usize;
+	}
+
+	// Reject ranges that wrap past the end of the address space
+	let last_byte = match vaddr.checked_add(num_bytes - 1) {
+		Some(last_byte) => last_byte,
+		None => return false,
+	};
+
+	// Reject anything that touches the shared kernel mapping
+	if last_byte >= KERNEL_VADDR_SPLIT as usize {
+		return false;
+	}
+
+	let mut pmem = crate::memory_manager::PHYS_MEM.lock();
+	let (phys_mem, page_dir) = match pmem.as_mut() {
+		Some(pmem) => pmem,
+		None => return false,
+	};
+
+	let first_page = (vaddr as u32) & !0xFFF;
+	let last_page = (last_byte as u32) & !0xFFF;
+
+	let mut page = first_page;
+	loop {
+		let permissions = page_dir.page_permissions(phys_mem, VirtAddr(page));
+		match permissions {
+			Some((user, write)) if user && (!require_write || write) => {},
+			_ => return false,
+		}
+
+		if page == last_page {
+			break;
+		}
+		page += 4096;
+	}
+
 	true
 }
\ No newline at end of file
@@ -1,9 +1,19 @@
 use lock_cell::LockCell;
 
+/// A device file that isn't backed by an ext2 inode, opened through a well-known path (e.g.
+/// `/dev/keyboard`) instead of being resolved against the filesystem
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceId {
+	/// `/dev/keyboard`, serving `SyscallKeyEvent` records out of `keyboard::KEYBOARD_EVENTS_QUEUE` -
+	/// see `syscall::syscall_read`
+	Keyboard,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum FileType {
 	File,
 	Directory,
+	Device(DeviceId),
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -12,12 +12,26 @@ pub const KERNEL_STACK_SIZE: u32 = 0x1000;
 /// The virtual address of the base of the kernel stack
 pub const KERNEL_STACK_BASE_VADDR: u32 = LAST_PAGE_TABLE_VADDR - KERNEL_STACK_SIZE;
 
+/// The virtual address at which the kernel/user address space split happens: every process's page
+/// directory shares the same mapping for entries at or above this address (the top 1GiB), while
+/// everything below is private, user-controlled address space
+pub const KERNEL_VADDR_SPLIT: u32 = 0xC0000000;
+
 /// The virtual address of the base of kernel virtual allocations
 pub const KERNEL_ALLOCATIONS_BASE_VADDR: u32 = 0xC4000000;
 
+/// The virtual address of the base of the region reserved for `ioremap`-style MMIO mappings.
+/// Placed directly after the kernel allocation arena.
+pub const IOREMAP_BASE_VADDR: u32 = KERNEL_ALLOCATIONS_BASE_VADDR + 0x200000;
+/// The size of the `ioremap` region
+pub const IOREMAP_REGION_SIZE: u32 = 0x4000000;
+
 /// The virtual address where the page table containing the last page is mapped
 pub const LAST_PAGE_TABLE_VADDR: u32 = 0xFFFFE000;
 
+/// The maximum number of bytes of `BootArgs::cmdline` that are meaningful
+pub const CMDLINE_MAX_LEN: usize = 256;
+
 /// A structure to hold data the bootloader wants to pass to the kernel
 #[derive(Clone, Copy)]
 #[repr(C)]
@@ -36,4 +50,18 @@ pub struct BootArgs {
     pub frame_buffer_width: u16,
     /// The height of the frame buffer
     pub frame_buffer_height: u16,
+
+    /// The physical address of the initramfs blob, if the bootloader found one. This memory is
+    /// deliberately never freed by the bootloader and so is excluded from `free_memory`, leaving it
+    /// valid until the kernel copies or maps it as it sees fit.
+    pub initramfs_paddr: Option<PhysAddr>,
+    /// The size in bytes of the initramfs blob. Meaningless if `initramfs_paddr` is `None`.
+    pub initramfs_size: u32,
+
+    /// The kernel command line, as raw bytes (not necessarily valid UTF-8), read from disk/network
+    /// alongside the kernel image. Only the first `cmdline_len` bytes are meaningful; the rest of
+    /// the array is zero-padded filler so `BootArgs` can stay `Copy`.
+    pub cmdline: [u8; CMDLINE_MAX_LEN],
+    /// The number of meaningful bytes at the start of `cmdline`
+    pub cmdline_len: u16,
 }
\ No newline at end of file
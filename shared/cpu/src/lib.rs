@@ -65,6 +65,42 @@ pub unsafe fn invlpg(addr: usize) {
     asm!("invlpg [{}]", in(reg) addr, options(preserves_flags, nostack));
 }
 
+/// Reads the 64-bit value of the model-specific register `msr`
+///
+/// ### Safety
+/// If the CPL is not zero, or `msr` does not exist on this CPU, this will cause a GPF
+#[inline]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high,
+        options(nomem, preserves_flags, nostack));
+    ((high as u64) << 32) | low as u64
+}
+
+/// Writes `value` to the model-specific register `msr`
+///
+/// ### Safety
+/// If the CPL is not zero, or `msr` does not exist on this CPU, this will cause a GPF
+#[inline]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+    asm!("wrmsr", in("ecx") msr, in("eax") low, in("edx") high,
+        options(nomem, preserves_flags, nostack));
+}
+
+/// Gets the value held in CR0
+///
+/// ### Safety
+/// If the CPL is not zero this will cause a GPF
+#[inline]
+pub unsafe fn get_cr0() -> usize {
+    let cr0: usize;
+    asm!("mov {}, cr0", out(reg) cr0, options(nomem, preserves_flags, nostack));
+    cr0
+}
+
 /// Gets the value held in CR3
 ///
 /// ### Safety
@@ -87,6 +123,38 @@ pub unsafe fn get_cr2() -> usize {
     cr2
 }
 
+/// Gets the value held in CR4
+///
+/// ### Safety
+/// If the CPL is not zero this will cause a GPF
+#[inline]
+pub unsafe fn get_cr4() -> usize {
+    let cr4: usize;
+    asm!("mov {}, cr4", out(reg) cr4, options(nomem, preserves_flags, nostack));
+    cr4
+}
+
+/// Sets the value of CR4
+///
+/// ### Safety
+/// If the CPL is not zero this will cause a GPF. The caller must not clear a feature bit (e.g.
+/// PAE, PSE) the processor is currently relying on
+#[inline]
+pub unsafe fn set_cr4(cr4: usize) {
+    asm!("mov cr4, {}", in(reg) cr4, options(nostack));
+}
+
+/// Sets the value of CR3, flushing the entire TLB (aside from global pages) in the process
+///
+/// ### Safety
+/// If the CPL is not zero this will cause a GPF. `cr3` must be the physical address of a valid page
+/// directory, and the caller must be prepared to keep executing after every non-global TLB entry is
+/// invalidated
+#[inline]
+pub unsafe fn set_cr3(cr3: u32) {
+    asm!("mov cr3, {:e}", in(reg) cr3, options(nostack));
+}
+
 /// Gets the value of the EFLAGS register
 #[inline]
 pub fn get_eflags() -> u32 {
@@ -289,4 +357,110 @@ pub unsafe fn ring0_context_switch(eip: u32, eflags: u32, regs: &PushADRegisterS
         ",
         in(reg) eflags, in(reg) eip, in(reg) cr3, in("eax") regs, options(noreturn)
     );
+}
+
+/// A width `in`/`out` can be performed in. Implemented for `u8` and `u16` so `Port<T>` can be
+/// monomorphized over either without every driver hand-picking `in8`/`in16`/`out8`/`out16` itself.
+pub trait PortWidth: Copy {
+    /// ### Safety
+    /// If the CPL is greater than the IOPL this will cause a GPF
+    unsafe fn port_in(addr: u16) -> Self;
+    /// ### Safety
+    /// If the CPL is greater than the IOPL this will cause a GPF
+    unsafe fn port_out(addr: u16, value: Self);
+}
+
+impl PortWidth for u8 {
+    unsafe fn port_in(addr: u16) -> u8 { in8(addr) }
+    unsafe fn port_out(addr: u16, value: u8) { out8(addr, value) }
+}
+
+impl PortWidth for u16 {
+    unsafe fn port_in(addr: u16) -> u16 { in16(addr) }
+    unsafe fn port_out(addr: u16, value: u16) { out16(addr, value) }
+}
+
+/// A single, typed IO port. Wraps the address so callers name it once and stop passing a bare
+/// `u16` (and the matching `in8`/`out8` width) to every access site by hand.
+#[derive(Clone, Copy, Debug)]
+pub struct Port<T: PortWidth>(u16, core::marker::PhantomData<T>);
+
+impl<T: PortWidth> Port<T> {
+    pub const fn new(addr: u16) -> Self {
+        Self(addr, core::marker::PhantomData)
+    }
+
+    /// ### Safety
+    /// If the CPL is greater than the IOPL this will cause a GPF
+    pub unsafe fn read(&self) -> T {
+        T::port_in(self.0)
+    }
+
+    /// ### Safety
+    /// If the CPL is greater than the IOPL this will cause a GPF
+    pub unsafe fn write(&self, value: T) {
+        T::port_out(self.0, value)
+    }
+}
+
+/// A `Port` that's only ever read from, for registers where writing wouldn't make sense (or isn't
+/// wired up by the device)
+#[derive(Clone, Copy, Debug)]
+pub struct ReadOnlyPort<T: PortWidth>(Port<T>);
+
+impl<T: PortWidth> ReadOnlyPort<T> {
+    pub const fn new(addr: u16) -> Self {
+        Self(Port::new(addr))
+    }
+
+    /// ### Safety
+    /// If the CPL is greater than the IOPL this will cause a GPF
+    pub unsafe fn read(&self) -> T {
+        self.0.read()
+    }
+}
+
+/// A `Port` that's only ever written to
+#[derive(Clone, Copy, Debug)]
+pub struct WriteOnlyPort<T: PortWidth>(Port<T>);
+
+impl<T: PortWidth> WriteOnlyPort<T> {
+    pub const fn new(addr: u16) -> Self {
+        Self(Port::new(addr))
+    }
+
+    /// ### Safety
+    /// If the CPL is greater than the IOPL this will cause a GPF
+    pub unsafe fn write(&self, value: T) {
+        self.0.write(value)
+    }
+}
+
+/// Declares a typed view over a raw register value, so drivers can read named bit flags
+/// symbolically (`status.updating()`) instead of hand-rolled `value & mask != 0` expressions at
+/// every call site.
+#[macro_export]
+macro_rules! typed_register {
+    ($(#[$outer:meta])* $vis:vis struct $name:ident : $repr:ty {
+        $($(#[$field_meta:meta])* $field:ident : $mask:expr),* $(,)?
+    }) => {
+        $(#[$outer])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+        $vis struct $name($repr);
+
+        impl $name {
+            $(
+                $(#[$field_meta])*
+                $vis fn $field(&self) -> bool {
+                    (self.0 & ($mask as $repr)) != 0
+                }
+            )*
+        }
+
+        impl From<$repr> for $name {
+            fn from(value: $repr) -> Self {
+                Self(value)
+            }
+        }
+    };
 }
\ No newline at end of file
@@ -6,24 +6,52 @@
 use core::convert::TryInto;
 
 pub const ELF_TYPE_ET_EXEC: u16 = 2;
+pub const ELF_TYPE_ET_DYN: u16 = 3;
 pub const ELF_MACHINE_X86: u16 = 3;
 pub const SEGMENT_TYPE_PT_LOAD: u32 = 1;
+pub const SEGMENT_TYPE_PT_DYNAMIC: u32 = 2;
 pub const SEGMENT_FLAGS_PF_X: u32 = 1;
 pub const SEGMENT_FLAGS_PF_W: u32 = 2;
 pub const SEGMENT_FLAGS_PF_R: u32 = 4;
 pub const ELF_PROGRAM_HEADER_32_SIZE: usize = 0x20;
 
+/// `.dynamic` tag marking the end of the tag array
+const DT_NULL: u32 = 0;
+/// `.dynamic` tag for the size, in bytes, of the `DT_JMPREL` table
+const DT_PLTRELSZ: u32 = 2;
+/// `.dynamic` tag for the virtual address of the `Elf32_Rel` relocation table
+const DT_REL: u32 = 17;
+/// `.dynamic` tag for the total size, in bytes, of the `DT_REL` table
+const DT_RELSZ: u32 = 18;
+/// `.dynamic` tag for the size, in bytes, of a single `Elf32_Rel` entry
+const DT_RELENT: u32 = 19;
+/// `.dynamic` tag for the virtual address of the PLT's `Elf32_Rel` relocation table
+const DT_JMPREL: u32 = 23;
+
+/// The only relocation type this loader knows how to apply: add the load bias to the existing
+/// 32-bit value at `r_offset`
+const R_386_RELATIVE: u8 = 8;
+
+/// Size, in bytes, of a single `Elf32_Rel` entry (`r_offset` + `r_info`, no explicit addend)
+const ELF32_REL_ENTRY_SIZE: usize = 8;
+
 /// A validated ELF file
 pub struct ElfParser<'a> {
-    /// Virtual address of the code entry point
+    /// Virtual address of the code entry point, relative to a load bias of 0
     pub entry_point: usize,
 
+    /// The ELF's `e_type` - either `ELF_TYPE_ET_EXEC` or `ELF_TYPE_ET_DYN`
+    elf_type: u16,
+
     /// Number of segments
     segment_count: usize,
 
     /// Offset into the file where the segment headers reside
     segment_headers_offset: usize,
 
+    /// File offset and size of the `PT_DYNAMIC` segment, if this ELF has one
+    dynamic_segment: Option<(usize, usize)>,
+
     /// Raw ELF file
     raw_bytes: &'a [u8],
 }
@@ -52,8 +80,9 @@ impl<'a> ElfParser<'a> {
             return None;
         }
 
-        // Check that the elf type is `ET_EXEC`
-        if u16::from_le_bytes(bytes[16..18].try_into().ok()?) != ELF_TYPE_ET_EXEC {
+        // Check that the elf type is `ET_EXEC` or `ET_DYN` (a position-independent executable)
+        let elf_type = u16::from_le_bytes(bytes[16..18].try_into().ok()?);
+        if elf_type != ELF_TYPE_ET_EXEC && elf_type != ELF_TYPE_ET_DYN {
             return None;
         }
 
@@ -81,23 +110,55 @@ impl<'a> ElfParser<'a> {
             return None;
         }
 
+        // Scan the program headers for a `PT_DYNAMIC` segment, which is where `ET_DYN` binaries
+        // record the relocation tables we need to apply the chosen load bias
+        let mut dynamic_segment = None;
+        for segment_idx in 0..program_header_count {
+            let off = program_header_offset + ELF_PROGRAM_HEADER_32_SIZE*segment_idx;
+
+            if u32::from_le_bytes(bytes[off..off+4].try_into().ok()?) != SEGMENT_TYPE_PT_DYNAMIC {
+                continue;
+            }
+
+            let seg_file_offset: usize =
+                u32::from_le_bytes(bytes[off+4..off+8].try_into().ok()?).try_into().ok()?;
+            let seg_file_bytes_size: usize =
+                u32::from_le_bytes(bytes[off+16..off+20].try_into().ok()?).try_into().ok()?;
+
+            let seg_end = seg_file_offset.checked_add(seg_file_bytes_size)?;
+            if seg_end > bytes.len() {
+                return None;
+            }
+
+            dynamic_segment = Some((seg_file_offset, seg_file_bytes_size));
+            break;
+        }
+
         Some(ElfParser {
             entry_point,
+            elf_type,
             segment_count: program_header_count,
             segment_headers_offset: program_header_offset,
+            dynamic_segment,
             raw_bytes: bytes,
         })
     }
 
-    /// Invokes the provided closure with the details of every LOAD segment in the ELF
-    /// The arguments are (virtual address, virtual size, raw init bytes, segment flags)
+    /// Invokes the provided closure with the details of every LOAD segment in the ELF.
+    /// The arguments are (virtual address, raw init bytes from the file, trailing BSS byte count,
+    /// segment flags). `file_bytes.len() + bss_len` is the segment's full size in memory; the
+    /// `bss_len` trailing bytes of that span aren't backed by the file and must be zero-filled by
+    /// the caller rather than copied.
+    ///
+    /// Every segment's file range is bounds-checked against the raw ELF bytes, and segments whose
+    /// file size exceeds their memory size are rejected, so a crafted ELF can't make this panic.
     pub fn for_segment<F>(&self, mut func: F) -> Option<()>
-        where F: FnMut(usize, usize, &[u8], u32) -> Option<()> {
+        where F: FnMut(usize, &[u8], usize, u32) -> Option<()> {
         let bytes = self.raw_bytes;
 
         for segment_idx in 0..self.segment_count {
             let off = self.segment_headers_offset + ELF_PROGRAM_HEADER_32_SIZE*segment_idx;
-            
+
             // We only care about loaded segments
             if u32::from_le_bytes(bytes[off..off+4].try_into().ok()?) != SEGMENT_TYPE_PT_LOAD {
                 continue;
@@ -106,15 +167,15 @@ impl<'a> ElfParser<'a> {
             // Get the file offset of the segment bytes
             let seg_file_offset: usize =
                 u32::from_le_bytes(bytes[off+4..off+8].try_into().ok()?).try_into().ok()?;
-            
+
             // Get the virtual address of the segment in memory
             let seg_vaddr: usize =
                 u32::from_le_bytes(bytes[off+8..off+12].try_into().ok()?).try_into().ok()?;
-            
+
             // Get the size of the segment bytes in the file
             let seg_file_bytes_size: usize =
                 u32::from_le_bytes(bytes[off+16..off+20].try_into().ok()?).try_into().ok()?;
-            
+
             // Get the size of the segment in memory
             let seg_mem_size: usize =
                 u32::from_le_bytes(bytes[off+20..off+24].try_into().ok()?).try_into().ok()?;
@@ -122,12 +183,151 @@ impl<'a> ElfParser<'a> {
             // Get the segment flags (R/W/X)
             let seg_flags = u32::from_le_bytes(bytes[off+24..off+28].try_into().ok()?);
 
-            func(seg_vaddr, seg_mem_size,
-                &bytes[seg_file_offset..seg_file_offset+seg_file_bytes_size], seg_flags)?;
+            // A segment can't occupy more bytes in the file than it does in memory - the rest is
+            // the BSS's zero-fill region
+            if seg_file_bytes_size > seg_mem_size {
+                return None;
+            }
+
+            // Bounds-check the segment's file range against the actual ELF bytes instead of
+            // trusting the header blindly
+            let seg_file_end = seg_file_offset.checked_add(seg_file_bytes_size)?;
+            if seg_file_end > bytes.len() {
+                return None;
+            }
+
+            let bss_len = seg_mem_size - seg_file_bytes_size;
+            func(seg_vaddr, &bytes[seg_file_offset..seg_file_end], bss_len, seg_flags)?;
         }
 
         Some(())
     }
+
+    /// Whether this ELF is a position-independent executable (`ET_DYN`), i.e. one that needs
+    /// `apply_relocations` to be called once it's been loaded at a chosen base address
+    pub fn is_position_independent(&self) -> bool {
+        self.elf_type == ELF_TYPE_ET_DYN
+    }
+
+    /// Applies this ELF's `R_386_RELATIVE` relocations to the bytes of its `PT_LOAD` segments as
+    /// they sit in memory at `load_base`, by adding `load_base` to the 32-bit word at each
+    /// relocation's `load_base + r_offset`. Every relocated address is checked to fall inside a
+    /// segment actually mapped by `for_segment`, and any relocation type other than
+    /// `R_386_RELATIVE` is rejected, since this loader doesn't know how to apply it.
+    ///
+    /// # Safety
+    /// The caller must ensure `load_base` is the address this ELF's `PT_LOAD` segments were
+    /// actually mapped at, and that the full range of each such segment is both mapped and
+    /// writable.
+    pub unsafe fn apply_relocations(&self, load_base: usize) -> Option<()> {
+        let (dyn_offset, dyn_size) = match self.dynamic_segment {
+            Some(segment) => segment,
+            // Nothing to do for an ELF without a `PT_DYNAMIC` segment
+            None => return Some(()),
+        };
+        let bytes = self.raw_bytes;
+
+        let mut rel_vaddr = None;
+        let mut rel_size = None;
+        let mut rel_entry_size = None;
+        let mut jmprel_vaddr = None;
+        let mut jmprel_size = None;
+
+        // Walk the `.dynamic` tag array looking for the tags that locate the relocation tables
+        let mut off = dyn_offset;
+        while off + 8 <= dyn_offset + dyn_size {
+            let tag = u32::from_le_bytes(bytes[off..off+4].try_into().ok()?);
+            let val = u32::from_le_bytes(bytes[off+4..off+8].try_into().ok()?);
+            off += 8;
+
+            match tag {
+                DT_NULL => break,
+                DT_REL => rel_vaddr = Some(val as usize),
+                DT_RELSZ => rel_size = Some(val as usize),
+                DT_RELENT => rel_entry_size = Some(val as usize),
+                DT_JMPREL => jmprel_vaddr = Some(val as usize),
+                DT_PLTRELSZ => jmprel_size = Some(val as usize),
+                _ => {},
+            }
+        }
+
+        // `DT_REL`/`DT_RELSZ`/`DT_RELENT` are required for any ELF that has relocations to apply
+        let rel_vaddr = rel_vaddr?;
+        let rel_size = rel_size?;
+        if rel_entry_size? != ELF32_REL_ENTRY_SIZE {
+            return None;
+        }
+        self.apply_rel_table(load_base, rel_vaddr, rel_size)?;
+
+        // `DT_JMPREL` is optional - not every PIE binary has PLT relocations
+        if let Some(jmprel_vaddr) = jmprel_vaddr {
+            self.apply_rel_table(load_base, jmprel_vaddr, jmprel_size?)?;
+        }
+
+        Some(())
+    }
+
+    /// Applies every `Elf32_Rel` entry in the table at `table_vaddr..table_vaddr+table_size`
+    unsafe fn apply_rel_table(&self, load_base: usize, table_vaddr: usize, table_size: usize)
+        -> Option<()> {
+        if table_size % ELF32_REL_ENTRY_SIZE != 0 {
+            return None;
+        }
+
+        for entry_off in (0..table_size).step_by(ELF32_REL_ENTRY_SIZE) {
+            let entry_vaddr = table_vaddr.checked_add(entry_off)?;
+            let entry_bytes = self.read_mapped(entry_vaddr, ELF32_REL_ENTRY_SIZE)?;
+
+            let r_offset = u32::from_le_bytes(entry_bytes[0..4].try_into().ok()?) as usize;
+            let r_info = u32::from_le_bytes(entry_bytes[4..8].try_into().ok()?);
+            let r_type = (r_info & 0xFF) as u8;
+
+            // Reject anything but R_386_RELATIVE - we don't know how to apply it
+            if r_type != R_386_RELATIVE {
+                return None;
+            }
+
+            // Make sure the relocated word actually lands inside a mapped segment
+            self.read_mapped(r_offset, 4)?;
+
+            let reloc_addr = (load_base + r_offset) as *mut u32;
+            let addend = reloc_addr.read_unaligned();
+            reloc_addr.write_unaligned(addend.wrapping_add(load_base as u32));
+        }
+
+        Some(())
+    }
+
+    /// Returns the file bytes backing `size` bytes at virtual address `vaddr`, if that whole
+    /// range falls inside a single `PT_LOAD` segment. This both translates vaddr to a file offset
+    /// and serves as the bounds check against the loaded image.
+    fn read_mapped(&self, vaddr: usize, size: usize) -> Option<&[u8]> {
+        let bytes = self.raw_bytes;
+
+        for segment_idx in 0..self.segment_count {
+            let off = self.segment_headers_offset + ELF_PROGRAM_HEADER_32_SIZE*segment_idx;
+
+            if u32::from_le_bytes(bytes[off..off+4].try_into().ok()?) != SEGMENT_TYPE_PT_LOAD {
+                continue;
+            }
+
+            let seg_file_offset: usize =
+                u32::from_le_bytes(bytes[off+4..off+8].try_into().ok()?).try_into().ok()?;
+            let seg_vaddr: usize =
+                u32::from_le_bytes(bytes[off+8..off+12].try_into().ok()?).try_into().ok()?;
+            let seg_file_bytes_size: usize =
+                u32::from_le_bytes(bytes[off+16..off+20].try_into().ok()?).try_into().ok()?;
+
+            let vaddr_end = vaddr.checked_add(size)?;
+            let seg_vaddr_end = seg_vaddr.checked_add(seg_file_bytes_size)?;
+            if vaddr >= seg_vaddr && vaddr_end <= seg_vaddr_end {
+                let file_off = seg_file_offset + (vaddr - seg_vaddr);
+                return bytes.get(file_off..file_off+size);
+            }
+        }
+
+        None
+    }
 }
 
 #[cfg(test)]
@@ -140,9 +340,9 @@ mod tests {
     fn works() {
         let file = std::fs::read("../../build/kernel/i586-unknown-linux-gnu/release/kernel").unwrap();
         let parser = ElfParser::parse(&file).unwrap();
-        parser.for_segment(|vaddr, vsize, raw_bytes, flags| {
-            std::println!("{:#09x} {} {:03b}", vaddr, vsize, flags);
-            std::println!("{:x?}", raw_bytes);
+        parser.for_segment(|vaddr, file_bytes, bss_len, flags| {
+            std::println!("{:#09x} {} {:03b}", vaddr, file_bytes.len() + bss_len, flags);
+            std::println!("{:x?}", file_bytes);
             Some(())
         }).unwrap();
     }
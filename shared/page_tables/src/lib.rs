@@ -9,15 +9,208 @@ pub const PAGE_ENTRY_WRITE: u32     = 1<<1;
 pub const PAGE_ENTRY_USER: u32      = 1<<2;
 pub const PAGE_ENTRY_PWT: u32       = 1<<3; // Page-level write-through
 pub const PAGE_ENTRY_PCD: u32       = 1<<4; // Page-level cache disable
+/// 4 KiB PTE only: page attribute table bit, see `CacheMode`. For a PDE this bit position is
+/// `PAGE_ENTRY_PAGE_SIZE` instead - large-page PAT lives at bit 12, which this kernel doesn't use.
+pub const PAGE_ENTRY_PAT: u32       = 1<<7;
+pub const PAGE_ENTRY_PAGE_SIZE: u32 = 1<<7; // PDE only: 4 MiB page, no second-level page table
+/// Marks a PTE as global, so it survives a `mov cr3` reload instead of being flushed from the TLB.
+/// Only takes effect once CR4.PGE is set; an explicit `invlpg` (as already issued by
+/// `map_raw`/`map_raw_directly`) still flushes a global entry.
+pub const PAGE_ENTRY_GLOBAL: u32     = 1<<8;
+/// Software-only marker bit (ignored by the CPU), set by `PageDirectory::clone_cow` on a page it
+/// cleared `PAGE_ENTRY_WRITE` from: a write fault against such a page should be resolved by
+/// `PageDirectory::handle_cow_fault` rather than reported as a genuine protection violation.
+pub const PAGE_ENTRY_COW: u32        = 1<<9;
+
+/// The size of a PSE large page
+const LARGE_PAGE_SIZE: u32 = 4 * 1024 * 1024;
+
+/// The `IA32_PAT` model-specific register
+const IA32_PAT_MSR: u32 = 0x277;
+/// Index into the PAT MSR's 8 memory-type entries that `CacheMode::WriteCombining` selects (PAT=1,
+/// PCD=0, PWT=1) - see `init_pat`
+const WRITE_COMBINING_PAT_ENTRY: u32 = 5;
+/// Index into the PAT MSR's 8 memory-type entries that `CacheMode::WriteProtect` selects (PAT=1,
+/// PCD=1, PWT=1) - see `init_pat`
+const WRITE_PROTECT_PAT_ENTRY: u32 = 7;
+/// PAT memory-type encoding for write-combining
+const PAT_MEMORY_TYPE_WRITE_COMBINING: u64 = 0x01;
+/// PAT memory-type encoding for write-protected
+const PAT_MEMORY_TYPE_WRITE_PROTECT: u64 = 0x05;
+
+/// Above this many pages, a range operation (e.g. `unmap_range`) flushes the whole TLB by reloading
+/// CR3 instead of issuing an individual `invlpg` per page
+const TLB_BATCH_FLUSH_THRESHOLD: usize = 32;
+
+/// The memory type a page mapping uses, encoded across a 4 KiB PTE's PWT (bit 3), PCD (bit 4) and
+/// PAT (bit 7) bits. Those three bits together index one of the 8 entries of the `IA32_PAT` MSR;
+/// `init_pat` programs that MSR so each variant's index names the matching Intel SDM memory type.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Cacheable, with writes and reads freely reordered and buffered - the right choice for
+    /// ordinary RAM. Selects PAT entry 0, which already holds this memory type out of reset, so
+    /// `init_pat` doesn't need to touch it.
+    WriteBack,
+    /// Cacheable for reads, but writes are propagated to memory immediately instead of being held
+    /// in the cache. Selects PAT entry 1, which already holds this memory type out of reset.
+    WriteThrough,
+    /// Not cached, but writes are buffered and combined into larger bursts before reaching memory,
+    /// and the buffering order isn't guaranteed - dramatically faster than `Uncacheable` for
+    /// write-heavy device memory such as a linear frame buffer. Selects PAT entry 5, which
+    /// `init_pat` reprograms from its reset write-through memory type.
+    WriteCombining,
+    /// Cacheable for reads, but writes go straight to memory and also invalidate the line in every
+    /// other cache that holds it - used for memory another agent (e.g. a video adapter scanning
+    /// out a frame buffer) may read concurrently with CPU writes. Selects PAT entry 7, which
+    /// `init_pat` reprograms from its reset uncacheable memory type.
+    WriteProtect,
+    /// Neither cached nor buffered; every access reaches memory in program order. Selects PAT
+    /// entry 3, which already holds this memory type out of reset - what `map_to_phys_page` used
+    /// to make with `cacheable: false` before this enum existed.
+    Uncacheable,
+}
+
+impl CacheMode {
+    /// The PWT/PCD/PAT PTE bits that select this cache mode, once `init_pat` has programmed the
+    /// PAT MSR to match
+    fn pte_bits(self) -> u32 {
+        match self {
+            CacheMode::WriteBack => 0,
+            CacheMode::WriteThrough => PAGE_ENTRY_PWT,
+            CacheMode::Uncacheable => PAGE_ENTRY_PWT | PAGE_ENTRY_PCD,
+            CacheMode::WriteCombining => PAGE_ENTRY_PAT | PAGE_ENTRY_PWT,
+            CacheMode::WriteProtect => PAGE_ENTRY_PAT | PAGE_ENTRY_PCD | PAGE_ENTRY_PWT,
+        }
+    }
+}
+
+/// Reprograms the `IA32_PAT` MSR so every `CacheMode` selects a PAT entry of the matching Intel
+/// SDM memory type. `WriteBack`, `WriteThrough` and `Uncacheable` already select entries 0, 1 and
+/// 3, which hold those exact memory types out of reset, so only two entries actually need
+/// reprogramming here: entry 5 (selected by `CacheMode::WriteCombining`), from its reset
+/// write-through memory type to write-combining, and entry 7 (selected by `CacheMode::
+/// WriteProtect`), from its reset uncacheable memory type to write-protected.
+///
+/// ### Safety
+/// Must be called at CPL 0, and on every core before it accesses a `WriteCombining` or
+/// `WriteProtect` mapping made through `PageDirectory::map_to_phys_page` or `PageDirectory::map`
+pub unsafe fn init_pat() {
+    let mut pat = cpu::rdmsr(IA32_PAT_MSR);
+
+    let wc_shift = WRITE_COMBINING_PAT_ENTRY * 8;
+    pat &= !(0xFFu64 << wc_shift);
+    pat |= PAT_MEMORY_TYPE_WRITE_COMBINING << wc_shift;
+
+    let wp_shift = WRITE_PROTECT_PAT_ENTRY * 8;
+    pat &= !(0xFFu64 << wp_shift);
+    pat |= PAT_MEMORY_TYPE_WRITE_PROTECT << wp_shift;
+
+    cpu::wrmsr(IA32_PAT_MSR, pat);
+}
+
+/// Bit 7 of CR4: Page Global Enable. Without it, a `PAGE_ENTRY_GLOBAL` PTE is flushed from the TLB
+/// by a `mov cr3` reload exactly like any other entry - see `enable_global_pages`.
+const CR4_PGE: usize = 1<<7;
+
+/// Sets CR4.PGE, so a mapping made with `global: true` (see `PageDirectory::map_to_phys_page`)
+/// survives a `mov cr3` reload instead of being flushed from the TLB with everything else.
+///
+/// ### Safety
+/// Must be called at CPL 0, on every core, before a `global: true` mapping is relied upon to
+/// survive a `mov cr3` reload on that core
+pub unsafe fn enable_global_pages() {
+    cpu::set_cr4(cpu::get_cr4() | CR4_PGE);
+}
 
 /// Strongly typed physical address to diffreniate addresses
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PhysAddr(pub u32);
 
+impl PhysAddr {
+    /// Rounds down to the start of the page containing this address
+    pub fn page_down(self) -> Self {
+        PhysAddr(self.0 & !0xfff)
+    }
+
+    /// Rounds up to the start of the next page, or stays put if already page-aligned
+    pub fn page_up(self) -> Self {
+        PhysAddr(self.0.saturating_add(0xfff) & !0xfff)
+    }
+
+    /// The offset of this address within its containing page
+    pub fn offset_in_page(self) -> u32 {
+        self.0 & 0xfff
+    }
+
+    /// Adds `offset` to this address, returning `None` on overflow
+    pub fn checked_add(self, offset: u32) -> Option<Self> {
+        self.0.checked_add(offset).map(PhysAddr)
+    }
+}
+
+impl core::ops::Add<u32> for PhysAddr {
+    type Output = PhysAddr;
+    fn add(self, rhs: u32) -> PhysAddr {
+        PhysAddr(self.0 + rhs)
+    }
+}
+
+impl core::ops::Sub<PhysAddr> for PhysAddr {
+    type Output = u32;
+    fn sub(self, rhs: PhysAddr) -> u32 {
+        self.0 - rhs.0
+    }
+}
+
 /// Strongly typed virtual address to diffreniate addresses
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct VirtAddr(pub u32);
 
+impl VirtAddr {
+    /// Rounds down to the start of the page containing this address
+    pub fn page_down(self) -> Self {
+        VirtAddr(self.0 & !0xfff)
+    }
+
+    /// Rounds up to the start of the next page, or stays put if already page-aligned
+    pub fn page_up(self) -> Self {
+        VirtAddr(self.0.saturating_add(0xfff) & !0xfff)
+    }
+
+    /// The offset of this address within its containing page
+    pub fn offset_in_page(self) -> u32 {
+        self.0 & 0xfff
+    }
+
+    /// Adds `offset` to this address, returning `None` on overflow
+    pub fn checked_add(self, offset: u32) -> Option<Self> {
+        self.0.checked_add(offset).map(VirtAddr)
+    }
+}
+
+impl core::ops::Add<u32> for VirtAddr {
+    type Output = VirtAddr;
+    fn add(self, rhs: u32) -> VirtAddr {
+        VirtAddr(self.0 + rhs)
+    }
+}
+
+impl core::ops::Sub<VirtAddr> for VirtAddr {
+    type Output = u32;
+    fn sub(self, rhs: VirtAddr) -> u32 {
+        self.0 - rhs.0
+    }
+}
+
+/// A physical memory allocation handed back by `PhysMem::allocate_phys_mem`: its address together
+/// with its size, so the two never travel separately. Also gives future per-frame bookkeeping (ref
+/// counts, an owning subsystem tag, ...) a single place to live.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub addr: PhysAddr,
+    pub size: usize,
+}
+
 pub trait PhysMem {
     /// If successful, returns a virtual address which maps to the physical address and is valid for
     /// at least `size` bytes.
@@ -32,40 +225,58 @@ pub trait PhysMem {
     // worry about this, or are page mappings only ever made in a single context at a time?
 
     /// Allocates physical memory with the requested `layout`
-    fn allocate_phys_mem(&mut self, layout: Layout) -> Option<PhysAddr>;
+    fn allocate_phys_mem(&mut self, layout: Layout) -> Option<Frame>;
 
     /// Releases physical memory allocated with `allocate_phys_mem`
     fn release_phys_mem(&mut self, phys_addr: PhysAddr, size: usize);
 
+    /// Same as `release_phys_mem`, but takes the `Frame` handed back by `allocate_phys_mem` directly
+    fn release_frame(&mut self, frame: Frame) {
+        self.release_phys_mem(frame.addr, frame.size);
+    }
+
+    /// Adds one reference to the frame at `phys_addr`, for copy-on-write sharing (see
+    /// `PageDirectory::clone_cow`). Every frame `allocate_phys_mem` hands out implicitly starts out
+    /// exclusively owned; the default implementation does nothing, since it never needs to tell an
+    /// exclusively-owned frame apart from a shared one.
+    fn inc_ref(&mut self, _phys_addr: PhysAddr) {}
+
+    /// Removes one reference from the frame at `phys_addr` and returns the reference count that
+    /// remains, not counting the one just removed. `release_phys_mem` is expected to only actually
+    /// free the frame once this reaches 0. The default implementation always returns 0, matching
+    /// `inc_ref`'s default no-op: every frame is assumed exclusively owned, so dropping its one
+    /// reference always leaves none behind.
+    fn dec_ref(&mut self, _phys_addr: PhysAddr) -> u32 { 0 }
+
     /// Same as `allocate_phys_mem` except the memory is also zeroed. A reference to `page_dir` is
     /// required if the zero-ing of memory would require to map the memory in.
     /// Calls `translate_phys`, so past translations are invalidated.
     fn allocate_zeroed_phys_mem(&mut self, page_dir: Option<&mut PageDirectory>, layout: Layout)
-        -> Option<PhysAddr> {
+        -> Option<Frame> {
         // Allocate the memory
-        let phys_addr = self.allocate_phys_mem(layout)?;
+        let frame = self.allocate_phys_mem(layout)?;
 
         unsafe {
             // Get a virtual address to the allocation
-            let virt_addr = self.translate_phys(page_dir, phys_addr, layout.size()).or_else(|| {
+            let virt_addr = self.translate_phys(page_dir, frame.addr, frame.size).or_else(|| {
                 // Translation of the address failed and so we can not zero the memory, but before
                 // we exit with failure, we need to release the physical memory we allocated
-                self.release_phys_mem(phys_addr, layout.size());
-                
+                self.release_frame(frame);
+
                 None
             })?;
             // Zero it out
-            core::ptr::write_bytes(virt_addr, 0, layout.size());
+            core::ptr::write_bytes(virt_addr, 0, frame.size);
         }
 
-        Some(phys_addr)
+        Some(frame)
     }
 }
 
 /// A 32-bit x86 page directory
 pub struct PageDirectory {
     // The physical address of the page directory, i.e. the address stored in CR3
-    directory: PhysAddr
+    directory: PhysAddr,
 }
 
 impl PageDirectory {
@@ -73,7 +284,7 @@ impl PageDirectory {
     pub fn new(phys_mem: &mut impl PhysMem) -> Option<Self> {
         // Allocate a page-aligned page directory
         let directory_layout = Layout::from_size_align(4096, 4096).unwrap();
-        let directory = phys_mem.allocate_zeroed_phys_mem(None, directory_layout)?;
+        let directory = phys_mem.allocate_zeroed_phys_mem(None, directory_layout)?.addr;
         Some(PageDirectory { directory })
     }
 
@@ -98,7 +309,16 @@ impl PageDirectory {
     #[must_use]
     pub fn map(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, size: u32,
         write: bool, user: bool) -> Option<()> {
-        self.map_internal(phys_mem, virt_addr, size, write, user, None::<fn(usize) -> u8>)
+        self.map_internal(phys_mem, virt_addr, size, write, user, CacheMode::WriteBack,
+            None::<fn(usize) -> u8>)
+    }
+
+    /// Same as `map`, but the mapped pages use `cache_mode` instead of always being `WriteBack`.
+    #[must_use]
+    pub fn map_with_cache_mode(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr,
+        size: u32, write: bool, user: bool, cache_mode: CacheMode) -> Option<()> {
+        self.map_internal(phys_mem, virt_addr, size, write, user, cache_mode,
+            None::<fn(usize) -> u8>)
     }
 
     /// Maps at least `size` bytes at virtual address `virt_addr` to physical memory with permissions
@@ -110,18 +330,26 @@ impl PageDirectory {
     pub fn map_init<F>(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr,
         size: u32, write: bool, user: bool, init: F) -> Option<()>
         where F: Fn(usize) -> u8 {
-        self.map_internal(phys_mem, virt_addr, size, write, user, Some(init))
+        self.map_internal(phys_mem, virt_addr, size, write, user, CacheMode::WriteBack, Some(init))
     }
 
     /// Maps at least `size` bytes at virtual address `virt_addr` to physical memory with permissions
     /// `write` and `user`.
     /// In practice, this maps all the pages that containg the `size` bytes.
-    /// 
+    ///
     /// If `init` is not None, Each byte in the pages containing the requested bytes will be
     /// initialized by calling `init` with its offset.
+    ///
+    /// Transactional: if any page in the range fails to allocate or map, every page this call
+    /// already mapped is unmapped (freeing its frame) and nothing is left leaked or half-mapped.
+    /// There's no heap-allocated reservation list up front (a `Vec` of the frames-to-be would need
+    /// to come from the very allocator this function helps back with virtual memory, which could
+    /// re-enter and deadlock on `PHYS_MEM` - see `BuddyAllocator::alloc`'s calls into `map`); instead
+    /// each iteration is rolled back individually and the range already committed is unwound via
+    /// `unmap_range` on failure.
     #[must_use]
     fn map_internal<F>(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, size: u32,
-        write: bool, user: bool, init: Option<F>) -> Option<()> 
+        write: bool, user: bool, cache_mode: CacheMode, init: Option<F>) -> Option<()>
         where F: Fn(usize) -> u8 {
         // Make sure the size is not zero and that the virtual address is page-aligned
         if size == 0 || virt_addr.0 & 0xfff != 0 {
@@ -132,47 +360,95 @@ impl PageDirectory {
         let first_addr_page = (virt_addr.0) >> 12;
         let last_addr = (virt_addr.0).checked_add(size - 1)?;
         let last_addr_page = last_addr >> 12;
-        
+
         // Iterate through each page containing the `size` bytes
         for page in first_addr_page..=last_addr_page {
-            // Allocate page-aligned pysical memory for the page
-            let page_layout = Layout::from_size_align(4096, 4096).unwrap();
-            let physical_page = phys_mem.allocate_phys_mem(page_layout)?;
-
-            // Check if we need to initialize
-            if let Some(init_bytes) = &init {
-                // Calculate the virtul address of the page
-                let page_vaddr = page << 12;
-                // Calculate the offset of the page from the original address
-                let page_offset: usize = (page_vaddr - virt_addr.0) as usize;
-
-                // Get a pointer to the memory we just allocated for the page
-                let page_slice = unsafe { 
-                    let page_ptr = phys_mem.translate_phys(Some(self), physical_page, 4096)?;
-                    core::slice::from_raw_parts_mut(page_ptr, 4096)
+            if self.map_internal_page(phys_mem, virt_addr, page, write, user, cache_mode, &init)
+                .is_none() {
+                // Roll back every page this call has already mapped; `page` itself was never
+                // successfully installed (and already cleaned up after itself), so only the pages
+                // strictly before it need unmapping
+                if page > first_addr_page {
+                    self.unmap_range(phys_mem, VirtAddr(first_addr_page << 12),
+                        (page - first_addr_page) * 4096, true);
+                }
+                return None;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Allocates and maps the single page `page` (a page-aligned virtual address, not yet shifted,
+    /// as used by `map_internal`'s loop) as part of a `map_internal` range starting at
+    /// `range_start`. If anything fails, any frame this call itself allocated is released before
+    /// returning - the caller is responsible for rolling back pages committed by earlier iterations.
+    fn map_internal_page<F>(&mut self, phys_mem: &mut impl PhysMem, range_start: VirtAddr, page: u32,
+        write: bool, user: bool, cache_mode: CacheMode, init: &Option<F>) -> Option<()>
+        where F: Fn(usize) -> u8 {
+        // Allocate page-aligned pysical memory for the page
+        let page_layout = Layout::from_size_align(4096, 4096).unwrap();
+        let physical_page = phys_mem.allocate_phys_mem(page_layout)?.addr;
+
+        // Check if we need to initialize
+        if let Some(init_bytes) = init {
+            // Calculate the virtul address of the page
+            let page_vaddr = page << 12;
+            // Calculate the offset of the page from the original address
+            let page_offset: usize = (page_vaddr - range_start.0) as usize;
+
+            // Get a pointer to the memory we just allocated for the page
+            let page_slice = unsafe {
+                let page_ptr = match phys_mem.translate_phys(Some(self), physical_page, 4096) {
+                    Some(ptr) => ptr,
+                    None => {
+                        phys_mem.release_phys_mem(physical_page, 4096);
+                        return None;
+                    }
                 };
+                core::slice::from_raw_parts_mut(page_ptr, 4096)
+            };
 
-                for (byte_offset, byte) in page_slice.iter_mut().enumerate() {
-                    // For each byte in the page, get its initial value from the closure
-                    *byte = init_bytes(page_offset + byte_offset);
-                }
+            for (byte_offset, byte) in page_slice.iter_mut().enumerate() {
+                // For each byte in the page, get its initial value from the closure
+                *byte = init_bytes(page_offset + byte_offset);
             }
+        }
 
-            // Make the virtual address mapping
-            let page_virt_addr = VirtAddr(page << 12);
-            self.map_to_phys_page(phys_mem, page_virt_addr, physical_page, write, user, false,
-                true)?;
+        // Make the virtual address mapping
+        let page_virt_addr = VirtAddr(page << 12);
+        if self.map_to_phys_page(phys_mem, page_virt_addr, physical_page, write, user, false,
+            cache_mode, false).is_none() {
+            phys_mem.release_phys_mem(physical_page, 4096);
+            return None;
         }
 
         Some(())
     }
 
+    /// Same as `map`, but named explicitly for callers that want to lean on the transactional
+    /// guarantee `map_internal` provides: if this returns `None`, `virt_addr`..`virt_addr + size` is
+    /// left exactly as it was before the call - every frame this call allocated has been released
+    /// and every PTE it wrote has been unmapped, so a failure partway through (e.g. running out of
+    /// physical memory) never leaves a half-mapped region behind.
+    #[must_use]
+    pub fn try_map_range(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, size: u32,
+        write: bool, user: bool) -> Option<()> {
+        self.map(phys_mem, virt_addr, size, write, user)
+    }
+
     /// Maps the virtual page at `virt_addr` to the physical page at `phys_addr` with the specified
     /// permissions `write` and `user`. If `update` is false, this will not overwrite an existing
-    /// mapping. If `cacheable` is false, the mapping will be marked as 'Strong Uncacheable (UC)'.
+    /// mapping. `cache_mode` selects the mapping's memory type (see `CacheMode`) - `init_pat` must
+    /// have been called (on every core) before a `WriteCombining` or `WriteProtect` mapping is
+    /// accessed. If `global` is true, the mapping is marked global (`PAGE_ENTRY_GLOBAL`) so it
+    /// survives a `mov cr3` reload instead of being flushed - the caller must have set CR4.PGE for
+    /// this to take effect, and it's still flushed by the `invlpg` this function issues via
+    /// `map_raw`.
     #[must_use]
     pub fn map_to_phys_page(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr,
-        phys_addr: PhysAddr, write: bool, user: bool, update: bool, cacheable: bool) -> Option<()> {
+        phys_addr: PhysAddr, write: bool, user: bool, update: bool, cache_mode: CacheMode,
+        global: bool) -> Option<()> {
         // Make sure that the requested virtual address is aligned to a page
         if (virt_addr.0 & 0xfff) != 0 {
             return None;
@@ -191,12 +467,9 @@ impl PageDirectory {
         if user {
             raw_page_table_entry |= PAGE_ENTRY_USER;
         }
-        if !cacheable {
-            // TODO: This is an extremely simplistic implementation which toggles between the
-            // default state and UC, which I added when working on the local APIC. I should read the
-            // relevant chapters in the manual (for future reference, 4.9, 4.10, 11) and update this
-            // for increased performance where I can enable some caching (i.e. screen buffers)
-            raw_page_table_entry |= PAGE_ENTRY_PWT | PAGE_ENTRY_PCD;
+        raw_page_table_entry |= cache_mode.pte_bits();
+        if global {
+            raw_page_table_entry |= PAGE_ENTRY_GLOBAL;
         }
 
         // Make the virtual address mapping
@@ -207,6 +480,40 @@ impl PageDirectory {
         Some(())
     }
 
+    /// Maps a single 4 MiB page at `virt_addr` to the physical page at `phys_addr` with
+    /// permissions `write` and `user`, using the PSE large-page PDE directly - no second-level
+    /// page table is involved. Both addresses must be 4 MiB-aligned. Always overwrites an existing
+    /// mapping, mirroring `map_to_phys_page`'s default `update: true`-ish callers.
+    ///
+    /// The caller is responsible for having set CR4.PSE; this function does not check it.
+    #[must_use]
+    pub fn map_large(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr,
+        phys_addr: PhysAddr, write: bool, user: bool) -> Option<()> {
+        // Make sure both addresses are aligned to a 4 MiB boundary
+        if (virt_addr.0 & (LARGE_PAGE_SIZE - 1)) != 0 || (phys_addr.0 & (LARGE_PAGE_SIZE - 1)) != 0 {
+            return None;
+        }
+
+        let mut raw_directory_entry = phys_addr.0 | PAGE_ENTRY_PAGE_SIZE | PAGE_ENTRY_PRESENT;
+        if write {
+            raw_directory_entry |= PAGE_ENTRY_WRITE;
+        }
+        if user {
+            raw_directory_entry |= PAGE_ENTRY_USER;
+        }
+
+        let directory_index = virt_addr.0 >> 22;
+        let directory_entry_paddr = PhysAddr(self.directory.0 + directory_index * 4);
+
+        unsafe {
+            let entry_vaddr = phys_mem.translate_phys(Some(self), directory_entry_paddr, 4)?;
+            *(entry_vaddr as *mut u32) = raw_directory_entry;
+            cpu::invlpg(virt_addr.0 as usize);
+        }
+
+        Some(())
+    }
+
     /// Set the page table entry for `virt_addr` to be `raw`. If `update` is false, this will not
     /// overwrite an existing mapping. If `create` is false, a page table won't be created if it
     /// doesn't exist (and the mapping will not occur).
@@ -216,6 +523,18 @@ impl PageDirectory {
     #[must_use]
     pub unsafe fn map_raw(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, raw: u32,
         update: bool, create: bool) -> Option<()> {
+        self.map_raw_invalidate(phys_mem, virt_addr, raw, update, create, true)
+    }
+
+    /// Same as `map_raw`, except callers doing many of these in a row (see `unmap_range`) can pass
+    /// `invalidate: false` to skip the per-page `invlpg` and flush the whole TLB once at the end
+    /// instead.
+    ///
+    /// ### Safety
+    /// `raw` must be a valid page table entry
+    #[must_use]
+    unsafe fn map_raw_invalidate(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr,
+        raw: u32, update: bool, create: bool, invalidate: bool) -> Option<()> {
         // Make sure that the requested virtual address is aligned to a page
         if (virt_addr.0 & 0xfff) != 0 {
             return None;
@@ -242,7 +561,7 @@ impl PageDirectory {
 
             // We need to add a new page table, so we allocate an aligned page
             let table_layout = Layout::from_size_align(4096, 4096).unwrap();
-            let new_table = phys_mem.allocate_zeroed_phys_mem(Some(self), table_layout)?;
+            let new_table = phys_mem.allocate_zeroed_phys_mem(Some(self), table_layout)?.addr;
 
             // Update the PDE
             directory_entry = new_table.0 | PAGE_ENTRY_USER | PAGE_ENTRY_WRITE | PAGE_ENTRY_PRESENT;
@@ -267,17 +586,17 @@ impl PageDirectory {
         *(table_entry_vaddr as *mut u32) = raw;
 
         // The entry already existed, so we need to invalidate any cached translations
-        if (table_entry & PAGE_ENTRY_PRESENT) != 0 {
+        if (table_entry & PAGE_ENTRY_PRESENT) != 0 && invalidate {
             cpu::invlpg(virt_addr.0 as usize);
         }
-        
+
         Some(())
     }
 
     /// Set the page table entry for `virt_addr` to be `raw`. If `update` is false, this will not
     /// overwrite an existing mapping. The page table of the specified page must be mapped at the
     /// virtual address `page_table_vaddr`.
-    /// 
+    ///
     /// The function will return `None` if the mapping was not updated for any reason.
     ///
     /// ### Safety
@@ -339,7 +658,7 @@ impl PageDirectory {
 
             // We need to add a new page table, so we allocate an aligned page
             let table_layout = Layout::from_size_align(4096, 4096).unwrap();
-            let new_table = phys_mem.allocate_zeroed_phys_mem(Some(self), table_layout)?;
+            let new_table = phys_mem.allocate_zeroed_phys_mem(Some(self), table_layout)?.addr;
 
             // Update the PDE
             let directory_entry =
@@ -363,38 +682,67 @@ impl PageDirectory {
     #[must_use]
     pub fn unmap(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, free_page: bool)
         -> Option<()> {
+        self.unmap_invalidate(phys_mem, virt_addr, free_page, true)
+    }
+
+    /// Same as `unmap`, except callers unmapping many pages in one logical operation (see
+    /// `unmap_range`) can pass `invalidate: false` to skip this page's `invlpg` and flush the whole
+    /// TLB once at the end instead.
+    #[must_use]
+    fn unmap_invalidate(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, free_page: bool,
+        invalidate: bool) -> Option<()> {
         // Make sure that the requested virtual address is aligned to a page
         if (virt_addr.0 & 0xfff) != 0 {
             return None;
         }
 
-        // Check if we need to free the physical page before unmapping it
-        if free_page {
-            // Get the physical address of the page
-            let page_paddr = self.translate_virt(phys_mem, virt_addr)?;
-            // Release the page
-            phys_mem.release_phys_mem(page_paddr, 4096);
-        }
-        
-        unsafe {
-            // Set the page entry as not present
-            self.map_raw(phys_mem, virt_addr, 0, true, false)?;
-        }
-
         // Index of the entry in the page directory
         let directory_index = virt_addr.0 >> 22;
-        
+
         // Compute the physical address of the PDE
         let directory_entry_paddr = PhysAddr(self.directory.0 + directory_index * 4);
         // Get the entry in the directory
-        let directory_entry = unsafe { 
+        let directory_entry = unsafe {
             // Translate the physical address into a virtual address
-            let directory_entry_vaddr = 
+            let directory_entry_vaddr =
                 phys_mem.translate_phys(Some(self), directory_entry_paddr, 4)?;
 
             *(directory_entry_vaddr as *const u32)
         };
 
+        // A 4 MiB (PS) mapping lives entirely in the PDE: there is no page table to scan or free,
+        // and the "page" to optionally free is the whole large frame
+        if (directory_entry & PAGE_ENTRY_PRESENT) != 0 && (directory_entry & PAGE_ENTRY_PAGE_SIZE) != 0 {
+            if free_page {
+                phys_mem.release_phys_mem(PhysAddr(directory_entry & !(LARGE_PAGE_SIZE - 1)),
+                    LARGE_PAGE_SIZE as usize);
+            }
+
+            unsafe {
+                let directory_entry_vaddr =
+                    phys_mem.translate_phys(Some(self), directory_entry_paddr, 4)?;
+                *(directory_entry_vaddr as *mut u32) = 0;
+                if invalidate {
+                    cpu::invlpg(virt_addr.0 as usize);
+                }
+            }
+
+            return Some(());
+        }
+
+        // Check if we need to free the physical page before unmapping it
+        if free_page {
+            // Get the physical address of the page
+            let page_paddr = self.translate_virt(phys_mem, virt_addr)?;
+            // Release the page
+            phys_mem.release_phys_mem(page_paddr, 4096);
+        }
+
+        unsafe {
+            // Set the page entry as not present
+            self.map_raw_invalidate(phys_mem, virt_addr, 0, true, false, invalidate)?;
+        }
+
         // Calculate the physical address of the relevant page table
         let table_paddr = directory_entry & !0xfff;
 
@@ -430,10 +778,199 @@ impl PageDirectory {
         Some(())
     }
 
+    /// Unmaps every page containing the `size` bytes starting at `virt_addr`, same as calling
+    /// `unmap` once per page. If more than `TLB_BATCH_FLUSH_THRESHOLD` pages are touched, the TLB is
+    /// flushed once in full (by reloading CR3) instead of issuing an `invlpg` for each page - once a
+    /// large enough range is touched at once (e.g. tearing down an `ioremap` mapping), a single
+    /// reload is cheaper than the individual flushes.
+    #[must_use]
+    pub fn unmap_range(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr, size: u32,
+        free_pages: bool) -> Option<()> {
+        // Make sure the size is not zero and that the virtual address is page-aligned
+        if size == 0 || virt_addr.0 & 0xfff != 0 {
+            return None;
+        }
+
+        // Calculate the page of the first address and the page of the last address
+        let first_addr_page = virt_addr.0 >> 12;
+        let last_addr = virt_addr.0.checked_add(size - 1)?;
+        let last_addr_page = last_addr >> 12;
+        let num_pages = (last_addr_page - first_addr_page + 1) as usize;
+
+        let invalidate_each = num_pages <= TLB_BATCH_FLUSH_THRESHOLD;
+
+        for page in first_addr_page..=last_addr_page {
+            self.unmap_invalidate(phys_mem, VirtAddr(page << 12), free_pages, invalidate_each)?;
+        }
+
+        if !invalidate_each {
+            unsafe {
+                self.flush_tlb();
+            }
+        }
+
+        Some(())
+    }
+
+    /// Produces a new page directory that shares every one of this directory's present *user*
+    /// pages with it copy-on-write, for `fork()`. Read-only user pages are simply shared as-is -
+    /// neither side can write them, so there's nothing to protect, though this does mean whichever
+    /// directory unmaps one with `free_page: true` first frees it out from under the other (a
+    /// known limitation; only writable pages get the full reference-counted treatment below).
+    /// Writable user pages are made read-only in *both* directories and marked `PAGE_ENTRY_COW`,
+    /// with the child pointing at the very same physical frame; `PhysMem::inc_ref` records the
+    /// extra owner so the frame outlives whichever directory happens to unmap it first. A later
+    /// write to one of these pages takes a fault that `handle_cow_fault` resolves.
+    ///
+    /// Kernel-half (non-user) mappings and 4 MiB PSE pages are left untouched - COW fork only ever
+    /// applies to ordinary 4 KiB user pages. The recursive self-map slot, if installed, is also
+    /// left alone: it's never given `PAGE_ENTRY_USER`, so the walk below skips it naturally.
+    pub fn clone_cow(&mut self, phys_mem: &mut impl PhysMem) -> Option<PageDirectory> {
+        let mut child = PageDirectory::new(phys_mem)?;
+
+        for directory_index in 0..1024u32 {
+            let directory_entry_paddr = PhysAddr(self.directory.0 + directory_index * 4);
+            let directory_entry = unsafe {
+                *(phys_mem.translate_phys(Some(self), directory_entry_paddr, 4)? as *const u32)
+            };
+
+            // Only descend into present, ordinary (non-PS) user page tables
+            if (directory_entry & PAGE_ENTRY_PRESENT) == 0 || (directory_entry & PAGE_ENTRY_USER) == 0
+                || (directory_entry & PAGE_ENTRY_PAGE_SIZE) != 0 {
+                continue;
+            }
+
+            let table_paddr = directory_entry & !0xfff;
+
+            for table_index in 0..1024u32 {
+                let table_entry_paddr = PhysAddr(table_paddr + table_index * 4);
+                let table_entry = unsafe {
+                    *(phys_mem.translate_phys(Some(self), table_entry_paddr, 4)? as *const u32)
+                };
+
+                if (table_entry & PAGE_ENTRY_PRESENT) == 0 || (table_entry & PAGE_ENTRY_USER) == 0 {
+                    continue;
+                }
+
+                let virt_addr = VirtAddr((directory_index << 22) | (table_index << 12));
+                let (raw, needs_inc_ref) = cow_child_entry(table_entry);
+
+                if needs_inc_ref {
+                    phys_mem.inc_ref(PhysAddr(table_entry & !0xfff));
+                }
+                if raw != table_entry {
+                    unsafe {
+                        self.map_raw(phys_mem, virt_addr, raw, true, false)?;
+                    }
+                }
+
+                unsafe {
+                    child.map_raw(phys_mem, virt_addr, raw, true, true)?;
+                }
+            }
+        }
+
+        Some(child)
+    }
+
+    /// Resolves a write fault against a copy-on-write page previously set up by `clone_cow`.
+    /// `faulting_addr` may be anywhere inside the page; only its containing page is touched.
+    /// Returns `None` if there's no mapping at `faulting_addr`, or if it isn't actually marked
+    /// `PAGE_ENTRY_COW` - such a fault is a genuine protection violation the caller should report,
+    /// not silently swallow.
+    ///
+    /// If another directory is still sharing the frame, a fresh frame is allocated, the shared
+    /// frame's contents are copied into it, and the copy is installed writable in this directory
+    /// in place of the shared frame, whose reference count is then dropped to reflect this
+    /// directory no longer pointing at it. If this directory already turns out to be the sole
+    /// remaining owner (the frame's reference count having already dropped to zero other owners,
+    /// e.g. because every other sharer already took its own copy-on-write fault or exited), the
+    /// existing frame is simply made writable in place instead - there's no one left to copy away
+    /// from.
+    pub fn handle_cow_fault(&mut self, phys_mem: &mut impl PhysMem, faulting_addr: VirtAddr)
+        -> Option<()> {
+        let page_addr = faulting_addr.page_down();
+        let (raw_entry, is_large) = self.lookup_raw_pte(phys_mem, page_addr)?;
+
+        if is_large || (raw_entry & PAGE_ENTRY_COW) == 0 {
+            return None;
+        }
+
+        let shared_frame = PhysAddr(raw_entry & !0xfff);
+        let user = (raw_entry & PAGE_ENTRY_USER) != 0;
+        let other_owners_remain = phys_mem.dec_ref(shared_frame) > 0;
+
+        let raw = if other_owners_remain {
+            // Still shared elsewhere: this directory needs its own private copy instead of
+            // mutating the frame out from under the other owner(s)
+            let mut copy = [0u8; 4096];
+            unsafe {
+                let shared_vaddr = phys_mem.translate_phys(Some(self), shared_frame, 4096)?;
+                copy.copy_from_slice(core::slice::from_raw_parts(shared_vaddr, 4096));
+            }
+
+            let page_layout = Layout::from_size_align(4096, 4096).unwrap();
+            let new_frame = phys_mem.allocate_phys_mem(page_layout)?.addr;
+
+            unsafe {
+                let new_vaddr = phys_mem.translate_phys(Some(self), new_frame, 4096)?;
+                core::ptr::copy_nonoverlapping(copy.as_ptr(), new_vaddr, 4096);
+            }
+
+            new_frame.0 | PAGE_ENTRY_PRESENT | PAGE_ENTRY_WRITE
+        } else {
+            // We were already the only owner left - the COW marker was stale, so just reclaim
+            // write access to the very same frame
+            shared_frame.0 | PAGE_ENTRY_PRESENT | PAGE_ENTRY_WRITE
+        };
+        let raw = if user { raw | PAGE_ENTRY_USER } else { raw };
+
+        unsafe {
+            self.map_raw(phys_mem, page_addr, raw, true, false)?;
+        }
+
+        Some(())
+    }
+
+    /// Flushes every non-global entry from the TLB by reloading CR3 with this page directory
+    ///
+    /// ### Safety
+    /// Must be called at CPL 0, and this page directory must currently be the one loaded in CR3
+    pub unsafe fn flush_tlb(&self) {
+        cpu::set_cr3(self.directory.0);
+    }
+
     /// Translates the virtual address `virt_addr` into the corresponding physical address based on
-    /// the page tables.
+    /// the page tables. Handles both ordinary 4 KiB PTEs and 4 MiB PSE large-page PDEs
+    /// transparently.
     pub fn translate_virt(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr)
         -> Option<PhysAddr> {
+        let (entry, is_large) = self.lookup_raw_pte(phys_mem, virt_addr)?;
+
+        if is_large {
+            Some(PhysAddr((entry & !(LARGE_PAGE_SIZE - 1)) + (virt_addr.0 & (LARGE_PAGE_SIZE - 1))))
+        } else {
+            // Calculate the physical address by adding the page address from the PTE and the page
+            // offset from the virtual address
+            Some(PhysAddr((entry & !0xFFF) + (virt_addr.0 & 0xFFF)))
+        }
+    }
+
+    /// Returns whether the page mapping `virt_addr` is accessible from user mode (CPL 3), and
+    /// whether it's writable, or `None` if there's no present mapping at `virt_addr` at all. Used
+    /// to validate user-supplied pointers before the kernel dereferences them.
+    pub fn page_permissions(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr)
+        -> Option<(bool, bool)> {
+        let (entry, _is_large) = self.lookup_raw_pte(phys_mem, virt_addr)?;
+
+        Some((entry & PAGE_ENTRY_USER != 0, entry & PAGE_ENTRY_WRITE != 0))
+    }
+
+    /// Walks the page tables for `virt_addr` and returns its raw page table entry together with
+    /// whether it came from a 4 MiB PSE PDE rather than an ordinary PTE, or `None` if there's no
+    /// page table, or no present mapping, at `virt_addr`.
+    fn lookup_raw_pte(&mut self, phys_mem: &mut impl PhysMem, virt_addr: VirtAddr)
+        -> Option<(u32, bool)> {
         // Index of the entry in the page directory
         let directory_index = virt_addr.0 >> 22;
         // Index of the entry in the page table
@@ -454,6 +991,11 @@ impl PageDirectory {
             return None;
         }
 
+        // A 4 MiB (PS) mapping lives entirely in the PDE; there is no PTE to consult
+        if (directory_entry & PAGE_ENTRY_PAGE_SIZE) != 0 {
+            return Some((directory_entry, true));
+        }
+
         // Compute the physical address of the PTE
         let table_entry_paddr = PhysAddr((directory_entry & !0xfff) + table_index * 4);
         // Get the entry in the table
@@ -465,12 +1007,60 @@ impl PageDirectory {
 
         // Check if the PTE is present (i.e. the page is already mapped)
         if (table_entry & PAGE_ENTRY_PRESENT) != 0 {
-            // Calculate the physical address by adding the page address from the PTE and the page
-            // offset from the virtual address
-            let paddr = (table_entry & !0xFFF) + (virt_addr.0 & 0xFFF);
-            Some(PhysAddr(paddr))
+            Some((table_entry, false))
         } else {
             None
         }
     }
+
+}
+
+/// Computes the raw page-table entry `clone_cow` should install for one present, user, non-huge
+/// page-table entry found in the parent directory, and whether the frame it points at has gained
+/// a new sharer that needs `PhysMem::inc_ref`'ing. A page still writable in the parent is
+/// downgraded to copy-on-write in both directories; a page already copy-on-write from an earlier
+/// fork is shared as-is. Either way the child is a brand new sharer of the frame and must be
+/// counted, or a later fault could think the frame has fewer owners than it really does and
+/// reclaim it while someone else is still pointing at it. Any other page (already read-only and
+/// never marked COW, e.g. a mapped file) is shared without bookkeeping, since nothing tracks its
+/// reference count.
+fn cow_child_entry(table_entry: u32) -> (u32, bool) {
+    if (table_entry & PAGE_ENTRY_WRITE) != 0 {
+        ((table_entry & !PAGE_ENTRY_WRITE) | PAGE_ENTRY_COW, true)
+    } else if (table_entry & PAGE_ENTRY_COW) != 0 {
+        (table_entry, true)
+    } else {
+        (table_entry, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+
+    #[test]
+    fn cow_child_entry_downgrades_writable_pages() {
+        let entry = PAGE_ENTRY_PRESENT | PAGE_ENTRY_USER | PAGE_ENTRY_WRITE;
+        let (raw, needs_inc_ref) = cow_child_entry(entry);
+        assert!(needs_inc_ref);
+        assert!(raw & PAGE_ENTRY_WRITE == 0);
+        assert!(raw & PAGE_ENTRY_COW != 0);
+    }
+
+    #[test]
+    fn cow_child_entry_recounts_pages_already_cow_from_an_earlier_fork() {
+        let entry = PAGE_ENTRY_PRESENT | PAGE_ENTRY_USER | PAGE_ENTRY_COW;
+        let (raw, needs_inc_ref) = cow_child_entry(entry);
+        assert!(needs_inc_ref);
+        assert!(raw == entry);
+    }
+
+    #[test]
+    fn cow_child_entry_leaves_plain_read_only_pages_uncounted() {
+        let entry = PAGE_ENTRY_PRESENT | PAGE_ENTRY_USER;
+        let (raw, needs_inc_ref) = cow_child_entry(entry);
+        assert!(!needs_inc_ref);
+        assert!(raw == entry);
+    }
 }
\ No newline at end of file
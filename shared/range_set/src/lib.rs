@@ -163,9 +163,21 @@ impl RangeSet {
     }
 
     /// Allocates `size` bytes from the RangeSet under the `align` alignment requirement.
-    /// 
+    ///
     /// The alignment must be a power of two.
     pub fn allocate(&mut self, size: u32, align: u32) -> Option<usize> {
+        self.allocate_constrained(size, align, u32::MAX)
+    }
+
+    /// Allocates `size` bytes from the RangeSet under the `align` alignment requirement, such that
+    /// the entire allocation (`[addr, addr+size-1]`) lies at or below `max_addr`.
+    ///
+    /// This is useful for e.g. allocating a buffer reachable from 16-bit real mode (under 1 MiB) or
+    /// a DMA region under a device's addressing limit. `allocate` is just this with
+    /// `max_addr = u32::MAX`.
+    ///
+    /// The alignment must be a power of two.
+    pub fn allocate_constrained(&mut self, size: u32, align: u32, max_addr: u32) -> Option<usize> {
         // We can't allocate a unique address for zero bytes
         if size == 0 {
             return None;
@@ -180,10 +192,19 @@ impl RangeSet {
         // in each range, and remember the best allocation with (padding_needed, allocation_addr)
         let mut best_allocation: Option<(u32, u32)> = None;
         for i in 0..self.num_ranges as usize {
+            // The allocation can't use any part of the range above `max_addr`
+            if self.ranges[i].start > max_addr {
+                continue;
+            }
+            let usable_end = core::cmp::min(self.ranges[i].end, max_addr);
+
             // We round up the start of the range to the alignment, so we can calculate if the
             // aligned allocation will fit in this range.
             let next_aligned_start = round_up_to_pow_of_2(self.ranges[i].start, align);
-            if size <= (self.ranges[i].end - next_aligned_start).saturating_add(1) {
+            if next_aligned_start > usable_end {
+                continue;
+            }
+            if size <= (usable_end - next_aligned_start).saturating_add(1) {
                 // If it does fit, we calculate the padding needed
                 let padding_needed = next_aligned_start - self.ranges[i].start;
 
@@ -244,6 +265,91 @@ fn does_range_contain(a: InclusiveRange, b: InclusiveRange) -> bool {
     (a.start <= b.start) && (b.end <= a.end)
 }
 
+/// Describes an inclusive range of 64-bit addresses, i.e. all addresses such that
+/// start <= addr <= end
+#[derive(Copy, Clone, Debug)]
+pub struct InclusiveRange64 {
+    pub start: u64,
+    pub end: u64
+}
+
+/// A set of non-overlaping and non-contiguous 64-bit inclusive ranges.
+///
+/// This is a separate, smaller sibling of `RangeSet` used to record the full, untrimmed memory map
+/// above the 4 GiB line, where the 32-bit allocator in `RangeSet` cannot represent addresses. It only
+/// supports insertion, since it is only used to accumulate the memory map for later hand-off, not to
+/// drive an allocator.
+pub struct RangeSet64 {
+    /// An array of ranges in the set
+    ranges: [InclusiveRange64; MAX_NUM_RANGES],
+
+    /// Number of ranges actually in use
+    num_ranges: u32
+}
+
+impl RangeSet64 {
+    /// Construct an empty RangeSet64
+    pub const fn new() -> Self {
+        RangeSet64 {
+            ranges: [InclusiveRange64 { start: 0, end: 0 }; MAX_NUM_RANGES],
+            num_ranges: 0
+        }
+    }
+
+    /// Get all ranges in the set
+    pub fn ranges(&self) -> &[InclusiveRange64] {
+        &self.ranges[..self.num_ranges as usize]
+    }
+
+    /// Deletes the range at index `idx` in the `ranges` array
+    pub fn delete(&mut self, idx: usize) {
+        assert!(idx < self.num_ranges as usize);
+
+        for i in idx..self.num_ranges as usize - 1 {
+            self.ranges[i] = self.ranges[i+1];
+        }
+
+        self.num_ranges -= 1;
+    }
+
+    /// Inserts `range` to the range set, merging ranges as necessary
+    pub fn insert(&mut self, mut range: InclusiveRange64) {
+        assert!(range.start <= range.end);
+
+        'try_merge: loop {
+            for i in 0..self.num_ranges as usize {
+                if !should_merge_ranges_64(range, self.ranges[i]) {
+                    continue;
+                }
+
+                range.start = core::cmp::min(range.start, self.ranges[i].start);
+                range.end = core::cmp::max(range.end, self.ranges[i].end);
+
+                self.delete(i);
+
+                continue 'try_merge;
+            }
+
+            break;
+        }
+
+        assert!((self.num_ranges as usize) < self.ranges.len());
+
+        self.ranges[self.num_ranges as usize] = range;
+        self.num_ranges += 1;
+    }
+}
+
+/// Checks whether or not the 64-bit ranges `a` and `b` should be merged, i.e. checks if the ranges
+/// overlap or are contiguous.
+fn should_merge_ranges_64(mut a: InclusiveRange64, mut b: InclusiveRange64) -> bool {
+    if a.start > b.start {
+        core::mem::swap(&mut a, &mut b);
+    }
+
+    b.start <= a.end.saturating_add(1)
+}
+
 /// Rounds up `val` to the next multiple of `power` which must be a power of 2
 fn round_up_to_pow_of_2(val: u32, power: u32) -> u32 {
     // Get a mask
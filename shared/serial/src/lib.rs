@@ -30,6 +30,35 @@ pub fn init_with_ports(serial_port: SerialPort) {
     *serial = Some(serial_port);
 }
 
+/// Blocks until a raw byte arrives on the first serial port, or returns `None` immediately if no
+/// serial port was ever initialized. Used by the GDB remote serial protocol stub, which needs to
+/// read individual protocol bytes rather than print text.
+pub fn read_raw_byte() -> Option<u8> {
+    SERIAL.lock().as_ref()?.read_raw_byte()
+}
+
+/// Writes a single raw byte to the first serial port, if one is present. See `read_raw_byte`.
+pub fn write_raw_byte(byte: u8) {
+    if let Some(serial) = SERIAL.lock().as_mut() {
+        serial.write_raw_byte(byte);
+    }
+}
+
+/// Reads a byte from the first serial port if one is ready, without blocking. Returns `None` if
+/// no serial port is present, or none has data waiting yet. See `SerialPort::read`.
+pub fn read() -> Option<u8> {
+    SERIAL.lock().as_ref()?.read()
+}
+
+/// Blocks until a full line (terminated by CR or LF) has been read from the first serial port,
+/// echoing input back as it's typed. See `SerialPort::read_line`.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    match SERIAL.lock().as_mut() {
+        Some(serial) => serial.read_line(buf),
+        None => 0,
+    }
+}
+
 impl SerialPort {
     /// Initializes all available serial ports with 115200 baud, 8n1.
     /// This function is unsafe because it relies on two unverified assumptions: that this function
@@ -102,7 +131,7 @@ impl SerialPort {
         if byte == b'\n' {
             self.write_byte(com_port, b'\r');
         }
-    
+
         // Wait until we can transmit
         while cpu::in8(com_port + 5) & 0x20 == 0 {
             core::hint::spin_loop();
@@ -111,6 +140,102 @@ impl SerialPort {
         cpu::out8(com_port, byte);
     }
 
+    /// The IO port of the first serial port found, if any. `read_raw_byte`/`write_raw_byte` talk
+    /// to this single port rather than broadcasting like `write` does, since a protocol like GDB's
+    /// remote serial protocol is a two-way conversation with one specific wire, not a log to mirror
+    /// everywhere.
+    fn first_port(&self) -> Option<u16> {
+        self.ports.iter().flatten().next().copied()
+    }
+
+    /// Blocks until a byte arrives on the first serial port and returns it, unmodified (no CRLF
+    /// translation, unlike `write`). Returns `None` if no serial port is present at all.
+    pub fn read_raw_byte(&self) -> Option<u8> {
+        let com_port = self.first_port()?;
+        unsafe {
+            while cpu::in8(com_port + 5) & 0x1 == 0 {
+                core::hint::spin_loop();
+            }
+            Some(cpu::in8(com_port))
+        }
+    }
+
+    /// Writes a single byte to the first serial port, unmodified. See `read_raw_byte`.
+    pub fn write_raw_byte(&mut self, byte: u8) {
+        if let Some(com_port) = self.first_port() {
+            unsafe {
+                while cpu::in8(com_port + 5) & 0x20 == 0 {
+                    core::hint::spin_loop();
+                }
+                cpu::out8(com_port, byte);
+            }
+        }
+    }
+
+    /// Reads a byte from the first serial port if one is ready, without blocking. Returns `None`
+    /// if no port is present, or the present port has no data waiting yet.
+    pub fn read(&self) -> Option<u8> {
+        read_byte(self.first_port()?)
+    }
+
+    /// Blocks until a full line, terminated by CR or LF (neither of which is stored in `buf`), has
+    /// been read from the first serial port. Each byte is echoed back as it's read, and backspace
+    /// (0x7F or 0x08) erases the previously read character, both in `buf` and on the terminal.
+    /// Returns the number of bytes written into `buf`, which is filled from the start and never
+    /// overrun - once it's full, further non-erasing bytes are read and echoed but discarded.
+    pub fn read_line(&mut self, buf: &mut [u8]) -> usize {
+        let com_port = match self.first_port() {
+            Some(com_port) => com_port,
+            None => return 0,
+        };
+
+        let mut len = 0;
+        loop {
+            let byte = loop {
+                if let Some(byte) = read_byte(com_port) {
+                    break byte;
+                }
+                core::hint::spin_loop();
+            };
+
+            match byte {
+                b'\r' | b'\n' => {
+                    unsafe { self.write_byte(com_port, b'\n'); }
+                    break;
+                },
+                0x7F | 0x08 => {
+                    if len > 0 {
+                        len -= 1;
+                        unsafe {
+                            self.write_byte(com_port, 0x08);
+                            self.write_byte(com_port, b' ');
+                            self.write_byte(com_port, 0x08);
+                        }
+                    }
+                },
+                byte => {
+                    if len < buf.len() {
+                        buf[len] = byte;
+                        len += 1;
+                    }
+                    unsafe { self.write_byte(com_port, byte); }
+                },
+            }
+        }
+
+        len
+    }
+}
+
+/// Polls the Line Status Register (bit 0, data-ready) on `com_port + 5`; if a byte is waiting,
+/// reads and returns it from the base port, without blocking.
+fn read_byte(com_port: u16) -> Option<u8> {
+    unsafe {
+        if cpu::in8(com_port + 5) & 0x1 == 0 {
+            return None;
+        }
+        Some(cpu::in8(com_port))
+    }
 }
 
 /// Dummy struct to implement `core::fmt::Write` on
@@ -18,6 +18,7 @@ pub enum Syscall {
 	Stat,
 	GetCWD,
 	ChangeCWD,
+	IoPerm,
 
     Count, // This must be kept last
 }
@@ -35,6 +36,7 @@ pub enum SyscallError {
 	PathIsNotDirectory,
 	BufferTooSmall,
 	InvalidElfFile,
+	InvalidIoPortRange,
 
     UnknownSyscallError, // This must be kept last, because `from_i32` uses it to determine if the
                          // error number is recognized
@@ -68,6 +70,10 @@ impl Syscall {
 	}
 }
 
+/// `open` flag requesting that `read` never block waiting for data to become available (currently
+/// only meaningful for `/dev/keyboard`); unset means the default blocking behaviour
+pub const O_NONBLOCK: u32 = 1 << 0;
+
 #[repr(C)]
 pub struct SyscallArray<'a, T> {
 	pub ptr: u32,
@@ -135,6 +141,30 @@ impl SyscallFileStat {
 	}
 }
 
+/// `event_type` value of a `SyscallKeyEvent` produced by a key being pressed down
+pub const KEY_EVENT_TYPE_DOWN: u8 = 0;
+/// `event_type` value of a `SyscallKeyEvent` produced by a key being released
+pub const KEY_EVENT_TYPE_UP: u8 = 1;
+
+/// `modifiers` bit flags of a `SyscallKeyEvent`
+pub const KEY_EVENT_SHIFT_DOWN: u8 = 1 << 0;
+pub const KEY_EVENT_CTRL_DOWN: u8 = 1 << 1;
+pub const KEY_EVENT_ALT_DOWN: u8 = 1 << 2;
+pub const KEY_EVENT_LOGO_DOWN: u8 = 1 << 3;
+pub const KEY_EVENT_CAPS_LOCK_ENABLED: u8 = 1 << 4;
+pub const KEY_EVENT_NUMBER_LOCK_ENABLED: u8 = 1 << 5;
+
+/// The on-wire record `read`ing from `/dev/keyboard` produces, one per queued key press/release.
+/// `key_code` is the kernel's `KeyCode` discriminant (see `keyboard::KeyCode`); `event_type` is
+/// `KEY_EVENT_TYPE_DOWN`/`KEY_EVENT_TYPE_UP`; `modifiers` is the `KEY_EVENT_*` flags above.
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+pub struct SyscallKeyEvent {
+	pub key_code: u8,
+	pub event_type: u8,
+	pub modifiers: u8,
+}
+
 #[repr(C)]
 pub struct SyscallDirectoryEntry {
 	pub inode: u32,
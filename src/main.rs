@@ -20,9 +20,10 @@ fn flatten_elf<P: AsRef<Path>>(file_path: P) -> Option<(usize, usize, Vec<u8>)>
 
     let mut program_start = None;
     let mut program_end = None; // Inclusive
-    parser.for_segment(|vaddr, size, _init_bytes, _flags| {
+    parser.for_segment(|vaddr, init_bytes, bss_len, _flags| {
         // Calculate the end of the segment. We sub before we add to prevent an overflow for a
         // segment that includes the last address.
+        let size = init_bytes.len() + bss_len;
         let segment_end = vaddr.checked_add(size.checked_sub(1)?)?;
 
         // Setup initial values
@@ -49,13 +50,12 @@ fn flatten_elf<P: AsRef<Path>>(file_path: P) -> Option<(usize, usize, Vec<u8>)>
     let mut flattened = vec![0u8; program_size];
 
     // Copy the segment into the flattened image
-    parser.for_segment(|vaddr, size, init_bytes, _flags| {
+    parser.for_segment(|vaddr, init_bytes, _bss_len, _flags| {
         // The segment's offset into the flat image
         let flat_offset = vaddr - program_start;
-        // We might not need to initialize the entire segment (e.g. bss segment)
-        let num_to_initialize = std::cmp::min(size, init_bytes.len());
-        // Copy the initialized bytes to the start of the segment
-        flattened[flat_offset..flat_offset.checked_add(num_to_initialize)?]
+        // Copy the initialized bytes to the start of the segment; the flattened image is already
+        // zeroed, so the trailing BSS bytes don't need anything further
+        flattened[flat_offset..flat_offset.checked_add(init_bytes.len())?]
             .copy_from_slice(init_bytes);
 
         Some(())
@@ -0,0 +1,57 @@
+//! A minimal global allocator for userland programs.
+//!
+//! There is no syscall yet to grow a process' address space on demand (no `sbrk`/`mmap`), so this
+//! just bump-allocates out of a fixed-size static arena baked into the binary. It never reclaims
+//! memory on `dealloc` - fine for the short-lived command-line tools that are the only consumers of
+//! `alloc` so far, but not a general-purpose heap.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+
+use lock_cell::LockCell;
+
+/// Size of the static arena backing the allocator
+const ARENA_SIZE: usize = 64 * 1024;
+
+struct Arena {
+	bytes: UnsafeCell<[u8; ARENA_SIZE]>,
+}
+
+// The arena is only ever accessed through `NEXT_FREE`, which is guarded by a `LockCell`
+unsafe impl Sync for Arena {}
+
+static ARENA: Arena = Arena { bytes: UnsafeCell::new([0u8; ARENA_SIZE]) };
+
+/// Byte offset of the next unused byte in `ARENA`
+static NEXT_FREE: LockCell<usize> = LockCell::new(0);
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: BumpAllocator = BumpAllocator;
+
+/// Dummy struct to implement `GlobalAlloc` on
+struct BumpAllocator;
+
+unsafe impl GlobalAlloc for BumpAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let arena_start = ARENA.bytes.get() as usize;
+
+		let mut next_free = NEXT_FREE.lock();
+
+		let alloc_start = (arena_start + *next_free + layout.align() - 1) & !(layout.align() - 1);
+		let alloc_end = match alloc_start.checked_add(layout.size()) {
+			Some(end) => end,
+			None => return core::ptr::null_mut(),
+		};
+
+		if alloc_end > arena_start + ARENA_SIZE {
+			return core::ptr::null_mut();
+		}
+
+		*next_free = alloc_end - arena_start;
+		alloc_start as *mut u8
+	}
+
+	unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+		// Never reclaimed - see module doc comment
+	}
+}
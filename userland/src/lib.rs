@@ -1,9 +1,14 @@
 #![no_std]
-#![feature(maybe_uninit_uninit_array, maybe_uninit_slice, panic_info_message)]
+#![feature(maybe_uninit_uninit_array, maybe_uninit_slice, panic_info_message,
+    default_alloc_error_handler)]
 
+extern crate alloc;
 extern crate compiler_reqs;
 
+mod allocator;
+
 pub mod syscalls;
+pub mod string;
 
 // TODO: Find a better place for these constants
 pub const STDIN_FD: u32 = 0;
@@ -0,0 +1,173 @@
+//! A small-string-optimized owned `String`, so command-line tools can build up paths/output
+//! without juggling raw `&[u8]`/`MaybeUninit` buffers and unsafe UTF-8 conversions by hand.
+//!
+//! `String` is a tagged union of two 12-byte (on this 32-bit target) representations: short
+//! strings are stored inline in the struct itself with no allocation at all, and longer ones fall
+//! back to a heap-backed `{ptr, len, capacity}` triple. The two states are distinguished by the top
+//! bit of the struct's last byte, which lines up with both the inline length byte and the top byte
+//! of the heap capacity - so checking the tag never needs to know which state is currently active.
+
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+use alloc::alloc::{alloc, dealloc, realloc, Layout, handle_alloc_error};
+
+/// Longest string `String` can store inline, without touching the heap
+const INLINE_CAPACITY: usize = size_of::<usize>() * 3 - 1;
+
+/// Set in the top bit of the struct's last byte while the string is in the heap state. Clear while
+/// inline - where that same byte holds the inline length in its low bits instead.
+const HEAP_TAG_BIT: u8 = 0x80;
+/// The same tag, but shaped to mask/set the top bit of the `capacity` word directly
+const HEAP_TAG_MASK: usize = 1 << (usize::BITS - 1);
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct HeapRepr {
+	ptr: NonNull<u8>,
+	len: usize,
+	/// The real capacity, OR'd with `HEAP_TAG_MASK` - see module docs
+	tagged_capacity: usize,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct InlineRepr {
+	bytes: [u8; INLINE_CAPACITY],
+	/// The string's length in the low 7 bits; the top bit is always clear - see module docs
+	tagged_len: u8,
+}
+
+union Repr {
+	inline: InlineRepr,
+	heap: HeapRepr,
+}
+
+/// An owned, growable string with small-string optimization
+pub struct String {
+	repr: Repr,
+}
+
+impl HeapRepr {
+	fn capacity(&self) -> usize {
+		self.tagged_capacity & !HEAP_TAG_MASK
+	}
+}
+
+impl String {
+	/// Constructs a new, empty `String`. This never allocates.
+	pub const fn new() -> Self {
+		String {
+			repr: Repr { inline: InlineRepr { bytes: [0u8; INLINE_CAPACITY], tagged_len: 0 } },
+		}
+	}
+
+	fn is_heap(&self) -> bool {
+		(unsafe { self.repr.inline.tagged_len } & HEAP_TAG_BIT) != 0
+	}
+
+	pub fn len(&self) -> usize {
+		if self.is_heap() {
+			unsafe { self.repr.heap.len }
+		} else {
+			unsafe { self.repr.inline.tagged_len as usize }
+		}
+	}
+
+	pub fn as_str(&self) -> &str {
+		let bytes = if self.is_heap() {
+			let heap = unsafe { &self.repr.heap };
+			unsafe { core::slice::from_raw_parts(heap.ptr.as_ptr(), heap.len) }
+		} else {
+			let inline = unsafe { &self.repr.inline };
+			&inline.bytes[..inline.tagged_len as usize]
+		};
+
+		unsafe { core::str::from_utf8_unchecked(bytes) }
+	}
+
+	/// Appends `s` to the end of this string, growing onto the heap if it no longer fits inline
+	pub fn push_str(&mut self, s: &str) {
+		let new_len = self.len() + s.len();
+
+		if !self.is_heap() && new_len <= INLINE_CAPACITY {
+			// Still fits inline - just write the new bytes in after the existing ones
+			let inline = unsafe { &mut self.repr.inline };
+			let old_len = inline.tagged_len as usize;
+			inline.bytes[old_len..new_len].copy_from_slice(s.as_bytes());
+			inline.tagged_len = new_len as u8;
+			return;
+		}
+
+		if !self.is_heap() {
+			// Promote from inline to heap: move the existing bytes into a fresh allocation
+			let inline = unsafe { self.repr.inline };
+			let old_len = inline.tagged_len as usize;
+			let capacity = new_len.max(INLINE_CAPACITY * 2);
+
+			let ptr = Self::alloc_heap_buffer(capacity);
+			unsafe {
+				ptr::copy_nonoverlapping(inline.bytes.as_ptr(), ptr.as_ptr(), old_len);
+			}
+
+			self.repr = Repr { heap: HeapRepr { ptr, len: old_len,
+				tagged_capacity: capacity | HEAP_TAG_MASK } };
+		}
+
+		let heap = unsafe { &mut self.repr.heap };
+		if new_len > heap.capacity() {
+			let new_capacity = new_len.max(heap.capacity() * 2);
+			let old_layout = Layout::array::<u8>(heap.capacity()).unwrap();
+			let new_ptr = unsafe {
+				realloc(heap.ptr.as_ptr(), old_layout, new_capacity)
+			};
+			heap.ptr = NonNull::new(new_ptr).unwrap_or_else(|| {
+				handle_alloc_error(Layout::array::<u8>(new_capacity).unwrap())
+			});
+			heap.tagged_capacity = new_capacity | HEAP_TAG_MASK;
+		}
+
+		unsafe {
+			ptr::copy_nonoverlapping(s.as_bytes().as_ptr(), heap.ptr.as_ptr().add(heap.len), s.len());
+		}
+		heap.len = new_len;
+	}
+
+	fn alloc_heap_buffer(capacity: usize) -> NonNull<u8> {
+		let layout = Layout::array::<u8>(capacity).unwrap();
+		let ptr = unsafe { alloc(layout) };
+		NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout))
+	}
+}
+
+impl Clone for String {
+	fn clone(&self) -> Self {
+		let mut new_string = String::new();
+		new_string.push_str(self.as_str());
+		new_string
+	}
+}
+
+impl Drop for String {
+	fn drop(&mut self) {
+		if self.is_heap() {
+			let heap = unsafe { self.repr.heap };
+			let layout = Layout::array::<u8>(heap.capacity()).unwrap();
+			unsafe { dealloc(heap.ptr.as_ptr(), layout) };
+		}
+	}
+}
+
+impl core::fmt::Write for String {
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		self.push_str(s);
+		Ok(())
+	}
+}
+
+impl From<&str> for String {
+	fn from(s: &str) -> Self {
+		let mut string = String::new();
+		string.push_str(s);
+		string
+	}
+}
@@ -148,4 +148,11 @@ pub fn change_cwd(path: &str) -> SyscallResult<()> {
 
 	syscall1(Syscall::ChangeCWD, &path_arg as *const SyscallString as u32)?;
 	Ok(())
+}
+
+/// Grants (`turn_on: true`) or revokes direct access to the `num_ports` I/O ports starting at
+/// `port`, without needing IOPL raised
+pub fn ioperm(port: u16, num_ports: u16, turn_on: bool) -> SyscallResult<()> {
+	syscall3(Syscall::IoPerm, port as u32, num_ports as u32, turn_on as u32)?;
+	Ok(())
 }
\ No newline at end of file